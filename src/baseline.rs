@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use toml_edit::{Array, DocumentMut, Item, Table};
+
+use crate::diagnostic;
+use crate::policy::Violation;
+
+/// Findings of `check` accepted for now, read from a `--baseline` file, so
+/// only new ones fail it:
+///
+/// ```toml
+/// unconsolidated = ["rand"]
+///
+/// [policy-violations]
+/// api = ["serde"]
+/// ```
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Baseline {
+    /// Dependencies left to consolidate
+    pub unconsolidated: BTreeSet<String>,
+    /// Dependencies outside of the policy, by the member requiring them
+    pub policy_violations: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Baseline {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        Self::parse(&content)
+            .map_err(|err| diagnostic::locate(err, path, &content))
+            .with_context(|| format!("Failed to parse '{}'", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        Ok(toml_edit::de::from_str(content)?)
+    }
+
+    /// Records the current findings
+    pub fn from_findings(unconsolidated: &[String], violations: &[Violation]) -> Self {
+        let mut baseline = Self {
+            unconsolidated: unconsolidated.iter().cloned().collect(),
+            ..Default::default()
+        };
+        for violation in violations {
+            baseline
+                .policy_violations
+                .entry(violation.member.clone())
+                .or_default()
+                .insert(violation.dependency.clone());
+        }
+        baseline
+    }
+
+    pub fn accepts_unconsolidated(&self, dep: &str) -> bool {
+        self.unconsolidated.contains(dep)
+    }
+
+    pub fn accepts_violation(&self, violation: &Violation) -> bool {
+        self.policy_violations
+            .get(&violation.member)
+            .is_some_and(|deps| deps.contains(&violation.dependency))
+    }
+
+    /// The entries of the baseline which `current` doesn't have anymore,
+    /// i.e. the debt paid down since it was written
+    pub fn fixed(&self, current: &Baseline) -> Baseline {
+        let mut fixed = Baseline {
+            unconsolidated: self
+                .unconsolidated
+                .difference(&current.unconsolidated)
+                .cloned()
+                .collect(),
+            ..Default::default()
+        };
+        for (member, deps) in &self.policy_violations {
+            let still = current.policy_violations.get(member);
+            let gone: BTreeSet<String> = deps
+                .iter()
+                .filter(|dep| !still.is_some_and(|still| still.contains(*dep)))
+                .cloned()
+                .collect();
+            if !gone.is_empty() {
+                fixed.policy_violations.insert(member.clone(), gone);
+            }
+        }
+        fixed
+    }
+
+    /// Removes the entries of `fixed`
+    pub fn prune(&mut self, fixed: &Baseline) {
+        self.unconsolidated
+            .retain(|dep| !fixed.unconsolidated.contains(dep));
+        for (member, gone) in &fixed.policy_violations {
+            if let Some(deps) = self.policy_violations.get_mut(member) {
+                deps.retain(|dep| !gone.contains(dep));
+                if deps.is_empty() {
+                    self.policy_violations.remove(member);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.unconsolidated.len()
+            + self
+                .policy_violations
+                .values()
+                .map(BTreeSet::len)
+                .sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.render())
+            .with_context(|| format!("Failed to write '{}'", path.display()))
+    }
+
+    fn render(&self) -> String {
+        let mut doc = DocumentMut::new();
+        let unconsolidated: Array = self.unconsolidated.iter().map(String::as_str).collect();
+        doc.insert("unconsolidated", toml_edit::value(unconsolidated));
+        if !self.policy_violations.is_empty() {
+            let mut table = Table::new();
+            for (member, deps) in &self.policy_violations {
+                let deps: Array = deps.iter().map(String::as_str).collect();
+                table.insert(member, toml_edit::value(deps));
+            }
+            doc.insert("policy-violations", Item::Table(table));
+        }
+        format!(
+            "# Findings `cargo consolidate check --baseline` accepts for now.\n\
+             # Shrink it with --prune-baseline as they are fixed.\n{}",
+            doc
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_and_prune() -> Result<()> {
+        let violation = |member: &str, dependency: &str| Violation {
+            member: member.to_string(),
+            dependency: dependency.to_string(),
+            requirement: "0.1".to_string(),
+            allowed: "1".to_string(),
+        };
+        let mut baseline = Baseline::from_findings(
+            &["rand".to_string(), "serde".to_string()],
+            &[violation("api", "serde"), violation("api", "tokio")],
+        );
+        let rendered = baseline.render();
+        assert!(rendered.ends_with(
+            "unconsolidated = [\"rand\", \"serde\"]\n\n[policy-violations]\napi = [\"serde\", \"tokio\"]\n"
+        ));
+        assert_eq!(Baseline::parse(&rendered)?, baseline);
+
+        let current = Baseline::from_findings(&["serde".to_string()], &[violation("api", "tokio")]);
+        assert!(baseline.accepts_unconsolidated("rand"));
+        assert!(baseline.accepts_violation(&violation("api", "serde")));
+        assert!(!baseline.accepts_violation(&violation("cli", "serde")));
+
+        let fixed = baseline.fixed(&current);
+        assert_eq!(fixed.len(), 2);
+        baseline.prune(&fixed);
+        assert_eq!(baseline, current);
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::{Context, Result};
+use cargo_metadata::Metadata;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use crate::cli::CargoOptions;
+use crate::manifest::Manifest;
+use crate::metadata;
+use crate::workspace;
+
+/// Environment variables moving the target directory, which is part of the
+/// metadata, or the configuration files cargo reads
+const CARGO_VARS: [&str; 3] = ["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR", "CARGO_HOME"];
+
+/// Metadata of an earlier run together with the hashes of the files it was
+/// derived from
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    files: Vec<(PathBuf, u64)>,
+    metadata: Metadata,
+}
+
+/// Returns the cached metadata of the project containing `manifest_path`
+/// if none of its manifests and cargo configuration files changed since it
+/// was cached, otherwise calls `load` and caches the result
+pub fn metadata(
+    manifest_path: Option<&Path>,
+    cargo: &CargoOptions,
+    load: impl FnOnce() -> Result<Metadata>,
+) -> Result<Metadata> {
+    let Some(cache_dir) = cache_dir() else {
+        return load();
+    };
+    // cargo reads its configuration from where it runs, e.g. build.target-dir
+    let current_dir = std::env::current_dir().context("Failed to get the current directory")?;
+    let config_dirs = metadata::config_dirs(&current_dir, metadata::cargo_home().as_deref());
+    cached_metadata(&cache_dir, manifest_path, cargo, &config_dirs, load)
+}
+
+/// `$XDG_CACHE_HOME/cargo-consolidate`, falling back to `~/.cache`
+fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|dir| dir.join("cargo-consolidate"))
+}
+
+fn cached_metadata(
+    cache_dir: &Path,
+    manifest_path: Option<&Path>,
+    cargo: &CargoOptions,
+    config_dirs: &[PathBuf],
+    load: impl FnOnce() -> Result<Metadata>,
+) -> Result<Metadata> {
+    let project = match manifest_path {
+        Some(path) => path.to_path_buf(),
+        None => std::env::current_dir().context("Failed to get the current directory")?,
+    };
+    let project = fs::canonicalize(&project).unwrap_or(project);
+
+    // Runs with different cargo flags, configuration or target directory
+    // see different metadata
+    let mut hasher = DefaultHasher::new();
+    project.hash(&mut hasher);
+    cargo.args().hash(&mut hasher);
+    cargo.full_metadata.hash(&mut hasher);
+    cargo.cargo.hash(&mut hasher);
+    config_dirs.hash(&mut hasher);
+    for var in CARGO_VARS {
+        std::env::var_os(var).hash(&mut hasher);
+    }
+    let entry_path = cache_dir.join(format!("metadata-{:016x}.json", hasher.finish()));
+
+    if let Some(metadata) = read_entry(&entry_path) {
+        debug!("Using cached metadata '{}'", entry_path.display());
+        return Ok(metadata);
+    }
+
+    let metadata = load()?;
+    // A cache which can't be written only costs time
+    if let Err(err) = write_entry(&entry_path, &metadata, config_dirs) {
+        warn!("Failed to cache the metadata: {:#}", err);
+    }
+    Ok(metadata)
+}
+
+/// Returns the metadata of the entry if the files it was derived from are
+/// unchanged and the member globs match no new crates
+fn read_entry(entry_path: &Path) -> Option<Metadata> {
+    let content = fs::read_to_string(entry_path).ok()?;
+    let entry: Entry = serde_json::from_str(&content).ok()?;
+    let unchanged = entry
+        .files
+        .iter()
+        .all(|(path, hash)| hash_file(path) == *hash);
+    if !unchanged {
+        return None;
+    }
+
+    let recorded: HashSet<&Path> = entry.files.iter().map(|(path, _)| path.as_path()).collect();
+    let workspace_root = entry.metadata.workspace_root.as_std_path();
+    let root = Manifest::open(&workspace_root.join("Cargo.toml")).ok()?;
+    let members = metadata::find_members(&root.doc, workspace_root).ok()?;
+    if members
+        .iter()
+        .any(|member| !recorded.contains(member.as_path()))
+    {
+        return None;
+    }
+    Some(entry.metadata)
+}
+
+fn write_entry(entry_path: &Path, metadata: &Metadata, config_dirs: &[PathBuf]) -> Result<()> {
+    let workspace_root = metadata.workspace_root.as_std_path();
+    let mut paths = vec![
+        workspace_root.join("Cargo.toml"),
+        workspace_root.join("Cargo.lock"),
+    ];
+    paths.extend(
+        workspace::workspace_packages(metadata)?
+            .iter()
+            .map(|package| package.manifest_path.clone().into_std_path_buf()),
+    );
+    for dir in config_dirs {
+        paths.push(dir.join("config"));
+        paths.push(dir.join("config.toml"));
+    }
+
+    // A missing Cargo.lock or configuration file is recorded as well,
+    // creating one changes it
+    let files = paths
+        .into_iter()
+        .map(|path| {
+            let hash = hash_file(&path);
+            (path, hash)
+        })
+        .collect();
+    let entry = Entry {
+        files,
+        metadata: metadata.clone(),
+    };
+
+    if let Some(dir) = entry_path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create '{}'", dir.display()))?;
+    }
+    fs::write(entry_path, serde_json::to_string(&entry)?)
+        .with_context(|| format!("Failed to write '{}'", entry_path.display()))
+}
+
+/// Hashes the content of a file, 0 standing for a missing file. The hash
+/// is only stable for one build of the tool, which at worst makes an
+/// upgrade miss the cache once
+pub fn hash_file(path: &Path) -> u64 {
+    match fs::read(path) {
+        Ok(content) => {
+            let mut hasher = DefaultHasher::new();
+            content.hash(&mut hasher);
+            hasher.finish()
+        }
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use std::cell::Cell;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_cached_metadata() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("ws");
+        let cache_dir = temp_dir.path().join("cache");
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )?;
+        write_file(
+            root.join("crates/a/Cargo.toml"),
+            "[package]\nname = \"a\"\n",
+        )?;
+
+        let config_dirs = metadata::config_dirs(&root, None);
+        let loads = Cell::new(0);
+        let load = || {
+            let manifest_path = root.join("Cargo.toml");
+            cached_metadata(
+                &cache_dir,
+                Some(&manifest_path),
+                &CargoOptions::default(),
+                &config_dirs,
+                || {
+                    loads.set(loads.get() + 1);
+                    metadata::from_manifests(Some(&manifest_path))
+                },
+            )
+        };
+
+        load()?;
+        assert_eq!(load()?.workspace_packages().len(), 1);
+        assert_eq!(loads.get(), 1);
+
+        // Edited members invalidate the cache
+        write_file(
+            root.join("crates/a/Cargo.toml"),
+            "[package]\nname = \"a\"\nversion = \"0.2.0\"\n",
+        )?;
+        load()?;
+        assert_eq!(loads.get(), 2);
+
+        // So do crates newly matched by the member globs
+        write_file(
+            root.join("crates/b/Cargo.toml"),
+            "[package]\nname = \"b\"\n",
+        )?;
+        assert_eq!(load()?.workspace_packages().len(), 2);
+        assert_eq!(loads.get(), 3);
+
+        // And cargo configuration, which can move the target directory
+        write_file(
+            root.join(".cargo/config.toml"),
+            "[build]\ntarget-dir = \"out\"\n",
+        )?;
+        load()?;
+        assert_eq!(loads.get(), 4);
+        load()?;
+        assert_eq!(loads.get(), 4);
+        Ok(())
+    }
+}
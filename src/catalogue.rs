@@ -0,0 +1,130 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::cli::{ExportCatalogueArgs, Opt};
+use crate::diagnostic;
+use crate::manifest::Manifest;
+use crate::workspace;
+
+/// Dependency specs shared between workspaces, stored as a TOML file with a
+/// single `[dependencies]` table
+#[derive(Debug, Default)]
+pub struct Catalogue {
+    dependencies: Table,
+}
+
+impl Catalogue {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        Self::parse(&content)
+            .map_err(|err| diagnostic::locate(err, path, &content))
+            .with_context(|| format!("Failed to parse '{}'", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut doc = content.parse::<DocumentMut>()?;
+        let dependencies = match doc.remove("dependencies") {
+            Some(Item::Table(table)) => table,
+            Some(_) => {
+                return Err(diagnostic::unexpected(
+                    &["dependencies"],
+                    "dependencies is not a table",
+                ))
+            }
+            None => Table::new(),
+        };
+        Ok(Self { dependencies })
+    }
+
+    /// Returns the catalogue's spec of `dep_name`
+    pub fn get(&self, dep_name: &str) -> Option<&Item> {
+        self.dependencies.get(dep_name)
+    }
+
+    /// Builds a catalogue from the `[workspace.dependencies]` of a root
+    /// manifest. Path dependencies only make sense within their workspace
+    /// and are left out
+    pub fn from_workspace(root: &DocumentMut) -> Self {
+        let mut dependencies = Table::new();
+        let ws_deps = root
+            .get("workspace")
+            .and_then(|workspace| workspace.get("dependencies"))
+            .and_then(Item::as_table_like);
+        for (dep_name, dep_item) in ws_deps.into_iter().flat_map(|deps| deps.iter()) {
+            let is_path = dep_item
+                .as_table_like()
+                .is_some_and(|tbl| tbl.contains_key("path"));
+            if is_path {
+                warn!(
+                    "Leaving out '{}', path dependencies can't be shared",
+                    dep_name
+                );
+                continue;
+            }
+
+            let mut dep_item = dep_item.clone();
+            // Drop comments like the ones of --used-by-comments
+            if let Some(value) = dep_item.as_value_mut() {
+                value.decor_mut().clear();
+            }
+            dependencies.insert(dep_name, dep_item);
+        }
+        Self { dependencies }
+    }
+
+    pub fn render(&self) -> String {
+        let mut doc = DocumentMut::new();
+        doc.insert("dependencies", Item::Table(self.dependencies.clone()));
+        doc.to_string()
+    }
+}
+
+/// Writes the `[workspace.dependencies]` of the workspace as a catalogue
+pub fn export(opt: &Opt, args: &ExportCatalogueArgs) -> Result<()> {
+    let metadata = workspace::load_metadata(opt.manifest_path(), &opt.cargo)?;
+    let root = Manifest::open(metadata.workspace_root.join("Cargo.toml").as_std_path())?;
+    let catalogue = Catalogue::from_workspace(&root.doc);
+
+    match &args.output {
+        Some(path) => {
+            info!("Writing catalogue to '{}'", path.display());
+            fs::write(path, catalogue.render())
+                .with_context(|| format!("Failed to write '{}'", path.display()))
+        }
+        None => {
+            print!("{}", catalogue.render());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_and_import() -> Result<()> {
+        let root = r#"
+            [workspace.dependencies]
+            serde = { version = "1.0.200", features = ["derive"] } # used by: a, b
+            shared = { path = "crates/shared" }
+            tokio = "1"
+        "#
+        .parse::<DocumentMut>()?;
+
+        let rendered = Catalogue::from_workspace(&root).render();
+        let catalogue = Catalogue::parse(&rendered)?;
+        assert_eq!(
+            rendered,
+            "[dependencies]\nserde = { version = \"1.0.200\", features = [\"derive\"] }\ntokio = \"1\"\n"
+        );
+        assert!(catalogue.get("serde").is_some());
+        assert_eq!(catalogue.get("tokio").and_then(Item::as_str), Some("1"));
+        assert!(catalogue.get("shared").is_none());
+        Ok(())
+    }
+}
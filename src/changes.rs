@@ -0,0 +1,437 @@
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Value};
+
+use crate::cli::{DiffArgs, Opt, ReportFormat};
+use crate::dependency;
+use crate::diagnostic;
+use crate::git;
+use crate::metadata;
+
+/// The consolidated dependencies of a workspace at one point in time
+#[derive(Debug, Default)]
+struct Snapshot {
+    /// `[workspace.dependencies]`, by name
+    workspace: BTreeMap<String, String>,
+    /// By manifest path relative to the workspace root
+    members: BTreeMap<PathBuf, Declarations>,
+}
+
+/// The dependencies a member declares, by name
+type Declarations = BTreeMap<String, Declaration>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Declaration {
+    Inherited,
+    Own(String),
+}
+
+/// How the consolidated dependencies changed between two revisions
+#[derive(Debug, Default, Serialize)]
+pub struct Changes {
+    pub since: String,
+    pub added: Vec<Entry>,
+    pub removed: Vec<Entry>,
+    pub changed: Vec<Change>,
+    pub members: Vec<MemberChanges>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub dependency: String,
+    pub spec: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub dependency: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MemberChanges {
+    pub manifest: PathBuf,
+    /// The member didn't exist at the old revision
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub new: bool,
+    /// The member doesn't exist anymore
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub removed: bool,
+    /// Declared itself before
+    pub now_inherited: Vec<String>,
+    /// Inherited before
+    pub no_longer_inherited: Vec<String>,
+    /// Changes of the specs the member declares itself
+    pub changed: Vec<Change>,
+}
+
+impl MemberChanges {
+    fn is_empty(&self) -> bool {
+        !self.new
+            && !self.removed
+            && self.now_inherited.is_empty()
+            && self.no_longer_inherited.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+/// Prints how the consolidated dependencies changed since `args.rev`
+pub fn run(opt: &Opt, args: &DiffArgs) -> Result<()> {
+    let manifest_path = match opt.manifest_path() {
+        Some(path) if path.is_dir() => path.join("Cargo.toml"),
+        Some(path) => path.to_path_buf(),
+        None => std::env::current_dir()
+            .context("Failed to get the current directory")?
+            .join("Cargo.toml"),
+    };
+    let manifest_path = fs::canonicalize(&manifest_path)
+        .with_context(|| format!("Failed to find '{}'", manifest_path.display()))?;
+    let root_manifest_path = metadata::find_workspace_root(&manifest_path)?;
+    let workspace_root = root_manifest_path
+        .parent()
+        .context("The root manifest has no parent directory")?;
+
+    let old = snapshot_at(workspace_root, &args.rev)?;
+    let new = snapshot(workspace_root)?;
+    let changes = compare(&args.rev, &old, &new);
+
+    match args.format {
+        ReportFormat::Markdown => print!("{}", changes),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&changes)?),
+    }
+    Ok(())
+}
+
+/// Reads the manifests of the working tree
+fn snapshot(workspace_root: &Path) -> Result<Snapshot> {
+    let root_path = workspace_root.join("Cargo.toml");
+    let root = read(&root_path, fs::read_to_string(&root_path).ok())?;
+    let mut snapshot = Snapshot::from_root(&root);
+    let mut members = metadata::find_members(&root, workspace_root)?;
+    if root.contains_key("package") {
+        members.push(root_path);
+    }
+    for manifest_path in members {
+        let doc = read(&manifest_path, fs::read_to_string(&manifest_path).ok())?;
+        let relative = manifest_path
+            .strip_prefix(workspace_root)
+            .unwrap_or(&manifest_path);
+        snapshot.add_member(relative, &doc);
+    }
+    Ok(snapshot)
+}
+
+/// Reads the manifests at `rev` from git, without checking anything out
+fn snapshot_at(workspace_root: &Path, rev: &str) -> Result<Snapshot> {
+    let root_path = Path::new("Cargo.toml");
+    let root = read(root_path, git::file_at(workspace_root, rev, root_path))
+        .with_context(|| format!("Failed to read the root manifest at '{}'", rev))?;
+    let mut snapshot = Snapshot::from_root(&root);
+    for manifest_path in git::manifests_at(workspace_root, rev)? {
+        let is_root = manifest_path == root_path;
+        if (is_root && !root.contains_key("package"))
+            || (!is_root && !is_member(&root, &manifest_path))
+        {
+            continue;
+        }
+        let doc = read(
+            &manifest_path,
+            git::file_at(workspace_root, rev, &manifest_path),
+        )?;
+        snapshot.add_member(&manifest_path, &doc);
+    }
+    Ok(snapshot)
+}
+
+fn read(path: &Path, content: Option<String>) -> Result<DocumentMut> {
+    let content = content.with_context(|| format!("Failed to read '{}'", path.display()))?;
+    content
+        .parse()
+        .map_err(|err| diagnostic::locate(anyhow::Error::new(err), path, &content))
+        .with_context(|| format!("Failed to parse '{}'", path.display()))
+}
+
+/// Whether the manifest at `manifest_path`, relative to the workspace root,
+/// is matched by `workspace.members` and not by `workspace.exclude`
+fn is_member(root: &DocumentMut, manifest_path: &Path) -> bool {
+    let Some(dir) = manifest_path.parent() else {
+        return false;
+    };
+    let patterns = |key: &str| -> Vec<String> {
+        root.get("workspace")
+            .and_then(|workspace| workspace.get(key))
+            .and_then(Item::as_array)
+            .into_iter()
+            .flat_map(|array| array.iter())
+            .filter_map(|value| value.as_str())
+            .map(|pattern| pattern.trim_end_matches('/').to_string())
+            .collect()
+    };
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let excluded = patterns("exclude")
+        .iter()
+        .any(|exclude| dir.starts_with(exclude));
+    !excluded
+        && patterns("members").iter().any(|pattern| {
+            Pattern::new(pattern).is_ok_and(|pattern| pattern.matches_path_with(dir, options))
+        })
+}
+
+impl Snapshot {
+    fn from_root(root: &DocumentMut) -> Self {
+        let workspace = root
+            .get("workspace")
+            .and_then(|workspace| workspace.get("dependencies"))
+            .and_then(Item::as_table_like)
+            .into_iter()
+            .flat_map(|deps| deps.iter())
+            .map(|(dep, item)| (dep.to_string(), render(item)))
+            .collect();
+        Self {
+            workspace,
+            members: BTreeMap::new(),
+        }
+    }
+
+    fn add_member(&mut self, manifest_path: &Path, doc: &DocumentMut) {
+        let mut declarations = Declarations::new();
+        for section in dependency::DEPENDENCY_SECTIONS {
+            let Some(deps) = doc.get(section).and_then(Item::as_table_like) else {
+                continue;
+            };
+            for (dep, item) in deps.iter() {
+                let declaration = if dependency::is_workspace_inherited(item) {
+                    Declaration::Inherited
+                } else {
+                    Declaration::Own(render(item))
+                };
+                declarations.entry(dep.to_string()).or_insert(declaration);
+            }
+        }
+        self.members
+            .insert(manifest_path.to_path_buf(), declarations);
+    }
+}
+
+/// A spec as one line without comments, e.g. `{ version = "1", features = ["derive"] }`
+fn render(item: &Item) -> String {
+    let value = match item {
+        Item::Value(value) => value.clone(),
+        Item::Table(table) => Value::InlineTable(table.clone().into_inline_table()),
+        _ => return String::new(),
+    };
+    let mut value = value;
+    value.decor_mut().clear();
+    if let Value::InlineTable(table) = &mut value {
+        table.fmt();
+    }
+    value.to_string().trim().to_string()
+}
+
+fn compare(since: &str, old: &Snapshot, new: &Snapshot) -> Changes {
+    let mut changes = Changes {
+        since: since.to_string(),
+        ..Default::default()
+    };
+
+    let deps: BTreeSet<&String> = old.workspace.keys().chain(new.workspace.keys()).collect();
+    for dep in deps {
+        match (old.workspace.get(dep), new.workspace.get(dep)) {
+            (None, Some(spec)) => changes.added.push(Entry {
+                dependency: dep.clone(),
+                spec: spec.clone(),
+            }),
+            (Some(spec), None) => changes.removed.push(Entry {
+                dependency: dep.clone(),
+                spec: spec.clone(),
+            }),
+            (Some(from), Some(to)) if from != to => changes.changed.push(Change {
+                dependency: dep.clone(),
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            _ => {}
+        }
+    }
+
+    let members: BTreeSet<&PathBuf> = old.members.keys().chain(new.members.keys()).collect();
+    let none = Declarations::new();
+    for manifest in members {
+        let before = old.members.get(manifest);
+        let after = new.members.get(manifest);
+        let mut member = MemberChanges {
+            manifest: manifest.clone(),
+            new: before.is_none(),
+            removed: after.is_none(),
+            ..Default::default()
+        };
+        let (before, after) = (before.unwrap_or(&none), after.unwrap_or(&none));
+        for (dep, declaration) in after {
+            match (before.get(dep), declaration) {
+                (Some(Declaration::Own(_)), Declaration::Inherited) => {
+                    member.now_inherited.push(dep.clone())
+                }
+                (Some(Declaration::Inherited), Declaration::Own(_)) => {
+                    member.no_longer_inherited.push(dep.clone())
+                }
+                (Some(Declaration::Own(from)), Declaration::Own(to)) if from != to => {
+                    member.changed.push(Change {
+                        dependency: dep.clone(),
+                        from: from.clone(),
+                        to: to.clone(),
+                    })
+                }
+                _ => {}
+            }
+        }
+        if !member.is_empty() {
+            changes.members.push(member);
+        }
+    }
+    changes
+}
+
+impl fmt::Display for Changes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Dependency changes since `{}`", self.since)?;
+        writeln!(f)?;
+        if self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.members.is_empty()
+        {
+            writeln!(f, "Nothing changed.")?;
+            return Ok(());
+        }
+
+        if !self.added.is_empty() || !self.removed.is_empty() || !self.changed.is_empty() {
+            writeln!(f, "## [workspace.dependencies]")?;
+            writeln!(f)?;
+            for entry in &self.added {
+                writeln!(f, "- Added `{}`: `{}`", entry.dependency, entry.spec)?;
+            }
+            for entry in &self.removed {
+                writeln!(f, "- Removed `{}`: `{}`", entry.dependency, entry.spec)?;
+            }
+            for change in &self.changed {
+                writeln!(
+                    f,
+                    "- `{}`: `{}` -> `{}`",
+                    change.dependency, change.from, change.to
+                )?;
+            }
+            writeln!(f)?;
+        }
+
+        if !self.members.is_empty() {
+            writeln!(f, "## Members")?;
+            writeln!(f)?;
+            for member in &self.members {
+                let mut notes = Vec::new();
+                if member.new {
+                    notes.push("new member".to_string());
+                }
+                if member.removed {
+                    notes.push("removed".to_string());
+                }
+                if !member.now_inherited.is_empty() {
+                    notes.push(format!("now inherits {}", code_list(&member.now_inherited)));
+                }
+                if !member.no_longer_inherited.is_empty() {
+                    notes.push(format!(
+                        "no longer inherits {}",
+                        code_list(&member.no_longer_inherited)
+                    ));
+                }
+                for change in &member.changed {
+                    notes.push(format!(
+                        "`{}`: `{}` -> `{}`",
+                        change.dependency, change.from, change.to
+                    ));
+                }
+                writeln!(f, "- `{}`: {}", member.manifest.display(), notes.join("; "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn code_list(deps: &[String]) -> String {
+    deps.iter()
+        .map(|dep| format!("`{}`", dep))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::git;
+    use crate::test_util::write_file;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_diff() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n\n[workspace.dependencies]\nserde = \"1.0.100\"\nrand = \"0.8\"\n",
+        )?;
+        write_file(
+            root.join("crates/a/Cargo.toml"),
+            "[package]\nname = \"a\"\n\n[dependencies]\nserde = { workspace = true }\nrand = { workspace = true }\ntokio = \"1\"\nlog = \"0.4.1\"\n",
+        )?;
+        git(root, &["init", "--quiet"])?;
+        git(root, &["add", "."])?;
+        git(
+            root,
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                "init",
+            ],
+        )?;
+
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n\n[workspace.dependencies]\nserde = \"1.0.200\" # used by: a\ntokio = \"1\"\n",
+        )?;
+        write_file(
+            root.join("crates/a/Cargo.toml"),
+            "[package]\nname = \"a\"\n\n[dependencies]\nserde = { workspace = true }\nrand = \"0.8\"\ntokio = { workspace = true }\nlog = \"0.4.2\"\n",
+        )?;
+        write_file(
+            root.join("crates/b/Cargo.toml"),
+            "[package]\nname = \"b\"\n",
+        )?;
+
+        let changes = compare("HEAD", &snapshot_at(root, "HEAD")?, &snapshot(root)?);
+        assert_eq!(
+            changes.to_string(),
+            "# Dependency changes since `HEAD`\n\n\
+             ## [workspace.dependencies]\n\n\
+             - Added `tokio`: `\"1\"`\n\
+             - Removed `rand`: `\"0.8\"`\n\
+             - `serde`: `\"1.0.100\"` -> `\"1.0.200\"`\n\n\
+             ## Members\n\n\
+             - `crates/a/Cargo.toml`: now inherits `tokio`; no longer inherits `rand`; `log`: `\"0.4.1\"` -> `\"0.4.2\"`\n\
+             - `crates/b/Cargo.toml`: new member\n"
+        );
+        Ok(())
+    }
+}
@@ -1,13 +1,27 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use log::LevelFilter;
-use std::path::PathBuf;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
 
-#[derive(Parser)]
+#[derive(Parser, Clone, Debug)]
+#[command(bin_name = "cargo consolidate")]
 pub struct Opt {
-    /// Path to the workspace root Cargo.toml
-    /// of the project you want to consolidate
-    #[arg(long)]
-    pub manifest_path: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to the Cargo.toml of the workspace you want to consolidate,
+    /// either its root or any of its members. Can be used multiple times to
+    /// consolidate several workspaces one after the other
+    #[arg(long, global = true)]
+    pub manifest_path: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub cargo: CargoOptions,
+
+    /// File listing the Cargo.toml of each workspace to consolidate, one
+    /// path per line relative to the file
+    #[arg(long, value_name = "FILE")]
+    pub manifests_from: Option<PathBuf>,
 
     /// Group dependencies of all members into workspace.dependencies
     /// If set to false, just dependencies which are used by 2 or more
@@ -15,13 +29,652 @@ pub struct Opt {
     #[arg(long)]
     pub group_all: bool,
 
+    /// Path to the consolidate.toml configuration. Defaults to
+    /// consolidate.toml in the workspace root, falling back to
+    /// [workspace.metadata.consolidate] in the root Cargo.toml
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Apply the settings of [presets.<NAME>] from the configuration on
+    /// top of the others, e.g. `ci` or `strict`. Flags still take precedence
+    #[arg(long, value_name = "NAME")]
+    pub preset: Option<String>,
+
+    /// Name of a workspace member which should not be counted or edited
+    /// (can be used multiple times)
+    #[arg(long, value_name = "NAME")]
+    pub exclude_member: Vec<String>,
+
+    /// Add crates below the workspace root which aren't covered by
+    /// workspace.members to it before consolidating
+    #[arg(long)]
+    pub add_orphans: bool,
+
+    /// Catalogue exported from another workspace with `export-catalogue`.
+    /// New workspace entries take their spec from it where it has one
+    #[arg(long, value_name = "FILE")]
+    pub catalogue: Option<PathBuf>,
+
+    /// Policy file with the allowed version requirement of each crate, in
+    /// an [allowed] table
+    #[arg(long, global = true, value_name = "FILE")]
+    pub policy: Option<PathBuf>,
+
+    /// What to do when a requirement of a member or a workspace entry falls
+    /// outside of the --policy
+    #[arg(long, value_enum, default_value_t = ViolationPolicy::Warn)]
+    pub on_policy_violation: ViolationPolicy,
+
+    /// What to do when a member declares a different version or features
+    /// than an existing [workspace.dependencies] entry. Defaults to the
+    /// on-mismatch of the --preset, or `warn`
+    #[arg(long, value_enum)]
+    pub on_mismatch: Option<MismatchPolicy>,
+
+    /// Switch members to a workspace entry even if it accepts versions
+    /// their pinned requirement, like `=1.2.3`, rules out
+    #[arg(long)]
+    pub allow_loosen: bool,
+
+    /// Edit members whose manifest resolves to a path outside the workspace
+    /// root, e.g. through a symlink or a path dependency
+    #[arg(long)]
+    pub allow_external: bool,
+
+    /// If the same package is used under different names by the members,
+    /// rename all of them to the package name instead of creating a
+    /// workspace entry per name. Code using the old names needs to be
+    /// updated afterwards
+    #[arg(long)]
+    pub unify_alias: bool,
+
+    /// How to handle members declaring a dependency with a wildcard
+    /// version like `foo = "*"`
+    #[arg(long, value_enum, default_value_t = WildcardPolicy::Ignore)]
+    pub wildcard: WildcardPolicy,
+
+    /// How to handle members requiring a pre-release version like
+    /// `1.0.0-rc.2` while others require a stable one
+    #[arg(long, value_enum, default_value_t = PrereleasePolicy::Keep)]
+    pub prerelease: PrereleasePolicy,
+
+    /// Which of the members' requirements a new workspace entry, or
+    /// --align-only, takes. Stable versions are preferred either way.
+    /// Defaults to the version-strategy of the configuration, or `first`.
+    /// Dependency classes and rules with a strategy of their own keep it
+    #[arg(long, value_enum)]
+    pub version_strategy: Option<VersionStrategy>,
+
+    /// Don't touch [workspace.dependencies], instead rewrite the diverging
+    /// requirements of shared dependencies in the members to the version
+    /// picked by --version-strategy, and print every change
+    #[arg(long)]
+    pub align_only: bool,
+
+    /// Leave out dependencies a member never mentions in its sources, and
+    /// warn that they can be removed instead of consolidating them
+    #[arg(long)]
+    pub skip_unused: bool,
+
+    /// Take the unused dependencies from the output of
+    /// `cargo udeps --output json` instead of scanning the sources.
+    /// Implies --skip-unused
+    #[arg(long, value_name = "FILE")]
+    pub unused_from: Option<PathBuf>,
+
+    /// Sort [workspace.dependencies] into groups under `# <group>` comments,
+    /// taken from the `groups` of the configuration or the crates.io
+    /// categories of each dependency. Implies --full-metadata
+    #[arg(long)]
+    pub group_by_category: bool,
+
+    /// When members need semver-incompatible versions of a dependency, give
+    /// each version its own workspace entry, e.g. `syn_1` and `syn_2`, and
+    /// rename the dependency in the members accordingly. By default such
+    /// dependencies are left alone
+    #[arg(long)]
+    pub allow_major_split: bool,
+
+    /// Write version requirements of new workspace entries in their
+    /// canonical form, e.g. `{ version = "^1.2" }` becomes `"1.2.0"`
+    #[arg(long)]
+    pub normalize: bool,
+
+    /// Also normalize the entries already in [workspace.dependencies]
+    #[arg(long)]
+    pub normalize_existing: bool,
+
+    /// Write workspace entries wider than this many columns as expanded
+    /// tables with one feature per line (default: 100)
+    #[arg(long, value_name = "COLUMNS")]
+    pub wrap_width: Option<usize>,
+
+    /// Set the resolver of the workspace to this version unless it already
+    /// uses the same or a newer one
+    #[arg(long, value_name = "VERSION", value_parser = ["2", "3"])]
+    pub set_resolver: Option<String>,
+
+    /// Remove keys like `version` or `path` from member dependencies which
+    /// already use `workspace = true`, cargo ignores them anyway
+    #[arg(long)]
+    pub clean_inherited: bool,
+
+    /// Annotate new [workspace.dependencies] entries with a
+    /// `# used by: ...` comment listing the members using them. Existing
+    /// comments are refreshed on every run
+    #[arg(long)]
+    pub used_by_comments: bool,
+
+    /// Print why each dependency was or wasn't consolidated
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Write a Markdown summary of the applied changes to this file, e.g.
+    /// for a pull request description
+    #[arg(long, value_name = "FILE")]
+    pub write_summary: Option<PathBuf>,
+
+    /// How to write member manifests which are symlinks
+    #[arg(long, value_enum, default_value_t = SymlinkPolicy::Refuse)]
+    pub symlinks: SymlinkPolicy,
+
+    /// Consolidate a temporary copy of the workspace instead, print the
+    /// resulting changes and check that the copy still resolves with cargo
+    #[arg(long)]
+    pub sandbox: bool,
+
+    /// Which manifests --sandbox prints the diffs of and --write-summary
+    /// lists. Everything is consolidated either way
+    #[arg(long, value_enum, default_value_t = ShowFiles::All)]
+    pub show: ShowFiles,
+
+    /// Consolidate a temporary copy of the workspace like --sandbox, write
+    /// its new root Cargo.toml to PATH for review and only print the diffs
+    /// of the members. The workspace is left as it is
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["recursive", "manifests_from"])]
+    pub out_root: Option<PathBuf>,
+
+    /// Create a new workspace at --manifest-path (or in the current
+    /// directory) combining the given workspaces or standalone crates, then
+    /// consolidate it (can be used multiple times)
+    #[arg(long, value_name = "PATH")]
+    pub merge: Vec<PathBuf>,
+
+    /// Review the dependencies to consolidate in a terminal interface:
+    /// toggle dependencies and members, preview the diffs, then apply
+    #[arg(long, conflicts_with_all = ["sandbox", "incremental"])]
+    pub review: bool,
+
+    /// Answer JSON-RPC requests on stdin for editor integrations: analyze,
+    /// plan or apply the consolidation of a workspace or one dependency
+    #[arg(long)]
+    pub serve: bool,
+
+    /// Find every workspace below the directory of --manifest-path (or the
+    /// current directory) and consolidate them one after the other
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// Analyze the repositories listed in this file (one path per line), or
+    /// every subdirectory of this directory, without changing them and print
+    /// a report ranking them by unconsolidated dependencies and version skew
+    #[arg(long, value_name = "PATH")]
+    pub fleet: Option<PathBuf>,
+
+    /// Format of the --fleet report
+    #[arg(long, value_enum, default_value_t = ReportFormat::Markdown)]
+    pub report_format: ReportFormat,
+
+    /// How to print what a consolidation does. `json-lines` streams one
+    /// JSON event per line to stdout as it happens, for wrapper tools.
+    /// Defaults to the format of the --preset, or `text`
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Don't draw progress bars, even on a terminal
+    #[arg(long, global = true)]
+    pub no_progress: bool,
+
+    /// Set for runs driven by --serve, --review or --sandbox, whose output
+    /// owns stdout. The text a run prints goes to the log instead
+    #[arg(skip)]
+    pub embedded: bool,
+
+    /// Print how long each phase of the run took to stderr, as a table or
+    /// as JSON with --timings=json
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "table"
+    )]
+    pub timings: Option<TimingsFormat>,
+
+    /// Only consider members whose Cargo.toml changed since this git
+    /// revision, e.g. `origin/main`. Members which didn't change still count
+    /// as users of a dependency but are left as they are
+    #[arg(long, value_name = "REV")]
+    pub since: Option<String>,
+
+    /// Skip the run if no manifest changed since the last --incremental
+    /// run, and leave members alone which already inherit a dependency
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Continue with the remaining dependencies and members when one of
+    /// them fails, and print a summary of everything that failed at the end
+    #[arg(long)]
+    pub keep_going: bool,
+
     /// Increase output verbosity (can be used multiple times)
-    #[arg(short, long, action = clap::ArgAction::Count)]
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
     pub verbose: u8,
 }
 
+// Options passed on to the cargo commands run by the tool. A plain comment,
+// a doc comment would become the about text of the whole command
+#[derive(Args, Clone, Debug, Default)]
+pub struct CargoOptions {
+    /// The cargo binary to run, defaults to $CARGO or the cargo on the PATH
+    #[arg(long, global = true, value_name = "PATH")]
+    pub cargo: Option<PathBuf>,
+
+    /// Run cargo without accessing the network
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Assert that Cargo.lock will remain unchanged
+    #[arg(long, global = true)]
+    pub locked: bool,
+
+    /// Equivalent to specifying both --locked and --offline
+    #[arg(long, global = true)]
+    pub frozen: bool,
+
+    /// Read the members and their dependencies from the manifests instead
+    /// of running `cargo metadata`
+    #[arg(long, global = true)]
+    pub no_cargo: bool,
+
+    /// Let `cargo metadata` resolve the full dependency graph instead of
+    /// passing it --no-deps
+    #[arg(long, global = true)]
+    pub full_metadata: bool,
+
+    /// Reuse the `cargo metadata` of an earlier run while none of the
+    /// manifests changed, cached in $XDG_CACHE_HOME/cargo-consolidate
+    #[arg(long, global = true)]
+    pub cache: bool,
+}
+
+impl CargoOptions {
+    /// The flags to pass on to cargo
+    pub fn args(&self) -> Vec<String> {
+        [
+            (self.offline, "--offline"),
+            (self.locked, "--locked"),
+            (self.frozen, "--frozen"),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| flag.to_string())
+        .collect()
+    }
+}
+
+impl Opt {
+    /// The manifest locating the project to work on, if one was given
+    pub fn manifest_path(&self) -> Option<&Path> {
+        self.manifest_path.first().map(PathBuf::as_path)
+    }
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Print the consolidation status of a workspace without changing it
+    Report(ReportArgs),
+    /// Fail if dependencies are left to consolidate or requirements fall
+    /// outside of the --policy, without changing anything
+    Check(CheckArgs),
+    /// Turn a directory of standalone crates into a workspace and
+    /// consolidate their dependencies
+    Init(InitArgs),
+    /// Write [workspace.dependencies] as a catalogue other workspaces can be
+    /// consolidated against with --catalogue
+    ExportCatalogue(ExportCatalogueArgs),
+    /// Sort and format the workspace tables of the root manifest the way
+    /// new entries are written. --normalize and --group-by-category apply
+    Fmt(FmtArgs),
+    /// Create a member crate which inherits the package fields, lints and
+    /// starter dependencies from the workspace, and add it to
+    /// workspace.members
+    NewMember(NewMemberArgs),
+    /// Show how [workspace.dependencies] and the members' inheritance
+    /// changed since a git revision, e.g. for release notes
+    Diff(DiffArgs),
+    /// Ask about thresholds, ignored members, the version strategy and
+    /// style, showing what they mean for the workspace, and write the
+    /// answers to consolidate.toml
+    InitConfig(InitConfigArgs),
+    /// Report [package.metadata.*] tables several members declare verbatim,
+    /// and move them to [workspace.metadata.*] with --hoist
+    ToolConfig(ToolConfigArgs),
+    /// Rename a [workspace.dependencies] key and the members' entries
+    /// inheriting it. Code using the old name needs to be updated afterwards
+    Rename(RenameArgs),
+    /// Compare the workspace entries and the members' own specs with the
+    /// golden versions of the --catalogue and --policy
+    Drift(DriftArgs),
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct DriftArgs {
+    /// Format of the report
+    #[arg(long, value_enum, default_value_t = ReportFormat::Markdown)]
+    pub format: ReportFormat,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct RenameArgs {
+    /// Current key in [workspace.dependencies]
+    pub old: String,
+
+    /// New key
+    pub new: String,
+
+    /// Leave references to the dependency in the members' [features], like
+    /// `dep:old` or `old/feature`, as they are instead of renaming them too
+    #[arg(long)]
+    pub no_features: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ToolConfigArgs {
+    /// Move the shared [package.metadata.<TOOL>] table to
+    /// [workspace.metadata.<TOOL>], for tools which read it from there (can
+    /// be used multiple times)
+    #[arg(long, value_name = "TOOL")]
+    pub hoist: Vec<String>,
+
+    /// Format of the report
+    #[arg(long, value_enum, default_value_t = ReportFormat::Markdown)]
+    pub format: ReportFormat,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct InitConfigArgs {
+    /// Write the answers to [workspace.metadata.consolidate] in the root
+    /// Cargo.toml instead
+    #[arg(long)]
+    pub metadata: bool,
+
+    /// Replace an existing configuration
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct DiffArgs {
+    /// Git revision to compare the working tree against, e.g. a tag
+    #[arg(value_name = "REV")]
+    pub rev: String,
+
+    /// Format of the changes
+    #[arg(long, value_enum, default_value_t = ReportFormat::Markdown)]
+    pub format: ReportFormat,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct NewMemberArgs {
+    /// Directory of the new member
+    pub path: PathBuf,
+
+    /// Package name, defaults to the name of the directory
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Workspace dependency the member starts with (can be used multiple
+    /// times). Defaults to `starter-dependencies` of the configuration
+    #[arg(long = "dep", value_name = "NAME")]
+    pub dependencies: Vec<String>,
+
+    /// Create a binary crate instead of a library
+    #[arg(long)]
+    pub bin: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct FmtArgs {
+    /// Print the changes and fail instead of writing them
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct CheckArgs {
+    /// Only check the member manifests staged in git, for pre-commit hooks:
+    /// fail if they declare dependencies themselves which are in
+    /// [workspace.dependencies] or which other staged members add too,
+    /// going by the configuration like consolidating does
+    #[arg(long)]
+    pub staged: bool,
+
+    /// How to print the findings. `json` gives one diagnostic per finding,
+    /// with the span of the entry and a suggested fix
+    #[arg(long, value_enum, default_value_t = CheckFormat::Text, conflicts_with = "staged")]
+    pub format: CheckFormat,
+
+    /// Accept the findings recorded in this file, so only new ones fail
+    #[arg(long, value_name = "FILE", conflicts_with = "staged")]
+    pub baseline: Option<PathBuf>,
+
+    /// Record the current findings in the --baseline file instead of
+    /// checking
+    #[arg(long, requires = "baseline")]
+    pub write_baseline: bool,
+
+    /// Remove the findings which are fixed by now from the --baseline file,
+    /// then check
+    #[arg(long, requires = "baseline", conflicts_with = "write_baseline")]
+    pub prune_baseline: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct InitArgs {
+    /// Path or glob of the members, e.g. `crates/*` (can be used multiple
+    /// times). Defaults to every crate below the new workspace root
+    #[arg(long, value_name = "PATH")]
+    pub members: Vec<String>,
+
+    /// Resolver version of the new workspace
+    #[arg(long, default_value = "2")]
+    pub resolver: String,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ExportCatalogueArgs {
+    /// File to write the catalogue to instead of stdout
+    #[arg(long, short, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ReportArgs {
+    /// Shallow-clone this git repository into a temporary directory and
+    /// report on it instead of the local workspace
+    #[arg(long, value_name = "URL")]
+    pub git: Option<String>,
+
+    /// Branch, tag or commit to check out with --git
+    #[arg(long, value_name = "REF", requires = "git")]
+    pub rev: Option<String>,
+
+    /// Format of the report. `cyclonedx` and `spdx` print a software bill of
+    /// materials of the external dependencies instead, with the versions
+    /// from Cargo.lock
+    #[arg(long, value_enum, default_value_t = WorkspaceReportFormat::Markdown)]
+    pub format: WorkspaceReportFormat,
+
+    /// Switch members whose edition or rust-version differs from
+    /// [workspace.package] to inheriting it, keeping those `cargo check -p`
+    /// fails for as they are
+    #[arg(long, conflicts_with = "git")]
+    pub fix_editions: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WorkspaceReportFormat {
+    /// Markdown for humans
+    Markdown,
+    /// JSON with all details
+    Json,
+    /// CycloneDX 1.5 JSON
+    Cyclonedx,
+    /// SPDX 2.3 JSON
+    Spdx,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MismatchPolicy {
+    /// Print a warning and switch the member to the workspace entry anyway
+    #[default]
+    Warn,
+    /// Fail for that member
+    Error,
+    /// Raise the workspace entry to the highest version the members
+    /// declare, never lowering it, and leave members alone which differ in
+    /// more than that
+    Update,
+    /// Leave that member's spec as it is
+    Skip,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WildcardPolicy {
+    /// Pick the version for the workspace entry from the other members
+    Ignore,
+    /// Fail for dependencies declared with a wildcard
+    Reject,
+    /// Use the version resolved in Cargo.lock for the workspace entry
+    Lockfile,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PrereleasePolicy {
+    /// Members requiring a pre-release keep their own spec
+    Keep,
+    /// Switch members requiring a pre-release to the stable workspace entry
+    PreferStable,
+    /// Fail for dependencies mixing pre-release and stable requirements
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VersionStrategy {
+    /// The requirement of the first member, by name
+    #[default]
+    First,
+    /// The requirement accepting the highest minimum version
+    Highest,
+    /// The requirement accepting the lowest minimum version
+    Lowest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ViolationPolicy {
+    /// Print the violations and consolidate anyway
+    Warn,
+    /// Fail before changing anything, or skip new workspace entries
+    /// outside of the policy
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SymlinkPolicy {
+    /// Fail for manifests which are symlinks
+    Refuse,
+    /// Write the changes to the file the symlink points to
+    Follow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CheckFormat {
+    /// A summary of the problems
+    Text,
+    /// A JSON array of diagnostics, for editors and CI annotations
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimingsFormat {
+    /// A table for humans
+    Table,
+    /// A JSON object for tracking runs over time
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// Markdown for humans
+    Markdown,
+    /// JSON with all details
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Warnings and summaries for humans
+    #[default]
+    Text,
+    /// One JSON event per line: dependency analyzed, conflict found, file
+    /// written and verification result
+    JsonLines,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ShowFiles {
+    /// Only the root manifest
+    Root,
+    /// Only the member manifests
+    Members,
+    /// The root and the member manifests
+    All,
+}
+
+impl ShowFiles {
+    /// Whether to show the manifest at `path`, relative to the workspace root
+    pub fn includes(self, path: &Path) -> bool {
+        if path == Path::new("Cargo.toml") {
+            self.root()
+        } else {
+            self.members()
+        }
+    }
+
+    /// Whether to show what changed in the root manifest
+    pub fn root(self) -> bool {
+        self != ShowFiles::Members
+    }
+
+    /// Whether to show what changed in the member manifests
+    pub fn members(self) -> bool {
+        self != ShowFiles::Root
+    }
+}
+
 pub fn parse_args() -> Opt {
-    Opt::parse()
+    // cargo passes the subcommand name on when invoked as `cargo consolidate`
+    let mut args: Vec<_> = std::env::args_os().collect();
+    if args.get(1).is_some_and(|arg| arg == "consolidate") {
+        args.remove(1);
+    }
+    Opt::parse_from(args)
 }
 
 pub fn setup_logging(verbose: u8) {
@@ -33,3 +686,30 @@ pub fn setup_logging(verbose: u8) {
     };
     env_logger::Builder::new().filter_level(log_level).init();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cargo_options_args() {
+        let opt = Opt::parse_from(["cargo-consolidate", "check", "--offline", "--locked"]);
+        assert_eq!(opt.cargo.args(), vec!["--offline", "--locked"]);
+        assert!(Opt::parse_from(["cargo-consolidate"])
+            .cargo
+            .args()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_show_files_includes() {
+        let root = Path::new("Cargo.toml");
+        let member = Path::new("crates/a/Cargo.toml");
+        assert!(ShowFiles::Root.includes(root));
+        assert!(!ShowFiles::Root.includes(member));
+        assert!(!ShowFiles::Members.includes(root));
+        assert!(ShowFiles::Members.includes(member));
+        assert!(ShowFiles::All.includes(root));
+        assert!(ShowFiles::All.includes(member));
+    }
+}
@@ -0,0 +1,486 @@
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use toml_edit::{Array, DocumentMut, InlineTable, Item, Value};
+
+use crate::cli::{MismatchPolicy, Opt, OutputFormat, VersionStrategy};
+use crate::dependency::Class;
+use crate::diagnostic;
+
+/// Name of the configuration file looked up in the workspace root
+pub const CONFIG_FILE_NAME: &str = "consolidate.toml";
+
+/// Settings read from `consolidate.toml` or `[workspace.metadata.consolidate]`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Glob patterns for members which are never counted or edited. A pattern
+    /// matches either the package name or the member directory relative to
+    /// the workspace root, e.g. `fuzz/*` or `*-workspace-hack`
+    pub ignore: Vec<String>,
+
+    /// Dependencies which are always moved into `[workspace.dependencies]`,
+    /// no matter how many members use them
+    pub force_include: Vec<String>,
+
+    /// Exact specs to write into `[workspace.dependencies]`, taking
+    /// precedence over whatever the members declare
+    pub pins: BTreeMap<String, DependencySpec>,
+
+    /// Groups for `--group-by-category`, each with glob patterns for the
+    /// dependencies it holds, e.g. `async = ["tokio*", "futures"]`. They take
+    /// precedence over the crates.io categories
+    pub groups: BTreeMap<String, Vec<String>>,
+
+    /// Workspace dependencies `new-member` adds to every new member
+    pub starter_dependencies: Vec<String>,
+
+    /// How many members have to use a dependency before it moves into
+    /// `[workspace.dependencies]`, 2 unless set
+    pub min_members: Option<usize>,
+
+    /// Used unless `--version-strategy` is passed
+    pub version_strategy: Option<VersionStrategy>,
+
+    /// Normalize new workspace entries as if `--normalize` was passed
+    pub normalize: bool,
+
+    /// Annotate new workspace entries as if `--used-by-comments` was passed
+    pub used_by_comments: bool,
+
+    /// Used unless `--wrap-width` is passed
+    pub wrap_width: Option<usize>,
+
+    /// Settings for dependencies the members only declare in
+    /// `[dev-dependencies]`, like test frameworks
+    pub dev_only: ClassSettings,
+
+    /// Settings for dependencies the members only declare in
+    /// `[build-dependencies]`
+    pub build_only: ClassSettings,
+
+    /// Rules for single dependencies, taking precedence over the settings
+    /// above, e.g. `tokio = { strategy = "highest", hoist-features = true }`
+    pub rules: BTreeMap<String, DependencyRule>,
+
+    /// Named bundles of settings, selected with `--preset`
+    pub presets: BTreeMap<String, Preset>,
+
+    /// The preset selected with `--preset`
+    #[serde(skip)]
+    preset: Preset,
+
+    #[serde(skip)]
+    ignore_patterns: Vec<Pattern>,
+
+    #[serde(skip)]
+    group_patterns: Vec<(String, Vec<Pattern>)>,
+}
+
+/// Settings applied on top of the others with `--preset`, e.g. for CI or a
+/// stricter local check
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Preset {
+    /// Added to the patterns of `ignore`
+    pub ignore: Vec<String>,
+    /// Added to `force-include`
+    pub force_include: Vec<String>,
+    pub min_members: Option<usize>,
+    pub version_strategy: Option<VersionStrategy>,
+    pub normalize: Option<bool>,
+    pub used_by_comments: Option<bool>,
+    /// Used unless `--on-mismatch` is passed
+    pub on_mismatch: Option<MismatchPolicy>,
+    /// Continue after failures as if `--keep-going` was passed
+    pub keep_going: bool,
+    /// Used unless `--format` is passed
+    pub format: Option<OutputFormat>,
+}
+
+/// Settings replacing the general ones for a [`Class`] of dependencies
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ClassSettings {
+    /// Used instead of `min-members`
+    pub min_members: Option<usize>,
+    /// Used instead of `version-strategy`, and of `--version-strategy` too
+    pub version_strategy: Option<VersionStrategy>,
+    /// Leave these dependencies as the members declare them
+    pub skip: bool,
+}
+
+/// How a single dependency is consolidated
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DependencyRule {
+    /// Used instead of `version-strategy`, `--version-strategy` and that of
+    /// its class
+    pub strategy: Option<VersionStrategy>,
+    /// Give the workspace entry the features all members enable and drop
+    /// them from the members, instead of taking the features of the spec
+    /// the strategy picks
+    pub hoist_features: bool,
+    /// Leave the dependency as the members declare it, even if it's listed
+    /// in `force-include`
+    pub skip: bool,
+}
+
+/// A dependency spec as it can be written in Cargo.toml
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum DependencySpec {
+    Version(String),
+    Detailed(DetailedDependencySpec),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DetailedDependencySpec {
+    pub version: Option<String>,
+    pub package: Option<String>,
+    pub path: Option<String>,
+    pub git: Option<String>,
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+    pub rev: Option<String>,
+    pub registry: Option<String>,
+    pub default_features: Option<bool>,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl DependencySpec {
+    pub fn to_item(&self) -> Item {
+        let spec = match self {
+            DependencySpec::Version(version) => return Item::Value(Value::from(version.as_str())),
+            DependencySpec::Detailed(spec) => spec,
+        };
+
+        let mut table = InlineTable::new();
+        let strings = [
+            ("version", &spec.version),
+            ("package", &spec.package),
+            ("path", &spec.path),
+            ("git", &spec.git),
+            ("branch", &spec.branch),
+            ("tag", &spec.tag),
+            ("rev", &spec.rev),
+            ("registry", &spec.registry),
+        ];
+        for (key, value) in strings {
+            if let Some(value) = value {
+                table.insert(key, Value::from(value.as_str()));
+            }
+        }
+        if let Some(default_features) = spec.default_features {
+            table.insert("default-features", Value::from(default_features));
+        }
+        if !spec.features.is_empty() {
+            let features: Array = spec.features.iter().map(String::as_str).collect();
+            table.insert("features", Value::Array(features));
+        }
+
+        Item::Value(Value::InlineTable(table))
+    }
+}
+
+impl Config {
+    /// Loads the configuration from `path` if given, otherwise from
+    /// `consolidate.toml` in the workspace root, otherwise from
+    /// `[workspace.metadata.consolidate]` in the root manifest
+    pub fn load(
+        path: Option<&Path>,
+        workspace_root: &Path,
+        root_doc: &DocumentMut,
+    ) -> Result<Self> {
+        let default_path = workspace_root.join(CONFIG_FILE_NAME);
+        let path = path.or_else(|| default_path.exists().then_some(default_path.as_path()));
+
+        let config: Config = match path {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read '{}'", path.display()))?;
+                toml_edit::de::from_str(&content)
+                    .map_err(|err| diagnostic::locate(err.into(), path, &content))
+                    .with_context(|| format!("Failed to parse '{}'", path.display()))?
+            }
+            None => return Config::from_workspace_metadata(root_doc),
+        };
+
+        config.compile()
+    }
+
+    /// Reads the configuration from `[workspace.metadata.consolidate]` of
+    /// `root_doc` only, without looking for a configuration file
+    pub fn from_workspace_metadata(root_doc: &DocumentMut) -> Result<Self> {
+        let config: Config = match root_doc
+            .get("workspace")
+            .and_then(|ws| ws.get("metadata"))
+            .and_then(|metadata| metadata.get("consolidate"))
+            .and_then(Item::as_table)
+        {
+            Some(table) => toml_edit::de::from_document(DocumentMut::from(table.clone()))
+                .context("Failed to parse [workspace.metadata.consolidate]")?,
+            None => Config::default(),
+        };
+
+        config.compile()
+    }
+
+    fn compile(mut self) -> Result<Self> {
+        self.ignore_patterns = self
+            .ignore
+            .iter()
+            .map(|pattern| {
+                Pattern::new(pattern)
+                    .with_context(|| format!("Invalid ignore pattern '{}'", pattern))
+            })
+            .collect::<Result<_>>()?;
+        self.group_patterns = self
+            .groups
+            .iter()
+            .map(|(group, patterns)| {
+                let patterns = patterns
+                    .iter()
+                    .map(|pattern| {
+                        Pattern::new(pattern).with_context(|| {
+                            format!("Invalid pattern '{}' in group '{}'", pattern, group)
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Ok((group.clone(), patterns))
+            })
+            .collect::<Result<_>>()?;
+        Ok(self)
+    }
+
+    /// Applies the preset `name` on top of the other settings
+    pub fn with_preset(mut self, name: Option<&str>) -> Result<Self> {
+        let Some(name) = name else {
+            return Ok(self);
+        };
+        let Some(preset) = self.presets.get(name).cloned() else {
+            let known: Vec<&str> = self.presets.keys().map(String::as_str).collect();
+            anyhow::bail!(
+                "There's no preset '{}' in the configuration, it has {}",
+                name,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            );
+        };
+
+        self.ignore.extend(preset.ignore.iter().cloned());
+        self.force_include
+            .extend(preset.force_include.iter().cloned());
+        self.min_members = preset.min_members.or(self.min_members);
+        self.version_strategy = preset.version_strategy.or(self.version_strategy);
+        self.normalize = preset.normalize.unwrap_or(self.normalize);
+        self.used_by_comments = preset.used_by_comments.unwrap_or(self.used_by_comments);
+        self.preset = preset;
+        self.compile()
+    }
+
+    /// The number of members using a dependency from which on it is
+    /// consolidated
+    pub fn min_members(&self) -> usize {
+        self.min_members.unwrap_or(2)
+    }
+
+    /// The settings of a class of dependencies, if it has its own
+    pub fn class_settings(&self, class: Class) -> Option<&ClassSettings> {
+        match class {
+            Class::Normal => None,
+            Class::DevOnly => Some(&self.dev_only),
+            Class::BuildOnly => Some(&self.build_only),
+        }
+    }
+
+    /// The rule of a dependency, if it has one
+    pub fn rule(&self, dep: &str) -> Option<&DependencyRule> {
+        self.rules.get(dep)
+    }
+
+    /// [`Config::min_members`] for a class of dependencies
+    pub fn min_members_of(&self, class: Class) -> usize {
+        self.class_settings(class)
+            .and_then(|settings| settings.min_members)
+            .unwrap_or_else(|| self.min_members())
+    }
+
+    /// `opt` with the settings of the configuration filled in where the
+    /// command line leaves them open
+    pub fn apply_defaults(&self, opt: &Opt) -> Opt {
+        let mut opt = opt.clone();
+        opt.version_strategy = opt.version_strategy.or(self.version_strategy);
+        opt.normalize |= self.normalize;
+        opt.used_by_comments |= self.used_by_comments;
+        opt.wrap_width = opt.wrap_width.or(self.wrap_width);
+        opt.on_mismatch = opt.on_mismatch.or(self.preset.on_mismatch);
+        opt.keep_going |= self.preset.keep_going;
+        opt.format = opt.format.or(self.preset.format);
+        opt
+    }
+
+    /// The configured group a dependency belongs to, the first one in
+    /// alphabetical order should several match
+    pub fn group_of(&self, dep: &str) -> Option<&str> {
+        self.group_patterns
+            .iter()
+            .find(|(_, patterns)| patterns.iter().any(|pattern| pattern.matches(dep)))
+            .map(|(group, _)| group.as_str())
+    }
+
+    /// Whether a member is matched by one of the `ignore` patterns
+    pub fn is_ignored(&self, package_name: &str, relative_dir: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+
+        self.ignore_patterns.iter().any(|pattern| {
+            pattern.matches_with(package_name, options)
+                || pattern.matches_path_with(relative_dir, options)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_is_ignored() -> Result<()> {
+        let config: Config =
+            toml_edit::de::from_str(r#"ignore = ["fuzz/*", "**/examples/*", "*-workspace-hack"]"#)?;
+        let config = config.compile()?;
+
+        assert!(config.is_ignored("fuzz-parser", Path::new("fuzz/parser")));
+        assert!(config.is_ignored("demo", Path::new("crates/api/examples/demo")));
+        assert!(config.is_ignored("demo", Path::new("examples/demo")));
+        assert!(config.is_ignored("my-workspace-hack", Path::new("hack")));
+        assert!(!config.is_ignored("api", Path::new("crates/api")));
+        assert!(!config.is_ignored("nested", Path::new("fuzz/parser/nested")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_from_workspace_metadata() -> Result<()> {
+        let root_doc = r#"
+            [workspace]
+            members = ["crates/*"]
+
+            [workspace.metadata.consolidate]
+            ignore = ["fuzz/*"]
+        "#
+        .parse::<DocumentMut>()?;
+        let temp_dir = tempfile::TempDir::new()?;
+
+        let config = Config::load(None, temp_dir.path(), &root_doc)?;
+        assert_eq!(config.ignore, vec!["fuzz/*".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_keys() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        fs::write(
+            temp_dir.path().join(CONFIG_FILE_NAME),
+            "ignroe = [\"fuzz/*\"]\n",
+        )?;
+
+        assert!(Config::load(None, temp_dir.path(), &DocumentMut::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_force_include() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        fs::write(
+            temp_dir.path().join(CONFIG_FILE_NAME),
+            "force-include = [\"serde\", \"anyhow\"]\n",
+        )?;
+
+        let config = Config::load(None, temp_dir.path(), &DocumentMut::new())?;
+        assert_eq!(config.force_include, vec!["serde", "anyhow"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_preset() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        fs::write(
+            temp_dir.path().join(CONFIG_FILE_NAME),
+            "ignore = [\"fuzz/*\"]\nmin-members = 3\n\n\
+             [presets.ci]\nignore = [\"examples/*\"]\nversion-strategy = \"highest\"\n\
+             on-mismatch = \"error\"\nkeep-going = true\nformat = \"json-lines\"\n",
+        )?;
+        let load = || Config::load(None, temp_dir.path(), &DocumentMut::new());
+
+        let config = load()?.with_preset(Some("ci"))?;
+        assert_eq!(config.min_members(), 3);
+        assert!(config.is_ignored("fuzz-a", Path::new("fuzz/a")));
+        assert!(config.is_ignored("demo", Path::new("examples/demo")));
+
+        // Flags take precedence over the preset
+        let opt = config.apply_defaults(&Opt::parse_from([
+            "cargo-consolidate",
+            "--preset",
+            "ci",
+            "--on-mismatch",
+            "skip",
+        ]));
+        assert_eq!(opt.version_strategy, Some(VersionStrategy::Highest));
+        assert_eq!(opt.on_mismatch, Some(MismatchPolicy::Skip));
+        assert_eq!(opt.format, Some(OutputFormat::JsonLines));
+        assert!(opt.keep_going);
+
+        let err = load()?.with_preset(Some("strict")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "There's no preset 'strict' in the configuration, it has ci"
+        );
+        assert!(load()?.with_preset(None)?.preset.format.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_of() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        fs::write(
+            temp_dir.path().join(CONFIG_FILE_NAME),
+            "[groups]\nasync = [\"tokio*\", \"futures\"]\nserialization = [\"serde*\"]\n",
+        )?;
+
+        let config = Config::load(None, temp_dir.path(), &DocumentMut::new())?;
+        assert_eq!(config.group_of("tokio-util"), Some("async"));
+        assert_eq!(config.group_of("serde_json"), Some("serialization"));
+        assert_eq!(config.group_of("rand"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pins_to_item() -> Result<()> {
+        let config: Config = toml_edit::de::from_str(
+            r#"
+            [pins]
+            serde = "1.0.210"
+            tokio = { version = "1.40", features = ["rt-multi-thread"] }
+            "#,
+        )?;
+
+        assert_eq!(config.pins["serde"].to_item().to_string(), "\"1.0.210\"");
+        assert_eq!(
+            config.pins["tokio"].to_item().to_string(),
+            "{ version = \"1.40\", features = [\"rt-multi-thread\"] }"
+        );
+        Ok(())
+    }
+}
@@ -1,11 +1,16 @@
-use anyhow::{Context, Result};
-use camino::Utf8PathBuf;
+use anyhow::Result;
 use cargo_metadata::{DependencyKind, Package};
-use std::collections::{BTreeSet, HashSet};
-use std::fs;
-use toml_edit::{DocumentMut, Item, Value};
+use semver::VersionReq;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Component, Path, PathBuf};
+use toml_edit::{Array, DocumentMut, InlineTable, Item, Key, RawString, TableLike, Value};
 
-pub fn collect_dependencies(package: &Package) -> HashSet<String> {
+use crate::cli::VersionStrategy;
+use crate::version;
+
+/// Returns the dependencies of a package, keyed by the name they are declared
+/// under in its manifest, together with the name of the package they refer to
+pub fn collect_dependencies(package: &Package) -> HashMap<String, String> {
     package
         .dependencies
         .iter()
@@ -15,32 +20,185 @@ pub fn collect_dependencies(package: &Package) -> HashSet<String> {
                 DependencyKind::Normal | DependencyKind::Build | DependencyKind::Development
             )
         })
-        .map(|dep| dep.name.clone())
+        .map(|dep| {
+            let key = dep.rename.clone().unwrap_or_else(|| dep.name.clone());
+            (key, dep.name.clone())
+        })
         .collect()
 }
 
-pub fn get_dependency_from_member(manifest_path: &Utf8PathBuf, dep_name: &str) -> Result<Item> {
-    let cargo_toml_content = fs::read_to_string(manifest_path)
-        .with_context(|| format!("Failed to read '{}'", manifest_path))?;
-    let doc = cargo_toml_content
-        .parse::<DocumentMut>()
-        .with_context(|| format!("Failed to parse '{}'", manifest_path))?;
+/// Returns the kinds each dependency of a package is declared as, keyed like
+/// [`collect_dependencies`]
+pub fn collect_kinds(package: &Package) -> HashMap<String, Kinds> {
+    let mut kinds: HashMap<String, Kinds> = HashMap::new();
+    for dep in &package.dependencies {
+        let key = dep.rename.clone().unwrap_or_else(|| dep.name.clone());
+        kinds.entry(key).or_default().insert(dep.kind);
+    }
+    kinds
+}
+
+/// Returns the features of its dependencies a package enables through its
+/// own feature definitions, like `extra = ["dep/extra"]` or `"dep?/extra"`,
+/// keyed by the name the dependency is declared under. `features` is the
+/// feature table `cargo metadata` reports
+pub fn collect_feature_references(
+    features: &BTreeMap<String, Vec<String>>,
+) -> HashMap<String, BTreeSet<String>> {
+    let mut references: HashMap<String, BTreeSet<String>> = HashMap::new();
+    for enabled in features.values().flatten() {
+        let Some((dep, feature)) = enabled.split_once('/') else {
+            continue;
+        };
+        let dep = dep.strip_suffix('?').unwrap_or(dep);
+        references
+            .entry(dep.to_string())
+            .or_default()
+            .insert(feature.to_string());
+    }
+    references
+}
+
+/// The kinds of dependency something is declared as, across one or more
+/// members
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Kinds {
+    normal: bool,
+    build: bool,
+    dev: bool,
+}
+
+impl Kinds {
+    pub fn insert(&mut self, kind: DependencyKind) {
+        match kind {
+            DependencyKind::Normal => self.normal = true,
+            DependencyKind::Build => self.build = true,
+            DependencyKind::Development => self.dev = true,
+            _ => {}
+        }
+    }
+
+    pub fn extend(&mut self, other: Kinds) {
+        self.normal |= other.normal;
+        self.build |= other.build;
+        self.dev |= other.dev;
+    }
+
+    /// Dependencies only declared in `[dev-dependencies]` or only in
+    /// `[build-dependencies]` are classes of their own, anything else is
+    /// normal
+    pub fn class(self) -> Class {
+        match (self.normal, self.build, self.dev) {
+            (false, false, true) => Class::DevOnly,
+            (false, true, false) => Class::BuildOnly,
+            _ => Class::Normal,
+        }
+    }
+}
+
+/// How the members use a dependency, which can be consolidated with its own
+/// threshold and version strategy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    Normal,
+    DevOnly,
+    BuildOnly,
+}
+
+impl Class {
+    /// The configuration table with the settings of the class
+    pub fn table(self) -> Option<&'static str> {
+        match self {
+            Class::Normal => None,
+            Class::DevOnly => Some("dev-only"),
+            Class::BuildOnly => Some("build-only"),
+        }
+    }
+}
+
+/// The manifest sections which can declare dependencies of a member
+pub const DEPENDENCY_SECTIONS: [&str; 3] =
+    ["dependencies", "build-dependencies", "dev-dependencies"];
+
+/// Returns the spec of `dep_name` from every section of the member which
+/// declares it. `manifest_path` names the member in errors
+pub fn get_dependency_sections(
+    doc: &DocumentMut,
+    manifest_path: &Path,
+    dep_name: &str,
+) -> Result<Vec<(&'static str, Item)>> {
+    let sections: Vec<_> = DEPENDENCY_SECTIONS
+        .iter()
+        .filter_map(|section| {
+            doc.get(section)
+                .and_then(Item::as_table_like)
+                .and_then(|dep_table| dep_table.get(dep_name))
+                .map(|dep_entry| (*section, dep_entry.clone()))
+        })
+        .collect();
+
+    if sections.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Dependency '{}' not found in '{}'",
+            dep_name,
+            manifest_path.display()
+        ));
+    }
+    Ok(sections)
+}
+
+/// Returns the spec a workspace entry for `dep_name` can be based on. If the
+/// member declares the dependency in several sections, it requires the highest
+/// minimum version of them, and only the features all of them ask for are
+/// kept, the others stay with their section
+pub fn get_dependency_from_member(
+    doc: &DocumentMut,
+    manifest_path: &Path,
+    dep_name: &str,
+) -> Result<Item> {
+    let sections = get_dependency_sections(doc, manifest_path, dep_name)?;
+    let mut dep_item = sections[0].1.clone();
+
+    if sections.len() > 1 {
+        // Every section inherits the entry, none may end up below its own
+        let versioned: Vec<Item> = sections
+            .iter()
+            .map(|(_, item)| item.clone())
+            .filter(|item| get_version(item).is_some() && !is_wildcard(item))
+            .collect();
+        if !versioned.is_empty() {
+            let picked = pick(&versioned, VersionStrategy::Highest);
+            if let Some(version) = get_version(&versioned[picked]) {
+                set_version(&mut dep_item, &version);
+            }
+        }
 
-    let dep_tables = ["dependencies", "build-dependencies", "dev-dependencies"];
+        let common: BTreeSet<String> = sections
+            .iter()
+            .map(|(_, item)| -> BTreeSet<String> {
+                get_features(item).unwrap_or_default().into_iter().collect()
+            })
+            .reduce(|common, features| common.intersection(&features).cloned().collect())
+            .unwrap_or_default();
 
-    for table_name in &dep_tables {
-        if let Some(dep_table) = doc.get(table_name).and_then(|t| t.as_table()) {
-            if let Some(dep_entry) = dep_table.get(dep_name) {
-                return Ok(dep_entry.clone());
+        if let Some(tbl) = dep_item.as_table_like_mut() {
+            if common.is_empty() {
+                tbl.remove("features");
+            } else if tbl.contains_key("features") {
+                let features: toml_edit::Array = common.into_iter().map(Value::from).collect();
+                tbl.insert("features", Item::Value(Value::Array(features)));
             }
         }
     }
+    // Whether a member needs it is up to the member, workspace entries can't
+    // be optional
+    if let Some(tbl) = dep_item.as_table_like_mut() {
+        if tbl.remove("optional").is_some() {
+            tbl.fmt();
+        }
+    }
 
-    Err(anyhow::anyhow!(
-        "Dependency '{}' not found in '{}'",
-        dep_name,
-        manifest_path
-    ))
+    Ok(dep_item)
 }
 
 pub fn merge_features(existing_item: Option<&Item>, new_item: &Item) -> Option<Value> {
@@ -68,8 +226,623 @@ pub fn merge_features(existing_item: Option<&Item>, new_item: &Item) -> Option<V
     }
 }
 
+/// The spec a member switches to when it inherits a dependency it declared
+/// as `member_item`: `workspace = true`, keeping whether it's optional and
+/// its features
+pub fn inherited_spec(member_item: &Item) -> InlineTable {
+    let mut inline_table = InlineTable::default();
+    inline_table.insert("workspace", Value::from(true));
+    let optional = member_item
+        .as_table_like()
+        .and_then(|tbl| tbl.get("optional"))
+        .and_then(Item::as_bool);
+    if let Some(optional) = optional {
+        inline_table.insert("optional", Value::from(optional));
+    }
+    if let Some(features) =
+        merge_features(Some(member_item), &Item::Value(inline_table.clone().into()))
+    {
+        inline_table.insert("features", features);
+    }
+    inline_table
+}
+
+/// Returns the version requirement of a dependency spec, if it has one
+pub fn get_version(item: &Item) -> Option<String> {
+    match item.as_str() {
+        Some(version) => Some(version.to_string()),
+        None => item
+            .as_table_like()
+            .and_then(|tbl| tbl.get("version"))
+            .and_then(|version| version.as_str())
+            .map(String::from),
+    }
+}
+
+/// Sets the version requirement of a dependency spec, keeping its other keys
+pub fn set_version(item: &mut Item, version: &str) {
+    match item.as_table_like_mut() {
+        Some(tbl) => {
+            tbl.insert("version", toml_edit::value(version));
+        }
+        None => *item = toml_edit::value(version),
+    }
+}
+
+/// Sets the path of a dependency spec, turning a plain version into a table
+pub fn set_path(item: &mut Item, path: &str) {
+    if item.as_table_like().is_none() {
+        let mut tbl = InlineTable::new();
+        if let Some(version) = get_version(item) {
+            tbl.insert("version", Value::from(version));
+        }
+        *item = Item::Value(tbl.into());
+    }
+    if let Some(tbl) = item.as_table_like_mut() {
+        tbl.insert("path", toml_edit::value(path));
+    }
+}
+
+/// Replaces the features of a dependency spec, dropping the key if there
+/// are none
+pub fn set_features(item: &mut Item, features: &BTreeSet<String>) {
+    if features.is_empty() {
+        if let Some(tbl) = item.as_table_like_mut() {
+            if tbl.remove("features").is_some() {
+                tbl.fmt();
+            }
+        }
+        return;
+    }
+    if item.as_table_like().is_none() {
+        let mut tbl = InlineTable::new();
+        if let Some(version) = get_version(item) {
+            tbl.insert("version", Value::from(version));
+        }
+        *item = Item::Value(tbl.into());
+    }
+    if let Some(tbl) = item.as_table_like_mut() {
+        let features: Array = features.iter().map(String::as_str).collect();
+        tbl.insert("features", Item::Value(Value::Array(features)));
+    }
+}
+
+/// Rewrites a dependency spec into its canonical form, see
+/// [`version::normalize_requirement`]. A table which only holds a version,
+/// like `{ version = "1.0.0" }`, becomes a plain `"1.0.0"`
+pub fn normalize_spec(item: &mut Item) {
+    let Some(version) = get_version(item) else {
+        return;
+    };
+    let normalized = version::normalize_requirement(&version);
+
+    let only_version = item
+        .as_table_like()
+        .is_some_and(|tbl| tbl.iter().all(|(key, _)| key == "version"));
+    if only_version {
+        *item = toml_edit::value(normalized);
+    } else if normalized != version {
+        set_version(item, &normalized);
+    }
+}
+
+/// Width past which workspace entries are written as expanded tables,
+/// unless `--wrap-width` or the configuration sets another one
+pub const DEFAULT_WRAP_WIDTH: usize = 100;
+
+/// Turns the inline spec of `dep_name` into an expanded table with one
+/// feature per line if `dep_name = { ... }` is wider than `width`
+pub fn wrap_spec(dep_name: &str, item: &mut Item, width: usize) {
+    let Some(inline) = item.as_inline_table() else {
+        return;
+    };
+    if format!("{} = {}", dep_name, inline.to_string().trim()).len() > width {
+        expand_spec(item, true);
+    }
+}
+
+/// Turns an inline spec into an expanded table, with one feature per line
+/// if `multiline_features`
+pub fn expand_spec(item: &mut Item, multiline_features: bool) {
+    if let Some(inline) = item.as_inline_table() {
+        let mut table = inline.clone().into_table();
+        table.fmt();
+        *item = Item::Table(table);
+    }
+    let features = item.get_mut("features").and_then(Item::as_array_mut);
+    if let Some(features) = features.filter(|_| multiline_features) {
+        set_multiline(features);
+    }
+}
+
+/// Lays out an array with one element per line, keeping its comments
+pub fn set_multiline(array: &mut Array) {
+    // What follows an element up to the comma moves behind the comma, e.g.
+    // a comment after the last element without a trailing comma
+    let mut carried = String::new();
+    for element in array.iter_mut() {
+        let decor = element.decor_mut();
+        let prefix = format!("{}{}", carried, raw_str(decor.prefix()));
+        decor.set_prefix(multiline_decor(&prefix, "    "));
+        carried = raw_str(decor.suffix()).to_string();
+        decor.set_suffix("");
+    }
+    let trailing = format!(
+        "{}{}",
+        carried,
+        array.trailing().as_str().unwrap_or_default()
+    );
+    array.set_trailing(multiline_decor(&trailing, ""));
+    array.set_trailing_comma(true);
+}
+
+fn raw_str(raw: Option<&RawString>) -> &str {
+    raw.and_then(RawString::as_str).unwrap_or_default()
+}
+
+/// The whitespace in front of an array element, or after the last one,
+/// once every element has a line of its own and is indented by `indent`.
+/// A comment at the end of the previous line stays there, comments on
+/// lines of their own keep them
+fn multiline_decor(raw: &str, indent: &str) -> String {
+    let mut lines = raw.split('\n');
+    let mut decor = String::new();
+    if let Some(comment) = lines
+        .next()
+        .map(str::trim)
+        .filter(|line| line.starts_with('#'))
+    {
+        decor.push(' ');
+        decor.push_str(comment);
+    }
+    for comment in lines.map(str::trim).filter(|line| line.starts_with('#')) {
+        decor.push_str("\n    ");
+        decor.push_str(comment);
+    }
+    decor.push('\n');
+    decor.push_str(indent);
+    decor
+}
+
+/// Whether an array is laid out over several lines
+pub fn is_multiline(array: &Array) -> bool {
+    array.to_string().trim().contains('\n')
+}
+
+/// Whether a dependency spec accepts any version, e.g. `foo = "*"`
+pub fn is_wildcard(item: &Item) -> bool {
+    get_version(item).is_some_and(|version| version.trim() == "*")
+}
+
+/// Whether a dependency spec requires a pre-release version, e.g. `"1.0.0-rc.2"`
+pub fn is_prerelease(item: &Item) -> bool {
+    get_version(item)
+        .and_then(|version| VersionReq::parse(&version).ok())
+        .is_some_and(|req| req.comparators.iter().any(|cmp| !cmp.pre.is_empty()))
+}
+
+/// Returns the position of the spec `strategy` picks from `candidates`,
+/// preferring stable versions. Ties go to the earlier spec
+pub fn pick(candidates: &[Item], strategy: VersionStrategy) -> usize {
+    let stable: Vec<usize> = (0..candidates.len())
+        .filter(|&position| !is_prerelease(&candidates[position]))
+        .collect();
+    let positions = if stable.is_empty() {
+        (0..candidates.len()).collect()
+    } else {
+        stable
+    };
+    let first = positions.first().copied().unwrap_or(0);
+
+    let versions: Vec<_> = positions
+        .iter()
+        .filter_map(|&position| {
+            let req = VersionReq::parse(&get_version(&candidates[position])?).ok()?;
+            Some((position, version::lowest_version(&req)))
+        })
+        .collect();
+    let picked = match strategy {
+        VersionStrategy::First => None,
+        // `max_by` returns the last maximum, so go backwards
+        VersionStrategy::Highest => versions.iter().rev().max_by(|a, b| a.1.cmp(&b.1)),
+        VersionStrategy::Lowest => versions.iter().min_by(|a, b| a.1.cmp(&b.1)),
+    };
+    picked.map_or(first, |(position, _)| *position)
+}
+
+/// Whether a dependency spec inherits from `[workspace.dependencies]`
+pub fn is_workspace_inherited(item: &Item) -> bool {
+    item.as_table_like()
+        .and_then(|tbl| tbl.get("workspace"))
+        .and_then(|workspace| workspace.as_bool())
+        .unwrap_or(false)
+}
+
+/// Prefixes the relative `path` of a dependency spec with `prefix`, for
+/// moving the spec into a manifest in a parent directory
+pub fn rebase_path(item: &mut Item, prefix: &Path) {
+    let Some(tbl) = item.as_table_like_mut() else {
+        return;
+    };
+    let Some(path) = tbl.get("path").and_then(|path| path.as_str()) else {
+        return;
+    };
+    if Path::new(path).is_absolute() {
+        return;
+    }
+
+    let rebased = normalize_path(&prefix.join(path));
+    tbl.insert(
+        "path",
+        toml_edit::value(rebased.to_string_lossy().replace('\\', "/")),
+    );
+}
+
+/// Resolves `.` and `..` components without touching the filesystem. Leading
+/// `..` components which can't be resolved are kept
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                let last = normalized.components().next_back();
+                match last {
+                    Some(Component::Normal(_)) => {
+                        normalized.pop();
+                    }
+                    _ => normalized.push(".."),
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Describes how a member's own spec differs from the workspace entry it
+/// would inherit, or returns `None` if switching it over changes nothing
+pub fn find_mismatch(workspace_item: &Item, member_item: &Item) -> Option<String> {
+    find_mismatch_enabling(workspace_item, member_item, &BTreeSet::new())
+}
+
+/// [`find_mismatch`] for a member whose feature definitions can enable the
+/// `enabled` features of the dependency, which the workspace entry may
+/// enable as well without changing what the member can build
+pub fn find_mismatch_enabling(
+    workspace_item: &Item,
+    member_item: &Item,
+    enabled: &BTreeSet<String>,
+) -> Option<String> {
+    if is_workspace_inherited(member_item) {
+        return None;
+    }
+
+    let workspace_version = get_version(workspace_item);
+    let member_version = get_version(member_item);
+    if member_version.is_some() && member_version != workspace_version {
+        return Some(format!(
+            "version '{}' differs from workspace version '{}'",
+            member_version.unwrap_or_default(),
+            workspace_version.unwrap_or_default()
+        ));
+    }
+
+    for key in ["git", "branch", "tag", "rev"] {
+        let workspace_value = workspace_item.get(key).and_then(|value| value.as_str());
+        let member_value = member_item.get(key).and_then(|value| value.as_str());
+        if member_value.is_some() && member_value != workspace_value {
+            return Some(format!(
+                "git {} '{}' differs from workspace {} '{}'",
+                key,
+                member_value.unwrap_or_default(),
+                key,
+                workspace_value.unwrap_or_default()
+            ));
+        }
+    }
+
+    // Members keep their own features when switching over, so only features
+    // the workspace entry would add on top are a change
+    let member_features: BTreeSet<_> = get_features(member_item)
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let added: Vec<_> = get_features(workspace_item)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|feature| !member_features.contains(feature) && !enabled.contains(feature))
+        .collect();
+    if !added.is_empty() {
+        return Some(format!(
+            "workspace entry enables additional features {:?}",
+            added
+        ));
+    }
+
+    None
+}
+
+/// Describes how the spec a member declared differs from the workspace
+/// entry it switches to. Features the entry lacks aren't a difference, the
+/// member keeps enabling them itself
+pub fn spec_differences(member_item: &Item, workspace_item: &Item) -> Vec<String> {
+    let mut differences = Vec::new();
+    let string = |item: &Item, key: &str| match key {
+        "version" => get_version(item),
+        _ => item
+            .get(key)
+            .and_then(|value| value.as_str())
+            .map(String::from),
+    };
+    for key in [
+        "version", "path", "git", "branch", "tag", "rev", "registry", "package",
+    ] {
+        let member_value = string(member_item, key);
+        let workspace_value = string(workspace_item, key);
+        if member_value != workspace_value {
+            differences.push(format!(
+                "{} `{}` became `{}`",
+                key,
+                member_value.as_deref().unwrap_or("-"),
+                workspace_value.as_deref().unwrap_or("-")
+            ));
+        }
+    }
+
+    let default_features = |item: &Item| {
+        item.get("default-features")
+            .or_else(|| item.get("default_features"))
+            .and_then(Item::as_bool)
+            .unwrap_or(true)
+    };
+    if default_features(member_item) != default_features(workspace_item) {
+        differences.push(format!(
+            "default-features `{}` became `{}`",
+            default_features(member_item),
+            default_features(workspace_item)
+        ));
+    }
+
+    let member_features = get_features(member_item).unwrap_or_default();
+    let added: Vec<_> = get_features(workspace_item)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|feature| !member_features.contains(feature))
+        .map(|feature| format!("`{}`", feature))
+        .collect();
+    if !added.is_empty() {
+        differences.push(format!("the entry adds features {}", added.join(", ")));
+    }
+    differences
+}
+
+/// Keys which cargo ignores next to `workspace = true`, because the
+/// workspace entry defines them
+pub const INHERITED_KEYS: [&str; 9] = [
+    "version",
+    "path",
+    "git",
+    "branch",
+    "tag",
+    "rev",
+    "registry",
+    "registry-index",
+    "package",
+];
+
+/// Removes keys conflicting with `workspace = true` from every inherited
+/// dependency of a member. Returns the removed `(section, dependency, key)`s
+pub fn strip_inherited_conflicts(doc: &mut DocumentMut) -> Vec<(&'static str, String, String)> {
+    let mut removed = Vec::new();
+
+    for section in DEPENDENCY_SECTIONS {
+        let Some(dep_table) = doc.get_mut(section).and_then(Item::as_table_like_mut) else {
+            continue;
+        };
+
+        for (dep_name, dep_item) in dep_table.iter_mut() {
+            if !is_workspace_inherited(dep_item) {
+                continue;
+            }
+            let Some(tbl) = dep_item.as_table_like_mut() else {
+                continue;
+            };
+            let before = removed.len();
+            for key in INHERITED_KEYS {
+                if tbl.remove(key).is_some() {
+                    removed.push((section, dep_name.to_string(), key.to_string()));
+                }
+            }
+            if removed.len() > before {
+                tbl.fmt();
+            }
+        }
+    }
+
+    removed
+}
+
+/// Renames the key `old` of `table` to `new` in place, keeping its position
+/// and the comments around it. Returns the renamed item, if there was one
+pub fn rename_key<'a>(table: &'a mut dyn TableLike, old: &str, new: &str) -> Option<&'a mut Item> {
+    let position = table.iter().position(|(key, _)| key == old)?;
+    // Only the entries from `old` on have to be inserted again to keep the order
+    let keys: Vec<Key> = table
+        .iter()
+        .skip(position)
+        .filter_map(|(key, _)| table.key(key).cloned())
+        .collect();
+    for key in keys {
+        let item = table.remove(key.get()).unwrap_or_default();
+        let key = if key.get() == old {
+            Key::new(new)
+                .with_leaf_decor(key.leaf_decor().clone())
+                .with_dotted_decor(key.dotted_decor().clone())
+        } else {
+            key
+        };
+        table.entry_format(&key).or_insert(item);
+    }
+    table.get_mut(new)
+}
+
+/// Renames the dependency key `from` to `to` in every section of a member,
+/// dropping the `package` field once the key names the package itself
+pub fn rename_dependency(doc: &mut DocumentMut, from: &str, to: &str) -> bool {
+    let implicit = has_implicit_feature(doc, from);
+    let mut renamed = false;
+
+    for section in DEPENDENCY_SECTIONS {
+        let Some(dep_table) = doc.get_mut(section).and_then(Item::as_table_like_mut) else {
+            continue;
+        };
+        if !dep_table.contains_key(from) {
+            continue;
+        }
+        // The renamed spec takes the place of one the key already names
+        dep_table.remove(to);
+        let Some(dep_item) = rename_key(dep_table, from, to) else {
+            continue;
+        };
+
+        if let Some(tbl) = dep_item.as_table_like_mut() {
+            if tbl.get("package").and_then(|package| package.as_str()) == Some(to) {
+                tbl.remove("package");
+            }
+        }
+        renamed = true;
+    }
+
+    if renamed {
+        follow_rename(doc, from, to, implicit);
+    }
+    renamed
+}
+
+/// Renames the dependency key `from` to `to` in every section of a member,
+/// adding a `package` field so the spec still refers to the same package
+pub fn alias_dependency(doc: &mut DocumentMut, from: &str, to: &str) -> bool {
+    let implicit = has_implicit_feature(doc, from);
+    let mut renamed = false;
+
+    for section in DEPENDENCY_SECTIONS {
+        let Some(dep_table) = doc.get_mut(section).and_then(Item::as_table_like_mut) else {
+            continue;
+        };
+        let Some(mut dep_item) = dep_table.remove(from) else {
+            continue;
+        };
+
+        if let Some(version) = dep_item.as_str() {
+            let mut tbl = InlineTable::new();
+            tbl.insert("package", from.into());
+            tbl.insert("version", version.into());
+            dep_item = Item::Value(Value::InlineTable(tbl));
+        } else if let Some(tbl) = dep_item.as_table_like_mut() {
+            if !tbl.contains_key("package") {
+                tbl.insert("package", toml_edit::value(from));
+                tbl.fmt();
+            }
+        }
+        dep_table.insert(to, dep_item);
+        renamed = true;
+    }
+
+    if renamed {
+        follow_rename(doc, from, to, implicit);
+    }
+    renamed
+}
+
+/// Rewrites the references to the dependency `old` in `[features]`:
+/// `dep:old`, `old/feature`, `old?/feature`, and with `rename_implicit`
+/// also `old` itself, unless that names a feature of its own
+pub fn rename_feature_references(
+    doc: &mut DocumentMut,
+    old: &str,
+    new: &str,
+    rename_implicit: bool,
+) {
+    let Some(features) = doc.get_mut("features").and_then(Item::as_table_like_mut) else {
+        return;
+    };
+    let own_feature = features.contains_key(old);
+    for (_, enabled) in features.iter_mut() {
+        let Some(enabled) = enabled.as_array_mut() else {
+            continue;
+        };
+        for value in enabled.iter_mut() {
+            let Some(reference) = value.as_str() else {
+                continue;
+            };
+            let renamed = if reference == format!("dep:{}", old) {
+                Some(format!("dep:{}", new))
+            } else if reference == old && rename_implicit && !own_feature {
+                Some(new.to_string())
+            } else {
+                [
+                    (format!("{}/", old), format!("{}/", new)),
+                    (format!("{}?/", old), format!("{}?/", new)),
+                ]
+                .into_iter()
+                .find_map(|(prefix, replacement)| {
+                    reference
+                        .strip_prefix(&prefix)
+                        .map(|feature| format!("{}{}", replacement, feature))
+                })
+            };
+            if let Some(renamed) = renamed {
+                let decor = value.decor().clone();
+                *value = Value::from(renamed);
+                *value.decor_mut() = decor;
+            }
+        }
+    }
+}
+
+/// Whether the member's optional dependency `dep` comes with an implicit
+/// feature of the same name, which cargo leaves out once `[features]`
+/// refers to it as `dep:dep`
+fn has_implicit_feature(doc: &DocumentMut, dep: &str) -> bool {
+    let optional = DEPENDENCY_SECTIONS.iter().any(|section| {
+        doc.get(section)
+            .and_then(|deps| deps.get(dep))
+            .and_then(|item| item.get("optional"))
+            .and_then(Item::as_bool)
+            .unwrap_or(false)
+    });
+    let features = doc.get("features").and_then(Item::as_table_like);
+    let explicit = features.is_some_and(|features| {
+        features.contains_key(dep)
+            || features
+                .iter()
+                .filter_map(|(_, enabled)| enabled.as_array())
+                .flatten()
+                .any(|value| value.as_str() == Some(&format!("dep:{}", dep)))
+    });
+    optional && !explicit
+}
+
+/// Keeps the `[features]` of a member working after its dependency `from`
+/// was renamed to `to`. An implicit feature `from` stays available to
+/// dependents as `from = ["dep:to"]`
+fn follow_rename(doc: &mut DocumentMut, from: &str, to: &str, implicit: bool) {
+    rename_feature_references(doc, from, to, false);
+    if !implicit {
+        return;
+    }
+    if !doc.contains_key("features") {
+        doc.insert("features", Item::Table(toml_edit::Table::new()));
+    }
+    if let Some(features) = doc.get_mut("features").and_then(Item::as_table_like_mut) {
+        let enabled: Array = [format!("dep:{}", to)].into_iter().collect();
+        features.insert(from, Item::Value(Value::Array(enabled)));
+    }
+}
+
 // Helper function to extract features from an Item
-fn get_features(item: &Item) -> Option<Vec<String>> {
+pub fn get_features(item: &Item) -> Option<Vec<String>> {
     item.as_table_like()
         .and_then(|tbl| tbl.get("features"))
         .and_then(|features_item| features_item.as_value())
@@ -151,4 +924,262 @@ mod tests {
             panic!("Expected an array of features");
         }
     }
+
+    #[test]
+    fn test_find_mismatch() {
+        let workspace_item = create_dep_item("1.2", Some(vec!["derive"]));
+
+        assert!(find_mismatch(
+            &workspace_item,
+            &create_dep_item("1.2", Some(vec!["derive"]))
+        )
+        .is_none());
+        assert!(find_mismatch(&workspace_item, &Item::Value(Value::from("1.2"))).is_some());
+        assert!(find_mismatch(
+            &workspace_item,
+            &create_dep_item("1.0", Some(vec!["derive"]))
+        )
+        .is_some());
+
+        let mut git_item = Table::new();
+        git_item.insert("git", toml_edit::value("https://github.com/foo/bar"));
+        git_item.insert("rev", toml_edit::value("abc123"));
+        let mut other_rev = git_item.clone();
+        other_rev.insert("rev", toml_edit::value("def456"));
+        assert!(find_mismatch(
+            &Item::Table(git_item.clone()),
+            &Item::Table(git_item.clone())
+        )
+        .is_none());
+        assert!(find_mismatch(&Item::Table(git_item), &Item::Table(other_rev)).is_some());
+
+        let mut inherited = Table::new();
+        inherited.insert("workspace", toml_edit::value(true));
+        assert!(find_mismatch(&workspace_item, &Item::Table(inherited)).is_none());
+    }
+
+    #[test]
+    fn test_spec_differences() -> Result<()> {
+        let doc = r#"
+member = { version = "1.0.100", default-features = false, features = ["derive"] }
+workspace = { version = "1.0.200", features = ["derive", "rc"] }
+same = "1.0.200"
+"#
+        .parse::<DocumentMut>()?;
+
+        assert_eq!(
+            spec_differences(&doc["member"], &doc["workspace"]),
+            vec![
+                "version `1.0.100` became `1.0.200`",
+                "default-features `false` became `true`",
+                "the entry adds features `rc`",
+            ]
+        );
+        assert!(spec_differences(&doc["same"], &doc["same"]).is_empty());
+        // The member keeps its own features
+        assert!(spec_differences(&doc["workspace"], &doc["same"]).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_dependency_from_member_multiple_sections() -> Result<()> {
+        let manifest_path = Path::new("Cargo.toml");
+        let doc = r#"
+            [dependencies]
+            foo = { version = "1", features = ["rt"] }
+
+            [dev-dependencies]
+            foo = { version = "1", features = ["rt", "test-util"] }
+        "#
+        .parse::<DocumentMut>()?;
+
+        let sections = get_dependency_sections(&doc, manifest_path, "foo")?;
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[1].0, "dev-dependencies");
+        assert!(get_dependency_sections(&doc, manifest_path, "bar").is_err());
+
+        let dep_item = get_dependency_from_member(&doc, manifest_path, "foo")?;
+        assert_eq!(get_features(&dep_item), Some(vec!["rt".to_string()]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_features() -> Result<()> {
+        let mut doc = "cc = { version = \"1.0\", features = [\"parallel\"] }\nlog = \"0.4\"\n"
+            .parse::<DocumentMut>()?;
+
+        set_features(&mut doc["log"], &BTreeSet::from(["std".to_string()]));
+        set_features(&mut doc["cc"], &BTreeSet::new());
+        assert_eq!(
+            doc.to_string(),
+            "cc = { version = \"1.0\" }\nlog = { version = \"0.4\", features = [\"std\"] }\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_dependency_from_member_combines_versions() -> Result<()> {
+        let doc = r#"
+            [dependencies]
+            cc = "1.0"
+
+            [build-dependencies]
+            cc = "1.0.80"
+        "#
+        .parse::<DocumentMut>()?;
+
+        let dep_item = get_dependency_from_member(&doc, Path::new("Cargo.toml"), "cc")?;
+        assert_eq!(get_version(&dep_item).as_deref(), Some("1.0.80"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_dependency() -> Result<()> {
+        let mut doc = r#"
+            [dependencies]
+            jwt = { package = "jsonwebtoken", version = "9" }
+
+            [dev-dependencies]
+            jwt = { package = "jsonwebtoken", version = "9", features = ["use_pem"] }
+        "#
+        .parse::<DocumentMut>()?;
+
+        assert!(rename_dependency(&mut doc, "jwt", "jsonwebtoken"));
+        assert!(!rename_dependency(&mut doc, "jwt", "jsonwebtoken"));
+
+        let content = doc.to_string();
+        assert!(content.contains("jsonwebtoken = { version = \"9\" }"));
+        assert!(content.contains("jsonwebtoken = { version = \"9\", features = [\"use_pem\"] }"));
+        assert!(!content.contains("jwt"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_dependency_keeps_position() -> Result<()> {
+        let mut doc = "[dependencies]\nanyhow = \"1\"\n# Tokens\njwt = { package = \"jsonwebtoken\", version = \"9\" }\nserde = \"1\"\n"
+            .parse::<DocumentMut>()?;
+
+        assert!(rename_dependency(&mut doc, "jwt", "jsonwebtoken"));
+        assert_eq!(
+            doc.to_string(),
+            "[dependencies]\nanyhow = \"1\"\n# Tokens\njsonwebtoken = { version = \"9\" }\nserde = \"1\"\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_alias_dependency() -> Result<()> {
+        let mut doc = r#"
+            [dependencies]
+            syn = "1"
+
+            [build-dependencies]
+            syn = { version = "1.0.100", features = ["full"] }
+        "#
+        .parse::<DocumentMut>()?;
+
+        assert!(alias_dependency(&mut doc, "syn", "syn1"));
+        assert!(!alias_dependency(&mut doc, "syn", "syn1"));
+
+        let content = doc.to_string();
+        assert!(content.contains("syn1 = { package = \"syn\", version = \"1\" }"));
+        assert!(content.contains(
+            "syn1 = { version = \"1.0.100\", features = [\"full\"], package = \"syn\" }"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_multiline_keeps_comments() -> Result<()> {
+        let mut doc = "features = [ # the defaults\n  \"a\",\n# \"b\",\n  # extras\n  \"c\" # last\n  # \"d\"\n]\n"
+            .parse::<DocumentMut>()?;
+        let features = doc["features"].as_array_mut().unwrap();
+        set_multiline(features);
+
+        assert_eq!(
+            doc.to_string(),
+            "features = [ # the defaults\n    \"a\",\n    # \"b\",\n    # extras\n    \"c\", # last\n    # \"d\"\n]\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_spec() {
+        let mut item = create_dep_item("^1.2", None);
+        normalize_spec(&mut item);
+        assert_eq!(item.as_str(), Some("1.2.0"));
+
+        let mut item = create_dep_item("^1.2", Some(vec!["derive"]));
+        normalize_spec(&mut item);
+        assert_eq!(get_version(&item).as_deref(), Some("1.2.0"));
+        assert_eq!(get_features(&item), Some(vec!["derive".to_string()]));
+
+        let mut item = Item::Value(Value::from("~1.2"));
+        normalize_spec(&mut item);
+        assert_eq!(item.as_str(), Some("~1.2"));
+    }
+
+    #[test]
+    fn test_pick() {
+        let candidates: Vec<_> = ["1.0.100", "1.2", "1.0.100", "2.0.0-rc.1", "*"]
+            .into_iter()
+            .map(|version| create_dep_item(version, None))
+            .collect();
+        assert_eq!(pick(&candidates, VersionStrategy::First), 0);
+        assert_eq!(pick(&candidates, VersionStrategy::Highest), 1);
+        assert_eq!(pick(&candidates, VersionStrategy::Lowest), 4);
+        assert_eq!(pick(&candidates[..3], VersionStrategy::Lowest), 0);
+        assert_eq!(pick(&candidates[3..4], VersionStrategy::Highest), 0);
+    }
+
+    #[test]
+    fn test_strip_inherited_conflicts() -> Result<()> {
+        let mut doc = r#"
+            [dependencies]
+            serde = { workspace = true, version = "1", features = ["derive"] }
+            anyhow = { version = "1" }
+
+            [dev-dependencies]
+            tokio = { workspace = true, path = "../tokio" }
+        "#
+        .parse::<DocumentMut>()?;
+
+        let removed = strip_inherited_conflicts(&mut doc);
+        assert_eq!(
+            removed,
+            vec![
+                ("dependencies", "serde".to_string(), "version".to_string()),
+                ("dev-dependencies", "tokio".to_string(), "path".to_string()),
+            ]
+        );
+
+        let content = doc.to_string();
+        assert!(content.contains("serde = { workspace = true, features = [\"derive\"] }"));
+        assert!(content.contains("anyhow = { version = \"1\" }"));
+        assert!(content.contains("tokio = { workspace = true }"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebase_path() -> Result<()> {
+        let mut doc = r#"
+            [dependencies]
+            shared = { path = "crates/shared" }
+            other = { path = "../other", version = "1" }
+            serde = "1"
+        "#
+        .parse::<DocumentMut>()?;
+
+        let deps = doc["dependencies"].as_table_mut().unwrap();
+        for (_, item) in deps.iter_mut() {
+            rebase_path(item, Path::new("one"));
+        }
+
+        let content = doc.to_string();
+        assert!(content.contains(r#"shared = { path = "one/crates/shared" }"#));
+        assert!(content.contains(r#"other = { path = "other", version = "1" }"#));
+        assert!(content.contains(r#"serde = "1""#));
+        assert_eq!(normalize_path(Path::new("a/../../b")), Path::new("../b"));
+        Ok(())
+    }
 }
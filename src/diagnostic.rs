@@ -0,0 +1,253 @@
+use serde::Serialize;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml_edit::{ImDocument, Item};
+
+/// A finding about a manifest, for editors and CI bots to annotate
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    /// What kind of finding it is, e.g. `not-inherited`
+    pub code: &'static str,
+    pub message: String,
+    pub manifest: PathBuf,
+    /// The entry the finding is about, if it could be found
+    pub span: Option<Span>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<Fix>,
+}
+
+/// A range of a file, in bytes and in lines and columns counted from 1
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+/// How to resolve a finding. `replacement` is the text the span should be
+/// replaced with, where the fix is that simple
+#[derive(Debug, Serialize)]
+pub struct Fix {
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+}
+
+/// An error about a TOML file, pointing at the line and column it's about
+/// with the offending line underlined, the way cargo reports them
+#[derive(Debug)]
+pub struct Located {
+    path: PathBuf,
+    message: String,
+    line: usize,
+    column: usize,
+    source_line: String,
+    width: usize,
+}
+
+impl std::error::Error for Located {}
+
+impl fmt::Display for Located {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        writeln!(f, "{}", self.message)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter,
+            self.path.display(),
+            self.line,
+            self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", number, self.source_line)?;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(self.column - 1),
+            "^".repeat(self.width)
+        )
+    }
+}
+
+/// A value of an unexpected type, e.g. a string where a table belongs.
+/// `keys` lead to it from the document root
+#[derive(Debug)]
+pub struct Unexpected {
+    keys: Vec<String>,
+    message: String,
+}
+
+impl std::error::Error for Unexpected {}
+
+impl fmt::Display for Unexpected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// An error for the value at `keys` having the wrong shape, located once the
+/// file it came from is known
+pub fn unexpected(keys: &[&str], message: impl Into<String>) -> anyhow::Error {
+    anyhow::Error::new(Unexpected {
+        keys: keys.iter().map(|key| key.to_string()).collect(),
+        message: message.into(),
+    })
+}
+
+/// Replaces a parse error, a deserialization error or an [`Unexpected`] value
+/// about `content` with one pointing into the file at `path`. Other errors,
+/// and those which have no position, are returned as they are
+pub fn locate(err: anyhow::Error, path: &Path, content: &str) -> anyhow::Error {
+    let located = if let Some(err) = err.downcast_ref::<toml_edit::TomlError>() {
+        err.span()
+            .map(|span| Located::new(path, content, span, err.message()))
+    } else if let Some(err) = err.downcast_ref::<toml_edit::de::Error>() {
+        err.span()
+            .map(|span| Located::new(path, content, span, err.message()))
+    } else if let Some(err) = err.downcast_ref::<Unexpected>() {
+        let keys: Vec<&str> = err.keys.iter().map(String::as_str).collect();
+        span_of(content, &keys).map(|span| Located::new(path, content, span, &err.message))
+    } else {
+        None
+    };
+    match located {
+        Some(located) => anyhow::Error::new(located),
+        None => err,
+    }
+}
+
+/// The span of `key = value` in the table at `table`, from the start of the
+/// key to the end of the value
+pub fn entry_span(content: &str, table: &[&str], key: &str) -> Option<Span> {
+    let doc = ImDocument::parse(content).ok()?;
+    let mut item: &Item = doc.as_item();
+    for name in table {
+        item = item.get(name)?;
+    }
+    let (key, value) = item.as_table_like()?.get_key_value(key)?;
+    let key_span = key.span();
+    let value_span = value.span();
+    let start = key_span.clone().or(value_span.clone())?.start;
+    let end = value_span.or(key_span)?.end;
+    Some(Span::new(content, start..end))
+}
+
+impl Span {
+    fn new(content: &str, range: Range<usize>) -> Self {
+        let position = |offset: usize| {
+            let before = &content[..offset.min(content.len())];
+            let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+            (
+                before.matches('\n').count() + 1,
+                before[line_start..].chars().count() + 1,
+            )
+        };
+        let (line, column) = position(range.start);
+        let (end_line, end_column) = position(range.end);
+        Self {
+            start: range.start,
+            end: range.end,
+            line,
+            column,
+            end_line,
+            end_column,
+        }
+    }
+}
+
+/// The span of the value at `keys`, or of the deepest of them that exists
+fn span_of(content: &str, keys: &[&str]) -> Option<Range<usize>> {
+    let doc = ImDocument::parse(content).ok()?;
+    let mut item: &Item = doc.as_item();
+    let mut span = None;
+    for key in keys {
+        let Some(next) = item.get(key) else {
+            break;
+        };
+        item = next;
+        span = item.span().or(span);
+    }
+    span
+}
+
+impl Located {
+    fn new(path: &Path, content: &str, span: Range<usize>, message: &str) -> Self {
+        let start = span.start.min(content.len());
+        let line_start = content[..start].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = content[start..]
+            .find('\n')
+            .map_or(content.len(), |pos| start + pos);
+        let source_line = content[line_start..line_end].trim_end_matches('\r');
+        let column = content[line_start..start].chars().count() + 1;
+        let end = span.end.clamp(start, line_end);
+        Self {
+            path: path.to_path_buf(),
+            message: message.trim_end().to_string(),
+            line: content[..start].matches('\n').count() + 1,
+            column,
+            source_line: source_line.to_string(),
+            width: content[start..end].chars().count().max(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml_edit::DocumentMut;
+
+    #[test]
+    fn test_locate() {
+        let path = Path::new("crates/a/Cargo.toml");
+        let content = "[package]\nname = \"a\"\n\n[dependencies]\nserde = \"1\nanyhow = \"1\"\n";
+        let err = content.parse::<DocumentMut>().unwrap_err();
+        let located = locate(anyhow::Error::new(err), path, content);
+        assert_eq!(
+            located.to_string(),
+            "invalid basic string\n \
+             --> crates/a/Cargo.toml:5:11\n  \
+             |\n\
+             5 | serde = \"1\n  \
+             |           ^"
+        );
+
+        let content =
+            "[workspace]\nmembers = [\"a\"]\n\n[workspace.dependencies]\nserde = [\"1\"]\n";
+        let err = unexpected(
+            &["workspace", "dependencies", "serde"],
+            "serde is neither a version nor a table",
+        );
+        let located = locate(err, path, content);
+        assert_eq!(
+            located.to_string(),
+            "serde is neither a version nor a table\n \
+             --> crates/a/Cargo.toml:5:9\n  \
+             |\n\
+             5 | serde = [\"1\"]\n  \
+             |         ^^^^^"
+        );
+
+        let err = locate(anyhow::anyhow!("Something else"), path, content);
+        assert_eq!(err.to_string(), "Something else");
+    }
+
+    #[test]
+    fn test_entry_span() {
+        let content = "[package]\nname = \"a\"\n\n[dependencies]\nserde = { version = \"1\" }\n";
+        let span = entry_span(content, &["dependencies"], "serde").unwrap();
+        assert_eq!(
+            &content[span.start..span.end],
+            "serde = { version = \"1\" }"
+        );
+        assert_eq!((span.line, span.column), (5, 1));
+        assert_eq!((span.end_line, span.end_column), (5, 26));
+        assert!(entry_span(content, &["dev-dependencies"], "serde").is_none());
+    }
+}
@@ -0,0 +1,33 @@
+use similar::TextDiff;
+use std::path::Path;
+
+/// Renders a unified diff between two versions of a file, or an empty
+/// string if they are the same
+pub fn unified_diff(path: &Path, old: &str, new: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+
+    let label = path.display().to_string();
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&label, &label)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "[dependencies]\nserde = \"1\"\n";
+        let new = "[dependencies]\nserde = { workspace = true }\n";
+
+        assert_eq!(unified_diff(Path::new("Cargo.toml"), old, old), "");
+        assert_eq!(
+            unified_diff(Path::new("Cargo.toml"), old, new),
+            "--- Cargo.toml\n+++ Cargo.toml\n@@ -1,2 +1,2 @@\n [dependencies]\n-serde = \"1\"\n+serde = { workspace = true }\n"
+        );
+    }
+}
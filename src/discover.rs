@@ -0,0 +1,314 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+use crate::cli::Opt;
+use crate::manifest::Manifest;
+use crate::sandbox;
+use crate::workspace;
+
+/// Consolidates every workspace found below the directory of
+/// `--manifest-path`, or the current directory, one after the other
+pub fn run(opt: &Opt) -> Result<()> {
+    let root = match opt.manifest_path() {
+        Some(path) if path.is_dir() => path.to_path_buf(),
+        Some(path) => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        None => std::env::current_dir().context("Failed to get the current directory")?,
+    };
+
+    // A broken manifest fails the run in the end, but doesn't keep the
+    // workspaces next to it from being consolidated
+    let (roots, unparsable) = scan(&root, |doc| doc.contains_key("workspace"))?;
+    if roots.is_empty() && unparsable.is_empty() {
+        anyhow::bail!("No workspace found below '{}'", root.display());
+    }
+    consolidate_each(opt, roots, unparsable)
+}
+
+/// Consolidates the workspaces given with several `--manifest-path` or
+/// listed in the `--manifests-from` file, one after the other
+pub fn run_manifests(opt: &Opt) -> Result<()> {
+    let mut manifest_paths = opt.manifest_path.clone();
+    if let Some(list) = &opt.manifests_from {
+        manifest_paths.extend(read_path_list(list)?);
+    }
+    consolidate_each(opt, manifest_paths, Vec::new())
+}
+
+/// Reads a file with one path per line, resolved relative to the file.
+/// Empty lines and lines starting with `#` are skipped
+pub fn read_path_list(list: &Path) -> Result<Vec<PathBuf>> {
+    let content =
+        fs::read_to_string(list).with_context(|| format!("Failed to read '{}'", list.display()))?;
+    let base = list.parent().unwrap_or(Path::new(""));
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect())
+}
+
+/// Consolidates each of `manifest_paths` and prints a summary, listing the
+/// manifests in `failed` as failed already. A failing workspace doesn't stop
+/// the remaining ones, but fails the run once all of them were processed
+fn consolidate_each(opt: &Opt, manifest_paths: Vec<PathBuf>, mut failed: Unparsable) -> Result<()> {
+    let mut consolidated = Vec::new();
+    let mut skipped = Vec::new();
+    for manifest_path in manifest_paths {
+        info!("Consolidating workspace '{}'", manifest_path.display());
+        let mut workspace_opt = opt.clone();
+        workspace_opt.recursive = false;
+        workspace_opt.manifests_from = None;
+        workspace_opt.manifest_path = vec![manifest_path.clone()];
+
+        let result = if opt.sandbox {
+            sandbox::run(&workspace_opt)
+        } else {
+            workspace::consolidate_dependencies(&workspace_opt)
+        };
+        match result {
+            Ok(()) => consolidated.push(manifest_path),
+            // A single crate is no failure, there's just nothing to do
+            Err(err) if err.is::<workspace::TooFewMembers>() => {
+                info!("{}: {}", manifest_path.display(), err.root_cause());
+                skipped.push(manifest_path);
+            }
+            Err(err) => {
+                warn!("{}: {:#}", manifest_path.display(), err);
+                failed.push((manifest_path, err));
+            }
+        }
+    }
+
+    println!(
+        "Consolidated {} of {} workspace(s)",
+        consolidated.len(),
+        consolidated.len() + skipped.len() + failed.len()
+    );
+    for manifest_path in &consolidated {
+        println!("  ok      {}", manifest_path.display());
+    }
+    for manifest_path in &skipped {
+        println!("  skipped {}", manifest_path.display());
+    }
+    for (manifest_path, _) in &failed {
+        println!("  failed  {}", manifest_path.display());
+    }
+
+    if !failed.is_empty() {
+        let mut summary = format!("{} workspace(s) could not be consolidated:", failed.len());
+        for (manifest_path, err) in &failed {
+            summary.push_str(&format!("\n  - {}: {:#}", manifest_path.display(), err));
+        }
+        anyhow::bail!(summary);
+    }
+    Ok(())
+}
+
+/// Returns the manifests below `root` which declare a `[workspace]`, sorted
+/// by path. Build output and VCS directories are skipped
+pub fn find_workspace_roots(root: &Path) -> Result<Vec<PathBuf>> {
+    find_manifests(root, |doc| doc.contains_key("workspace"))
+}
+
+/// Returns the manifests below `root`, including its own, for which `filter`
+/// returns true, sorted by path. Manifests which can't be parsed are skipped
+/// with a warning
+pub fn find_manifests(root: &Path, filter: impl Fn(&DocumentMut) -> bool) -> Result<Vec<PathBuf>> {
+    let (manifests, unparsable) = scan(root, filter)?;
+    for (manifest_path, err) in unparsable {
+        warn!("Skipping '{}': {:#}", manifest_path.display(), err);
+    }
+    Ok(manifests)
+}
+
+/// Manifests which can't be parsed, with the reason
+type Unparsable = Vec<(PathBuf, anyhow::Error)>;
+
+/// Like [`find_manifests`], returning the manifests which can't be parsed
+/// instead of skipping them
+fn scan(root: &Path, filter: impl Fn(&DocumentMut) -> bool) -> Result<(Vec<PathBuf>, Unparsable)> {
+    let mut manifests = Vec::new();
+    let mut unparsable = Vec::new();
+    walk(root, &filter, &mut manifests, &mut unparsable)?;
+    manifests.sort();
+    Ok((manifests, unparsable))
+}
+
+fn walk(
+    dir: &Path,
+    filter: &impl Fn(&DocumentMut) -> bool,
+    manifests: &mut Vec<PathBuf>,
+    unparsable: &mut Unparsable,
+) -> Result<()> {
+    let manifest_path = dir.join("Cargo.toml");
+    if manifest_path.is_file() {
+        match Manifest::open(&manifest_path) {
+            Ok(manifest) if filter(&manifest.doc) => manifests.push(manifest_path),
+            Ok(_) => {}
+            Err(err) => unparsable.push((manifest_path, err)),
+        }
+    }
+
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Failed to read '{}'", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if !file_type.is_dir()
+            || sandbox::SKIPPED_DIRS
+                .iter()
+                .any(|skipped| entry.file_name() == *skipped)
+        {
+            continue;
+        }
+        walk(&entry.path(), filter, manifests, unparsable)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run_records_unparsable_manifests() -> Result<()> {
+        let dir = TempDir::new()?;
+        for (path, content) in [
+            ("one/Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            (
+                "one/a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\n",
+            ),
+            ("one/a/src/lib.rs", ""),
+            (
+                "one/b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\n",
+            ),
+            ("one/b/src/lib.rs", ""),
+            ("broken/Cargo.toml", "[workspace\n"),
+        ] {
+            write_file(dir.path().join(path), content)?;
+        }
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--recursive",
+            "--manifest-path",
+            dir.path().to_str().unwrap(),
+        ]);
+        let err = run(&opt).unwrap_err();
+        assert!(format!("{:#}", err).contains("broken"));
+        // The other workspace was consolidated all the same
+        assert!(fs::read_to_string(dir.path().join("one/Cargo.toml"))?
+            .contains("[workspace.dependencies]\nserde = \"1\"\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_workspace_roots() -> Result<()> {
+        let dir = TempDir::new()?;
+        for (path, content) in [
+            ("one/Cargo.toml", "[workspace]\nmembers = [\"a\"]\n"),
+            ("one/a/Cargo.toml", "[package]\nname = \"a\"\n"),
+            ("two/nested/Cargo.toml", "[workspace]\n"),
+            ("standalone/Cargo.toml", "[package]\nname = \"s\"\n"),
+            ("one/target/package/Cargo.toml", "[workspace]\n"),
+        ] {
+            write_file(dir.path().join(path), content)?;
+        }
+
+        let list = dir.path().join("workspaces.txt");
+        fs::write(
+            &list,
+            "# checkouts\none/Cargo.toml\n\n/srv/web/Cargo.toml\n",
+        )?;
+        assert_eq!(
+            read_path_list(&list)?,
+            vec![
+                dir.path().join("one/Cargo.toml"),
+                PathBuf::from("/srv/web/Cargo.toml")
+            ]
+        );
+
+        let roots = find_workspace_roots(dir.path())?;
+        assert_eq!(
+            roots,
+            vec![
+                dir.path().join("one/Cargo.toml"),
+                dir.path().join("two/nested/Cargo.toml"),
+            ]
+        );
+        Ok(())
+    }
+
+    /// Writes a workspace at `dir/name` whose members `a` and `b` depend on
+    /// `dep` through `paths`, which fails to consolidate if they differ
+    fn write_workspace(dir: &Path, name: &str, paths: [&str; 2]) -> Result<PathBuf> {
+        write_file(
+            dir.join(name).join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n",
+        )?;
+        for (member, path) in ["a", "b"].into_iter().zip(paths) {
+            write_file(
+                dir.join(name).join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\ndep = {{ path = \"{}\" }}\n",
+                    member, path
+                ),
+            )?;
+            write_file(dir.join(name).join(member).join("src/lib.rs"), "")?;
+        }
+        Ok(dir.join(name).join("Cargo.toml"))
+    }
+
+    #[test]
+    fn test_run_manifests_continues_past_failures() -> Result<()> {
+        for keep_going in [false, true] {
+            let dir = TempDir::new()?;
+            let failing = write_workspace(dir.path(), "failing", ["../x", "../y"])?;
+            let passing = write_workspace(dir.path(), "passing", ["../x", "../x"])?;
+
+            let mut args = vec!["cargo-consolidate", "--no-cargo"];
+            if keep_going {
+                args.push("--keep-going");
+            }
+            for manifest_path in [&failing, &passing] {
+                args.extend(["--manifest-path", manifest_path.to_str().unwrap()]);
+            }
+            let err = run_manifests(&Opt::parse_from(args)).unwrap_err();
+            let message = format!("{:#}", err);
+            assert!(message.starts_with("1 workspace(s) could not be consolidated"));
+            assert!(message.contains("'dep' points to different paths"));
+            // The workspace after the failing one was consolidated all the same
+            assert!(fs::read_to_string(&passing)?
+                .contains("[workspace.dependencies]\ndep = { path = \"x\" }\n"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_manifests_all_passing() -> Result<()> {
+        let dir = TempDir::new()?;
+        let one = write_workspace(dir.path(), "one", ["../x", "../x"])?;
+        let two = write_workspace(dir.path(), "two", ["../y", "../y"])?;
+
+        run_manifests(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            one.to_str().unwrap(),
+            "--manifest-path",
+            two.to_str().unwrap(),
+        ]))?;
+        assert!(fs::read_to_string(&two)?.contains("[workspace.dependencies]"));
+        Ok(())
+    }
+}
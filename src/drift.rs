@@ -0,0 +1,258 @@
+use anyhow::Result;
+use semver::VersionReq;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::fmt;
+use toml_edit::{DocumentMut, Item};
+
+use crate::catalogue::Catalogue;
+use crate::cli::{DriftArgs, Opt, ReportFormat};
+use crate::dependency;
+use crate::manifest::Manifest;
+use crate::policy::{Policy, WORKSPACE};
+use crate::version::lowest_version;
+use crate::workspace;
+
+/// How a requirement relates to the golden version of its crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Current,
+    /// Its lowest version is newer than the catalogue's
+    Ahead,
+    /// Its lowest version is older than the catalogue's
+    Behind,
+    /// The policy doesn't allow it
+    OffPolicy,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Current => "current",
+            Status::Ahead => "ahead",
+            Status::Behind => "behind",
+            Status::OffPolicy => "off-policy",
+        })
+    }
+}
+
+/// A requirement which isn't on the golden version
+#[derive(Debug, Serialize)]
+pub struct Drifted {
+    /// The member declaring the requirement, or `[workspace.dependencies]`
+    pub member: String,
+    pub dependency: String,
+    pub requirement: String,
+    /// The catalogue's requirement
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub golden: Option<String>,
+    /// The policy's allowed requirement
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<String>,
+    pub status: Status,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Drift {
+    /// Requirements of crates the catalogue or policy has golden versions of
+    pub tracked: usize,
+    /// Tracked requirements on the golden version
+    pub current: usize,
+    pub drifted: Vec<Drifted>,
+}
+
+/// Compares the workspace entries and the members' own specs with the
+/// golden versions of the `--catalogue` and `--policy`
+pub fn run(opt: &Opt, args: &DriftArgs) -> Result<()> {
+    let catalogue = opt.catalogue.as_deref().map(Catalogue::load).transpose()?;
+    let policy = match &opt.policy {
+        Some(path) => Policy::load(path)?,
+        None if catalogue.is_none() => {
+            anyhow::bail!("drift needs the golden versions from --catalogue or --policy")
+        }
+        None => Policy::default(),
+    };
+
+    let metadata = workspace::load_metadata(opt.manifest_path(), &opt.cargo)?;
+    let root = Manifest::open(metadata.workspace_root.join("Cargo.toml").as_std_path())?;
+    let mut packages = workspace::workspace_packages(&metadata)?;
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut members = Vec::new();
+    for package in packages {
+        let member = Manifest::open(package.manifest_path.as_std_path())?;
+        members.push((package.name.clone(), member.doc));
+    }
+
+    let drift = find_drift(&root.doc, &members, catalogue.as_ref(), &policy);
+    match args.format {
+        ReportFormat::Markdown => print!("{}", drift),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&drift)?),
+    }
+    Ok(())
+}
+
+/// Classifies the `[workspace.dependencies]` entries of `root` and the specs
+/// `members` declare themselves
+fn find_drift(
+    root: &DocumentMut,
+    members: &[(String, DocumentMut)],
+    catalogue: Option<&Catalogue>,
+    policy: &Policy,
+) -> Drift {
+    let mut drift = Drift::default();
+    let mut record = |member: &str, dep_name: &str, item: &Item| {
+        let Some(drifted) = classify(member, dep_name, item, catalogue, policy) else {
+            return;
+        };
+        drift.tracked += 1;
+        match drifted.status {
+            Status::Current => drift.current += 1,
+            _ => drift.drifted.push(drifted),
+        }
+    };
+
+    let ws_deps = root
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"))
+        .and_then(Item::as_table_like);
+    for (dep_name, item) in ws_deps.into_iter().flat_map(|deps| deps.iter()) {
+        record(WORKSPACE, dep_name, item);
+    }
+    for (member, doc) in members {
+        for section in dependency::DEPENDENCY_SECTIONS {
+            let Some(deps) = doc.get(section).and_then(Item::as_table_like) else {
+                continue;
+            };
+            for (dep_name, item) in deps.iter() {
+                if !dependency::is_workspace_inherited(item) {
+                    record(member, dep_name, item);
+                }
+            }
+        }
+    }
+    drift
+}
+
+/// Compares a spec with the golden version of its crate. Returns `None` if
+/// there's none or the spec has no version requirement
+fn classify(
+    member: &str,
+    dep_name: &str,
+    item: &Item,
+    catalogue: Option<&Catalogue>,
+    policy: &Policy,
+) -> Option<Drifted> {
+    // Renamed dependencies are compared by the package they refer to
+    let package = item
+        .as_table_like()
+        .and_then(|tbl| tbl.get("package"))
+        .and_then(Item::as_str)
+        .unwrap_or(dep_name);
+    let golden = catalogue
+        .and_then(|catalogue| catalogue.get(package))
+        .and_then(dependency::get_version);
+    let allowed = policy.allowed(package);
+    if golden.is_none() && allowed.is_none() {
+        return None;
+    }
+    let requirement = dependency::get_version(item)?;
+    let lowest = lowest_version(&VersionReq::parse(&requirement).ok()?);
+
+    let status = if policy.check(member, dep_name, item).is_some() {
+        Status::OffPolicy
+    } else {
+        let golden_lowest = golden
+            .as_deref()
+            .and_then(|golden| VersionReq::parse(golden).ok())
+            .map(|golden| lowest_version(&golden));
+        match golden_lowest.map(|golden| lowest.cmp(&golden)) {
+            Some(Ordering::Greater) => Status::Ahead,
+            Some(Ordering::Less) => Status::Behind,
+            Some(Ordering::Equal) | None => Status::Current,
+        }
+    };
+    Some(Drifted {
+        member: member.to_string(),
+        dependency: package.to_string(),
+        requirement,
+        golden,
+        allowed: allowed.map(ToString::to_string),
+        status,
+    })
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Drift from the golden versions")?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{} of {} tracked requirement(s) are on the golden version.",
+            self.current, self.tracked
+        )?;
+        if self.drifted.is_empty() {
+            return Ok(());
+        }
+        writeln!(f)?;
+        for drifted in &self.drifted {
+            write!(
+                f,
+                "- {}: `{}` requires `{}`, {}",
+                drifted.member, drifted.dependency, drifted.requirement, drifted.status
+            )?;
+            if let Some(golden) = &drifted.golden {
+                write!(f, ", the catalogue has `{}`", golden)?;
+            }
+            if let Some(allowed) = drifted
+                .allowed
+                .as_ref()
+                .filter(|_| drifted.status == Status::OffPolicy)
+            {
+                write!(f, ", the policy allows `{}`", allowed)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_drift() -> Result<()> {
+        let catalogue = Catalogue::parse(
+            "[dependencies]\nserde = \"1.0.200\"\ntokio = { version = \"1.38\", features = [\"full\"] }\n",
+        )?;
+        let policy = Policy::parse("[allowed]\nrand = \">=0.8, <0.9\"\n")?;
+        let root = "[workspace]\n\n[workspace.dependencies]\nserde = \"1.0.200\"\nanyhow = \"1\"\n"
+            .parse::<DocumentMut>()?;
+        let members = vec![
+            (
+                "api".to_string(),
+                "[dependencies]\nserde = { workspace = true }\ntokio = \"1.40\"\nrand = \"0.7\"\n"
+                    .parse::<DocumentMut>()?,
+            ),
+            (
+                "cli".to_string(),
+                "[dependencies]\ntokio-old = { package = \"tokio\", version = \"1.20\" }\nrand = \"0.8.5\"\n"
+                    .parse::<DocumentMut>()?,
+            ),
+        ];
+
+        let drift = find_drift(&root, &members, Some(&catalogue), &policy);
+        assert_eq!(drift.tracked, 5);
+        assert_eq!(drift.current, 2);
+        assert_eq!(
+            drift.to_string(),
+            "# Drift from the golden versions\n\n\
+             2 of 5 tracked requirement(s) are on the golden version.\n\n\
+             - api: `tokio` requires `1.40`, ahead, the catalogue has `1.38`\n\
+             - api: `rand` requires `0.7`, off-policy, the policy allows `>=0.8, <0.9`\n\
+             - cli: `tokio` requires `1.20`, behind, the catalogue has `1.38`\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::cli::{CargoOptions, SymlinkPolicy};
+use crate::manifest::Manifest;
+
+/// The edition of packages which don't declare one
+const DEFAULT_EDITION: &str = "2015";
+
+/// The keys of `[package]` compared across the members
+const KEYS: [&str; 2] = ["edition", "rust-version"];
+
+/// The value a member ends up with for one of the [`KEYS`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Declared {
+    /// `None` if the member declares no rust-version
+    pub value: Option<String>,
+    /// Whether it's inherited from `[workspace.package]`
+    pub inherited: bool,
+    /// Whether it differs from what the other members use
+    pub inconsistent: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberEdition {
+    pub member: String,
+    pub edition: Declared,
+    pub rust_version: Declared,
+}
+
+/// The editions and rust-versions of the members
+#[derive(Debug, Default, Serialize)]
+pub struct Editions {
+    /// The edition the members should share, that of `[workspace.package]`
+    /// or else the one most members use
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edition: Option<String>,
+    /// Like `edition`, for the rust-version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rust_version: Option<String>,
+    pub members: Vec<MemberEdition>,
+}
+
+impl Editions {
+    /// Reads what `members`, pairs of name and manifest, declare and marks
+    /// the values which differ from the workspace's
+    pub fn find(root: &DocumentMut, members: &[(&str, &DocumentMut)]) -> Self {
+        let workspace_package = root
+            .get("workspace")
+            .and_then(|workspace| workspace.get("package"));
+        let declared = |doc: &DocumentMut, key: &str| {
+            let item = doc.get("package").and_then(|package| package.get(key));
+            let inherited = item
+                .and_then(|item| item.get("workspace"))
+                .and_then(Item::as_bool)
+                .unwrap_or(false);
+            let value = match inherited {
+                true => workspace_package.and_then(|package| package.get(key)),
+                false => item,
+            };
+            let value = value.and_then(Item::as_str).map(str::to_string);
+            Declared {
+                value: value.or_else(|| (key == "edition").then(|| DEFAULT_EDITION.to_string())),
+                inherited,
+                inconsistent: false,
+            }
+        };
+
+        let mut editions = Editions {
+            members: members
+                .iter()
+                .map(|(member, doc)| MemberEdition {
+                    member: member.to_string(),
+                    edition: declared(doc, "edition"),
+                    rust_version: declared(doc, "rust-version"),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        for key in KEYS {
+            let expected = workspace_package
+                .and_then(|package| package.get(key))
+                .and_then(Item::as_str)
+                .map(str::to_string)
+                .or_else(|| editions.most_common(key));
+            for member in &mut editions.members {
+                let declared = member.get_mut(key);
+                declared.inconsistent = expected.is_some() && declared.value != expected;
+            }
+            match key {
+                "edition" => editions.edition = expected,
+                _ => editions.rust_version = expected,
+            }
+        }
+        editions
+    }
+
+    /// The value of `key` most members declare, the highest one on a tie
+    fn most_common(&self, key: &str) -> Option<String> {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for member in &self.members {
+            if let Some(value) = &member.get(key).value {
+                *counts.entry(value).or_default() += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .max_by_key(|(value, count)| (*count, *value))
+            .map(|(value, _)| value.to_string())
+    }
+
+    /// The number of members with an edition or rust-version differing from
+    /// the workspace's
+    pub fn inconsistent(&self) -> usize {
+        self.members
+            .iter()
+            .filter(|member| member.edition.inconsistent || member.rust_version.inconsistent)
+            .count()
+    }
+}
+
+impl MemberEdition {
+    fn get(&self, key: &str) -> &Declared {
+        match key {
+            "edition" => &self.edition,
+            _ => &self.rust_version,
+        }
+    }
+
+    fn get_mut(&mut self, key: &str) -> &mut Declared {
+        match key {
+            "edition" => &mut self.edition,
+            _ => &mut self.rust_version,
+        }
+    }
+}
+
+impl fmt::Display for Declared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.value.as_deref().unwrap_or("-");
+        match self.inconsistent {
+            true => write!(f, "**{}**", value)?,
+            false => write!(f, "{}", value)?,
+        }
+        if self.inherited {
+            write!(f, " (inherited)")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Editions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "| Member | Edition | rust-version |")?;
+        writeln!(f, "| --- | --- | --- |")?;
+        for member in &self.members {
+            writeln!(
+                f,
+                "| `{}` | {} | {} |",
+                member.member, member.edition, member.rust_version
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Switches the members whose edition or rust-version differs from
+/// `[workspace.package]` to inheriting it. A member is only kept switched if
+/// `cargo check -p` still accepts it afterwards. Returns the names of the
+/// members which were switched
+pub fn fix(
+    root: &DocumentMut,
+    members: &mut [(String, Manifest)],
+    editions: &Editions,
+    cargo: &CargoOptions,
+    symlinks: SymlinkPolicy,
+) -> Result<Vec<String>> {
+    let workspace_package = root
+        .get("workspace")
+        .and_then(|workspace| workspace.get("package"));
+    let mut fixed = Vec::new();
+    for ((name, manifest), member) in members.iter_mut().zip(&editions.members) {
+        let mut keys = Vec::new();
+        for key in KEYS {
+            if !member.get(key).inconsistent {
+                continue;
+            }
+            if workspace_package
+                .and_then(|package| package.get(key))
+                .is_none()
+            {
+                warn!(
+                    "'{}' has a different {} than the other members, but [workspace.package] has none to inherit",
+                    name, key
+                );
+                continue;
+            }
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            continue;
+        }
+
+        let Some(package) = manifest.doc.get_mut("package").and_then(Item::as_table_mut) else {
+            continue;
+        };
+        for key in &keys {
+            let mut inherited = Table::new();
+            inherited.set_dotted(true);
+            inherited.insert("workspace", toml_edit::value(true));
+            package.insert(key, Item::Table(inherited));
+        }
+        manifest.write(symlinks)?;
+
+        let dir = manifest.path().parent().unwrap_or(Path::new("."));
+        match cargo_check(dir, name, cargo) {
+            Ok(true) => {
+                info!("'{}' now inherits its {}", name, keys.join(" and "));
+                fixed.push(name.clone());
+            }
+            checked => {
+                // Also when cargo couldn't run at all, the member must not
+                // stay switched without having been checked
+                manifest.write_rendered(manifest.source(), symlinks)?;
+                checked?;
+                warn!(
+                    "'{}' doesn't build with the {} of the workspace, it is left as it was",
+                    name,
+                    keys.join(" and ")
+                );
+            }
+        }
+    }
+    Ok(fixed)
+}
+
+/// Runs `cargo check -p` for a package, returning whether it succeeded
+fn cargo_check(dir: &Path, package: &str, cargo: &CargoOptions) -> Result<bool> {
+    let program = cargo
+        .cargo
+        .clone()
+        .or_else(|| std::env::var_os("CARGO").map(Into::into))
+        .unwrap_or_else(|| "cargo".into());
+    let output = Command::new(&program)
+        .args(["check", "--quiet", "-p", package])
+        .args(cargo.args())
+        .current_dir(dir)
+        .output()
+        .with_context(|| format!("Failed to run '{}'", program.display()))?;
+    if !output.status.success() {
+        info!(
+            "`cargo check -p {}` failed:\n{}",
+            package,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output.status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_find() -> Result<()> {
+        let root = "[workspace.package]\nedition = \"2021\"\n".parse::<DocumentMut>()?;
+        let members = [
+            (
+                "api",
+                "[package]\nname = \"api\"\nedition.workspace = true\nrust-version = \"1.74\"\n",
+            ),
+            (
+                "cli",
+                "[package]\nname = \"cli\"\nedition = \"2021\"\nrust-version = \"1.74\"\n",
+            ),
+            (
+                "legacy",
+                "[package]\nname = \"legacy\"\nedition = \"2018\"\n",
+            ),
+        ]
+        .map(|(name, doc)| (name, doc.parse::<DocumentMut>().unwrap()));
+        let members: Vec<_> = members.iter().map(|(name, doc)| (*name, doc)).collect();
+
+        let editions = Editions::find(&root, &members);
+        assert_eq!(editions.edition.as_deref(), Some("2021"));
+        assert_eq!(editions.rust_version.as_deref(), Some("1.74"));
+        assert_eq!(editions.inconsistent(), 1);
+        assert_eq!(
+            editions.to_string(),
+            "| Member | Edition | rust-version |\n\
+             | --- | --- | --- |\n\
+             | `api` | 2021 (inherited) | 1.74 |\n\
+             | `cli` | 2021 | 1.74 |\n\
+             | `legacy` | **2018** | **-** |\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fix() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let root_manifest =
+            "[workspace]\nmembers = [\"old\", \"prefixed\"]\n\n[workspace.package]\nedition = \"2021\"\n";
+        fs::write(root.join("Cargo.toml"), root_manifest)?;
+        // Reserved prefixes like `z"…"` are an error from 2021 on
+        let sources = [
+            ("old", ""),
+            (
+                "prefixed",
+                "macro_rules! m { ($($t:tt)*) => {}; }\nm!(z\"x\");\n",
+            ),
+        ];
+        let mut members = Vec::new();
+        for (name, source) in sources {
+            write_file(root.join(name).join("src/lib.rs"), source)?;
+            let path = root.join(name).join("Cargo.toml");
+            let content = format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2018\"\n",
+                name
+            );
+            fs::write(&path, &content)?;
+            members.push((name.to_string(), Manifest::parse(&path, &content)?));
+        }
+
+        let root_doc = root_manifest.parse::<DocumentMut>()?;
+        let docs: Vec<_> = members
+            .iter()
+            .map(|(name, manifest)| (name.as_str(), &manifest.doc))
+            .collect();
+        let editions = Editions::find(&root_doc, &docs);
+        let cargo = CargoOptions {
+            offline: true,
+            ..Default::default()
+        };
+        let fixed = fix(
+            &root_doc,
+            &mut members,
+            &editions,
+            &cargo,
+            SymlinkPolicy::Refuse,
+        )?;
+
+        assert_eq!(fixed, vec!["old"]);
+        assert!(
+            fs::read_to_string(root.join("old/Cargo.toml"))?.contains("edition.workspace = true\n")
+        );
+        assert!(
+            fs::read_to_string(root.join("prefixed/Cargo.toml"))?.contains("edition = \"2018\"\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fix_restores_manifest_when_check_fails() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let root_manifest =
+            "[workspace]\nmembers = [\"old\"]\n\n[workspace.package]\nedition = \"2021\"\n";
+        write_file(root.join("Cargo.toml"), root_manifest)?;
+        write_file(root.join("old/src/lib.rs"), "")?;
+        let path = root.join("old/Cargo.toml");
+        let content = "[package]\nname = \"old\"\nversion = \"0.1.0\"\nedition = \"2018\"\n";
+        write_file(&path, content)?;
+        let mut members = vec![("old".to_string(), Manifest::parse(&path, content)?)];
+
+        let root_doc = root_manifest.parse::<DocumentMut>()?;
+        let editions = Editions::find(&root_doc, &[("old", &members[0].1.doc)]);
+        let cargo = CargoOptions {
+            cargo: Some(root.join("missing/cargo")),
+            ..Default::default()
+        };
+        let err = fix(
+            &root_doc,
+            &mut members,
+            &editions,
+            &cargo,
+            SymlinkPolicy::Refuse,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Failed to run"), "{:#}", err);
+        assert_eq!(fs::read_to_string(&path)?, content);
+        Ok(())
+    }
+}
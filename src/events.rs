@@ -0,0 +1,112 @@
+use log::warn;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::cli::{Opt, OutputFormat};
+
+/// Something that happened during a run, streamed to wrapper tools with
+/// `--format json-lines`
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// The members using a dependency were counted
+    DependencyAnalyzed {
+        dependency: &'a str,
+        members: Vec<&'a str>,
+        /// Whether it goes into `[workspace.dependencies]`
+        consolidated: bool,
+    },
+    /// A member's spec doesn't match the workspace entry
+    Conflict {
+        dependency: &'a str,
+        manifest: &'a Path,
+        section: &'a str,
+        message: String,
+    },
+    /// `--align-only` changed the requirement a member declares
+    Aligned {
+        dependency: &'a str,
+        manifest: &'a Path,
+        section: &'a str,
+        from: &'a str,
+        to: &'a str,
+    },
+    /// A member declares a dependency it doesn't use
+    Unused {
+        dependency: &'a str,
+        manifest: &'a Path,
+    },
+    FileWritten {
+        manifest: &'a Path,
+    },
+    /// The changes `--sandbox` made to a manifest, as a unified diff
+    Diff {
+        manifest: &'a Path,
+        diff: String,
+    },
+    /// Whether the workspace still resolves after `--sandbox` consolidated it
+    Verification {
+        resolves: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// Prints each event as one line of JSON as it happens, or nothing unless
+/// `--format json-lines` was passed
+#[derive(Debug, Default)]
+pub struct Events {
+    enabled: bool,
+}
+
+impl Events {
+    pub fn new(opt: &Opt) -> Self {
+        Self {
+            enabled: opt.format == Some(OutputFormat::JsonLines),
+        }
+    }
+
+    /// Whether events replace the text output of the run
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn emit(&self, event: Event) {
+        if !self.enabled {
+            return;
+        }
+        // stdout is line buffered, so every event reaches the reader at once
+        match serde_json::to_string(&event) {
+            Ok(line) => println!("{}", line),
+            Err(err) => warn!("Failed to serialize {:?}: {}", event, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_serialization() -> serde_json::Result<()> {
+        let event = Event::DependencyAnalyzed {
+            dependency: "serde",
+            members: vec!["a", "b"],
+            consolidated: true,
+        };
+        assert_eq!(
+            serde_json::to_string(&event)?,
+            r#"{"event":"dependency-analyzed","dependency":"serde","members":["a","b"],"consolidated":true}"#
+        );
+
+        let event = Event::Verification {
+            resolves: true,
+            error: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&event)?,
+            r#"{"event":"verification","resolves":true}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Default)]
+struct Decision {
+    consolidated: bool,
+    reasons: Vec<String>,
+}
+
+/// Records why each dependency was or wasn't consolidated, for `--explain`
+#[derive(Debug, Default)]
+pub struct Explain {
+    decisions: BTreeMap<String, Decision>,
+    members: Vec<String>,
+}
+
+impl Explain {
+    /// Records that `dep` is being consolidated and why
+    pub fn group(&mut self, dep: &str, reason: impl Into<String>) {
+        let decision = self.decisions.entry(dep.to_string()).or_default();
+        decision.consolidated = true;
+        decision.reasons.push(reason.into());
+    }
+
+    /// Records that `dep` is not consolidated and why
+    pub fn skip(&mut self, dep: &str, reason: impl Into<String>) {
+        let decision = self.decisions.entry(dep.to_string()).or_default();
+        decision.consolidated = false;
+        decision.reasons.push(reason.into());
+    }
+
+    /// Adds a detail to the decision about `dep` without changing it
+    pub fn note(&mut self, dep: &str, reason: impl Into<String>) {
+        self.decisions
+            .entry(dep.to_string())
+            .or_default()
+            .reasons
+            .push(reason.into());
+    }
+
+    /// Records why a member was left out of the analysis
+    pub fn skip_member(&mut self, member: &str, reason: impl Into<String>) {
+        self.members.push(format!("{}: {}", member, reason.into()));
+    }
+}
+
+impl fmt::Display for Explain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for member in &self.members {
+            writeln!(f, "member {}", member)?;
+        }
+
+        for (dep, decision) in &self.decisions {
+            let status = if decision.consolidated {
+                "consolidated"
+            } else {
+                "not consolidated"
+            };
+            writeln!(f, "{}: {}", dep, status)?;
+            for reason in &decision.reasons {
+                writeln!(f, "  - {}", reason)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let mut explain = Explain::default();
+        explain.skip_member("fuzz", "matches an ignore pattern");
+        explain.skip("rand", "used by 1 member (api), below the threshold of 2");
+        explain.group("serde", "used by 2 members (api, cli)");
+        explain.note("serde", "already in [workspace.dependencies]");
+
+        assert_eq!(
+            explain.to_string(),
+            "member fuzz: matches an ignore pattern\n\
+             rand: not consolidated\n  \
+             - used by 1 member (api), below the threshold of 2\n\
+             serde: consolidated\n  \
+             - used by 2 members (api, cli)\n  \
+             - already in [workspace.dependencies]\n"
+        );
+    }
+}
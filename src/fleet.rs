@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{Opt, ReportFormat};
+use crate::discover;
+use crate::report::{self, WorkspaceReport};
+
+/// The report of one repository of the fleet
+#[derive(Debug, Serialize)]
+struct Entry {
+    repository: PathBuf,
+    #[serde(flatten)]
+    report: Option<WorkspaceReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Entry {
+    fn unconsolidated(&self) -> usize {
+        self.report
+            .as_ref()
+            .map_or(0, |report| report.unconsolidated.len())
+    }
+
+    fn skew(&self) -> usize {
+        self.report.as_ref().map_or(0, |report| report.skew.len())
+    }
+}
+
+/// Analyzes every repository listed in the `--fleet` file, or every
+/// subdirectory of the `--fleet` directory, and prints one report ranking
+/// them by unconsolidated dependencies and version skew
+pub fn run(opt: &Opt, fleet: &Path) -> Result<()> {
+    let repositories = list_repositories(fleet)?;
+
+    let mut entries = Vec::new();
+    for repository in repositories {
+        info!("Analyzing '{}'", repository.display());
+        let manifest_path = repository.join("Cargo.toml");
+        let entry = match report::analyze(Some(&manifest_path), opt) {
+            Ok(report) => Entry {
+                repository,
+                report: Some(report),
+                error: None,
+            },
+            Err(err) => {
+                warn!("{}: {:#}", repository.display(), err);
+                Entry {
+                    repository,
+                    report: None,
+                    error: Some(format!("{:#}", err)),
+                }
+            }
+        };
+        entries.push(entry);
+    }
+
+    entries.sort_by(|a, b| {
+        b.unconsolidated()
+            .cmp(&a.unconsolidated())
+            .then(b.skew().cmp(&a.skew()))
+            .then(a.repository.cmp(&b.repository))
+    });
+
+    match opt.report_format {
+        ReportFormat::Markdown => print!("{}", render_markdown(&entries)),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+    }
+    Ok(())
+}
+
+/// Reads the repositories from a file with one path per line, resolved
+/// relative to the file, or takes every subdirectory of a directory
+fn list_repositories(fleet: &Path) -> Result<Vec<PathBuf>> {
+    if fleet.is_dir() {
+        let mut repositories = Vec::new();
+        let entries =
+            fs::read_dir(fleet).with_context(|| format!("Failed to read '{}'", fleet.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.join("Cargo.toml").is_file() {
+                repositories.push(path);
+            }
+        }
+        repositories.sort();
+        return Ok(repositories);
+    }
+
+    discover::read_path_list(fleet)
+}
+
+fn render_markdown(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    out.push_str("| Repository | Members | Unconsolidated | Version skew |\n");
+    out.push_str("|---|---|---|---|\n");
+    for entry in entries {
+        let row = match &entry.report {
+            Some(report) => format!(
+                "| {} | {} | {} | {} |\n",
+                entry.repository.display(),
+                report.members,
+                report.unconsolidated.len(),
+                report.skew.len()
+            ),
+            None => format!(
+                "| {} | error: {} | | |\n",
+                entry.repository.display(),
+                entry
+                    .error
+                    .as_deref()
+                    .and_then(|error| error.lines().next())
+                    .unwrap_or_default()
+            ),
+        };
+        out.push_str(&row);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_list_repositories() -> Result<()> {
+        let dir = TempDir::new()?;
+        for repository in ["api", "web"] {
+            write_file(dir.path().join(repository).join("Cargo.toml"), "")?;
+        }
+        fs::create_dir_all(dir.path().join("docs"))?;
+
+        assert_eq!(
+            list_repositories(dir.path())?,
+            vec![dir.path().join("api"), dir.path().join("web")]
+        );
+
+        let list = dir.path().join("fleet.txt");
+        fs::write(&list, "# checkouts\napi\n\n/srv/web\n")?;
+        assert_eq!(
+            list_repositories(&list)?,
+            vec![dir.path().join("api"), PathBuf::from("/srv/web")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let entries = [
+            Entry {
+                repository: PathBuf::from("api"),
+                report: Some(WorkspaceReport {
+                    members: 4,
+                    unconsolidated: vec!["serde".to_string()],
+                    ..Default::default()
+                }),
+                error: None,
+            },
+            Entry {
+                repository: PathBuf::from("broken"),
+                report: None,
+                error: Some("no Cargo.toml".to_string()),
+            },
+        ];
+
+        assert_eq!(
+            render_markdown(&entries),
+            "| Repository | Members | Unconsolidated | Version skew |\n\
+             |---|---|---|---|\n\
+             | api | 4 | 1 | 0 |\n\
+             | broken | error: no Cargo.toml | | |\n"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use anyhow::Result;
+use log::info;
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+use crate::cli::{FmtArgs, Opt};
+use crate::config::Config;
+use crate::dependency;
+use crate::diff;
+use crate::groups;
+use crate::lock;
+use crate::manifest::Manifest;
+use crate::workspace;
+
+/// Formats the workspace tables of the root manifest, or with `--check`
+/// fails if that would change anything
+pub fn run(opt: &Opt, args: &FmtArgs) -> Result<()> {
+    let mut cargo = opt.cargo.clone();
+    cargo.full_metadata |= opt.group_by_category;
+    let metadata = workspace::load_metadata(opt.manifest_path(), &cargo)?;
+    let _lock = lock::acquire(metadata.target_directory.as_std_path())?;
+    let root_manifest_path = metadata.workspace_root.join("Cargo.toml");
+    let mut root = Manifest::open(root_manifest_path.as_std_path())?;
+    let original = root.render();
+
+    let wrap_width = opt.wrap_width.unwrap_or(dependency::DEFAULT_WRAP_WIDTH);
+    format_workspace(&mut root.doc, opt.normalize, wrap_width);
+    if opt.group_by_category {
+        let config = Config::load(
+            opt.config.as_deref(),
+            metadata.workspace_root.as_std_path(),
+            &root.doc,
+        )?;
+        let groups = groups::assign(&root.doc, &metadata, &config);
+        groups::apply(&mut root.doc, &groups);
+    }
+
+    let formatted = root.render();
+    if formatted == original {
+        println!("'{}' is formatted.", root.path().display());
+        return Ok(());
+    }
+    if args.check {
+        print!("{}", diff::unified_diff(root.path(), &original, &formatted));
+        anyhow::bail!("'{}' isn't formatted", root.path().display());
+    }
+    info!("Formatting '{}'", root.path().display());
+    root.write_rendered(&formatted, opt.symlinks)
+}
+
+/// Sorts `[workspace.dependencies]`, writes every entry as an inline table
+/// or plain version, or as an expanded table if it's wider than
+/// `wrap_width`, and gives all values of the workspace tables the same
+/// quoting and array style. `normalize` also normalizes the requirements,
+/// like `--normalize` does for new entries
+fn format_workspace(doc: &mut DocumentMut, normalize: bool, wrap_width: usize) {
+    let Some(workspace) = doc.get_mut("workspace").and_then(Item::as_table_mut) else {
+        return;
+    };
+
+    for (_, item) in workspace.iter_mut() {
+        match item {
+            Item::Value(value) => format_value(value),
+            Item::Table(table) => {
+                for (_, item) in table.iter_mut() {
+                    if let Some(value) = item.as_value_mut() {
+                        format_value(value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let Some(ws_deps) = workspace
+        .get_mut("dependencies")
+        .and_then(Item::as_table_mut)
+    else {
+        return;
+    };
+    let deps: Vec<String> = ws_deps.iter().map(|(dep, _)| dep.to_string()).collect();
+    for dep in deps {
+        let Some(item) = ws_deps.get_mut(&dep) else {
+            continue;
+        };
+        // `[workspace.dependencies.foo]` becomes `foo = { ... }`, the comments
+        // above its header move above the entry
+        if let Item::Table(table) = item {
+            let comments: String = table
+                .decor()
+                .prefix()
+                .and_then(|prefix| prefix.as_str())
+                .unwrap_or_default()
+                .lines()
+                .filter(|line| line.trim_start().starts_with('#'))
+                .map(|line| format!("{}\n", line))
+                .collect();
+            let mut inline = table.clone().into_inline_table();
+            inline.fmt();
+            *item = Item::Value(Value::InlineTable(inline));
+            if let Some(mut key) = ws_deps.key_mut(&dep) {
+                key.fmt();
+                key.leaf_decor_mut().set_prefix(comments);
+            }
+        }
+        let Some(item) = ws_deps.get_mut(&dep) else {
+            continue;
+        };
+        if let Some(value) = item.as_value_mut() {
+            format_value(value);
+        }
+        if normalize {
+            dependency::normalize_spec(item);
+        }
+        dependency::wrap_spec(&dep, item, wrap_width);
+        if item.is_table() {
+            let comments = comments_of(ws_deps, &dep);
+            if let Some(Item::Table(table)) = ws_deps.get_mut(&dep) {
+                table.decor_mut().set_prefix(comments);
+            }
+        }
+    }
+    ws_deps.sort_values();
+    ws_deps.set_implicit(false);
+}
+
+/// The comments above the key of `dep`, to go above its header once it's
+/// expanded into a table
+fn comments_of(ws_deps: &mut Table, dep: &str) -> String {
+    let Some(mut key) = ws_deps.key_mut(dep) else {
+        return String::new();
+    };
+    let comments = key
+        .leaf_decor()
+        .prefix()
+        .and_then(|prefix| prefix.as_str())
+        .unwrap_or_default()
+        .to_string();
+    // A header has no room for the spaces around the key
+    key.leaf_decor_mut().clear();
+    format!("\n{}", comments)
+}
+
+/// Rewrites strings as basic strings and lays out arrays and inline tables
+/// the same way, keeping comments. Arrays spanning several lines keep one
+/// element per line
+fn format_value(value: &mut Value) {
+    match value {
+        Value::String(string) => {
+            let decor = string.decor().clone();
+            *value = Value::from(string.value().as_str());
+            *value.decor_mut() = decor;
+        }
+        Value::Array(array) => {
+            let multiline = dependency::is_multiline(array);
+            for element in array.iter_mut() {
+                format_value(element);
+            }
+            if multiline {
+                dependency::set_multiline(array);
+            } else {
+                array.fmt();
+            }
+        }
+        Value::InlineTable(table) => {
+            for (_, value) in table.iter_mut() {
+                format_value(value);
+            }
+            table.fmt();
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_workspace() -> Result<()> {
+        let mut doc = r#"[workspace]
+members = [
+  'a',
+    "b"
+]
+
+[workspace.package]
+edition = '2021'
+
+[workspace.dependencies]
+tokio = {version='1',features=[ "full",'macros' ]}
+# serialization
+serde = "1"
+
+
+# errors
+[workspace.dependencies.anyhow]
+version = "^1.0"
+"#
+        .parse::<DocumentMut>()?;
+
+        format_workspace(&mut doc, true, 100);
+        assert_eq!(
+            doc.to_string(),
+            r#"[workspace]
+members = [
+    "a",
+    "b",
+]
+
+[workspace.package]
+edition = "2021"
+
+[workspace.dependencies]
+# errors
+anyhow = "1.0.0"
+# serialization
+serde = "1.0.0"
+tokio = { version = "1.0.0", features = ["full", "macros"] }
+"#
+        );
+
+        // Entries wider than the wrap width are expanded
+        format_workspace(&mut doc, true, 40);
+        assert!(doc.to_string().ends_with(
+            r#"serde = "1.0.0"
+
+[workspace.dependencies.tokio]
+version = "1.0.0"
+features = [
+    "full",
+    "macros",
+]
+"#
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_workspace_keeps_comments() -> Result<()> {
+        let mut doc = r#"[workspace]
+members = [
+  # core crates
+  'a',
+  # "b",
+  "c" # the cli
+]
+"#
+        .parse::<DocumentMut>()?;
+
+        format_workspace(&mut doc, false, 100);
+        assert_eq!(
+            doc.to_string(),
+            r#"[workspace]
+members = [
+    # core crates
+    "a",
+    # "b",
+    "c", # the cli
+]
+"#
+        );
+        Ok(())
+    }
+}
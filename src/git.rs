@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Runs git in `dir` and returns what it printed
+pub fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the manifests below `workspace_root` which changed since `rev`,
+/// whether committed or not, including new ones git doesn't track yet
+pub fn changed_manifests(workspace_root: &Path, rev: &str) -> Result<HashSet<PathBuf>> {
+    let changed = git(
+        workspace_root,
+        &["diff", "--name-only", "--relative", rev, "--"],
+    )?;
+    let untracked = git(
+        workspace_root,
+        &["ls-files", "--others", "--exclude-standard"],
+    )?;
+    Ok(manifests(
+        workspace_root,
+        changed.lines().chain(untracked.lines()),
+    ))
+}
+
+fn manifests<'a>(workspace_root: &Path, paths: impl Iterator<Item = &'a str>) -> HashSet<PathBuf> {
+    paths
+        .map(Path::new)
+        .filter(|path| path.file_name().is_some_and(|name| name == "Cargo.toml"))
+        .map(|path| workspace_root.join(path))
+        .collect()
+}
+
+/// Returns the manifests staged for the next commit below `workspace_root`,
+/// relative to it. Deleted ones are left out
+pub fn staged_manifests(workspace_root: &Path) -> Result<Vec<PathBuf>> {
+    let staged = git(
+        workspace_root,
+        &[
+            "diff",
+            "--cached",
+            "--name-only",
+            "--relative",
+            "--diff-filter=ACMR",
+        ],
+    )?;
+    let mut manifests: Vec<_> = manifests(Path::new(""), staged.lines())
+        .into_iter()
+        .collect();
+    manifests.sort();
+    Ok(manifests)
+}
+
+/// Returns the manifests below `dir` at `rev`, relative to it
+pub fn manifests_at(dir: &Path, rev: &str) -> Result<Vec<PathBuf>> {
+    let files = git(dir, &["ls-tree", "-r", "--name-only", rev, "--", "."])?;
+    let mut manifests: Vec<_> = manifests(Path::new(""), files.lines())
+        .into_iter()
+        .collect();
+    manifests.sort();
+    Ok(manifests)
+}
+
+/// Returns the content of `path`, relative to `dir`, in the index
+pub fn staged_file(dir: &Path, path: &Path) -> Option<String> {
+    file_at(dir, "", path)
+}
+
+/// Returns the content of `path`, relative to `dir`, at `rev`, or `None`
+/// if it didn't exist there
+pub fn file_at(dir: &Path, rev: &str, path: &Path) -> Option<String> {
+    git(dir, &["show", &format!("{}:./{}", rev, path.display())]).ok()
+}
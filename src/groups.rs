@@ -0,0 +1,155 @@
+use cargo_metadata::Metadata;
+use std::collections::{BTreeMap, HashSet};
+use toml_edit::{DocumentMut, Item};
+
+use crate::config::Config;
+
+/// The group of workspace dependencies declared with a `path`
+const INTERNAL: &str = "internal crates";
+/// The group of dependencies neither the configuration nor crates.io place
+const OTHER: &str = "other";
+
+/// Picks the group each workspace dependency goes into: the one the
+/// configuration maps it to, `internal crates` for path dependencies, or
+/// else the first crates.io category of the package it resolved to
+pub fn assign(doc: &DocumentMut, metadata: &Metadata, config: &Config) -> BTreeMap<String, String> {
+    let Some(ws_deps) = workspace_dependencies(doc) else {
+        return BTreeMap::new();
+    };
+
+    ws_deps
+        .iter()
+        .map(|(dep, item)| {
+            let group = if let Some(group) = config.group_of(dep) {
+                group.to_string()
+            } else if item.get("path").is_some() {
+                INTERNAL.to_string()
+            } else {
+                let package = item.get("package").and_then(Item::as_str).unwrap_or(dep);
+                category(metadata, package).unwrap_or_else(|| OTHER.to_string())
+            };
+            (dep.to_string(), group)
+        })
+        .collect()
+}
+
+/// The first crates.io category of a dependency, without its subcategory
+fn category(metadata: &Metadata, package: &str) -> Option<String> {
+    metadata
+        .packages
+        .iter()
+        .filter(|candidate| candidate.name == package && candidate.source.is_some())
+        .find_map(|candidate| candidate.categories.first())
+        .map(|category| category.split("::").next().unwrap_or(category).to_string())
+}
+
+/// Sorts `[workspace.dependencies]` by group and name and puts a `# <group>`
+/// comment above the first entry of every group. Comments of an earlier run
+/// are replaced, any other comments are kept
+pub fn apply(doc: &mut DocumentMut, groups: &BTreeMap<String, String>) {
+    let Some(ws_deps) = doc
+        .get_mut("workspace")
+        .and_then(|ws| ws.get_mut("dependencies"))
+        .and_then(Item::as_table_mut)
+    else {
+        return;
+    };
+
+    let group_of = |dep: &str| groups.get(dep).map(String::as_str).unwrap_or(OTHER);
+    // `other` goes last, the rest alphabetically
+    let rank = |dep: &str| {
+        let group = group_of(dep);
+        (group == OTHER, group.to_string())
+    };
+    ws_deps.sort_values_by(|dep1, _, dep2, _| {
+        (rank(dep1.get()), dep1.get()).cmp(&(rank(dep2.get()), dep2.get()))
+    });
+
+    let headings: HashSet<String> = groups
+        .values()
+        .map(String::as_str)
+        .chain([INTERNAL, OTHER])
+        .map(heading)
+        .collect();
+    let deps: Vec<String> = ws_deps
+        .iter()
+        .filter(|(_, item)| item.is_value())
+        .map(|(dep, _)| dep.to_string())
+        .collect();
+    let mut previous = None;
+    for dep in &deps {
+        let group = group_of(dep);
+        let Some(mut key) = ws_deps.key_mut(dep) else {
+            continue;
+        };
+        let decor = key.leaf_decor_mut();
+        let kept: String = decor
+            .prefix()
+            .and_then(|prefix| prefix.as_str())
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !headings.contains(line.trim()))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let prefix = match previous {
+            Some(previous) if previous == group => kept,
+            Some(_) => format!("\n{}\n{}", heading(group), kept),
+            None => format!("{}\n{}", heading(group), kept),
+        };
+        decor.set_prefix(prefix);
+        previous = Some(group);
+    }
+}
+
+fn heading(group: &str) -> String {
+    format!("# {}", group)
+}
+
+fn workspace_dependencies(doc: &DocumentMut) -> Option<&toml_edit::Table> {
+    doc.get("workspace")
+        .and_then(|ws| ws.get("dependencies"))
+        .and_then(Item::as_table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() -> anyhow::Result<()> {
+        let mut doc = r#"[workspace.dependencies]
+# keep this
+tokio = "1"
+serde = "1"
+core = { path = "core" }
+rand = "0.8"
+"#
+        .parse::<DocumentMut>()?;
+        let groups = BTreeMap::from([
+            ("tokio".to_string(), "asynchronous".to_string()),
+            ("serde".to_string(), "encoding".to_string()),
+            ("core".to_string(), INTERNAL.to_string()),
+        ]);
+        apply(&mut doc, &groups);
+        let expected = r#"[workspace.dependencies]
+# asynchronous
+# keep this
+tokio = "1"
+
+# encoding
+serde = "1"
+
+# internal crates
+core = { path = "core" }
+
+# other
+rand = "0.8"
+"#;
+        assert_eq!(doc.to_string(), expected);
+
+        // Running again changes nothing
+        apply(&mut doc, &groups);
+        assert_eq!(doc.to_string(), expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Consolidation of manifests held in memory, e.g. by build systems or
+//! code-mod services. Nothing is read from or written to the file system and
+//! cargo isn't run, the members are described by the caller instead. The
+//! manifests go through the same engine as a regular run, configured by
+//! `[workspace.metadata.consolidate]` of the root manifest
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::cli::Opt;
+pub use crate::cli::VersionStrategy;
+use crate::config::Config;
+use crate::manifest::{Manifest, Manifests};
+use crate::metadata;
+use crate::state::State;
+use crate::workspace::{self, Selection};
+
+/// A member of the workspace, like a package reported by `cargo metadata`
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// Its package name, which takes precedence over the one in its manifest
+    pub name: String,
+    /// The key of its manifest in the manifests passed to [`consolidate`]
+    pub manifest_path: PathBuf,
+}
+
+/// The settings of an in-memory consolidation. Those left unset come from
+/// `[workspace.metadata.consolidate]` of the root manifest
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// How many members have to declare a dependency before it moves into
+    /// `[workspace.dependencies]`
+    pub min_members: Option<usize>,
+    /// Which of the members' specs a new workspace entry takes
+    pub version_strategy: Option<VersionStrategy>,
+}
+
+/// Consolidates the dependencies of `members`. `manifests` maps paths to the
+/// content of the manifests, including the root manifest at
+/// `root_manifest_path`. Returns the manifests which changed, with their new
+/// content
+pub fn consolidate(
+    manifests: &BTreeMap<PathBuf, String>,
+    root_manifest_path: &Path,
+    members: &[Member],
+    options: &Options,
+) -> Result<BTreeMap<PathBuf, String>> {
+    let mut memory = Manifests::in_memory(manifests.clone());
+    let root = memory.read(root_manifest_path)?;
+    let docs = members
+        .iter()
+        .map(|member| memory.read(&member.manifest_path))
+        .collect::<Result<Vec<Manifest>>>()?;
+    let root_dir = root_manifest_path.parent().unwrap_or(Path::new(""));
+    let member_docs: Vec<(&Path, _)> = docs
+        .iter()
+        .map(|manifest| (manifest.path(), &manifest.doc))
+        .collect();
+    let mut metadata = metadata::from_docs(root_dir, &root.doc, &member_docs)
+        .context("Failed to describe the members")?;
+    for (package, member) in metadata.packages.iter_mut().zip(members) {
+        package.name = member.name.clone();
+    }
+
+    let mut config = Config::from_workspace_metadata(&root.doc)?;
+    config.min_members = options.min_members.or(config.min_members);
+    let mut opt = Opt::parse_from(["cargo-consolidate"]);
+    opt.no_progress = true;
+    opt.embedded = true;
+    opt.allow_external = true;
+    opt.version_strategy = options.version_strategy;
+
+    workspace::consolidate_workspace(
+        &opt,
+        &Selection::default(),
+        &metadata,
+        config,
+        &State::default(),
+        root,
+        &mut memory,
+    )?;
+
+    Ok(memory
+        .into_memory()
+        .into_iter()
+        .filter(|(path, content)| manifests.get(path) != Some(content))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consolidate() -> Result<()> {
+        let manifests = BTreeMap::from([
+            (
+                PathBuf::from("Cargo.toml"),
+                "[workspace]\nmembers = [\"api\", \"cli\"]\n\n[workspace.dependencies]\nlog = \"0.4.20\"\n"
+                    .to_string(),
+            ),
+            (
+                PathBuf::from("api/Cargo.toml"),
+                "[package]\nname = \"api\"\n\n[dependencies]\nserde = { version = \"1.0.100\", features = [\"derive\"] }\nlog = \"0.4.20\"\nrand = \"0.8\"\n"
+                    .to_string(),
+            ),
+            (
+                PathBuf::from("cli/Cargo.toml"),
+                "[package]\nname = \"cli\"\n\n[dependencies]\nserde = \"1.0.200\"\nlog = \"0.3\"\n"
+                    .to_string(),
+            ),
+        ]);
+        let members = ["api", "cli"].map(|name| Member {
+            name: name.to_string(),
+            manifest_path: PathBuf::from(name).join("Cargo.toml"),
+        });
+        let options = Options {
+            version_strategy: Some(VersionStrategy::Highest),
+            ..Default::default()
+        };
+
+        let edited = consolidate(&manifests, Path::new("Cargo.toml"), &members, &options)?;
+        assert_eq!(
+            edited[Path::new("Cargo.toml")],
+            "[workspace]\nmembers = [\"api\", \"cli\"]\n\n[workspace.dependencies]\nlog = \"0.4.20\"\nserde = \"1.0.200\"\n"
+        );
+        assert_eq!(
+            edited[Path::new("api/Cargo.toml")],
+            "[package]\nname = \"api\"\n\n[dependencies]\nserde = { workspace = true, features = [\"derive\"] }\nlog = \"0.4.20\"\nrand = \"0.8\"\n"
+        );
+        // The members need incompatible versions of log, so it's left alone
+        assert_eq!(
+            edited[Path::new("cli/Cargo.toml")],
+            "[package]\nname = \"cli\"\n\n[dependencies]\nserde = { workspace = true }\nlog = \"0.3\"\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_uses_config() -> Result<()> {
+        let manifests = BTreeMap::from([
+            (
+                PathBuf::from("Cargo.toml"),
+                "[workspace]\nmembers = [\"api\", \"cli\"]\n\n[workspace.metadata.consolidate.pins]\nserde = \"1.0.210\"\n"
+                    .to_string(),
+            ),
+            (
+                PathBuf::from("api/Cargo.toml"),
+                "[package]\nname = \"api\"\n\n[dependencies]\nserde = \"1.0.100\"\n".to_string(),
+            ),
+            (
+                PathBuf::from("cli/Cargo.toml"),
+                "[package]\nname = \"cli\"\n\n[dependencies]\nserde = \"1.0.200\"\n".to_string(),
+            ),
+        ]);
+        let members = ["api", "cli"].map(|name| Member {
+            name: name.to_string(),
+            manifest_path: PathBuf::from(name).join("Cargo.toml"),
+        });
+
+        let edited = consolidate(
+            &manifests,
+            Path::new("Cargo.toml"),
+            &members,
+            &Options::default(),
+        )?;
+        assert!(edited[Path::new("Cargo.toml")]
+            .ends_with("[workspace.dependencies]\nserde = \"1.0.210\"\n"));
+        assert_eq!(
+            edited[Path::new("api/Cargo.toml")],
+            "[package]\nname = \"api\"\n\n[dependencies]\nserde = { workspace = true }\n"
+        );
+        Ok(())
+    }
+}
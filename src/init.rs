@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use glob::glob;
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, Item, Table, Value};
+
+use crate::cli::{InitArgs, Opt};
+use crate::discover;
+use crate::lock;
+use crate::manifest::Manifest;
+use crate::metadata;
+use crate::workspace;
+
+/// Turns the directory of `--manifest-path`, or the current directory, into
+/// a workspace of the crates below it and consolidates their dependencies
+pub fn run(opt: &Opt, args: &InitArgs) -> Result<()> {
+    let root_manifest_path = match opt.manifest_path() {
+        Some(path) if path.is_dir() => path.join("Cargo.toml"),
+        Some(path) => path.to_path_buf(),
+        None => std::env::current_dir()
+            .context("Failed to get the current directory")?
+            .join("Cargo.toml"),
+    };
+    let root_dir = root_manifest_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    let lock = lock::acquire(&metadata::target_directory(&root_dir)?)?;
+
+    // An existing root package becomes part of the new workspace
+    let mut root = if root_manifest_path.exists() {
+        let root = Manifest::open(&root_manifest_path)?;
+        if root.doc.contains_key("workspace") {
+            anyhow::bail!(
+                "'{}' already declares a [workspace]",
+                root_manifest_path.display()
+            );
+        }
+        root
+    } else {
+        Manifest::parse(&root_manifest_path, "")?
+    };
+
+    let members = if args.members.is_empty() {
+        find_members(&root_dir)?
+    } else {
+        check_member_globs(&root_dir, &args.members)?;
+        args.members.clone()
+    };
+    if members.is_empty() {
+        anyhow::bail!("No crates found below '{}'", root_dir.display());
+    }
+
+    let mut workspace = Table::new();
+    workspace.insert("resolver", toml_edit::value(args.resolver.as_str()));
+    let members: Array = members
+        .iter()
+        .map(|member| Value::from(member.as_str()))
+        .collect();
+    workspace.insert("members", toml_edit::value(members));
+    root.doc.insert("workspace", Item::Table(workspace));
+
+    info!("Writing [workspace] to '{}'", root_manifest_path.display());
+    root.write(opt.symlinks)?;
+
+    let mut consolidate_opt = opt.clone();
+    consolidate_opt.command = None;
+    consolidate_opt.manifest_path = vec![root_manifest_path];
+    workspace::consolidate_new_workspace(&consolidate_opt, lock)
+}
+
+/// Returns the directories of all crates below `root_dir`, relative to it
+fn find_members(root_dir: &Path) -> Result<Vec<String>> {
+    let manifests = discover::find_manifests(root_dir, |doc| {
+        doc.contains_key("package") && !doc.contains_key("workspace")
+    })?;
+
+    let mut members = Vec::new();
+    for manifest_path in manifests {
+        let Some(dir) = manifest_path.parent() else {
+            continue;
+        };
+        let relative = dir.strip_prefix(root_dir).unwrap_or(dir);
+        // The root package is a member implicitly
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        members.push(relative.to_string_lossy().replace('\\', "/"));
+    }
+    Ok(members)
+}
+
+/// Makes sure every pattern given with `--members` matches a crate, cargo
+/// would fail on them later anyway
+fn check_member_globs(root_dir: &Path, patterns: &[String]) -> Result<()> {
+    for pattern in patterns {
+        let full = root_dir.join(pattern);
+        let matches: Vec<PathBuf> = glob(&full.to_string_lossy())
+            .with_context(|| format!("Invalid members pattern '{}'", pattern))?
+            .filter_map(Result::ok)
+            .filter(|dir| fs::metadata(dir.join("Cargo.toml")).is_ok())
+            .collect();
+        if matches.is_empty() {
+            anyhow::bail!("'{}' doesn't match any crate", pattern);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_init() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+
+        write_file(
+            root.join("shared/Cargo.toml"),
+            "[package]\nname = \"shared\"\nversion = \"0.1.0\"\n",
+        )?;
+        for name in ["a", "b"] {
+            write_file(
+                root.join(format!("apps/{}/Cargo.toml", name)),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\nshared = {{ path = \"../../shared\" }}\n",
+                    name
+                ),
+            )?;
+        }
+        for dir in ["shared", "apps/a", "apps/b"] {
+            write_file(root.join(format!("{}/src/lib.rs", dir)), "")?;
+        }
+
+        assert_eq!(find_members(root)?, vec!["apps/a", "apps/b", "shared"]);
+        assert!(check_member_globs(root, &["tools/*".to_string()]).is_err());
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "init",
+            "--manifest-path",
+            root.to_str().unwrap(),
+            "--members",
+            "apps/*",
+            "--members",
+            "shared",
+        ]);
+        let Some(crate::cli::Command::Init(args)) = &opt.command else {
+            unreachable!()
+        };
+        run(&opt, args)?;
+
+        let manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(manifest
+            .starts_with("[workspace]\nresolver = \"2\"\nmembers = [\"apps/*\", \"shared\"]\n"));
+        let member = fs::read_to_string(root.join("apps/a/Cargo.toml"))?;
+        assert!(member.contains("shared = { workspace = true }"));
+        Ok(())
+    }
+}
@@ -0,0 +1,337 @@
+use anyhow::{Context, Result};
+use cargo_metadata::Package;
+use glob::Pattern;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use toml_edit::{Array, DocumentMut, Item, Table};
+
+use crate::cli::{InitConfigArgs, Opt, VersionStrategy};
+use crate::config::CONFIG_FILE_NAME;
+use crate::dependency;
+use crate::lock;
+use crate::manifest::Manifest;
+use crate::workspace;
+
+/// Member directories which usually hold crates that shouldn't be counted
+const SIDE_CRATE_DIRS: [&str; 3] = ["benches", "examples", "fuzz"];
+
+/// What the wizard shows about the workspace before asking
+#[derive(Debug, Default)]
+struct Inspection {
+    members: usize,
+    /// How many members use each dependency
+    usage: BTreeMap<String, usize>,
+    /// How many dependencies the members require in differing versions
+    diverging: usize,
+    /// Ignore patterns for members which look like examples, benchmarks or
+    /// fuzzing crates
+    suggested_ignores: Vec<String>,
+}
+
+/// Asks questions on one stream and reads the answers from another
+struct Prompt<R, W> {
+    input: R,
+    output: W,
+}
+
+/// Asks about the settings of `consolidate.toml`, showing what they mean for
+/// the workspace, and writes the answers to the file or with `--metadata` to
+/// `[workspace.metadata.consolidate]`
+pub fn run(opt: &Opt, args: &InitConfigArgs) -> Result<()> {
+    let metadata = workspace::load_metadata(opt.manifest_path(), &opt.cargo)?;
+    let _lock = lock::acquire(metadata.target_directory.as_std_path())?;
+    let workspace_root = metadata.workspace_root.as_std_path();
+    let root_manifest_path = workspace_root.join("Cargo.toml");
+    let config_path = workspace_root.join(CONFIG_FILE_NAME);
+    let mut root = Manifest::open(&root_manifest_path)?;
+    let in_metadata = root
+        .doc
+        .get("workspace")
+        .and_then(|workspace| workspace.get("metadata"))
+        .and_then(|metadata| metadata.get("consolidate"))
+        .is_some();
+    if !args.force {
+        if args.metadata && in_metadata {
+            anyhow::bail!(
+                "'{}' already has a [workspace.metadata.consolidate] table, pass --force to replace it",
+                root_manifest_path.display()
+            );
+        }
+        if !args.metadata && config_path.exists() {
+            anyhow::bail!(
+                "'{}' already exists, pass --force to replace it",
+                config_path.display()
+            );
+        }
+    }
+
+    let packages = workspace::workspace_packages(&metadata)?;
+    let inspection = inspect(&packages, workspace_root);
+    let mut prompt = Prompt {
+        input: io::stdin().lock(),
+        output: io::stdout(),
+    };
+    let config = ask_config(&inspection, &mut prompt)?;
+
+    if args.metadata {
+        let workspace = root
+            .doc
+            .get_mut("workspace")
+            .and_then(Item::as_table_mut)
+            .context("The root manifest has no [workspace] table")?;
+        let metadata = workspace
+            .entry("metadata")
+            .or_insert_with(|| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_mut()
+            .context("workspace.metadata isn't a table")?;
+        let mut table = Table::new();
+        for (key, item) in config.iter() {
+            table.insert(key, item.clone());
+        }
+        metadata.insert("consolidate", Item::Table(table));
+        root.write(opt.symlinks)?;
+        println!(
+            "Wrote [workspace.metadata.consolidate] to '{}'.",
+            root.path().display()
+        );
+    } else {
+        fs::write(&config_path, config.to_string())
+            .with_context(|| format!("Failed to write '{}'", config_path.display()))?;
+        println!("Wrote '{}'.", config_path.display());
+    }
+    Ok(())
+}
+
+fn inspect(packages: &[&Package], workspace_root: &std::path::Path) -> Inspection {
+    let mut inspection = Inspection {
+        members: packages.len(),
+        ..Default::default()
+    };
+    let mut requirements: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    let mut ignores = BTreeSet::new();
+    for package in packages {
+        for dep in dependency::collect_dependencies(package).into_keys() {
+            *inspection.usage.entry(dep).or_default() += 1;
+        }
+        for dep in &package.dependencies {
+            requirements
+                .entry(dep.name.as_str())
+                .or_default()
+                .insert(dep.req.to_string());
+        }
+
+        let relative_dir = package
+            .manifest_path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(workspace_root).ok());
+        let side_dir = relative_dir
+            .and_then(|dir| dir.components().next())
+            .map(|component| component.as_str())
+            .filter(|dir| SIDE_CRATE_DIRS.contains(dir));
+        if let Some(dir) = side_dir {
+            ignores.insert(format!("{}/*", dir));
+        }
+        if package.name.ends_with("-workspace-hack") {
+            ignores.insert("*-workspace-hack".to_string());
+        }
+    }
+    inspection.diverging = requirements
+        .values()
+        .filter(|requirements| requirements.len() > 1)
+        .count();
+    inspection.suggested_ignores = ignores.into_iter().collect();
+    inspection
+}
+
+fn ask_config<R: BufRead, W: Write>(
+    inspection: &Inspection,
+    prompt: &mut Prompt<R, W>,
+) -> Result<DocumentMut> {
+    let mut config = DocumentMut::new();
+    let used_by = |count: usize| {
+        inspection
+            .usage
+            .values()
+            .filter(|users| **users >= count)
+            .count()
+    };
+
+    prompt.say(&format!(
+        "The workspace has {} member(s). Dependencies used by at least",
+        inspection.members
+    ))?;
+    for count in 1..=4 {
+        prompt.say(&format!("  {} member(s): {}", count, used_by(count)))?;
+    }
+    let min_members = loop {
+        let answer = prompt.ask(
+            "How many members have to use a dependency before it's consolidated?",
+            "2",
+        )?;
+        match answer.parse::<usize>() {
+            Ok(count) if count > 0 => break count,
+            _ => prompt.say("Please enter a number of at least 1.")?,
+        }
+    };
+    if min_members != 2 {
+        config.insert("min-members", toml_edit::value(min_members as i64));
+    }
+
+    if !inspection.suggested_ignores.is_empty() {
+        prompt.say(&format!(
+            "Some members look like they shouldn't be counted: {}",
+            inspection.suggested_ignores.join(", ")
+        ))?;
+    }
+    let suggested = if inspection.suggested_ignores.is_empty() {
+        "none".to_string()
+    } else {
+        inspection.suggested_ignores.join(", ")
+    };
+    let ignore = loop {
+        let answer = prompt.ask(
+            "Members to ignore, as comma separated names or directory globs, or none",
+            &suggested,
+        )?;
+        let patterns: Vec<&str> = answer
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty() && *pattern != "none")
+            .collect();
+        match patterns
+            .iter()
+            .find(|pattern| Pattern::new(pattern).is_err())
+        {
+            Some(invalid) => prompt.say(&format!("'{}' isn't a valid glob.", invalid))?,
+            None => break patterns.into_iter().collect::<Array>(),
+        }
+    };
+    if !ignore.is_empty() {
+        config.insert("ignore", toml_edit::value(ignore));
+    }
+
+    prompt.say(&format!(
+        "{} dependencies are required in differing versions. New workspace entries can take the requirement of the first member (first), the one with the highest (highest) or lowest (lowest) minimum version",
+        inspection.diverging
+    ))?;
+    let default = if inspection.diverging > 0 {
+        "highest"
+    } else {
+        "first"
+    };
+    let strategy = loop {
+        let answer = prompt.ask("Version strategy", default)?;
+        match answer.as_str() {
+            "first" => break VersionStrategy::First,
+            "highest" => break VersionStrategy::Highest,
+            "lowest" => break VersionStrategy::Lowest,
+            _ => prompt.say("Please answer first, highest or lowest.")?,
+        }
+    };
+    if strategy != VersionStrategy::First {
+        config.insert("version-strategy", toml_edit::value(answer_of(strategy)));
+    }
+
+    if prompt.confirm(
+        "Normalize new workspace entries, e.g. `^1.2` to `1.2.0`?",
+        false,
+    )? {
+        config.insert("normalize", toml_edit::value(true));
+    }
+    if prompt.confirm(
+        "Annotate new workspace entries with the members using them?",
+        false,
+    )? {
+        config.insert("used-by-comments", toml_edit::value(true));
+    }
+    Ok(config)
+}
+
+fn answer_of(strategy: VersionStrategy) -> &'static str {
+    match strategy {
+        VersionStrategy::First => "first",
+        VersionStrategy::Highest => "highest",
+        VersionStrategy::Lowest => "lowest",
+    }
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    fn say(&mut self, line: &str) -> Result<()> {
+        writeln!(self.output, "{}", line).context("Failed to write the question")
+    }
+
+    /// Returns the trimmed answer, or `default` for an empty one and once the
+    /// input is exhausted
+    fn ask(&mut self, question: &str, default: &str) -> Result<String> {
+        write!(self.output, "{} [{}]: ", question, default)
+            .and_then(|_| self.output.flush())
+            .context("Failed to write the question")?;
+        let mut answer = String::new();
+        self.input
+            .read_line(&mut answer)
+            .context("Failed to read the answer")?;
+        let answer = answer.trim();
+        Ok(if answer.is_empty() {
+            default.to_string()
+        } else {
+            answer.to_string()
+        })
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+        let hint = if default { "Y/n" } else { "y/N" };
+        loop {
+            let answer = self.ask(question, hint)?;
+            match answer.to_lowercase().as_str() {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ if answer == hint => return Ok(default),
+                _ => self.say("Please answer y or n.")?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_ask_config() -> Result<()> {
+        let inspection = Inspection {
+            members: 3,
+            usage: BTreeMap::from([
+                ("serde".to_string(), 3),
+                ("tokio".to_string(), 2),
+                ("rand".to_string(), 1),
+            ]),
+            diverging: 1,
+            suggested_ignores: vec!["fuzz/*".to_string()],
+        };
+        // Invalid answers are asked again, empty ones take the default
+        let mut prompt = Prompt {
+            input: "0\n3\nfuzz/*, [\n\n\nmaybe\ny\n".as_bytes(),
+            output: Vec::new(),
+        };
+
+        let config = ask_config(&inspection, &mut prompt)?;
+        assert_eq!(
+            config.to_string(),
+            "min-members = 3\nignore = [\"fuzz/*\"]\nversion-strategy = \"highest\"\nnormalize = true\n"
+        );
+        let output = String::from_utf8(prompt.output)?;
+        assert!(output.contains("  2 member(s): 2\n"));
+        assert!(output.contains("'[' isn't a valid glob."));
+
+        let config: Config = toml_edit::de::from_str(&config.to_string())?;
+        assert_eq!(config.min_members(), 3);
+        assert_eq!(config.version_strategy, Some(VersionStrategy::Highest));
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! Consolidates the dependencies of a Rust workspace into
+//! `[workspace.dependencies]`. The binary runs [`run`], tools holding
+//! manifests in memory can use [`in_memory`] instead
+
+use anyhow::Result;
+
+mod baseline;
+mod cache;
+mod catalogue;
+mod changes;
+mod cli;
+mod config;
+mod dependency;
+mod diagnostic;
+mod diff;
+mod discover;
+mod drift;
+mod editions;
+mod events;
+mod explain;
+mod fleet;
+mod fmt;
+mod git;
+mod groups;
+pub mod in_memory;
+mod init;
+mod init_config;
+mod lock;
+mod lockfile;
+mod manifest;
+mod merge;
+mod metadata;
+mod new_member;
+mod policy;
+mod progress;
+mod remote;
+mod rename;
+mod report;
+mod review;
+mod sandbox;
+mod sbom;
+mod serve;
+mod sources;
+mod staged;
+mod state;
+mod summary;
+#[cfg(test)]
+mod test_util;
+mod tool_config;
+mod unused;
+mod version;
+mod workspace;
+
+/// Runs the command given on the command line
+pub fn run() -> Result<()> {
+    let opt = cli::parse_args();
+    cli::setup_logging(opt.verbose);
+
+    let result = dispatch(&opt);
+    // Also for failed runs, they may be the slow ones
+    match opt.timings {
+        Some(cli::TimingsFormat::Table) => eprint!("{}", progress::take_timings()),
+        Some(cli::TimingsFormat::Json) => eprintln!(
+            "{}",
+            serde_json::to_string_pretty(&progress::take_timings())?
+        ),
+        None => {}
+    }
+    result
+}
+
+/// The exit code of a run failing with `err`: 3 if the workspace has too
+/// few members to consolidate, 1 otherwise
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<workspace::TooFewMembers>() {
+        Some(_) => 3,
+        None => 1,
+    }
+}
+
+fn dispatch(opt: &cli::Opt) -> Result<()> {
+    let several_manifests = opt.manifest_path.len() > 1 || opt.manifests_from.is_some();
+    if several_manifests && opt.command.is_some() {
+        anyhow::bail!("Several workspaces can only be given when consolidating");
+    }
+    if several_manifests && opt.out_root.is_some() {
+        anyhow::bail!("--out-root only takes the root manifest of a single workspace");
+    }
+
+    match &opt.command {
+        Some(cli::Command::Report(args)) => return report::run(opt, args),
+        Some(cli::Command::Check(args)) if args.staged => return staged::check(opt),
+        Some(cli::Command::Check(args)) => return report::check(opt, args),
+        Some(cli::Command::Init(args)) => return init::run(opt, args),
+        Some(cli::Command::ExportCatalogue(args)) => return catalogue::export(opt, args),
+        Some(cli::Command::Fmt(args)) => return fmt::run(opt, args),
+        Some(cli::Command::NewMember(args)) => return new_member::run(opt, args),
+        Some(cli::Command::Diff(args)) => return changes::run(opt, args),
+        Some(cli::Command::InitConfig(args)) => return init_config::run(opt, args),
+        Some(cli::Command::ToolConfig(args)) => return tool_config::run(opt, args),
+        Some(cli::Command::Rename(args)) => return rename::run(opt, args),
+        Some(cli::Command::Drift(args)) => return drift::run(opt, args),
+        None => {}
+    }
+    if let Some(fleet) = &opt.fleet {
+        return fleet::run(opt, fleet);
+    }
+    if !opt.merge.is_empty() {
+        return merge::run(opt);
+    }
+    if several_manifests {
+        return discover::run_manifests(opt);
+    }
+    if opt.recursive {
+        return discover::run(opt);
+    }
+    if opt.serve {
+        return serve::run(opt);
+    }
+    if opt.review {
+        return review::run(opt);
+    }
+    if opt.sandbox || opt.out_root.is_some() {
+        return sandbox::run(opt);
+    }
+    workspace::consolidate_dependencies(opt)
+}
@@ -0,0 +1,77 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// An advisory lock keeping other runs from editing the manifests of a
+/// workspace at the same time, released when dropped
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+/// Where the lock of the workspace is kept, next to its state
+pub fn path(target_directory: &Path) -> PathBuf {
+    target_directory.join("cargo-consolidate").join("lock")
+}
+
+/// Takes the lock of the workspace whose build output goes to
+/// `target_directory`, failing right away if another run holds it
+pub fn acquire(target_directory: &Path) -> Result<Lock> {
+    let path = path(target_directory);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create '{}'", dir.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("Failed to open '{}'", path.display()))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut holder = String::new();
+            file.read_to_string(&mut holder).ok();
+            let holder = match holder.trim() {
+                "" => String::new(),
+                pid => format!(" (process {})", pid),
+            };
+            anyhow::bail!(
+                "Another run of cargo-consolidate{} is editing this workspace, try again once it's done",
+                holder
+            );
+        }
+        Err(TryLockError::Error(err)) => {
+            return Err(err).with_context(|| format!("Failed to lock '{}'", path.display()))
+        }
+    }
+
+    // Only for the message of a run which has to wait
+    file.set_len(0)
+        .and_then(|()| file.rewind())
+        .and_then(|()| write!(file, "{}", std::process::id()))
+        .with_context(|| format!("Failed to write '{}'", path.display()))?;
+    Ok(Lock { _file: file })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_acquire() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let lock = acquire(temp_dir.path())?;
+
+        let err = acquire(temp_dir.path()).unwrap_err().to_string();
+        assert!(err.contains(&format!("(process {})", std::process::id())));
+
+        drop(lock);
+        acquire(temp_dir.path())?;
+        Ok(())
+    }
+}
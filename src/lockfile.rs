@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use semver::Version;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use toml_edit::DocumentMut;
+
+use crate::diagnostic;
+use crate::sources;
+
+/// The package versions recorded in a workspace's Cargo.lock
+#[derive(Debug, Default)]
+pub struct Lockfile {
+    versions: HashMap<String, Vec<Version>>,
+}
+
+impl Lockfile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        Self::parse(&content)
+            .map_err(|err| diagnostic::locate(err, path, &content))
+            .with_context(|| format!("Failed to parse '{}'", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let doc = content.parse::<DocumentMut>()?;
+        let mut versions: HashMap<String, Vec<Version>> = HashMap::new();
+
+        let packages = doc
+            .get("package")
+            .and_then(|packages| packages.as_array_of_tables())
+            .into_iter()
+            .flatten();
+        for package in packages {
+            let (Some(name), Some(version)) = (
+                package.get("name").and_then(|name| name.as_str()),
+                package.get("version").and_then(|version| version.as_str()),
+            ) else {
+                continue;
+            };
+            let version = Version::parse(version)
+                .with_context(|| format!("Invalid version '{}' of '{}'", version, name))?;
+            versions.entry(name.to_string()).or_default().push(version);
+        }
+
+        Ok(Self { versions })
+    }
+
+    /// Records the versions of vendored crates, for workspaces building
+    /// against a vendor directory
+    pub fn add_vendored(&mut self, dir: &Path) -> Result<()> {
+        for (name, version) in sources::vendored_versions(dir)? {
+            let version = Version::parse(&version)
+                .with_context(|| format!("Invalid version '{}' of '{}'", version, name))?;
+            self.versions.entry(name).or_default().push(version);
+        }
+        Ok(())
+    }
+
+    /// Returns every locked version of a package
+    pub fn versions_of(&self, package: &str) -> &[Version] {
+        self.versions.get(package).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the highest locked version of a package
+    pub fn version_of(&self, package: &str) -> Option<&Version> {
+        self.versions
+            .get(package)
+            .and_then(|versions| versions.iter().max())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_of() -> Result<()> {
+        let lockfile = Lockfile::parse(
+            r#"
+            version = 3
+
+            [[package]]
+            name = "syn"
+            version = "1.0.109"
+
+            [[package]]
+            name = "syn"
+            version = "2.0.86"
+
+            [[package]]
+            name = "serde"
+            version = "1.0.214"
+            "#,
+        )?;
+
+        assert_eq!(lockfile.version_of("syn"), Some(&Version::new(2, 0, 86)));
+        assert_eq!(lockfile.version_of("serde"), Some(&Version::new(1, 0, 214)));
+        assert_eq!(lockfile.version_of("tokio"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+use crate::cli::SymlinkPolicy;
+use crate::diagnostic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+/// A parsed Cargo.toml which remembers the formatting conventions of the
+/// file it was read from, so writing it back doesn't produce spurious diffs
+pub struct Manifest {
+    path: PathBuf,
+    pub doc: DocumentMut,
+    /// The content the manifest was parsed from
+    source: String,
+    line_ending: LineEnding,
+    trailing_newline: bool,
+}
+
+impl Manifest {
+    pub fn open(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        Self::parse(path, &content)
+    }
+
+    pub fn parse(path: &Path, content: &str) -> Result<Self> {
+        let doc = content
+            .parse::<DocumentMut>()
+            .map_err(|err| diagnostic::locate(err.into(), path, content))
+            .with_context(|| format!("Failed to parse '{}'", path.display()))?;
+
+        // Go with whatever the first line break uses, that's what git and
+        // editors do as well when they have to guess
+        let line_ending = match content.find('\n') {
+            Some(pos) if content[..pos].ends_with('\r') => LineEnding::CrLf,
+            _ => LineEnding::Lf,
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            doc,
+            source: content.to_string(),
+            line_ending,
+            trailing_newline: content.is_empty() || content.ends_with('\n'),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The content the manifest was read from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Points an error about a value of the manifest at where it was read
+    /// from, see [`diagnostic::locate`]
+    pub fn locate(&self, err: anyhow::Error) -> anyhow::Error {
+        diagnostic::locate(err, &self.path, &self.source)
+    }
+
+    /// Renders the document using the line endings and trailing newline of the original file
+    pub fn render(&self) -> String {
+        // toml_edit writes `\n` for everything it creates, so start from a uniform base
+        let mut content = self.doc.to_string().replace("\r\n", "\n");
+
+        if self.trailing_newline && !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        } else if !self.trailing_newline && content.ends_with('\n') {
+            content.pop();
+        }
+
+        match self.line_ending {
+            LineEnding::Lf => content,
+            LineEnding::CrLf => content.replace('\n', "\r\n"),
+        }
+    }
+
+    /// Writes the manifest back to where it was read from. Symlinked
+    /// manifests are either written through to their target or refused,
+    /// depending on `symlinks`
+    pub fn write(&self, symlinks: SymlinkPolicy) -> Result<()> {
+        self.write_rendered(&self.render(), symlinks)
+    }
+
+    /// Like [`Manifest::write`], with the content rendered already
+    pub fn write_rendered(&self, content: &str, symlinks: SymlinkPolicy) -> Result<()> {
+        write_file(&self.path, content, symlinks)
+    }
+}
+
+/// The manifests edited during a run. Each is read once on first use,
+/// edited in memory and written back once by the caller at the end
+#[derive(Default)]
+pub struct Manifests {
+    /// Every manifest read so far, together with its original rendering
+    opened: BTreeMap<PathBuf, (Manifest, String)>,
+    /// The content of manifests which aren't on disk, see
+    /// [`Manifests::in_memory`]
+    memory: Option<BTreeMap<PathBuf, String>>,
+}
+
+impl Manifests {
+    /// Manifests which are read from `contents` and written back to it, the
+    /// file system isn't touched
+    pub fn in_memory(contents: BTreeMap<PathBuf, String>) -> Self {
+        Manifests {
+            opened: BTreeMap::new(),
+            memory: Some(contents),
+        }
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        self.memory.is_some()
+    }
+
+    /// The content of the manifests held in memory, including what was
+    /// written to them
+    pub fn into_memory(self) -> BTreeMap<PathBuf, String> {
+        self.memory.unwrap_or_default()
+    }
+
+    /// Reads the manifest at `path` without keeping it
+    pub fn read(&self, path: &Path) -> Result<Manifest> {
+        match &self.memory {
+            Some(contents) => {
+                let content = contents
+                    .get(path)
+                    .with_context(|| format!("There's no manifest for '{}'", path.display()))?;
+                Manifest::parse(path, content)
+            }
+            None => Manifest::open(path),
+        }
+    }
+
+    /// Writes `content` to the manifest at `path`, see [`Manifest::write`]
+    pub fn write(&mut self, path: &Path, content: &str, symlinks: SymlinkPolicy) -> Result<()> {
+        match &mut self.memory {
+            Some(contents) => {
+                contents.insert(path.to_path_buf(), content.to_string());
+                Ok(())
+            }
+            None => write_file(path, content, symlinks),
+        }
+    }
+
+    pub fn get(&mut self, path: &Path) -> Result<&Manifest> {
+        Ok(&self.entry(path)?.0)
+    }
+
+    /// Returns the manifest if it was read already
+    pub fn loaded(&self, path: &Path) -> Option<&Manifest> {
+        self.opened.get(path).map(|(manifest, _)| manifest)
+    }
+
+    pub fn get_mut(&mut self, path: &Path) -> Result<&mut Manifest> {
+        Ok(&mut self.entry(path)?.0)
+    }
+
+    /// Removes the manifest at `path`, returning it if it was changed
+    pub fn take_changed(&mut self, path: &Path) -> Option<Manifest> {
+        let (manifest, original) = self.opened.remove(path)?;
+        (manifest.render() != original).then_some(manifest)
+    }
+
+    /// Reads the manifests at `paths` in parallel, unless they were read
+    /// already. Returns the errors of those which couldn't be read, in the
+    /// order of `paths`
+    pub fn preload(&mut self, paths: &[&Path]) -> Vec<anyhow::Error> {
+        let results: Vec<_> = paths
+            .par_iter()
+            .filter(|path| !self.opened.contains_key(**path))
+            .map(|path| {
+                self.read(path)
+                    .map(|manifest| (path.to_path_buf(), manifest))
+            })
+            .collect();
+
+        let mut errors = Vec::new();
+        for result in results {
+            match result {
+                Ok((path, manifest)) => {
+                    let original = manifest.render();
+                    self.opened.insert(path, (manifest, original));
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+        errors
+    }
+
+    /// The manifests whose content changed together with their new content,
+    /// rendered in parallel and sorted by path
+    pub fn render_changed(&self) -> Vec<(&Manifest, String)> {
+        let opened: Vec<_> = self.opened.values().collect();
+        opened
+            .par_iter()
+            .filter_map(|(manifest, original)| {
+                let content = manifest.render();
+                (content != *original).then_some((manifest, content))
+            })
+            .collect()
+    }
+
+    fn entry(&mut self, path: &Path) -> Result<&mut (Manifest, String)> {
+        if !self.opened.contains_key(path) {
+            let manifest = self.read(path)?;
+            let original = manifest.render();
+            self.opened.insert(path.to_path_buf(), (manifest, original));
+        }
+        Ok(self.opened.get_mut(path).unwrap())
+    }
+}
+
+fn write_file(path: &Path, content: &str, symlinks: SymlinkPolicy) -> Result<()> {
+    let path = resolve_write_path(path, symlinks)?;
+    fs::write(&path, content).with_context(|| format!("Failed to write '{}'", path.display()))
+}
+
+/// Returns the file which writing to `path` should modify, following a
+/// symlink only if `symlinks` allows it
+pub fn resolve_write_path(path: &Path, symlinks: SymlinkPolicy) -> Result<PathBuf> {
+    let is_symlink = fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false);
+    if !is_symlink {
+        return Ok(path.to_path_buf());
+    }
+
+    let target = fs::canonicalize(path)
+        .with_context(|| format!("Failed to resolve symlink '{}'", path.display()))?;
+    match symlinks {
+        SymlinkPolicy::Follow => Ok(target),
+        SymlinkPolicy::Refuse => Err(anyhow::anyhow!(
+            "'{}' is a symlink to '{}', refusing to write through it. Pass --symlinks follow to modify the target",
+            path.display(),
+            target.display()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use toml_edit::{value, Item, Table};
+
+    #[test]
+    fn test_manifests_changed() -> Result<()> {
+        let dir = TempDir::new()?;
+        let (a, b) = (dir.path().join("a.toml"), dir.path().join("b.toml"));
+        fs::write(&a, "[dependencies]\nserde = \"1\"\n")?;
+        fs::write(&b, "[dependencies]\nserde = \"1\"\n")?;
+
+        let mut manifests = Manifests::default();
+        manifests.get_mut(&a)?.doc["dependencies"]["serde"] = value("1.0.200");
+        // Reading again sees the edit instead of the file
+        assert_eq!(
+            manifests.get(&a)?.doc["dependencies"]["serde"].as_str(),
+            Some("1.0.200")
+        );
+        manifests.get_mut(&b)?;
+
+        let changed: Vec<_> = manifests
+            .render_changed()
+            .into_iter()
+            .map(|(manifest, _)| manifest.path())
+            .collect();
+        assert_eq!(changed, vec![a.as_path()]);
+        assert!(manifests.take_changed(&b).is_none());
+        assert!(manifests.take_changed(&a).is_some());
+        assert!(manifests.render_changed().is_empty());
+
+        let missing = dir.path().join("missing.toml");
+        let errors = manifests.preload(&[&a, &missing, &b]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().contains("missing.toml"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_preserves_crlf() -> Result<()> {
+        let content = "[package]\r\nname = \"foo\"\r\n\r\n[dependencies]\r\nserde = \"1\"\r\n";
+        let mut manifest = Manifest::parse(Path::new("Cargo.toml"), content)?;
+
+        let mut table = Table::new();
+        table.insert("anyhow", value("1"));
+        manifest
+            .doc
+            .insert("build-dependencies", Item::Table(table));
+
+        let rendered = manifest.render();
+        assert!(rendered.starts_with(content));
+        assert!(rendered.ends_with("anyhow = \"1\"\r\n"));
+        assert!(!rendered.replace("\r\n", "").contains('\n'));
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_preserves_missing_trailing_newline() -> Result<()> {
+        let content = "[dependencies]\nserde = \"1\"";
+        let manifest = Manifest::parse(Path::new("Cargo.toml"), content)?;
+
+        assert_eq!(manifest.render(), content);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_write_path_symlink() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let target = temp_dir.path().join("template.toml");
+        let link = temp_dir.path().join("Cargo.toml");
+        fs::write(&target, "[dependencies]\n")?;
+        std::os::unix::fs::symlink(&target, &link)?;
+
+        assert!(resolve_write_path(&link, SymlinkPolicy::Refuse).is_err());
+        assert_eq!(
+            resolve_write_path(&link, SymlinkPolicy::Follow)?,
+            fs::canonicalize(&target)?
+        );
+        assert_eq!(resolve_write_path(&target, SymlinkPolicy::Refuse)?, target);
+        Ok(())
+    }
+}
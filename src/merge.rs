@@ -0,0 +1,452 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, DocumentMut, Item, Table, Value};
+
+use crate::cli::{CargoOptions, MismatchPolicy, Opt};
+use crate::dependency;
+use crate::lock;
+use crate::manifest::Manifest;
+use crate::metadata;
+use crate::workspace;
+
+/// Keys of `[workspace.package]` holding paths relative to the workspace root
+const PACKAGE_PATH_KEYS: [&str; 2] = ["readme", "license-file"];
+
+/// A workspace or standalone crate passed to `--merge`
+struct Source {
+    manifest: Manifest,
+    /// Directory of the source relative to the new workspace root
+    prefix: PathBuf,
+    /// Directories of the crates it contributes, relative to the new root
+    members: Vec<PathBuf>,
+}
+
+/// Creates a new workspace in the directory of `--manifest-path`, or the
+/// current directory, combining the workspaces and crates passed to `--merge`
+/// and consolidates it afterwards
+pub fn run(opt: &Opt) -> Result<()> {
+    let root_manifest_path = match opt.manifest_path() {
+        Some(path) if path.is_dir() => path.join("Cargo.toml"),
+        Some(path) => path.to_path_buf(),
+        None => std::env::current_dir()
+            .context("Failed to get the current directory")?
+            .join("Cargo.toml"),
+    };
+    if root_manifest_path.exists() {
+        anyhow::bail!(
+            "'{}' already exists, --merge only creates new workspaces",
+            root_manifest_path.display()
+        );
+    }
+    let root_dir = root_manifest_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let root_dir = fs::canonicalize(root_dir)
+        .with_context(|| format!("Failed to resolve '{}'", root_dir.display()))?;
+    let lock = lock::acquire(&metadata::target_directory(&root_dir)?)?;
+
+    let sources = opt
+        .merge
+        .iter()
+        .map(|path| load_source(path, &root_dir, &opt.cargo))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Plan every change before writing anything
+    let mut root = Manifest::parse(&root_manifest_path, "")?;
+    let mut members = Vec::new();
+    let mut removed = Vec::new();
+    let mut rewritten = Vec::new();
+    // Keys of [workspace.package] left out, by the members which can't
+    // inherit them anymore
+    let mut dropped: HashMap<PathBuf, Vec<&str>> = HashMap::new();
+    for mut source in sources {
+        let Some(workspace) = source.manifest.doc.remove("workspace") else {
+            members.append(&mut source.members);
+            continue;
+        };
+        let keys = merge_workspace(
+            &mut root.doc,
+            &root_dir,
+            workspace,
+            &source,
+            opt.on_mismatch.unwrap_or_default(),
+        )?;
+        for member in &source.members {
+            dropped.insert(member.clone(), keys.clone());
+        }
+        members.append(&mut source.members);
+        if source.manifest.doc.contains_key("package") {
+            rewritten.push(source.manifest);
+        } else {
+            removed.push(source.manifest.path().to_path_buf());
+        }
+    }
+    members.sort();
+    members.dedup();
+
+    let mut member_manifests = Vec::new();
+    for member in &members {
+        let path = root_dir.join(member).join("Cargo.toml");
+        let mut manifest = Manifest::open(&path)?;
+        let Some(package) = manifest
+            .doc
+            .get_mut("package")
+            .and_then(Item::as_table_like_mut)
+        else {
+            continue;
+        };
+        // A pointer to the old workspace root would bypass the new one
+        let mut changed = package.remove("workspace").is_some();
+        for key in dropped.get(member).into_iter().flatten() {
+            let inherited = package
+                .get(key)
+                .and_then(|item| item.get("workspace"))
+                .is_some();
+            if inherited {
+                warn!(
+                    "'{}' no longer inherits {}, as the file it names is missing",
+                    path.display(),
+                    key
+                );
+                package.remove(key);
+                changed = true;
+            }
+        }
+        if changed {
+            member_manifests.push(manifest);
+        }
+    }
+
+    let workspace = root
+        .doc
+        .entry("workspace")
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_mut()
+        .context("workspace in the new root is not a table")?;
+    workspace.set_position(0);
+    if !workspace.contains_key("resolver") {
+        workspace.insert("resolver", toml_edit::value("2"));
+    }
+    let members: Array = members
+        .iter()
+        .map(|member| Value::from(member.to_string_lossy().replace('\\', "/")))
+        .collect();
+    workspace.insert("members", toml_edit::value(members));
+
+    info!("Creating '{}'", root_manifest_path.display());
+    root.write(opt.symlinks)?;
+    for manifest in rewritten.iter().chain(&member_manifests) {
+        manifest.write(opt.symlinks)?;
+    }
+    for path in &removed {
+        info!("Removing the old workspace root '{}'", path.display());
+        fs::remove_file(path).with_context(|| format!("Failed to remove '{}'", path.display()))?;
+        let lockfile = path.with_file_name("Cargo.lock");
+        if lockfile.exists() {
+            warn!(
+                "'{}' is no longer used and can be deleted",
+                lockfile.display()
+            );
+        }
+    }
+
+    let mut consolidate_opt = opt.clone();
+    consolidate_opt.merge.clear();
+    consolidate_opt.manifest_path = vec![root_manifest_path];
+    workspace::consolidate_new_workspace(&consolidate_opt, lock)
+}
+
+fn load_source(path: &Path, root_dir: &Path, cargo: &CargoOptions) -> Result<Source> {
+    let manifest_path = if path.is_dir() {
+        path.join("Cargo.toml")
+    } else {
+        path.to_path_buf()
+    };
+    let manifest_path = fs::canonicalize(&manifest_path)
+        .with_context(|| format!("Failed to resolve '{}'", manifest_path.display()))?;
+    let dir = manifest_path.parent().unwrap_or(Path::new(""));
+    let prefix = dir
+        .strip_prefix(root_dir)
+        .with_context(|| {
+            format!(
+                "'{}' is not inside the new workspace root '{}'",
+                manifest_path.display(),
+                root_dir.display()
+            )
+        })?
+        .to_path_buf();
+
+    let manifest = Manifest::open(&manifest_path)?;
+    let members = if manifest.doc.contains_key("workspace") {
+        let metadata = workspace::load_metadata(Some(&manifest_path), cargo)?;
+        workspace::workspace_packages(&metadata)?
+            .iter()
+            .filter_map(|package| package.manifest_path.parent())
+            .filter_map(|dir| dir.as_std_path().strip_prefix(root_dir).ok())
+            .map(Path::to_path_buf)
+            .collect()
+    } else if manifest.doc.contains_key("package") {
+        vec![prefix.clone()]
+    } else {
+        anyhow::bail!(
+            "'{}' is neither a workspace nor a crate",
+            manifest_path.display()
+        );
+    };
+
+    Ok(Source {
+        manifest,
+        prefix,
+        members,
+    })
+}
+
+/// Moves the `[workspace]` table of `source` into the new root, rebasing the
+/// paths it contains. Returns the keys of `[workspace.package]` naming files
+/// which don't exist in `root_dir` once rebased, which are left out
+fn merge_workspace(
+    root: &mut DocumentMut,
+    root_dir: &Path,
+    workspace: Item,
+    source: &Source,
+    policy: MismatchPolicy,
+) -> Result<Vec<&'static str>> {
+    let Item::Table(mut workspace) = workspace else {
+        anyhow::bail!(
+            "workspace in '{}' is not a table",
+            source.manifest.path().display()
+        );
+    };
+    let target = root
+        .entry("workspace")
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_mut()
+        .context("workspace in the new root is not a table")?;
+
+    // Members are collected from cargo metadata instead
+    workspace.remove("members");
+    if workspace.remove("default-members").is_some() {
+        warn!(
+            "Dropping workspace.default-members of '{}'",
+            source.manifest.path().display()
+        );
+    }
+
+    if let Some(resolver) = workspace.remove("resolver") {
+        let resolver = resolver.as_str().unwrap_or_default().to_string();
+        let current = target.get("resolver").and_then(Item::as_str);
+        if current.is_none_or(|current| current < resolver.as_str()) {
+            target.insert("resolver", toml_edit::value(resolver));
+        }
+    }
+
+    if let Some(exclude) = workspace.remove("exclude") {
+        let target_exclude = target
+            .entry("exclude")
+            .or_insert_with(|| toml_edit::value(Array::new()))
+            .as_array_mut()
+            .context("workspace.exclude in the new root is not an array")?;
+        for path in exclude.as_array().into_iter().flatten() {
+            if let Some(path) = path.as_str() {
+                let rebased = dependency::normalize_path(&source.prefix.join(path));
+                target_exclude.push(rebased.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+
+    let mut dropped = Vec::new();
+    if let Some(Item::Table(mut package)) = workspace.remove("package") {
+        for key in PACKAGE_PATH_KEYS {
+            if let Some(path) = package.get(key).and_then(Item::as_str) {
+                // Inherited paths resolve from the workspace root, cargo
+                // package would fail on a file that isn't there
+                let rebased = dependency::normalize_path(&source.prefix.join(path));
+                if !root_dir.join(&rebased).is_file() {
+                    warn!(
+                        "Leaving workspace.package.{} of '{}' out, '{}' doesn't exist",
+                        key,
+                        source.manifest.path().display(),
+                        root_dir.join(&rebased).display()
+                    );
+                    package.remove(key);
+                    dropped.push(key);
+                    continue;
+                }
+                package.insert(
+                    key,
+                    toml_edit::value(rebased.to_string_lossy().replace('\\', "/")),
+                );
+            }
+        }
+        merge_table(target, "package", package, source)?;
+    }
+
+    if let Some(Item::Table(mut deps)) = workspace.remove("dependencies") {
+        for (_, item) in deps.iter_mut() {
+            dependency::rebase_path(item, &source.prefix);
+        }
+        let target_deps = target
+            .entry("dependencies")
+            .or_insert_with(|| Item::Table(Table::new()))
+            .as_table_mut()
+            .context("workspace.dependencies in the new root is not a table")?;
+        for (dep, item) in deps.iter() {
+            let Some(existing) = target_deps.get(dep) else {
+                target_deps.insert(dep, item.clone());
+                continue;
+            };
+            let Some(mismatch) = dependency::find_mismatch(existing, item) else {
+                continue;
+            };
+            let what = format!(
+                "'{}' of '{}' differs from the merged entry: {}",
+                dep,
+                source.manifest.path().display(),
+                mismatch
+            );
+            match policy {
+                MismatchPolicy::Error => anyhow::bail!(what),
+                MismatchPolicy::Update => {
+                    warn!("{}, taking the spec of '{}'", what, source.prefix.display());
+                    target_deps.insert(dep, item.clone());
+                }
+                MismatchPolicy::Warn | MismatchPolicy::Skip => {
+                    warn!("{}, keeping the merged entry", what);
+                }
+            }
+        }
+    }
+
+    for (key, item) in workspace.iter() {
+        match item {
+            Item::Table(table) => merge_table(target, key, table.clone(), source)?,
+            _ => anyhow::bail!(
+                "Don't know how to merge workspace.{} of '{}'",
+                key,
+                source.manifest.path().display()
+            ),
+        }
+    }
+    Ok(dropped)
+}
+
+/// Adds the keys of `table` to `workspace.<key>` in the new root, failing on
+/// keys which already have a different value
+fn merge_table(target: &mut Table, key: &str, table: Table, source: &Source) -> Result<()> {
+    let target = target
+        .entry(key)
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_mut()
+        .with_context(|| format!("workspace.{} in the new root is not a table", key))?;
+    for (name, item) in table.iter() {
+        match target.get(name) {
+            Some(existing) if existing.to_string().trim() != item.to_string().trim() => {
+                anyhow::bail!(
+                    "workspace.{}.{} of '{}' conflicts with another workspace: {} vs {}",
+                    key,
+                    name,
+                    source.manifest.path().display(),
+                    existing.to_string().trim(),
+                    item.to_string().trim()
+                );
+            }
+            Some(_) => {}
+            None => {
+                target.insert(name, item.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_merge_workspaces() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+
+        write_file(
+            root.join("one/Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"shared\"]\n\n[workspace.dependencies]\nshared = { path = \"shared\" }\n",
+        )?;
+        write_file(
+            root.join("one/a/Cargo.toml"),
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nshared = { workspace = true }\n",
+        )?;
+        write_file(
+            root.join("one/shared/Cargo.toml"),
+            "[package]\nname = \"shared\"\nversion = \"0.1.0\"\n",
+        )?;
+        write_file(
+            root.join("standalone/Cargo.toml"),
+            "[package]\nname = \"standalone\"\nversion = \"0.1.0\"\n\n[dependencies]\nshared = { path = \"../one/shared\" }\n",
+        )?;
+        for dir in ["one/a", "one/shared", "standalone"] {
+            write_file(root.join(format!("{}/src/lib.rs", dir)), "")?;
+        }
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+            "--group-all",
+            "--merge",
+            root.join("one").to_str().unwrap(),
+            "--merge",
+            root.join("standalone").to_str().unwrap(),
+        ]);
+        run(&opt)?;
+
+        let manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(manifest.contains(r#"members = ["one/a", "one/shared", "standalone"]"#));
+        assert!(manifest.contains(r#"shared = { path = "one/shared" }"#));
+        assert!(!root.join("one/Cargo.toml").exists());
+        let standalone = fs::read_to_string(root.join("standalone/Cargo.toml"))?;
+        assert!(standalone.contains("shared = { workspace = true }"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_package_paths() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+
+        write_file(
+            root.join("one/Cargo.toml"),
+            "[workspace]\nmembers = [\"a\"]\n\n[workspace.package]\nreadme = \"README.md\"\nlicense-file = \"LICENSE\"\n",
+        )?;
+        write_file(root.join("one/README.md"), "")?;
+        write_file(
+            root.join("one/a/Cargo.toml"),
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\nreadme.workspace = true\nlicense-file.workspace = true\n",
+        )?;
+        write_file(root.join("one/a/src/lib.rs"), "")?;
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+            "--merge",
+            root.join("one").to_str().unwrap(),
+        ]);
+        run(&opt)?;
+
+        let manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(manifest.contains("readme = \"one/README.md\""));
+        assert!(!manifest.contains("license-file"));
+        let member = fs::read_to_string(root.join("one/a/Cargo.toml"))?;
+        assert!(member.contains("readme.workspace = true"));
+        assert!(!member.contains("license-file"));
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+use anyhow::{Context, Result};
+use cargo_metadata::Metadata;
+use glob::{glob, Pattern};
+use log::debug;
+use rayon::prelude::*;
+use semver::{Version, VersionReq};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item};
+
+use crate::dependency;
+use crate::manifest::Manifest;
+
+/// Builds the metadata of the project containing `manifest_path`, or the
+/// current directory, from its manifests alone, without running cargo.
+/// Members are found by expanding the `members` and `exclude` globs of the
+/// root manifest
+pub fn from_manifests(manifest_path: Option<&Path>) -> Result<Metadata> {
+    let manifest_path = match manifest_path {
+        Some(path) if path.is_dir() => path.join("Cargo.toml"),
+        Some(path) => path.to_path_buf(),
+        None => std::env::current_dir()
+            .context("Failed to get the current directory")?
+            .join("Cargo.toml"),
+    };
+    let manifest_path = fs::canonicalize(&manifest_path)
+        .with_context(|| format!("Failed to find '{}'", manifest_path.display()))?;
+    let root_manifest_path = find_workspace_root(&manifest_path)?;
+    let workspace_root = root_manifest_path
+        .parent()
+        .context("The root manifest has no parent directory")?;
+    let root = Manifest::open(&root_manifest_path)?;
+
+    let mut member_manifests = Vec::new();
+    if root.doc.contains_key("package") {
+        member_manifests.push(root_manifest_path.clone());
+    }
+    member_manifests.extend(find_members(&root.doc, workspace_root)?);
+
+    let members = member_manifests
+        .par_iter()
+        .map(|member_manifest| Manifest::open(member_manifest))
+        .collect::<Result<Vec<_>>>()?;
+    let members: Vec<_> = members
+        .iter()
+        .map(|member| (member.path(), &member.doc))
+        .collect();
+    let mut metadata = from_docs(workspace_root, &root.doc, &members)?;
+    let target_directory = target_directory(workspace_root)?;
+    metadata.target_directory = utf8(&target_directory)?.into();
+    Ok(metadata)
+}
+
+/// Builds the metadata of the workspace at `workspace_root` from the parsed
+/// root manifest and the paths and parsed manifests of its members
+pub fn from_docs(
+    workspace_root: &Path,
+    root: &DocumentMut,
+    members: &[(&Path, &DocumentMut)],
+) -> Result<Metadata> {
+    let packages = members
+        .par_iter()
+        .map(|(manifest_path, doc)| package(manifest_path, doc, root))
+        .collect::<Result<Vec<_>>>()?;
+    let workspace_members: Vec<Value> = packages
+        .iter()
+        .map(|package| package["id"].clone())
+        .collect();
+
+    let metadata = json!({
+        "packages": packages,
+        "workspace_members": workspace_members,
+        "resolve": null,
+        "workspace_root": utf8(workspace_root)?,
+        "target_directory": utf8(&workspace_root.join("target"))?,
+        "version": 1,
+    });
+    serde_json::from_value(metadata).context("Failed to build the workspace metadata")
+}
+
+/// The directory cargo builds the workspace at `workspace_root` in. Like
+/// cargo, $CARGO_TARGET_DIR and $CARGO_BUILD_TARGET_DIR take precedence over
+/// `build.target-dir` of the configuration files found from the current
+/// directory up and in $CARGO_HOME
+pub fn target_directory(workspace_root: &Path) -> Result<PathBuf> {
+    let current_dir = std::env::current_dir().context("Failed to get the current directory")?;
+    for var in ["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR"] {
+        if let Some(dir) = std::env::var_os(var).filter(|dir| !dir.is_empty()) {
+            return Ok(current_dir.join(dir));
+        }
+    }
+    let configured = configured_target_dir(&current_dir, cargo_home().as_deref())?;
+    Ok(configured.unwrap_or_else(|| workspace_root.join("target")))
+}
+
+/// `$CARGO_HOME`, falling back to `~/.cargo`
+pub fn cargo_home() -> Option<PathBuf> {
+    std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")))
+}
+
+/// The directories cargo looks for configuration files in when run from
+/// `dir`, the nearest first: the `.cargo` directories from `dir` up, then
+/// `cargo_home`
+pub fn config_dirs(dir: &Path, cargo_home: Option<&Path>) -> Vec<PathBuf> {
+    let mut config_dirs: Vec<PathBuf> = dir.ancestors().map(|dir| dir.join(".cargo")).collect();
+    if let Some(cargo_home) = cargo_home {
+        if !config_dirs.iter().any(|dir| dir == cargo_home) {
+            config_dirs.push(cargo_home.to_path_buf());
+        }
+    }
+    config_dirs
+}
+
+/// `build.target-dir` of the closest cargo configuration file, see
+/// [`config_dirs`]. A relative path is relative to the directory holding
+/// `.cargo`
+fn configured_target_dir(dir: &Path, cargo_home: Option<&Path>) -> Result<Option<PathBuf>> {
+    for config_dir in config_dirs(dir, cargo_home) {
+        // cargo prefers the old name should both exist
+        let Some(path) = ["config", "config.toml"]
+            .iter()
+            .map(|name| config_dir.join(name))
+            .find(|path| path.is_file())
+        else {
+            continue;
+        };
+        let doc = Manifest::open(&path)?.doc;
+        let target_dir = doc
+            .get("build")
+            .and_then(|build| build.get("target-dir"))
+            .and_then(Item::as_str);
+        if let Some(target_dir) = target_dir {
+            let base = config_dir.parent().unwrap_or(&config_dir);
+            return Ok(Some(base.join(target_dir)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the first manifest declaring a `[workspace]`, starting at
+/// `manifest_path` and going up. A manifest outside of any workspace is a
+/// workspace of its own
+pub fn find_workspace_root(manifest_path: &Path) -> Result<PathBuf> {
+    let mut dir = manifest_path.parent();
+    let mut candidate = manifest_path.to_path_buf();
+    while let Some(current) = dir {
+        if candidate.is_file() && Manifest::open(&candidate)?.doc.contains_key("workspace") {
+            return Ok(candidate);
+        }
+        dir = current.parent();
+        if let Some(parent) = dir {
+            candidate = parent.join("Cargo.toml");
+        }
+    }
+    Ok(manifest_path.to_path_buf())
+}
+
+/// Returns the manifests of the packages matched by `workspace.members`
+/// and not covered by `workspace.exclude`, sorted by path
+pub fn find_members(root: &DocumentMut, workspace_root: &Path) -> Result<Vec<PathBuf>> {
+    let patterns = |key: &str| -> Vec<String> {
+        root.get("workspace")
+            .and_then(|workspace| workspace.get(key))
+            .and_then(Item::as_array)
+            .into_iter()
+            .flat_map(|array| array.iter())
+            .filter_map(|value| value.as_str())
+            .map(str::to_string)
+            .collect()
+    };
+    let excludes: Vec<PathBuf> = patterns("exclude")
+        .iter()
+        .map(|path| workspace_root.join(path))
+        .collect();
+
+    let escaped_root = Pattern::escape(&workspace_root.to_string_lossy());
+    let mut members = Vec::new();
+    for pattern in patterns("members") {
+        let full = format!("{}/{}", escaped_root, pattern);
+        let dirs = glob(&full).with_context(|| format!("Invalid members pattern '{}'", pattern))?;
+        for dir in dirs.filter_map(Result::ok) {
+            let manifest_path = dir.join("Cargo.toml");
+            if !manifest_path.is_file() {
+                continue;
+            }
+            if excludes.iter().any(|exclude| dir.starts_with(exclude)) {
+                debug!(
+                    "Skipping '{}', it is listed in workspace.exclude",
+                    dir.display()
+                );
+                continue;
+            }
+            if dir == workspace_root {
+                continue;
+            }
+            members.push(manifest_path);
+        }
+    }
+    members.sort();
+    members.dedup();
+    Ok(members)
+}
+
+/// Describes a member the way `cargo metadata` does, as far as the
+/// manifest tells
+fn package(manifest_path: &Path, doc: &DocumentMut, root: &DocumentMut) -> Result<Value> {
+    let name = doc
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(Item::as_str)
+        .with_context(|| format!("'{}' has no package name", manifest_path.display()))?;
+    // Inherited or missing versions don't matter for consolidating
+    let version = doc
+        .get("package")
+        .and_then(|package| package.get("version"))
+        .and_then(Item::as_str)
+        .and_then(|version| Version::parse(version).ok())
+        .unwrap_or(Version::new(0, 0, 0));
+    let dir = manifest_path
+        .parent()
+        .context("The manifest has no parent directory")?;
+
+    let mut dependencies = Vec::new();
+    push_dependencies(doc.as_table(), None, root, &mut dependencies);
+    let targets = doc.get("target").and_then(Item::as_table_like);
+    for (target, table) in targets.into_iter().flat_map(|targets| targets.iter()) {
+        if let Some(table) = table.as_table_like() {
+            push_dependencies(table, Some(target), root, &mut dependencies);
+        }
+    }
+
+    // Tool configuration, which cargo passes on as JSON
+    let metadata = match doc
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+    {
+        Some(item) => {
+            let mut wrapper = DocumentMut::new();
+            wrapper.insert("metadata", item.clone());
+            let mut table: serde_json::Map<String, Value> =
+                toml_edit::de::from_str(&wrapper.to_string()).with_context(|| {
+                    format!(
+                        "Failed to read package.metadata of '{}'",
+                        manifest_path.display()
+                    )
+                })?;
+            table.remove("metadata").unwrap_or(Value::Null)
+        }
+        None => Value::Null,
+    };
+
+    // Feature definitions, which cargo reports as they are declared
+    let features: serde_json::Map<String, Value> = doc
+        .get("features")
+        .and_then(Item::as_table_like)
+        .into_iter()
+        .flat_map(|features| features.iter())
+        .map(|(feature, enables)| {
+            let enables: Vec<&str> = enables
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|value| value.as_str())
+                .collect();
+            (feature.to_string(), json!(enables))
+        })
+        .collect();
+
+    Ok(json!({
+        "name": name,
+        "version": version.to_string(),
+        "id": format!("path+file://{}#{}@{}", utf8(dir)?, name, version),
+        "source": null,
+        "dependencies": dependencies,
+        "targets": [],
+        "features": features,
+        "manifest_path": utf8(manifest_path)?,
+        "metadata": metadata,
+    }))
+}
+
+/// Adds the dependencies declared in the dependency sections of `table`
+fn push_dependencies(
+    table: &dyn toml_edit::TableLike,
+    target: Option<&str>,
+    root: &DocumentMut,
+    dependencies: &mut Vec<Value>,
+) {
+    let kinds = ["normal", "build", "dev"];
+    for (section, kind) in dependency::DEPENDENCY_SECTIONS.iter().zip(kinds) {
+        let Some(deps) = table.get(section).and_then(Item::as_table_like) else {
+            continue;
+        };
+        for (dep_name, item) in deps.iter() {
+            let spec = if dependency::is_workspace_inherited(item) {
+                root.get("workspace")
+                    .and_then(|workspace| workspace.get("dependencies"))
+                    .and_then(|deps| deps.get(dep_name))
+                    .unwrap_or(item)
+            } else {
+                item
+            };
+            let package = spec
+                .as_table_like()
+                .and_then(|tbl| tbl.get("package"))
+                .and_then(Item::as_str)
+                .unwrap_or(dep_name);
+            let req = dependency::get_version(spec)
+                .filter(|req| VersionReq::parse(req).is_ok())
+                .unwrap_or_else(|| "*".to_string());
+            let optional = item
+                .as_table_like()
+                .and_then(|tbl| tbl.get("optional"))
+                .and_then(Item::as_bool)
+                .unwrap_or(false);
+            let field = |key: &str| spec.get(key).and_then(Item::as_str);
+            // Like cargo, path dependencies have no source
+            let source = match (field("git"), field("path"), field("registry")) {
+                (Some(git), _, _) => Some(format!("git+{}", git)),
+                (None, Some(_), _) => None,
+                (None, None, Some(registry)) => Some(format!("registry+{}", registry)),
+                (None, None, None) => Some(CRATES_IO_SOURCE.to_string()),
+            };
+
+            dependencies.push(json!({
+                "name": package,
+                "source": source,
+                "req": req,
+                "kind": kind,
+                "rename": (package != dep_name).then_some(dep_name),
+                "optional": optional,
+                "uses_default_features": item
+                    .get("default-features")
+                    .and_then(Item::as_bool)
+                    .unwrap_or(true),
+                "features": dependency::get_features(item).unwrap_or_default(),
+                "target": target,
+                "registry": null,
+            }));
+        }
+    }
+}
+
+/// The source cargo reports for crates.io dependencies
+const CRATES_IO_SOURCE: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/// cargo metadata reports paths as UTF-8 only
+fn utf8(path: &Path) -> Result<&str> {
+    path.to_str()
+        .with_context(|| format!("Path '{}' is not valid UTF-8", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dependency;
+    use crate::test_util::write_file;
+    use std::collections::{BTreeSet, HashMap};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_from_manifests() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/legacy\"]\n\n[workspace.dependencies]\nserde = \"1.0.200\"\n",
+        )?;
+        write_file(
+            root.join("crates/a/Cargo.toml"),
+            "[package]\nname = \"a\"\nversion.workspace = true\n\n[dependencies]\nserde = { workspace = true }\nold-tokio = { package = \"tokio\", version = \"0.2\", optional = true }\n\n[features]\nlegacy = [\"old-tokio?/rt\"]\n\n[target.'cfg(unix)'.dev-dependencies]\nlibc = \"0.2\"\n",
+        )?;
+        write_file(
+            root.join("crates/b/Cargo.toml"),
+            "[package]\nname = \"b\"\nversion = \"0.3.0\"\n",
+        )?;
+        write_file(
+            root.join("crates/legacy/Cargo.toml"),
+            "[package]\nname = \"legacy\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::create_dir_all(root.join("crates/docs"))?;
+
+        let metadata = from_manifests(Some(&root.join("crates/a/Cargo.toml")))?;
+        assert_eq!(
+            metadata.workspace_root.as_std_path(),
+            fs::canonicalize(root)?
+        );
+        let names: Vec<_> = metadata
+            .workspace_packages()
+            .iter()
+            .map(|package| (package.name.clone(), package.version.to_string()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("a".to_string(), "0.0.0".to_string()),
+                ("b".to_string(), "0.3.0".to_string())
+            ]
+        );
+
+        let deps = dependency::collect_dependencies(metadata.workspace_packages()[0]);
+        assert_eq!(
+            deps,
+            HashMap::from([
+                ("serde".to_string(), "serde".to_string()),
+                ("old-tokio".to_string(), "tokio".to_string()),
+                ("libc".to_string(), "libc".to_string()),
+            ])
+        );
+        let references =
+            dependency::collect_feature_references(&metadata.workspace_packages()[0].features);
+        assert_eq!(
+            references,
+            HashMap::from([("old-tokio".to_string(), BTreeSet::from(["rt".to_string()]))])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_configured_target_dir() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let home = root.join("home/.cargo");
+        let project = root.join("project/crates/a");
+        fs::create_dir_all(&project)?;
+        assert_eq!(configured_target_dir(&project, Some(&home))?, None);
+
+        write_file(
+            home.join("config.toml"),
+            "[build]\ntarget-dir = \"/tmp/shared\"\n",
+        )?;
+        assert_eq!(
+            configured_target_dir(&project, Some(&home))?,
+            Some(PathBuf::from("/tmp/shared"))
+        );
+
+        // The closest configuration wins, relative to the directory holding .cargo
+        write_file(
+            root.join("project/.cargo/config"),
+            "[build]\ntarget-dir = \"out\"\n",
+        )?;
+        assert_eq!(
+            configured_target_dir(&project, Some(&home))?,
+            Some(root.join("project/out"))
+        );
+        write_file(
+            root.join("project/.cargo/config.toml"),
+            "[build]\njobs = 4\n",
+        )?;
+        assert_eq!(
+            configured_target_dir(&project, Some(&home))?,
+            Some(root.join("project/out"))
+        );
+        fs::remove_file(root.join("project/.cargo/config"))?;
+        assert_eq!(
+            configured_target_dir(&project, Some(&home))?,
+            Some(PathBuf::from("/tmp/shared"))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, Item, Value};
+
+use crate::cli::{NewMemberArgs, Opt};
+use crate::config::Config;
+use crate::diagnostic;
+use crate::lock;
+use crate::manifest::Manifest;
+use crate::metadata;
+
+/// Creates a member crate at `args.path` which inherits everything the
+/// workspace offers, and adds it to `workspace.members` unless a pattern
+/// there already covers it
+pub fn run(opt: &Opt, args: &NewMemberArgs) -> Result<()> {
+    let manifest_path = match opt.manifest_path() {
+        Some(path) if path.is_dir() => path.join("Cargo.toml"),
+        Some(path) => path.to_path_buf(),
+        None => std::env::current_dir()
+            .context("Failed to get the current directory")?
+            .join("Cargo.toml"),
+    };
+    let manifest_path = fs::canonicalize(&manifest_path)
+        .with_context(|| format!("Failed to find '{}'", manifest_path.display()))?;
+    let root_manifest_path = metadata::find_workspace_root(&manifest_path)?;
+    let workspace_root = root_manifest_path
+        .parent()
+        .context("The root manifest has no parent directory")?;
+    let _lock = lock::acquire(&metadata::target_directory(workspace_root)?)?;
+    let mut root = Manifest::open(&root_manifest_path)?;
+    if !root.doc.contains_key("workspace") {
+        anyhow::bail!(
+            "'{}' doesn't declare a [workspace]",
+            root_manifest_path.display()
+        );
+    }
+
+    let member_dir = std::env::current_dir()
+        .context("Failed to get the current directory")?
+        .join(&args.path);
+    let relative = relative_to(&member_dir, workspace_root).with_context(|| {
+        format!(
+            "'{}' is outside of the workspace at '{}'",
+            args.path.display(),
+            workspace_root.display()
+        )
+    })?;
+    let member_manifest_path = member_dir.join("Cargo.toml");
+    if member_manifest_path.exists() {
+        anyhow::bail!("'{}' already exists", member_manifest_path.display());
+    }
+    let name = match &args.name {
+        Some(name) => name.clone(),
+        None => member_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("Can't derive a name from '{}'", args.path.display()))?
+            .to_string(),
+    };
+
+    let config = Config::load(opt.config.as_deref(), workspace_root, &root.doc)?;
+    let dependencies = if args.dependencies.is_empty() {
+        &config.starter_dependencies
+    } else {
+        &args.dependencies
+    };
+    let content = member_manifest(&root.doc, &name, dependencies)?;
+
+    info!("Creating '{}'", member_manifest_path.display());
+    fs::create_dir_all(member_dir.join("src"))
+        .with_context(|| format!("Failed to create '{}'", member_dir.display()))?;
+    fs::write(&member_manifest_path, content)
+        .with_context(|| format!("Failed to write '{}'", member_manifest_path.display()))?;
+    let (source, code) = if args.bin {
+        (
+            "main.rs",
+            "fn main() {\n    println!(\"Hello, world!\");\n}\n",
+        )
+    } else {
+        ("lib.rs", "")
+    };
+    let source_path = member_dir.join("src").join(source);
+    if !source_path.exists() {
+        fs::write(&source_path, code)
+            .with_context(|| format!("Failed to write '{}'", source_path.display()))?;
+    }
+
+    let members = metadata::find_members(&root.doc, workspace_root)?;
+    let member_manifest_path = fs::canonicalize(&member_manifest_path)
+        .with_context(|| format!("Failed to find '{}'", member_manifest_path.display()))?;
+    if members.contains(&member_manifest_path) {
+        info!("'{}' is already covered by workspace.members", relative);
+    } else {
+        let members = root.doc["workspace"]
+            .as_table_mut()
+            .ok_or_else(|| diagnostic::unexpected(&["workspace"], "[workspace] isn't a table"))
+            .and_then(|workspace| {
+                workspace
+                    .entry("members")
+                    .or_insert_with(|| toml_edit::value(Array::new()))
+                    .as_array_mut()
+                    .ok_or_else(|| {
+                        diagnostic::unexpected(
+                            &["workspace", "members"],
+                            "workspace.members isn't an array",
+                        )
+                    })
+            });
+        let members = match members {
+            Ok(members) => members,
+            Err(err) => return Err(root.locate(err)),
+        };
+        members.push(relative.as_str());
+        info!("Adding '{}' to workspace.members", relative);
+        root.write(opt.symlinks)?;
+    }
+
+    println!("Created member '{}' in '{}'.", name, relative);
+    Ok(())
+}
+
+/// The manifest of a new member: every `[workspace.package]` field and the
+/// lints are inherited, and so are `dependencies`, which have to be in
+/// `[workspace.dependencies]`
+fn member_manifest(
+    root: &toml_edit::DocumentMut,
+    name: &str,
+    dependencies: &[String],
+) -> Result<String> {
+    let workspace = root.get("workspace");
+    let inherited_fields: Vec<&str> = workspace
+        .and_then(|workspace| workspace.get("package"))
+        .and_then(Item::as_table_like)
+        .map(|package| package.iter().map(|(key, _)| key).collect())
+        .unwrap_or_default();
+    let workspace_deps = workspace
+        .and_then(|workspace| workspace.get("dependencies"))
+        .and_then(Item::as_table_like);
+    let missing: Vec<&str> = dependencies
+        .iter()
+        .map(String::as_str)
+        .filter(|dep| !workspace_deps.is_some_and(|deps| deps.contains_key(dep)))
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!("Not in [workspace.dependencies]: {}", missing.join(", "));
+    }
+
+    let mut content = format!("[package]\nname = {}\n", Value::from(name));
+    for field in ["version", "edition"] {
+        if !inherited_fields.contains(&field) {
+            let default = if field == "version" { "0.1.0" } else { "2021" };
+            content.push_str(&format!("{} = \"{}\"\n", field, default));
+        }
+    }
+    for field in inherited_fields {
+        content.push_str(&format!("{}.workspace = true\n", field));
+    }
+    if workspace.is_some_and(|workspace| workspace.get("lints").is_some()) {
+        content.push_str("\n[lints]\nworkspace = true\n");
+    }
+    content.push_str("\n[dependencies]\n");
+    for dep in dependencies {
+        content.push_str(&format!("{} = {{ workspace = true }}\n", dep));
+    }
+    Ok(content)
+}
+
+/// `path` relative to `base` with `/` separators, once `.` and `..` are
+/// resolved, or `None` if it isn't below `base`
+fn relative_to(path: &Path, base: &Path) -> Option<String> {
+    let path: PathBuf = crate::dependency::normalize_path(path);
+    let relative = path.strip_prefix(base).ok()?;
+    if relative.as_os_str().is_empty() {
+        return None;
+    }
+    Some(relative.to_string_lossy().replace('\\', "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_new_member() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = fs::canonicalize(temp_dir.path())?;
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"apps/*\"]\n\n[workspace.package]\nversion = \"0.3.0\"\nedition = \"2021\"\nlicense = \"MIT\"\n\n[workspace.lints.rust]\nunsafe_code = \"forbid\"\n\n[workspace.dependencies]\nserde = \"1\"\nanyhow = \"1\"\n",
+        )?;
+
+        let new_member = |path: &Path, extra: &[&str]| -> Result<()> {
+            let mut args = vec![
+                "cargo-consolidate".to_string(),
+                "--manifest-path".to_string(),
+                root.join("Cargo.toml").to_string_lossy().to_string(),
+                "new-member".to_string(),
+                path.to_string_lossy().to_string(),
+            ];
+            args.extend(extra.iter().map(|arg| arg.to_string()));
+            let opt = Opt::parse_from(args);
+            let Some(crate::cli::Command::NewMember(args)) = &opt.command else {
+                unreachable!()
+            };
+            run(&opt, args)
+        };
+
+        new_member(&root.join("libs/core"), &["--dep", "serde"])?;
+        assert_eq!(
+            fs::read_to_string(root.join("libs/core/Cargo.toml"))?,
+            "[package]\nname = \"core\"\nversion.workspace = true\nedition.workspace = true\nlicense.workspace = true\n\n[lints]\nworkspace = true\n\n[dependencies]\nserde = { workspace = true }\n"
+        );
+        assert!(root.join("libs/core/src/lib.rs").exists());
+        let manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(manifest.starts_with("[workspace]\nmembers = [\"apps/*\", \"libs/core\"]\n"));
+
+        // Covered by `apps/*` already
+        new_member(&root.join("apps/cli"), &["--bin"])?;
+        assert!(root.join("apps/cli/src/main.rs").exists());
+        assert_eq!(fs::read_to_string(root.join("Cargo.toml"))?, manifest);
+
+        assert!(new_member(&root.join("apps/web"), &["--dep", "tokio"]).is_err());
+        assert!(new_member(&root.join("libs/core"), &[]).is_err());
+        Ok(())
+    }
+}
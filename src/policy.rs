@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use semver::VersionReq;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use toml_edit::{DocumentMut, Item};
+
+use crate::dependency;
+use crate::diagnostic;
+use crate::version::lowest_version;
+
+/// Name used in violations for entries of `[workspace.dependencies]`
+pub const WORKSPACE: &str = "[workspace.dependencies]";
+
+/// Allowed version requirements per crate, read from a `--policy` file:
+///
+/// ```toml
+/// [allowed]
+/// serde = ">=1.0.150, <2"
+/// ```
+#[derive(Debug, Default)]
+pub struct Policy {
+    allowed: BTreeMap<String, VersionReq>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    allowed: BTreeMap<String, String>,
+}
+
+/// A requirement outside of the policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The member declaring the requirement, or [`WORKSPACE`]
+    pub member: String,
+    pub dependency: String,
+    pub requirement: String,
+    pub allowed: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: '{}' requires '{}', the policy allows '{}'",
+            self.member, self.dependency, self.requirement, self.allowed
+        )
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        Self::parse(&content)
+            .map_err(|err| diagnostic::locate(err, path, &content))
+            .with_context(|| format!("Failed to parse '{}'", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let file: PolicyFile = toml_edit::de::from_str(content)?;
+        let allowed = file
+            .allowed
+            .into_iter()
+            .map(|(name, req)| {
+                let parsed = VersionReq::parse(&req).map_err(|err| {
+                    diagnostic::unexpected(
+                        &["allowed", &name],
+                        format!("Invalid requirement '{}' for '{}': {}", req, name, err),
+                    )
+                })?;
+                Ok((name, parsed))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { allowed })
+    }
+
+    /// Returns the requirement the policy allows for `package`
+    pub fn allowed(&self, package: &str) -> Option<&VersionReq> {
+        self.allowed.get(package)
+    }
+
+    /// Checks a dependency spec of `member`. A requirement is allowed if the
+    /// lowest version it accepts is allowed by the policy
+    pub fn check(&self, member: &str, dep_name: &str, item: &Item) -> Option<Violation> {
+        // Renamed dependencies are governed by the package they refer to
+        let package = item
+            .as_table_like()
+            .and_then(|tbl| tbl.get("package"))
+            .and_then(Item::as_str)
+            .unwrap_or(dep_name);
+        let allowed = self.allowed.get(package)?;
+        let requirement = dependency::get_version(item)?;
+        let lowest = VersionReq::parse(&requirement)
+            .map(|req| lowest_version(&req))
+            .ok()?;
+        if allowed.matches(&lowest) {
+            return None;
+        }
+
+        Some(Violation {
+            member: member.to_string(),
+            dependency: package.to_string(),
+            requirement,
+            allowed: allowed.to_string(),
+        })
+    }
+
+    /// Checks the specs a member declares itself, ignoring inherited ones
+    pub fn check_member(&self, member: &str, doc: &DocumentMut) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for section in dependency::DEPENDENCY_SECTIONS {
+            let Some(deps) = doc.get(section).and_then(Item::as_table_like) else {
+                continue;
+            };
+            for (dep_name, item) in deps.iter() {
+                if dependency::is_workspace_inherited(item) {
+                    continue;
+                }
+                violations.extend(self.check(member, dep_name, item));
+            }
+        }
+        violations
+    }
+
+    /// Checks the entries of `[workspace.dependencies]`
+    pub fn check_workspace(&self, doc: &DocumentMut) -> Vec<Violation> {
+        doc.get("workspace")
+            .and_then(|workspace| workspace.get("dependencies"))
+            .and_then(Item::as_table_like)
+            .into_iter()
+            .flat_map(|deps| deps.iter())
+            .filter_map(|(dep_name, item)| self.check(WORKSPACE, dep_name, item))
+            .collect()
+    }
+}
+
+/// Lists `violations` below `headline`, one per line
+pub fn summarize(headline: &str, violations: &[Violation]) -> String {
+    let mut summary = format!("{} ({}):", headline, violations.len());
+    for violation in violations {
+        summary.push_str(&format!("\n  - {}", violation));
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_member() -> Result<()> {
+        let policy = Policy::parse(
+            r#"
+            [allowed]
+            serde = ">=1.0.150, <2"
+            tokio = "1"
+            "#,
+        )?;
+        let doc = r#"
+            [dependencies]
+            serde = "1.0.100"
+            tokio = { version = "1.38", features = ["full"] }
+            anyhow = "1"
+
+            [dev-dependencies]
+            tokio-old = { package = "tokio", version = "0.2" }
+            serde_json = { workspace = true }
+        "#
+        .parse::<DocumentMut>()?;
+
+        let violations = policy.check_member("api", &doc);
+        let rendered: Vec<_> = violations.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            rendered,
+            vec![
+                "api: 'serde' requires '1.0.100', the policy allows '>=1.0.150, <2'",
+                "api: 'tokio' requires '0.2', the policy allows '^1'",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use log::info;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+
+use crate::cli::Opt;
+
+/// How often progress is logged when stderr isn't a terminal
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+thread_local! {
+    /// The phase started last, named in the error should a run fail
+    static PHASE: Cell<Option<&'static str>> = const { Cell::new(None) };
+    /// The time spent in each phase so far, for --timings
+    static TIMINGS: RefCell<Timings> = RefCell::new(Timings::default());
+}
+
+/// Time spent in a phase, summed over every time it ran, e.g. once per
+/// workspace with --recursive
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Timing {
+    pub phase: &'static str,
+    pub seconds: f64,
+    /// The steps done in it, like the members analyzed
+    pub steps: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Timings {
+    pub phases: Vec<Timing>,
+    pub total_seconds: f64,
+}
+
+impl Timings {
+    fn record(&mut self, phase: &'static str, elapsed: Duration, steps: usize) {
+        let seconds = elapsed.as_secs_f64();
+        self.total_seconds += seconds;
+        match self.phases.iter_mut().find(|timing| timing.phase == phase) {
+            Some(timing) => {
+                timing.seconds += seconds;
+                timing.steps += steps;
+            }
+            None => self.phases.push(Timing {
+                phase,
+                seconds,
+                steps,
+            }),
+        }
+    }
+}
+
+/// Returns the time spent in each phase so far and forgets it
+pub fn take_timings() -> Timings {
+    TIMINGS.with(|timings| timings.take())
+}
+
+/// Starts a phase too short to show progress for
+pub fn enter(phase: &'static str) {
+    PHASE.with(|current| current.set(Some(phase)));
+}
+
+/// Returns the phase started last and forgets it
+pub fn take_phase() -> Option<&'static str> {
+    PHASE.with(Cell::take)
+}
+
+/// Progress through one phase of a run. Drawn as a bar on a terminal,
+/// logged every few seconds otherwise, or with `--verbose` where the bar
+/// would get in the way of the log
+pub struct Progress {
+    phase: &'static str,
+    bar: Option<ProgressBar>,
+    total: Option<usize>,
+    done: usize,
+    started: Instant,
+    last_log: Instant,
+}
+
+impl Progress {
+    /// Starts a phase of `total` steps, or of an unknown number of them
+    pub fn start(opt: &Opt, phase: &'static str, total: Option<usize>) -> Self {
+        let show_bar = !opt.no_progress && opt.verbose == 0 && std::io::stderr().is_terminal();
+        let bar = show_bar.then(|| new_bar(phase, total));
+        enter(phase);
+        match total {
+            Some(total) => info!("{} ({})", phase, total),
+            None => info!("{}", phase),
+        }
+
+        Self {
+            phase,
+            bar,
+            total,
+            done: 0,
+            started: Instant::now(),
+            last_log: Instant::now(),
+        }
+    }
+
+    /// Marks one step as done, `item` names what it was about
+    pub fn inc(&mut self, item: &str) {
+        self.done += 1;
+        if let Some(bar) = &self.bar {
+            bar.set_message(item.to_string());
+            bar.inc(1);
+        } else if self.last_log.elapsed() >= LOG_INTERVAL {
+            match self.total {
+                Some(total) => info!("{}: {}/{}", self.phase, self.done, total),
+                None => info!("{}: {}", self.phase, self.done),
+            }
+            self.last_log = Instant::now();
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // Errors end a phase early, so don't leave a half-full bar behind
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+        let elapsed = self.started.elapsed();
+        TIMINGS.with(|timings| timings.borrow_mut().record(self.phase, elapsed, self.done));
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<14} {:>9} {:>6} {:>7}",
+            "Phase", "Time", "Share", "Steps"
+        )?;
+        for timing in &self.phases {
+            let share = if self.total_seconds > 0.0 {
+                timing.seconds / self.total_seconds * 100.0
+            } else {
+                0.0
+            };
+            writeln!(
+                f,
+                "{:<14} {:>8.3}s {:>5.0}% {:>7}",
+                timing.phase, timing.seconds, share, timing.steps
+            )?;
+        }
+        writeln!(f, "{:<14} {:>8.3}s", "Total", self.total_seconds)
+    }
+}
+
+fn new_bar(phase: &'static str, total: Option<usize>) -> ProgressBar {
+    let bar = match total {
+        Some(total) => ProgressBar::new(total as u64).with_style(
+            ProgressStyle::with_template("{prefix:>12} [{bar:30}] {pos}/{len} {wide_msg}")
+                .unwrap()
+                .progress_chars("=> "),
+        ),
+        None => {
+            let bar = ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template("{prefix:>12} {spinner} {wide_msg}").unwrap(),
+            );
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        }
+    };
+    bar.with_prefix(phase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_progress_without_bar() {
+        let opt = Opt::parse_from(["cargo-consolidate", "--no-progress"]);
+        let mut progress = Progress::start(&opt, "Analyzing", Some(2));
+        assert!(progress.bar.is_none());
+        progress.inc("a");
+        progress.inc("b");
+        assert_eq!(progress.done, 2);
+    }
+
+    #[test]
+    fn test_timings() {
+        let opt = Opt::parse_from(["cargo-consolidate", "--no-progress"]);
+        take_timings();
+        for steps in [2, 3] {
+            let mut progress = Progress::start(&opt, "Analyzing", Some(steps));
+            for _ in 0..steps {
+                progress.inc("member");
+            }
+        }
+        drop(Progress::start(&opt, "Writing", None));
+
+        let timings = take_timings();
+        let phases: Vec<_> = timings
+            .phases
+            .iter()
+            .map(|timing| (timing.phase, timing.steps))
+            .collect();
+        assert_eq!(phases, vec![("Analyzing", 5), ("Writing", 0)]);
+        let rendered = timings.to_string();
+        assert!(rendered.starts_with("Phase"));
+        assert!(rendered.lines().last().unwrap().starts_with("Total"));
+        assert!(take_timings().phases.is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use log::info;
+use tempfile::TempDir;
+
+use crate::git::git;
+
+/// Shallow-clones `url` at `rev`, or its default branch, into a temporary
+/// directory which is removed again once the returned handle is dropped
+pub fn clone(url: &str, rev: Option<&str>) -> Result<TempDir> {
+    let dir = tempfile::Builder::new()
+        .prefix("cargo-consolidate-")
+        .tempdir()
+        .context("Failed to create a temporary directory")?;
+    info!("Cloning '{}' into '{}'", url, dir.path().display());
+
+    match rev {
+        // `clone --branch` only takes branches and tags, fetching works for commits too
+        Some(rev) => {
+            git(dir.path(), &["init", "--quiet"])?;
+            git(dir.path(), &["remote", "add", "origin", url])?;
+            git(
+                dir.path(),
+                &["fetch", "--quiet", "--depth", "1", "origin", rev],
+            )?;
+            git(dir.path(), &["checkout", "--quiet", "FETCH_HEAD"])?;
+        }
+        None => {
+            git(
+                dir.path(),
+                &["clone", "--quiet", "--depth", "1", "--", url, "."],
+            )?;
+        }
+    }
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_clone_rev() -> Result<()> {
+        let origin = TempDir::new()?;
+        let commit = |content: &str| -> Result<()> {
+            fs::write(origin.path().join("Cargo.toml"), content)?;
+            git(origin.path(), &["add", "Cargo.toml"])?;
+            git(
+                origin.path(),
+                &[
+                    "-c",
+                    "user.name=test",
+                    "-c",
+                    "user.email=test@example.com",
+                    "commit",
+                    "--quiet",
+                    "-m",
+                    content,
+                ],
+            )?;
+            Ok(())
+        };
+        git(origin.path(), &["init", "--quiet"])?;
+        commit("[workspace]\n")?;
+        git(origin.path(), &["tag", "first"])?;
+        commit("[workspace]\nmembers = []\n")?;
+
+        let url = format!("file://{}", origin.path().display());
+        let latest = clone(&url, None)?;
+        assert_eq!(
+            fs::read_to_string(latest.path().join("Cargo.toml"))?,
+            "[workspace]\nmembers = []\n"
+        );
+        let first = clone(&url, Some("first"))?;
+        assert_eq!(
+            fs::read_to_string(first.path().join("Cargo.toml"))?,
+            "[workspace]\n"
+        );
+        Ok(())
+    }
+}
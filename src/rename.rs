@@ -0,0 +1,233 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::fs;
+use toml_edit::{DocumentMut, InlineTable, Item, Value};
+
+use crate::cli::{Opt, RenameArgs};
+use crate::dependency;
+use crate::lock;
+use crate::manifest::Manifest;
+use crate::metadata;
+
+/// Renames the `[workspace.dependencies]` entry `args.old` to `args.new`,
+/// together with every member entry inheriting it
+pub fn run(opt: &Opt, args: &RenameArgs) -> Result<()> {
+    let manifest_path = match opt.manifest_path() {
+        Some(path) if path.is_dir() => path.join("Cargo.toml"),
+        Some(path) => path.to_path_buf(),
+        None => std::env::current_dir()
+            .context("Failed to get the current directory")?
+            .join("Cargo.toml"),
+    };
+    let manifest_path = fs::canonicalize(&manifest_path)
+        .with_context(|| format!("Failed to find '{}'", manifest_path.display()))?;
+    let root_manifest_path = metadata::find_workspace_root(&manifest_path)?;
+    let workspace_root = root_manifest_path
+        .parent()
+        .context("The root manifest has no parent directory")?;
+    let _lock = lock::acquire(&metadata::target_directory(workspace_root)?)?;
+    let mut root = Manifest::open(&root_manifest_path)?;
+
+    rename_workspace_entry(&mut root.doc, &args.old, &args.new).with_context(|| {
+        format!(
+            "Failed to rename '{}' in '{}'",
+            args.old,
+            root.path().display()
+        )
+    })?;
+
+    let mut members = Vec::new();
+    for member_path in metadata::find_members(&root.doc, workspace_root)? {
+        members.push(Manifest::open(&member_path)?);
+    }
+    // A root package inherits like any other member
+    let mut renamed = usize::from(rename_in_member(&mut root, args)?);
+    for member in &mut members {
+        renamed += usize::from(rename_in_member(member, args)?);
+    }
+
+    for member in &members {
+        member.write(opt.symlinks)?;
+    }
+    root.write(opt.symlinks)?;
+    info!("Renamed '{}' to '{}'", args.old, args.new);
+    println!(
+        "Renamed '{}' to '{}' in [workspace.dependencies] and {} member(s). Code using `{}` needs to use `{}` now.",
+        args.old,
+        args.new,
+        renamed,
+        args.old.replace('-', "_"),
+        args.new.replace('-', "_")
+    );
+    Ok(())
+}
+
+/// Renames the entries of `member` inheriting `args.old`, and unless
+/// `--no-features` is passed the references to them. Returns whether there
+/// were any
+fn rename_in_member(member: &mut Manifest, args: &RenameArgs) -> Result<bool> {
+    let (renamed, own) = rename_member_entries(&mut member.doc, &args.old, &args.new)
+        .with_context(|| {
+            format!(
+                "Failed to rename '{}' in '{}'",
+                args.old,
+                member.path().display()
+            )
+        })?;
+    for section in own {
+        warn!(
+            "'{}' declares '{}' in [{}] itself, leaving it as it is",
+            member.path().display(),
+            args.old,
+            section
+        );
+    }
+    if renamed > 0 && !args.no_features {
+        dependency::rename_feature_references(&mut member.doc, &args.old, &args.new, true);
+    }
+    Ok(renamed > 0)
+}
+
+/// Renames the key of the workspace entry, keeping it pointed at the same
+/// package with a `package` field unless the new key is the package name
+fn rename_workspace_entry(doc: &mut DocumentMut, old: &str, new: &str) -> Result<()> {
+    let ws_deps = doc
+        .get_mut("workspace")
+        .and_then(|workspace| workspace.get_mut("dependencies"))
+        .and_then(Item::as_table_like_mut)
+        .context("There's no [workspace.dependencies] table")?;
+    if ws_deps.contains_key(new) {
+        anyhow::bail!("[workspace.dependencies] already has an entry '{}'", new);
+    }
+    let dep_item = dependency::rename_key(ws_deps, old, new)
+        .with_context(|| format!("[workspace.dependencies] has no entry '{}'", old))?;
+
+    let package = dep_item
+        .get("package")
+        .and_then(Item::as_str)
+        .unwrap_or(old)
+        .to_string();
+    if let Some(version) = dep_item.as_str().filter(|_| package != new) {
+        let mut tbl = InlineTable::new();
+        tbl.insert("package", package.as_str().into());
+        tbl.insert("version", version.into());
+        let decor = dep_item.as_value().map(|value| value.decor().clone());
+        *dep_item = Item::Value(Value::InlineTable(tbl));
+        if let (Some(value), Some(decor)) = (dep_item.as_value_mut(), decor) {
+            *value.decor_mut() = decor;
+        }
+    } else if let Some(tbl) = dep_item.as_table_like_mut() {
+        if package == new {
+            tbl.remove("package");
+        } else if !tbl.contains_key("package") {
+            tbl.insert("package", toml_edit::value(package));
+            tbl.fmt();
+        }
+    }
+    Ok(())
+}
+
+/// Renames the entries of a member inheriting `old`, including those for
+/// specific targets. Returns how many were renamed and the sections
+/// declaring `old` without inheriting it, which are left alone
+fn rename_member_entries(
+    doc: &mut DocumentMut,
+    old: &str,
+    new: &str,
+) -> Result<(usize, Vec<String>)> {
+    let mut renamed = 0;
+    let mut own = Vec::new();
+    let mut rename = |table: &mut dyn toml_edit::TableLike, prefix: &str| -> Result<()> {
+        for section in dependency::DEPENDENCY_SECTIONS {
+            let Some(dep_table) = table.get_mut(section).and_then(Item::as_table_like_mut) else {
+                continue;
+            };
+            match dep_table.get(old) {
+                Some(_) if dep_table.contains_key(new) => {
+                    anyhow::bail!("[{}{}] already has an entry '{}'", prefix, section, new);
+                }
+                Some(item) if dependency::is_workspace_inherited(item) => {
+                    dependency::rename_key(dep_table, old, new);
+                    renamed += 1;
+                }
+                Some(_) => own.push(format!("{}{}", prefix, section)),
+                None => {}
+            }
+        }
+        Ok(())
+    };
+
+    rename(doc.as_table_mut(), "")?;
+    let targets = doc.get_mut("target").and_then(Item::as_table_like_mut);
+    for (target, table) in targets.into_iter().flat_map(|targets| targets.iter_mut()) {
+        if let Some(table) = table.as_table_like_mut() {
+            rename(table, &format!("target.{}.", target))?;
+        }
+    }
+    Ok((renamed, own))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rename() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.dependencies]\n# auth\njwt = \"9\"\nserde = \"1\"\n",
+        )?;
+        write_file(
+            root.join("a/Cargo.toml"),
+            "[package]\nname = \"a\"\n\n[dependencies]\njwt = { workspace = true, optional = true }\nserde = { workspace = true }\n\n\
+             [target.'cfg(unix)'.dev-dependencies]\njwt = { workspace = true }\n\n\
+             [features]\nauth = [\"dep:jwt\", \"jwt/use_pem\", \"jwt?/rust_crypto\", \"serde\"]\n",
+        )?;
+        write_file(
+            root.join("b/Cargo.toml"),
+            "[package]\nname = \"b\"\n\n[dependencies]\njwt = \"8\"\n",
+        )?;
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        let args = RenameArgs {
+            old: "jwt".to_string(),
+            new: "jsonwebtoken".to_string(),
+            no_features: false,
+        };
+        run(&opt, &args)?;
+
+        let root_manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(root_manifest.contains(
+            "# auth\njsonwebtoken = { package = \"jwt\", version = \"9\" }\nserde = \"1\"\n"
+        ));
+        assert_eq!(
+            fs::read_to_string(root.join("a/Cargo.toml"))?,
+            "[package]\nname = \"a\"\n\n[dependencies]\njsonwebtoken = { workspace = true, optional = true }\nserde = { workspace = true }\n\n\
+             [target.'cfg(unix)'.dev-dependencies]\njsonwebtoken = { workspace = true }\n\n\
+             [features]\nauth = [\"dep:jsonwebtoken\", \"jsonwebtoken/use_pem\", \"jsonwebtoken?/rust_crypto\", \"serde\"]\n"
+        );
+        // Members declaring it themselves are left alone
+        assert!(fs::read_to_string(root.join("b/Cargo.toml"))?.contains("jwt = \"8\""));
+
+        // Back to the package name the `package` field is dropped again
+        let args = RenameArgs {
+            old: "jsonwebtoken".to_string(),
+            new: "jwt".to_string(),
+            no_features: true,
+        };
+        run(&opt, &args)?;
+        assert!(
+            fs::read_to_string(root.join("Cargo.toml"))?.contains("jwt = { version = \"9\" }\n")
+        );
+        Ok(())
+    }
+}
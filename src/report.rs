@@ -0,0 +1,698 @@
+use anyhow::Result;
+use cargo_metadata::{DependencyKind, Metadata};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item};
+
+use crate::baseline::Baseline;
+use crate::cli::{CargoOptions, CheckArgs, CheckFormat, Opt, ReportArgs, WorkspaceReportFormat};
+use crate::config::Config;
+use crate::dependency::{self, Kinds};
+use crate::diagnostic::{self, Diagnostic, Fix};
+use crate::editions::{self, Editions};
+use crate::lock;
+use crate::manifest::Manifest;
+use crate::policy::{self, Policy, Violation};
+use crate::remote;
+use crate::sbom;
+use crate::sources::{self, SourceReplacement};
+use crate::workspace;
+
+/// The consolidation status of a workspace, without changing anything
+#[derive(Debug, Default, Serialize)]
+pub struct WorkspaceReport {
+    pub manifest_path: PathBuf,
+    pub members: usize,
+    /// Dependencies used by several members, or listed in
+    /// `[workspace.dependencies]`, which some member still declares itself
+    pub unconsolidated: Vec<String>,
+    /// Dependencies declared with different version requirements
+    pub skew: Vec<Skew>,
+    /// Sources replaced in the cargo config, e.g. by vendored crates, which
+    /// the versions cargo sees come from
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub source_replacements: Vec<SourceReplacement>,
+    /// The edition and rust-version of each member
+    pub editions: Editions,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Skew {
+    pub dependency: String,
+    pub requirements: Vec<String>,
+}
+
+#[derive(Default)]
+struct Usage {
+    users: usize,
+    kinds: Kinds,
+    own_specs: usize,
+    requirements: BTreeSet<String>,
+}
+
+/// Prints the report of the local workspace, or of a fresh clone with `--git`
+pub fn run(opt: &Opt, args: &ReportArgs) -> Result<()> {
+    let checkout = match &args.git {
+        Some(url) => Some(remote::clone(url, args.rev.as_deref())?),
+        None => None,
+    };
+    let checkout_manifest = checkout
+        .as_ref()
+        .map(|checkout| checkout.path().join("Cargo.toml"));
+    let manifest_path = checkout_manifest.as_deref().or(opt.manifest_path());
+
+    let sbom: Option<fn(&sbom::Inventory, u64) -> serde_json::Value> = match args.format {
+        WorkspaceReportFormat::Cyclonedx => Some(sbom::cyclonedx),
+        WorkspaceReportFormat::Spdx => Some(sbom::spdx),
+        _ => None,
+    };
+    if let Some(format) = sbom {
+        let inventory = sbom::inventory(manifest_path, &opt.cargo)?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&format(&inventory, sbom::created()))?
+        );
+        return Ok(());
+    }
+
+    if args.fix_editions {
+        let metadata = workspace::load_metadata(manifest_path, &opt.cargo)?;
+        let _lock = lock::acquire(metadata.target_directory.as_std_path())?;
+        let mut workspace = read_workspace(&metadata)?;
+        let found = Editions::find(&workspace.root.doc, &workspace.named_docs());
+        let mut members: Vec<_> = workspace
+            .names
+            .drain(..)
+            .zip(workspace.members.drain(..))
+            .collect();
+        let fixed = editions::fix(
+            &workspace.root.doc,
+            &mut members,
+            &found,
+            &opt.cargo,
+            opt.symlinks,
+        )?;
+        println!(
+            "Switched {} member(s) to the edition and rust-version of the workspace.",
+            fixed.len()
+        );
+    }
+
+    let mut report = analyze(manifest_path, opt)?;
+    if let Some(url) = &args.git {
+        // The checkout is gone after this, so name the repository instead
+        report.manifest_path = PathBuf::from(url);
+    }
+    match args.format {
+        WorkspaceReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        _ => print!("{}", report),
+    }
+    Ok(())
+}
+
+/// Analyzes the workspace at `manifest_path`, or the one containing the
+/// current directory, with the thresholds of its configuration
+pub fn analyze(manifest_path: Option<&Path>, opt: &Opt) -> Result<WorkspaceReport> {
+    let workspace = load_workspace(manifest_path, &opt.cargo)?;
+    let config = workspace.config(opt)?;
+    let mut report = analyze_manifests(
+        &workspace.root.doc,
+        &workspace.consolidated_docs(opt, &config),
+        &config,
+        opt.group_all,
+    );
+    report.manifest_path = workspace.root.path().to_path_buf();
+    report.editions = Editions::find(&workspace.root.doc, &workspace.named_docs());
+    if let Some(workspace_root) = workspace.root.path().parent() {
+        report.source_replacements = sources::detect(workspace_root)?;
+    }
+    Ok(report)
+}
+
+/// Fails if any dependency is left to consolidate or any requirement falls
+/// outside of the `--policy`
+pub fn check(opt: &Opt, args: &CheckArgs) -> Result<()> {
+    let workspace = load_workspace(opt.manifest_path(), &opt.cargo)?;
+    let config = workspace.config(opt)?;
+    let mut report = analyze_manifests(
+        &workspace.root.doc,
+        &workspace.consolidated_docs(opt, &config),
+        &config,
+        opt.group_all,
+    );
+    let policy = match &opt.policy {
+        Some(path) => Policy::load(path)?,
+        None => Policy::default(),
+    };
+    let mut violations = policy.check_workspace(&workspace.root.doc);
+    for (name, member) in workspace.names.iter().zip(&workspace.members) {
+        violations.extend(policy.check_member(name, &member.doc));
+    }
+
+    let current = Baseline::from_findings(&report.unconsolidated, &violations);
+    let baseline = match &args.baseline {
+        Some(path) if args.write_baseline => {
+            current.write(path)?;
+            println!(
+                "Recorded {} finding(s) in '{}'.",
+                current.len(),
+                path.display()
+            );
+            return Ok(());
+        }
+        Some(path) => {
+            let mut baseline = Baseline::load(path)?;
+            let fixed = baseline.fixed(&current);
+            if args.prune_baseline {
+                baseline.prune(&fixed);
+                baseline.write(path)?;
+                info!(
+                    "Removed {} fixed finding(s) from '{}'",
+                    fixed.len(),
+                    path.display()
+                );
+            } else if !fixed.is_empty() {
+                warn!(
+                    "{} finding(s) in '{}' are fixed, remove them with --prune-baseline",
+                    fixed.len(),
+                    path.display()
+                );
+            }
+            baseline
+        }
+        None => Baseline::default(),
+    };
+    report
+        .unconsolidated
+        .retain(|dep| !baseline.accepts_unconsolidated(dep));
+    violations.retain(|violation| !baseline.accepts_violation(violation));
+
+    if args.format == CheckFormat::Json {
+        let diagnostics = diagnostics(&workspace, &report, &policy, &baseline);
+        println!("{}", serde_json::to_string_pretty(&diagnostics)?);
+        if !diagnostics.is_empty() {
+            anyhow::bail!("Found {} problem(s)", diagnostics.len());
+        }
+        return Ok(());
+    }
+
+    let mut problems = Vec::new();
+    if !report.unconsolidated.is_empty() {
+        problems.push(format!(
+            "{} dependencies left to consolidate: {}",
+            report.unconsolidated.len(),
+            report.unconsolidated.join(", ")
+        ));
+    }
+    if !violations.is_empty() {
+        problems.push(policy::summarize("Version policy violated", &violations));
+    }
+    if !problems.is_empty() {
+        anyhow::bail!(problems.join("\n"));
+    }
+
+    println!(
+        "All dependencies of '{}' are consolidated.",
+        workspace.root.path().display()
+    );
+    Ok(())
+}
+
+/// The root manifest and the member manifests of a workspace
+struct Workspace {
+    root: Manifest,
+    names: Vec<String>,
+    /// The directories of the members, relative to the workspace root
+    dirs: Vec<PathBuf>,
+    members: Vec<Manifest>,
+}
+
+impl Workspace {
+    fn docs(&self) -> Vec<&DocumentMut> {
+        self.members.iter().map(|member| &member.doc).collect()
+    }
+
+    /// The configuration consolidating this workspace would use
+    fn config(&self, opt: &Opt) -> Result<Config> {
+        let workspace_root = self.root.path().parent().unwrap_or(Path::new(""));
+        Config::load(opt.config.as_deref(), workspace_root, &self.root.doc)?
+            .with_preset(opt.preset.as_deref())
+    }
+
+    /// The members consolidating this workspace would edit, leaving out the
+    /// ones excluded with `--exclude-member` or an ignore pattern
+    fn consolidated_docs(&self, opt: &Opt, config: &Config) -> Vec<&DocumentMut> {
+        self.names
+            .iter()
+            .zip(&self.dirs)
+            .zip(&self.members)
+            .filter(|((name, dir), _)| {
+                !opt.exclude_member.contains(name) && !config.is_ignored(name, dir)
+            })
+            .map(|(_, member)| &member.doc)
+            .collect()
+    }
+
+    fn named_docs(&self) -> Vec<(&str, &DocumentMut)> {
+        self.names
+            .iter()
+            .map(String::as_str)
+            .zip(self.docs())
+            .collect()
+    }
+}
+
+fn load_workspace(manifest_path: Option<&Path>, cargo: &CargoOptions) -> Result<Workspace> {
+    read_workspace(&workspace::load_metadata(manifest_path, cargo)?)
+}
+
+fn read_workspace(metadata: &Metadata) -> Result<Workspace> {
+    let root = Manifest::open(metadata.workspace_root.join("Cargo.toml").as_std_path())?;
+
+    let mut names = Vec::new();
+    let mut dirs = Vec::new();
+    let mut members = Vec::new();
+    for package in workspace::workspace_packages(metadata)? {
+        let manifest_path = package.manifest_path.as_std_path();
+        let dir = manifest_path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(&metadata.workspace_root).ok())
+            .unwrap_or(Path::new(""));
+        names.push(package.name.clone());
+        dirs.push(dir.to_path_buf());
+        members.push(Manifest::open(manifest_path)?);
+    }
+    Ok(Workspace {
+        root,
+        names,
+        dirs,
+        members,
+    })
+}
+
+/// Every finding of `check`, pointing at the entry it is about
+fn diagnostics(
+    workspace: &Workspace,
+    report: &WorkspaceReport,
+    policy: &Policy,
+    baseline: &Baseline,
+) -> Vec<Diagnostic> {
+    let root = &workspace.root;
+    let ws_deps = root
+        .doc
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"))
+        .and_then(Item::as_table_like);
+    let mut diagnostics = Vec::new();
+    let violation = |manifest: &Manifest, table: &[&str], violation: Violation| Diagnostic {
+        code: "policy-violation",
+        span: diagnostic::entry_span(manifest.source(), table, &violation.dependency),
+        message: violation.to_string(),
+        manifest: manifest.path().to_path_buf(),
+        fix: Some(Fix {
+            message: format!("Require a version the policy allows: {}", violation.allowed),
+            replacement: None,
+        }),
+    };
+    let violations = policy.check_workspace(&root.doc).into_iter();
+    for found in violations.filter(|found| !baseline.accepts_violation(found)) {
+        diagnostics.push(violation(root, &["workspace", "dependencies"], found));
+    }
+
+    for (name, member) in workspace.names.iter().zip(&workspace.members) {
+        for section in dependency::DEPENDENCY_SECTIONS {
+            let Some(deps) = member.doc.get(section).and_then(Item::as_table_like) else {
+                continue;
+            };
+            for (dep, item) in deps.iter() {
+                let span = diagnostic::entry_span(member.source(), &[section], dep);
+                let finding = |code, message: String, fix: Option<Fix>| Diagnostic {
+                    code,
+                    message,
+                    manifest: member.path().to_path_buf(),
+                    span: span.clone(),
+                    fix,
+                };
+                // Header tables span several lines, only inline specs are
+                // replaced as a whole
+                let replace = |spec: &Item| {
+                    item.is_value()
+                        .then(|| format!("{} = {}", dep, spec.to_string().trim()))
+                };
+
+                if dependency::is_workspace_inherited(item) {
+                    let leftover: Vec<&str> = dependency::INHERITED_KEYS
+                        .into_iter()
+                        .filter(|key| item.get(key).is_some())
+                        .collect();
+                    if !leftover.is_empty() {
+                        let mut cleaned = item.clone();
+                        if let Some(tbl) = cleaned.as_table_like_mut() {
+                            for key in &leftover {
+                                tbl.remove(key);
+                            }
+                        }
+                        if let Some(tbl) = cleaned.as_inline_table_mut() {
+                            tbl.fmt();
+                        }
+                        diagnostics.push(finding(
+                            "leftover-keys",
+                            format!(
+                                "'{}' is inherited from the workspace, so cargo ignores its {}",
+                                dep,
+                                leftover.join(", ")
+                            ),
+                            Some(Fix {
+                                message: "Remove the ignored keys, e.g. with --clean-inherited"
+                                    .to_string(),
+                                replacement: replace(&cleaned),
+                            }),
+                        ));
+                    }
+                    continue;
+                }
+
+                match ws_deps.and_then(|deps| deps.get(dep)) {
+                    Some(ws_item) => match dependency::find_mismatch(ws_item, item) {
+                        Some(mismatch) => diagnostics.push(finding(
+                            "workspace-mismatch",
+                            format!(
+                                "'{}' is in [workspace.dependencies], but the member's {}",
+                                dep, mismatch
+                            ),
+                            Some(Fix {
+                                message: "Align the spec with the workspace entry, then inherit it"
+                                    .to_string(),
+                                replacement: None,
+                            }),
+                        )),
+                        None => {
+                            let inherited = Item::Value(dependency::inherited_spec(item).into());
+                            diagnostics.push(finding(
+                                "not-inherited",
+                                format!(
+                                    "'{}' is in [workspace.dependencies] but declared here",
+                                    dep
+                                ),
+                                Some(Fix {
+                                    message: "Inherit it from the workspace".to_string(),
+                                    replacement: replace(&inherited),
+                                }),
+                            ));
+                        }
+                    },
+                    None if report.unconsolidated.iter().any(|name| name == dep) => {
+                        diagnostics.push(finding(
+                            "unconsolidated",
+                            format!(
+                                "'{}' is used by several members but not in [workspace.dependencies]",
+                                dep
+                            ),
+                            Some(Fix {
+                                message: "Run `cargo consolidate` to move it into [workspace.dependencies]"
+                                    .to_string(),
+                                replacement: None,
+                            }),
+                        ));
+                    }
+                    None => {}
+                }
+
+                let found = policy
+                    .check(name, dep, item)
+                    .filter(|found| !baseline.accepts_violation(found));
+                if let Some(found) = found {
+                    diagnostics.push(violation(member, &[section], found));
+                }
+            }
+        }
+    }
+    diagnostics
+}
+
+/// A dependency counts as unconsolidated if it's in `[workspace.dependencies]`
+/// or consolidation would group it, by the same thresholds and rules of
+/// `config`, and some member still declares it itself
+fn analyze_manifests(
+    root: &DocumentMut,
+    members: &[&DocumentMut],
+    config: &Config,
+    group_all: bool,
+) -> WorkspaceReport {
+    let workspace_deps = root
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"))
+        .and_then(Item::as_table_like);
+
+    let mut usage: BTreeMap<String, Usage> = BTreeMap::new();
+    for doc in members {
+        let mut declared: BTreeMap<&str, &Item> = BTreeMap::new();
+        for section in dependency::DEPENDENCY_SECTIONS {
+            let Some(deps) = doc.get(section).and_then(Item::as_table_like) else {
+                continue;
+            };
+            let kind = match section {
+                "build-dependencies" => DependencyKind::Build,
+                "dev-dependencies" => DependencyKind::Development,
+                _ => DependencyKind::Normal,
+            };
+            for (dep, item) in deps.iter() {
+                declared.entry(dep).or_insert(item);
+                usage.entry(dep.to_string()).or_default().kinds.insert(kind);
+            }
+        }
+
+        for (dep, item) in declared {
+            let entry = usage.entry(dep.to_string()).or_default();
+            entry.users += 1;
+            let spec = if dependency::is_workspace_inherited(item) {
+                workspace_deps.and_then(|deps| deps.get(dep))
+            } else {
+                entry.own_specs += 1;
+                Some(item)
+            };
+            if let Some(version) = spec.and_then(dependency::get_version) {
+                entry.requirements.insert(version);
+            }
+        }
+    }
+
+    let mut report = WorkspaceReport {
+        members: members.len(),
+        ..Default::default()
+    };
+    for (dep, usage) in usage {
+        let in_workspace = workspace_deps.is_some_and(|deps| deps.contains_key(&dep));
+        let grouped =
+            workspace::should_group(&dep, usage.users, usage.kinds.class(), group_all, config)
+                .is_some();
+        if usage.own_specs > 0 && (grouped || in_workspace) {
+            report.unconsolidated.push(dep.clone());
+        }
+        if usage.requirements.len() > 1 {
+            report.skew.push(Skew {
+                dependency: dep,
+                requirements: usage.requirements.into_iter().collect(),
+            });
+        }
+    }
+    report
+}
+
+impl fmt::Display for WorkspaceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "# Consolidation report for `{}`",
+            self.manifest_path.display()
+        )?;
+        writeln!(f)?;
+        writeln!(f, "Members: {}", self.members)?;
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "## Unconsolidated dependencies ({})",
+            self.unconsolidated.len()
+        )?;
+        writeln!(f)?;
+        for dep in &self.unconsolidated {
+            writeln!(f, "- `{}`", dep)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "## Version skew ({})", self.skew.len())?;
+        writeln!(f)?;
+        for skew in &self.skew {
+            writeln!(
+                f,
+                "- `{}`: {}",
+                skew.dependency,
+                skew.requirements.join(", ")
+            )?;
+        }
+
+        if !self.editions.members.is_empty() {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "## Editions ({} inconsistent)",
+                self.editions.inconsistent()
+            )?;
+            writeln!(f)?;
+            write!(f, "{}", self.editions)?;
+        }
+
+        if !self.source_replacements.is_empty() {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "## Source replacement ({})",
+                self.source_replacements.len()
+            )?;
+            writeln!(f)?;
+            for replacement in &self.source_replacements {
+                writeln!(f, "- {}", replacement)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze_manifests() -> Result<()> {
+        let root = r#"
+            [workspace.dependencies]
+            serde = "1.0.200"
+        "#
+        .parse::<DocumentMut>()?;
+        let members = [
+            r#"
+            [dependencies]
+            serde = { workspace = true }
+            anyhow = "1"
+            "#,
+            r#"
+            [dependencies]
+            serde = "1.0.100"
+            anyhow = "1"
+            rand = "0.8"
+            "#,
+            r#"
+            [dev-dependencies]
+            anyhow = { workspace = true }
+            "#,
+        ]
+        .iter()
+        .map(|member| member.parse::<DocumentMut>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+        let members: Vec<_> = members.iter().collect();
+        let report = analyze_manifests(&root, &members, &Config::default(), false);
+        assert_eq!(report.members, 3);
+        assert_eq!(report.unconsolidated, vec!["anyhow", "serde"]);
+        assert_eq!(report.skew.len(), 1);
+        assert_eq!(report.skew[0].dependency, "serde");
+        assert_eq!(report.skew[0].requirements, vec!["1.0.100", "1.0.200"]);
+
+        let rendered = report.to_string();
+        assert!(rendered.contains("## Unconsolidated dependencies (2)\n\n- `anyhow`\n- `serde`\n"));
+        assert!(rendered.contains("- `serde`: 1.0.100, 1.0.200\n"));
+        assert!(!rendered.contains("## Source replacement"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_analyze_manifests_uses_config() -> Result<()> {
+        let root = "[workspace]\n".parse::<DocumentMut>()?;
+        let members = [
+            "[dependencies]\nserde = \"1\"\n",
+            "[dependencies]\nserde = \"1\"\n\n[dev-dependencies]\nrand = \"0.8\"\n",
+            "[dev-dependencies]\nrand = \"0.8\"\n",
+        ]
+        .iter()
+        .map(|member| member.parse::<DocumentMut>())
+        .collect::<Result<Vec<_>, _>>()?;
+        let members: Vec<_> = members.iter().collect();
+
+        let config: Config = toml_edit::de::from_str("min-members = 3\n")?;
+        let report = analyze_manifests(&root, &members, &config, false);
+        assert!(report.unconsolidated.is_empty());
+
+        // Dev-only dependencies have a threshold of their own
+        let config: Config =
+            toml_edit::de::from_str("min-members = 3\n\n[dev-only]\nmin-members = 2\n")?;
+        let report = analyze_manifests(&root, &members, &config, false);
+        assert_eq!(report.unconsolidated, vec!["rand"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diagnostics() -> Result<()> {
+        let root = Manifest::parse(
+            Path::new("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.dependencies]\nserde = \"1.0.200\"\nlog = \"0.4\"\n",
+        )?;
+        let a = Manifest::parse(
+            Path::new("a/Cargo.toml"),
+            "[package]\nname = \"a\"\n\n[dependencies]\nserde = { version = \"1.0.200\", features = [\"derive\"] }\nanyhow = \"1\"\nlog = { workspace = true, version = \"0.4\" }\n",
+        )?;
+        let b = Manifest::parse(
+            Path::new("b/Cargo.toml"),
+            "[package]\nname = \"b\"\n\n[dependencies]\nserde = \"1.0.100\"\nanyhow = \"1\"\n",
+        )?;
+        let workspace = Workspace {
+            root,
+            names: vec!["a".to_string(), "b".to_string()],
+            dirs: vec![PathBuf::from("a"), PathBuf::from("b")],
+            members: vec![a, b],
+        };
+        let report = analyze_manifests(
+            &workspace.root.doc,
+            &workspace.docs(),
+            &Config::default(),
+            false,
+        );
+
+        let diagnostics = diagnostics(
+            &workspace,
+            &report,
+            &Policy::default(),
+            &Baseline::default(),
+        );
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span.as_ref().unwrap();
+                let replacement = diagnostic
+                    .fix
+                    .as_ref()
+                    .and_then(|fix| fix.replacement.as_deref());
+                (diagnostic.code, span.line, replacement)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "not-inherited",
+                    5,
+                    Some("serde = { workspace = true, features = [\"derive\"] }")
+                ),
+                ("unconsolidated", 6, None),
+                ("leftover-keys", 7, Some("log = { workspace = true }")),
+                ("workspace-mismatch", 5, None),
+                ("unconsolidated", 6, None),
+            ]
+        );
+        assert_eq!(diagnostics[3].manifest, Path::new("b/Cargo.toml"));
+        Ok(())
+    }
+}
@@ -0,0 +1,536 @@
+use anyhow::{Context, Result};
+use log::LevelFilter;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item};
+
+use crate::cli::Opt;
+use crate::dependency;
+use crate::diff;
+use crate::sandbox::Preview;
+use crate::workspace::{self, Selection};
+
+/// A dependency the consolidation would move into `[workspace.dependencies]`
+#[derive(Debug, PartialEq, Eq)]
+struct Candidate {
+    dependency: String,
+    /// The workspace entry the members would inherit
+    spec: String,
+    members: Vec<CandidateMember>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct CandidateMember {
+    name: String,
+    /// The requirement the member declares itself so far
+    requirement: Option<String>,
+}
+
+/// Collects every dependency which members start to inherit in the
+/// `changed` manifests, along with the members and the new workspace entry
+fn candidates(
+    originals: &BTreeMap<PathBuf, String>,
+    changed: &[(PathBuf, String)],
+) -> Result<Vec<Candidate>> {
+    let root = Path::new("Cargo.toml");
+    let root_content = changed
+        .iter()
+        .find(|(relative, _)| relative == root)
+        .map(|(_, content)| content)
+        .or_else(|| originals.get(root))
+        .context("The workspace has no root manifest")?;
+    let root_doc = root_content.parse::<DocumentMut>()?;
+    let workspace_deps = root_doc
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"));
+
+    let mut candidates: BTreeMap<String, Candidate> = BTreeMap::new();
+    for (relative, content) in changed {
+        let before = originals[relative].parse::<DocumentMut>()?;
+        let after = content.parse::<DocumentMut>()?;
+        let Some(name) = after
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(Item::as_str)
+        else {
+            continue;
+        };
+
+        for section in dependency::DEPENDENCY_SECTIONS {
+            let Some(deps) = after.get(section).and_then(Item::as_table_like) else {
+                continue;
+            };
+            for (dep, item) in deps.iter() {
+                let old = before.get(section).and_then(|deps| deps.get(dep));
+                if !dependency::is_workspace_inherited(item)
+                    || old.is_some_and(dependency::is_workspace_inherited)
+                {
+                    continue;
+                }
+
+                let candidate = candidates
+                    .entry(dep.to_string())
+                    .or_insert_with(|| Candidate {
+                        dependency: dep.to_string(),
+                        spec: workspace_deps
+                            .and_then(|deps| deps.get(dep))
+                            .map(|spec| spec.to_string().trim().to_string())
+                            .unwrap_or_default(),
+                        members: Vec::new(),
+                    });
+                if !candidate.members.iter().any(|member| member.name == name) {
+                    candidate.members.push(CandidateMember {
+                        name: name.to_string(),
+                        requirement: old.and_then(dependency::get_version),
+                    });
+                }
+            }
+        }
+    }
+    Ok(candidates.into_values().collect())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Focus {
+    Dependencies,
+    Members,
+}
+
+enum Mode {
+    Select,
+    /// Shows the diff of one changed manifest after the other
+    Diff {
+        diffs: Vec<(PathBuf, String)>,
+        file: usize,
+        scroll: u16,
+    },
+}
+
+enum Outcome {
+    Apply(Selection),
+    Quit,
+}
+
+/// The state of the review interface
+struct App {
+    candidates: Vec<Candidate>,
+    /// Whether each candidate is selected, and each of its members
+    selected: Vec<(bool, Vec<bool>)>,
+    dep: usize,
+    member: usize,
+    focus: Focus,
+    mode: Mode,
+    status: Option<String>,
+}
+
+impl App {
+    fn new(candidates: Vec<Candidate>) -> Self {
+        let selected = candidates
+            .iter()
+            .map(|candidate| (true, vec![true; candidate.members.len()]))
+            .collect();
+        Self {
+            candidates,
+            selected,
+            dep: 0,
+            member: 0,
+            focus: Focus::Dependencies,
+            mode: Mode::Select,
+            status: None,
+        }
+    }
+
+    fn move_cursor(&mut self, down: bool) {
+        let (cursor, len) = match self.focus {
+            Focus::Dependencies => (&mut self.dep, self.candidates.len()),
+            Focus::Members => (&mut self.member, self.candidates[self.dep].members.len()),
+        };
+        *cursor = if down {
+            (*cursor + 1).min(len.saturating_sub(1))
+        } else {
+            cursor.saturating_sub(1)
+        };
+        if self.focus == Focus::Dependencies {
+            self.member = 0;
+        }
+    }
+
+    fn toggle(&mut self) {
+        let (dep_selected, members) = &mut self.selected[self.dep];
+        match self.focus {
+            Focus::Dependencies => *dep_selected = !*dep_selected,
+            Focus::Members => members[self.member] = !members[self.member],
+        }
+    }
+
+    fn switch_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Dependencies => Focus::Members,
+            Focus::Members => Focus::Dependencies,
+        };
+    }
+
+    fn selection(&self) -> Selection {
+        let mut selection = Selection::default();
+        for (candidate, (dep_selected, members)) in self.candidates.iter().zip(&self.selected) {
+            if !dep_selected {
+                selection.deselect(&candidate.dependency);
+                continue;
+            }
+            for (member, selected) in candidate.members.iter().zip(members) {
+                if !selected {
+                    selection.deselect_member(&candidate.dependency, &member.name);
+                }
+            }
+        }
+        selection
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        match &self.mode {
+            Mode::Select => self.draw_select(frame, main),
+            Mode::Diff {
+                diffs,
+                file,
+                scroll,
+            } => draw_diff(frame, main, diffs, *file, *scroll),
+        }
+
+        let keys = match self.mode {
+            Mode::Select => "space toggle  tab switch pane  d preview diffs  a apply  q quit",
+            Mode::Diff { .. } => "up/down file  j/k scroll  a apply  esc back",
+        };
+        let help_line = match &self.status {
+            Some(status) => Line::from(status.as_str()).style(Style::new().fg(Color::Red)),
+            None => Line::from(keys).style(Style::new().add_modifier(Modifier::DIM)),
+        };
+        frame.render_widget(help_line, help);
+    }
+
+    fn draw_select(&self, frame: &mut Frame, area: Rect) {
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(area);
+        let candidate = &self.candidates[self.dep];
+        let (dep_selected, members_selected) = &self.selected[self.dep];
+        let spec = format!("{} = {}", candidate.dependency, candidate.spec);
+        let [members_area, spec_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(spec.lines().count() as u16 + 2),
+        ])
+        .areas(right);
+
+        let deps =
+            self.candidates
+                .iter()
+                .zip(&self.selected)
+                .map(|(candidate, (selected, members))| {
+                    ListItem::new(format!(
+                        "{} {} ({}/{})",
+                        checkbox(*selected),
+                        candidate.dependency,
+                        members.iter().filter(|selected| **selected).count(),
+                        members.len()
+                    ))
+                });
+        frame.render_stateful_widget(
+            List::new(deps)
+                .block(Block::bordered().title("Dependencies"))
+                .highlight_style(highlight(self.focus == Focus::Dependencies)),
+            left,
+            &mut ListState::default().with_selected(Some(self.dep)),
+        );
+
+        let members = candidate
+            .members
+            .iter()
+            .zip(members_selected)
+            .map(|(member, selected)| {
+                ListItem::new(format!(
+                    "{} {} {}",
+                    checkbox(*selected && *dep_selected),
+                    member.name,
+                    member.requirement.as_deref().unwrap_or("")
+                ))
+            });
+        frame.render_stateful_widget(
+            List::new(members)
+                .block(Block::bordered().title("Members"))
+                .highlight_style(highlight(self.focus == Focus::Members)),
+            members_area,
+            &mut ListState::default().with_selected(Some(self.member)),
+        );
+
+        frame.render_widget(
+            Paragraph::new(spec).block(Block::bordered().title("Workspace entry")),
+            spec_area,
+        );
+    }
+}
+
+fn draw_diff(frame: &mut Frame, area: Rect, diffs: &[(PathBuf, String)], file: usize, scroll: u16) {
+    let [left, right] =
+        Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)]).areas(area);
+
+    let files = diffs
+        .iter()
+        .map(|(relative, _)| ListItem::new(relative.display().to_string()));
+    frame.render_stateful_widget(
+        List::new(files)
+            .block(Block::bordered().title("Changed manifests"))
+            .highlight_style(highlight(true)),
+        left,
+        &mut ListState::default().with_selected(Some(file)),
+    );
+
+    let lines: Vec<Line> = diffs
+        .get(file)
+        .map(|(_, diff)| diff.lines().map(diff_line).collect())
+        .unwrap_or_default();
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(Block::bordered().title("Diff"))
+            .scroll((scroll, 0)),
+        right,
+    );
+}
+
+fn diffs(preview: &Preview, changed: &[(PathBuf, String)]) -> Vec<(PathBuf, String)> {
+    changed
+        .iter()
+        .map(|(relative, content)| {
+            let diff = diff::unified_diff(relative, &preview.originals[relative], content);
+            (relative.clone(), diff)
+        })
+        .collect()
+}
+
+fn diff_line(line: &str) -> Line<'_> {
+    let color = match line.chars().next() {
+        Some('+') => Color::Green,
+        Some('-') => Color::Red,
+        Some('@') => Color::Cyan,
+        _ => Color::Reset,
+    };
+    Line::from(Span::styled(line, Style::new().fg(color)))
+}
+
+fn checkbox(selected: bool) -> &'static str {
+    if selected {
+        "[x]"
+    } else {
+        "[ ]"
+    }
+}
+
+fn highlight(focused: bool) -> Style {
+    if focused {
+        Style::new().add_modifier(Modifier::REVERSED)
+    } else {
+        Style::new().add_modifier(Modifier::BOLD)
+    }
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App, preview: &Preview) -> Result<Outcome> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        app.status = None;
+
+        match &mut app.mode {
+            Mode::Select => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(Outcome::Quit),
+                KeyCode::Char('a') => return Ok(Outcome::Apply(app.selection())),
+                KeyCode::Up | KeyCode::Char('k') => app.move_cursor(false),
+                KeyCode::Down | KeyCode::Char('j') => app.move_cursor(true),
+                KeyCode::Tab | KeyCode::Left | KeyCode::Right => app.switch_focus(),
+                KeyCode::Char(' ') => app.toggle(),
+                KeyCode::Char('d') => match preview.run(&app.selection()) {
+                    Ok(changed) => {
+                        app.mode = Mode::Diff {
+                            diffs: diffs(preview, &changed),
+                            file: 0,
+                            scroll: 0,
+                        }
+                    }
+                    Err(err) => app.status = Some(format!("Preview failed: {:#}", err)),
+                },
+                _ => {}
+            },
+            Mode::Diff {
+                diffs,
+                file,
+                scroll,
+            } => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => app.mode = Mode::Select,
+                KeyCode::Char('a') => return Ok(Outcome::Apply(app.selection())),
+                KeyCode::Up => {
+                    *file = file.saturating_sub(1);
+                    *scroll = 0;
+                }
+                KeyCode::Down => {
+                    *file = (*file + 1).min(diffs.len().saturating_sub(1));
+                    *scroll = 0;
+                }
+                KeyCode::Char('k') => *scroll = scroll.saturating_sub(1),
+                KeyCode::Char('j') => *scroll = scroll.saturating_add(1),
+                KeyCode::PageUp => *scroll = scroll.saturating_sub(20),
+                KeyCode::PageDown => *scroll = scroll.saturating_add(20),
+                _ => {}
+            },
+        }
+    }
+}
+
+/// Lets the user pick the dependencies and members to consolidate in a
+/// terminal interface, previewing the changes in a copy of the workspace,
+/// then applies the selection to the workspace
+pub fn run(opt: &Opt) -> Result<()> {
+    if !std::io::stdout().is_terminal() {
+        anyhow::bail!("--review needs a terminal");
+    }
+    let metadata = workspace::load_metadata(opt.manifest_path(), &opt.cargo)?;
+    let workspace_root = metadata.workspace_root.as_std_path();
+    let preview = Preview::new(opt, workspace_root)?;
+
+    // Log lines would scribble over the interface, and warnings are
+    // printed once more when the selection is applied anyway
+    let max_level = log::max_level();
+    log::set_max_level(LevelFilter::Off);
+    let outcome = preview
+        .run(&Selection::default())
+        .and_then(|changed| candidates(&preview.originals, &changed))
+        .and_then(|candidates| {
+            if candidates.is_empty() {
+                return Ok(None);
+            }
+            let mut app = App::new(candidates);
+            let mut terminal = ratatui::init();
+            let outcome = event_loop(&mut terminal, &mut app, &preview);
+            ratatui::restore();
+            outcome.map(Some)
+        });
+    log::set_max_level(max_level);
+
+    match outcome? {
+        Some(Outcome::Apply(selection)) => workspace::consolidate_selected(opt, &selection),
+        Some(Outcome::Quit) => {
+            println!("Nothing was changed.");
+            Ok(())
+        }
+        None => {
+            println!("Nothing to consolidate.");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() -> Result<()> {
+        let originals = BTreeMap::from([
+            (
+                PathBuf::from("Cargo.toml"),
+                "[workspace]\nmembers = [\"a\", \"b\"]\n".to_string(),
+            ),
+            (
+                PathBuf::from("a/Cargo.toml"),
+                "[package]\nname = \"a\"\n\n[dependencies]\nserde = \"1.0.100\"\nlog = { workspace = true }\n".to_string(),
+            ),
+            (
+                PathBuf::from("b/Cargo.toml"),
+                "[package]\nname = \"b\"\n\n[dev-dependencies]\nserde = \"1\"\n".to_string(),
+            ),
+        ]);
+        let changed = vec![
+            (
+                PathBuf::from("Cargo.toml"),
+                "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.dependencies]\nserde = \"1.0.100\"\n".to_string(),
+            ),
+            (
+                PathBuf::from("a/Cargo.toml"),
+                "[package]\nname = \"a\"\n\n[dependencies]\nserde = { workspace = true }\nlog = { workspace = true }\n".to_string(),
+            ),
+            (
+                PathBuf::from("b/Cargo.toml"),
+                "[package]\nname = \"b\"\n\n[dev-dependencies]\nserde = { workspace = true }\n".to_string(),
+            ),
+        ];
+
+        assert_eq!(
+            candidates(&originals, &changed)?,
+            vec![Candidate {
+                dependency: "serde".to_string(),
+                spec: "\"1.0.100\"".to_string(),
+                members: vec![
+                    CandidateMember {
+                        name: "a".to_string(),
+                        requirement: Some("1.0.100".to_string()),
+                    },
+                    CandidateMember {
+                        name: "b".to_string(),
+                        requirement: Some("1".to_string()),
+                    },
+                ],
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_app_selection() -> Result<()> {
+        let candidate = |dependency: &str, members: &[&str]| Candidate {
+            dependency: dependency.to_string(),
+            spec: "\"1\"".to_string(),
+            members: members
+                .iter()
+                .map(|name| CandidateMember {
+                    name: name.to_string(),
+                    requirement: None,
+                })
+                .collect(),
+        };
+        let mut app = App::new(vec![
+            candidate("log", &["a", "b"]),
+            candidate("serde", &["a", "b", "c"]),
+        ]);
+        assert!(app.selection().is_empty());
+
+        // Deselect `log` altogether
+        app.toggle();
+        // and member `b` of `serde`
+        app.move_cursor(true);
+        app.switch_focus();
+        app.move_cursor(true);
+        app.toggle();
+
+        let mut expected = Selection::default();
+        expected.deselect("log");
+        expected.deselect_member("serde", "b");
+        assert_eq!(app.selection(), expected);
+
+        let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(60, 12))?;
+        terminal.draw(|frame| app.draw(frame))?;
+        let screen = format!("{:?}", terminal.backend().buffer());
+        assert!(screen.contains("[ ] log (2/2)"));
+        assert!(screen.contains("[x] serde (2/3)"));
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+use crate::cli::{Opt, OutputFormat};
+use crate::diff;
+use crate::events::{Event, Events};
+use crate::progress::Progress;
+use crate::workspace::{self, Selection};
+
+/// Directories which are never copied into the sandbox
+pub const SKIPPED_DIRS: [&str; 2] = ["target", ".git"];
+
+/// Consolidates a temporary copy of the workspace, prints the resulting
+/// changes and verifies that the copy still resolves with `cargo metadata`.
+/// With `--out-root`, the new root manifest is written there instead of
+/// being printed, once the copy resolves
+pub fn run(opt: &Opt) -> Result<()> {
+    let metadata = workspace::load_metadata(opt.manifest_path(), &opt.cargo)?;
+    let workspace_root = metadata.workspace_root.as_std_path();
+
+    let sandbox = tempfile::Builder::new()
+        .prefix("cargo-consolidate-")
+        .tempdir()
+        .context("Failed to create sandbox directory")?;
+    info!(
+        "Copying '{}' into sandbox '{}'",
+        workspace_root.display(),
+        sandbox.path().display()
+    );
+    let mut manifests = Vec::new();
+    copy_workspace(workspace_root, sandbox.path(), &mut manifests)?;
+    manifests.sort();
+
+    let sandbox_opt = sandbox_opt(opt, workspace_root, sandbox.path())?;
+    workspace::consolidate_dependencies(&sandbox_opt)
+        .context("Consolidation failed in the sandbox")?;
+
+    let events = Events::new(opt);
+    let root = Path::new("Cargo.toml");
+    for relative in manifests
+        .iter()
+        .filter(|relative| opt.show.includes(relative))
+        .filter(|relative| opt.out_root.is_none() || *relative != root)
+    {
+        let original = fs::read_to_string(workspace_root.join(relative))
+            .with_context(|| format!("Failed to read '{}'", relative.display()))?;
+        let consolidated = fs::read_to_string(sandbox.path().join(relative))
+            .with_context(|| format!("Failed to read '{}'", relative.display()))?;
+        let diff = diff::unified_diff(relative, &original, &consolidated);
+        if events.enabled() {
+            if !diff.is_empty() {
+                events.emit(Event::Diff {
+                    manifest: relative,
+                    diff,
+                });
+            }
+        } else {
+            print!("{}", diff);
+        }
+    }
+
+    let progress = Progress::start(opt, "Verifying", None);
+    let verified = workspace::load_metadata(sandbox_opt.manifest_path(), &opt.cargo)
+        .context("The consolidated workspace doesn't resolve anymore");
+    drop(progress);
+    events.emit(Event::Verification {
+        resolves: verified.is_ok(),
+        error: verified.as_ref().err().map(|err| format!("{:#}", err)),
+    });
+    // A root manifest which doesn't resolve isn't written to --out-root
+    verified?;
+    if !events.enabled() {
+        println!("The consolidated workspace resolves with `cargo metadata`.");
+    }
+
+    if let Some(out_root) = &opt.out_root {
+        let consolidated = sandbox.path().join(root);
+        fs::copy(&consolidated, out_root)
+            .with_context(|| format!("Failed to write '{}'", out_root.display()))?;
+        if !events.enabled() {
+            println!(
+                "Wrote the consolidated root manifest to '{}'.",
+                out_root.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The options of `opt` pointed at the copy of the workspace in `sandbox`
+pub fn sandbox_opt(opt: &Opt, workspace_root: &Path, sandbox: &Path) -> Result<Opt> {
+    let mut sandbox_opt = opt.clone();
+    sandbox_opt.sandbox = false;
+    sandbox_opt.out_root = None;
+    sandbox_opt.embedded = true;
+    sandbox_opt.manifest_path = vec![match opt.manifest_path() {
+        Some(path) => {
+            let path = fs::canonicalize(path)
+                .with_context(|| format!("Failed to resolve '{}'", path.display()))?;
+            let relative = path
+                .strip_prefix(workspace_root)
+                .unwrap_or(Path::new("Cargo.toml"));
+            sandbox.join(relative)
+        }
+        None => sandbox.join("Cargo.toml"),
+    }];
+    Ok(sandbox_opt)
+}
+
+/// A copy of the workspace which is consolidated again for every preview,
+/// starting from the original manifests each time
+pub struct Preview {
+    dir: TempDir,
+    opt: Opt,
+    /// Every manifest relative to the workspace root, with its content
+    pub originals: BTreeMap<PathBuf, String>,
+}
+
+impl Preview {
+    pub fn new(opt: &Opt, workspace_root: &Path) -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("cargo-consolidate-")
+            .tempdir()
+            .context("Failed to create sandbox directory")?;
+        let mut manifests = Vec::new();
+        copy_workspace(workspace_root, dir.path(), &mut manifests)?;
+
+        let mut originals = BTreeMap::new();
+        for relative in manifests {
+            let path = workspace_root.join(&relative);
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read '{}'", path.display()))?;
+            originals.insert(relative, content);
+        }
+
+        let mut preview_opt = sandbox_opt(opt, workspace_root, dir.path())?;
+        preview_opt.review = false;
+        preview_opt.serve = false;
+        preview_opt.explain = false;
+        preview_opt.no_progress = true;
+        preview_opt.format = Some(OutputFormat::Text);
+        Ok(Self {
+            dir,
+            opt: preview_opt,
+            originals,
+        })
+    }
+
+    /// Consolidates the pristine copy with `selection`, returning the
+    /// manifests which change together with their new content
+    pub fn run(&self, selection: &Selection) -> Result<Vec<(PathBuf, String)>> {
+        for (relative, content) in &self.originals {
+            let path = self.dir.path().join(relative);
+            fs::write(&path, content)
+                .with_context(|| format!("Failed to write '{}'", path.display()))?;
+        }
+        workspace::consolidate_selected(&self.opt, selection)?;
+
+        let mut changed = Vec::new();
+        for (relative, original) in &self.originals {
+            let path = self.dir.path().join(relative);
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read '{}'", path.display()))?;
+            if content != *original {
+                changed.push((relative.clone(), content));
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Copies the workspace into the sandbox, collecting the paths of all
+/// manifests relative to the workspace root
+pub fn copy_workspace(from: &Path, to: &Path, manifests: &mut Vec<PathBuf>) -> Result<()> {
+    copy_dir(from, from, to, manifests)
+}
+
+fn copy_dir(root: &Path, from: &Path, to: &Path, manifests: &mut Vec<PathBuf>) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("Failed to create '{}'", to.display()))?;
+
+    let entries =
+        fs::read_dir(from).with_context(|| format!("Failed to read '{}'", from.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let target = to.join(entry.file_name());
+
+        if path.is_dir() {
+            if SKIPPED_DIRS.iter().any(|dir| entry.file_name() == *dir) {
+                continue;
+            }
+            copy_dir(root, &path, &target, manifests)?;
+        } else {
+            fs::copy(&path, &target)
+                .with_context(|| format!("Failed to copy '{}'", path.display()))?;
+            if entry.file_name() == "Cargo.toml" {
+                manifests.push(path.strip_prefix(root)?.to_path_buf());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run_out_root() -> Result<()> {
+        let workspace = TempDir::new()?;
+        let root = workspace.path();
+        let root_manifest = "[workspace]\nmembers = [\"a\", \"b\"]\n";
+        fs::write(root.join("Cargo.toml"), root_manifest)?;
+        for member in ["a", "b"] {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\n\n[dependencies]\nserde = \"1\"\n",
+                    member
+                ),
+            )?;
+        }
+
+        let out_root = root.join("Cargo.proposed.toml");
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+            "--out-root",
+            out_root.to_str().unwrap(),
+        ]);
+        run(&opt)?;
+
+        assert!(
+            fs::read_to_string(&out_root)?.contains("[workspace.dependencies]\nserde = \"1\"\n")
+        );
+        assert_eq!(fs::read_to_string(root.join("Cargo.toml"))?, root_manifest);
+        assert!(fs::read_to_string(root.join("a/Cargo.toml"))?.contains("serde = \"1\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_workspace_skips_target() -> Result<()> {
+        let workspace = TempDir::new()?;
+        let sandbox = TempDir::new()?;
+        for path in [
+            "Cargo.toml",
+            "crates/a/Cargo.toml",
+            "crates/a/src/lib.rs",
+            "target/debug/foo",
+        ] {
+            write_file(workspace.path().join(path), "")?;
+        }
+
+        let mut manifests = Vec::new();
+        copy_workspace(workspace.path(), sandbox.path(), &mut manifests)?;
+        manifests.sort();
+
+        assert_eq!(
+            manifests,
+            vec![
+                PathBuf::from("Cargo.toml"),
+                PathBuf::from("crates/a/Cargo.toml")
+            ]
+        );
+        assert!(sandbox.path().join("crates/a/src/lib.rs").exists());
+        assert!(!sandbox.path().join("target").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+use anyhow::Result;
+use cargo_metadata::DependencyKind;
+use log::warn;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cli::CargoOptions;
+use crate::lockfile::Lockfile;
+use crate::workspace;
+
+/// The external dependencies of a workspace and the members using them,
+/// for a software bill of materials
+#[derive(Debug, Default)]
+pub struct Inventory {
+    name: String,
+    members: Vec<Member>,
+    /// By name and version
+    components: BTreeMap<(String, String), Component>,
+}
+
+#[derive(Debug)]
+struct Member {
+    name: String,
+    version: String,
+    /// The components it depends on, by name and version
+    dependencies: BTreeSet<(String, String)>,
+}
+
+#[derive(Debug)]
+struct Component {
+    /// Whether the version was taken from Cargo.lock rather than being the
+    /// requirement
+    locked: bool,
+    /// The repository and commit of a git dependency
+    vcs_url: Option<String>,
+}
+
+/// Collects the external dependencies of the workspace at `manifest_path`,
+/// with the versions locked in its Cargo.lock. Without a Cargo.lock the
+/// requirements stand in for the versions
+pub fn inventory(manifest_path: Option<&Path>, cargo: &CargoOptions) -> Result<Inventory> {
+    let metadata = workspace::load_metadata(manifest_path, cargo)?;
+    let workspace_root = metadata.workspace_root.as_std_path();
+    let lockfile_path = workspace_root.join("Cargo.lock");
+    let lockfile = if lockfile_path.exists() {
+        Lockfile::load(&lockfile_path)?
+    } else {
+        warn!(
+            "'{}' doesn't exist, listing version requirements instead of versions",
+            lockfile_path.display()
+        );
+        Lockfile::default()
+    };
+
+    let mut packages = workspace::workspace_packages(&metadata)?;
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    let root_package = packages
+        .iter()
+        .find(|package| package.manifest_path.parent() == Some(metadata.workspace_root.as_path()));
+    let mut inventory = Inventory {
+        name: match root_package {
+            Some(package) => package.name.clone(),
+            None => workspace_root
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "workspace".to_string()),
+        },
+        ..Default::default()
+    };
+
+    for package in packages {
+        let mut dependencies = BTreeSet::new();
+        for dep in &package.dependencies {
+            if !matches!(
+                dep.kind,
+                DependencyKind::Normal | DependencyKind::Build | DependencyKind::Development
+            ) {
+                continue;
+            }
+            // Path dependencies are part of the workspace or vendored
+            let Some(source) = &dep.source else {
+                continue;
+            };
+            let locked = lockfile
+                .versions_of(&dep.name)
+                .iter()
+                .filter(|version| dep.req.matches(version))
+                .max();
+            let version = match locked {
+                Some(version) => version.to_string(),
+                None => dep.req.to_string(),
+            };
+            let key = (dep.name.clone(), version);
+            inventory
+                .components
+                .entry(key.clone())
+                .or_insert_with(|| Component {
+                    locked: locked.is_some(),
+                    vcs_url: vcs_url(source),
+                });
+            dependencies.insert(key);
+        }
+        inventory.members.push(Member {
+            name: package.name.clone(),
+            version: package.version.to_string(),
+            dependencies,
+        });
+    }
+    Ok(inventory)
+}
+
+/// The inventory as a CycloneDX 1.5 document
+pub fn cyclonedx(inventory: &Inventory, created: u64) -> Value {
+    let members: Vec<Value> = inventory
+        .members
+        .iter()
+        .map(|member| {
+            json!({
+                "type": "library",
+                "bom-ref": member_ref(member),
+                "name": member.name,
+                "version": member.version,
+                "purl": purl(&member.name, &member.version, None),
+            })
+        })
+        .collect();
+    let components = inventory
+        .components
+        .iter()
+        .map(|((name, version), component)| {
+            let mut value = json!({
+                "type": "library",
+                "bom-ref": component_ref(name, version),
+                "name": name,
+                "version": version,
+            });
+            if component.locked {
+                value["purl"] = json!(purl(name, version, component.vcs_url.as_deref()));
+            }
+            value
+        });
+    let dependencies: Vec<Value> = inventory
+        .members
+        .iter()
+        .map(|member| {
+            let depends_on: Vec<String> = member
+                .dependencies
+                .iter()
+                .map(|(name, version)| component_ref(name, version))
+                .collect();
+            json!({ "ref": member_ref(member), "dependsOn": depends_on })
+        })
+        .collect();
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": timestamp(created),
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "component": {
+                "type": "application",
+                "bom-ref": "workspace",
+                "name": inventory.name,
+            },
+        },
+        "components": members.into_iter().chain(components).collect::<Vec<_>>(),
+        "dependencies": dependencies,
+    })
+}
+
+/// The inventory as an SPDX 2.3 document
+pub fn spdx(inventory: &Inventory, created: u64) -> Value {
+    let mut packages = Vec::new();
+    let mut relationships = Vec::new();
+    for member in &inventory.members {
+        let id = spdx_id("Member", &member.name, &member.version);
+        packages.push(json!({
+            "name": member.name,
+            "SPDXID": id,
+            "versionInfo": member.version,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+        }));
+        relationships.push(json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": id,
+        }));
+        for (name, version) in &member.dependencies {
+            relationships.push(json!({
+                "spdxElementId": id,
+                "relationshipType": "DEPENDS_ON",
+                "relatedSpdxElement": spdx_id("Package", name, version),
+            }));
+        }
+    }
+    for ((name, version), component) in &inventory.components {
+        let mut package = json!({
+            "name": name,
+            "SPDXID": spdx_id("Package", name, version),
+            "versionInfo": version,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+        });
+        if component.locked {
+            package["externalRefs"] = json!([{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": purl(name, version, component.vcs_url.as_deref()),
+            }]);
+        }
+        packages.push(package);
+    }
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": inventory.name,
+        "documentNamespace": format!(
+            "https://spdx.org/spdxdocs/{}-{}",
+            sanitize(&inventory.name),
+            created
+        ),
+        "creationInfo": {
+            "created": timestamp(created),
+            "creators": [format!("Tool: {}-{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// Seconds since the epoch to date the document with. `SOURCE_DATE_EPOCH`
+/// makes it reproducible
+pub fn created() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        })
+}
+
+/// The repository and commit of a git source, like
+/// `git+https://github.com/foo/bar?branch=main#1234abcd`, as a purl
+/// `vcs_url`
+fn vcs_url(source: &str) -> Option<String> {
+    let url = source.strip_prefix("git+")?;
+    let (url, commit) = match url.split_once('#') {
+        Some((url, commit)) => (url, Some(commit)),
+        None => (url, None),
+    };
+    let url = url.split('?').next().unwrap_or(url);
+    Some(match commit {
+        Some(commit) => format!("git+{}@{}", url, commit),
+        None => format!("git+{}", url),
+    })
+}
+
+fn purl(name: &str, version: &str, vcs_url: Option<&str>) -> String {
+    match vcs_url {
+        Some(vcs_url) => format!("pkg:cargo/{}@{}?vcs_url={}", name, version, vcs_url),
+        None => format!("pkg:cargo/{}@{}", name, version),
+    }
+}
+
+fn member_ref(member: &Member) -> String {
+    format!("member:{}@{}", member.name, member.version)
+}
+
+fn component_ref(name: &str, version: &str) -> String {
+    format!("{}@{}", name, version)
+}
+
+fn spdx_id(kind: &str, name: &str, version: &str) -> String {
+    format!("SPDXRef-{}-{}-{}", kind, sanitize(name), sanitize(version))
+}
+
+/// Replaces the characters SPDX identifiers can't hold
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Formats seconds since the epoch as an RFC 3339 UTC timestamp
+fn timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use clap::Parser;
+    use std::fs;
+    use tempfile::TempDir;
+
+    use crate::cli::Opt;
+
+    #[test]
+    fn test_sbom() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("shop");
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n\n[workspace.dependencies]\nserde = \"1\"\n",
+        )?;
+        let members = [
+            ("a", "serde = { workspace = true }\nc = { path = \"../c\" }\n"),
+            ("b", "serde = { workspace = true }\nlog = \"0.4\"\nfoo = { git = \"https://example.com/foo\" }\n"),
+            ("c", ""),
+        ];
+        for (name, deps) in members {
+            write_file(
+                root.join(name).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\n{}",
+                    name, deps
+                ),
+            )?;
+        }
+        fs::write(
+            root.join("Cargo.lock"),
+            "[[package]]\nname = \"serde\"\nversion = \"1.0.200\"\n\n\
+             [[package]]\nname = \"serde\"\nversion = \"0.9.15\"\n\n\
+             [[package]]\nname = \"foo\"\nversion = \"0.3.0\"\n",
+        )?;
+
+        let opt = Opt::parse_from(["cargo-consolidate", "--no-cargo"]);
+        let inventory = inventory(Some(&root.join("Cargo.toml")), &opt.cargo)?;
+
+        let bom = cyclonedx(&inventory, 1_700_000_000);
+        assert_eq!(bom["metadata"]["timestamp"], "2023-11-14T22:13:20Z");
+        assert_eq!(bom["metadata"]["component"]["name"], "shop");
+        let purls: Vec<Option<&str>> = bom["components"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|component| component["purl"].as_str())
+            .collect();
+        assert_eq!(
+            purls,
+            vec![
+                Some("pkg:cargo/a@0.1.0"),
+                Some("pkg:cargo/b@0.1.0"),
+                Some("pkg:cargo/c@0.1.0"),
+                Some("pkg:cargo/foo@0.3.0?vcs_url=git+https://example.com/foo"),
+                // Not in Cargo.lock, so only the requirement is known
+                None,
+                Some("pkg:cargo/serde@1.0.200"),
+            ]
+        );
+        assert_eq!(
+            bom["dependencies"][1],
+            json!({
+                "ref": "member:b@0.1.0",
+                "dependsOn": ["foo@0.3.0", "log@^0.4", "serde@1.0.200"],
+            })
+        );
+
+        let document = spdx(&inventory, 1_700_000_000);
+        let relationships = document["relationships"].as_array().unwrap();
+        assert!(relationships.contains(&json!({
+            "spdxElementId": "SPDXRef-Member-a-0.1.0",
+            "relationshipType": "DEPENDS_ON",
+            "relatedSpdxElement": "SPDXRef-Package-serde-1.0.200",
+        })));
+        // Path dependencies are part of the workspace
+        assert!(!relationships
+            .iter()
+            .any(|relationship| relationship["relatedSpdxElement"]
+                .as_str()
+                .is_some_and(|id| id.starts_with("SPDXRef-Package-c-"))));
+        Ok(())
+    }
+}
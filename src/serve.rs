@@ -0,0 +1,376 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use similar::TextDiff;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cli::{Opt, OutputFormat};
+use crate::report;
+use crate::sandbox::Preview;
+use crate::workspace::{self, Selection};
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Analysis or consolidation failed
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    /// Missing for notifications, which get no response
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Params,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Params {
+    /// The workspace to work on, defaults to the one --serve was started in
+    manifest_path: Option<PathBuf>,
+    dependency: Option<String>,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(err: anyhow::Error) -> Self {
+        Self {
+            code: SERVER_ERROR,
+            message: format!("{:#}", err),
+        }
+    }
+}
+
+/// A replacement of whole lines in a manifest, in the shape of an LSP
+/// `TextEdit` plus the file it applies to
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TextEdit {
+    path: PathBuf,
+    range: Range,
+    new_text: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Range {
+    start: Position,
+    end: Position,
+}
+
+/// Zero-based, `character` is always 0 as edits replace whole lines
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Position {
+    line: usize,
+    character: usize,
+}
+
+impl Position {
+    fn line(line: usize) -> Self {
+        Self { line, character: 0 }
+    }
+}
+
+/// Answers JSON-RPC requests on stdin until `exit` or the end of input.
+/// Messages are framed with `Content-Length` headers like in LSP
+pub fn run(opt: &Opt) -> Result<()> {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    serve(opt, &mut stdin.lock(), &mut stdout.lock())
+}
+
+fn serve(opt: &Opt, reader: &mut impl BufRead, writer: &mut impl Write) -> Result<()> {
+    // stdout carries the protocol, so nothing else may be printed to it
+    let mut opt = opt.clone();
+    opt.serve = false;
+    opt.explain = false;
+    opt.no_progress = true;
+    opt.format = Some(OutputFormat::Text);
+
+    info!("Serving JSON-RPC on stdio");
+    while let Some(message) = read_message(reader)? {
+        let request: Request = match serde_json::from_str(&message) {
+            Ok(request) => request,
+            Err(err) => {
+                let error = RpcError {
+                    code: PARSE_ERROR,
+                    message: err.to_string(),
+                };
+                write_message(writer, &response(Value::Null, Err(error)))?;
+                continue;
+            }
+        };
+        debug!("Received '{}'", request.method);
+        if request.method == "exit" {
+            break;
+        }
+
+        let result = handle(&opt, &request.method, request.params);
+        if let Some(id) = request.id {
+            write_message(writer, &response(id, result))?;
+        }
+    }
+    Ok(())
+}
+
+fn handle(opt: &Opt, method: &str, params: Params) -> Result<Value, RpcError> {
+    let mut opt = opt.clone();
+    if let Some(manifest_path) = params.manifest_path {
+        opt.manifest_path = vec![manifest_path];
+    }
+
+    match method {
+        "initialize" => Ok(json!({
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "methods": ["analyze", "plan", "apply", "shutdown", "exit"],
+        })),
+        "shutdown" => Ok(Value::Null),
+        "analyze" => {
+            let report = report::analyze(opt.manifest_path(), &opt)?;
+            Ok(serde_json::to_value(report).map_err(anyhow::Error::from)?)
+        }
+        "plan" => {
+            let Some(dep) = params.dependency else {
+                return Err(RpcError {
+                    code: INVALID_PARAMS,
+                    message: "'plan' needs a dependency".to_string(),
+                });
+            };
+            let edits = plan(&opt, &Selection::only(&dep))?;
+            Ok(serde_json::to_value(edits).map_err(anyhow::Error::from)?)
+        }
+        "apply" => {
+            let selection = match &params.dependency {
+                Some(dep) => Selection::only(dep),
+                None => Selection::default(),
+            };
+            let edits = apply(&opt, &selection)?;
+            Ok(serde_json::to_value(edits).map_err(anyhow::Error::from)?)
+        }
+        _ => Err(RpcError {
+            code: METHOD_NOT_FOUND,
+            message: format!("Unknown method '{}'", method),
+        }),
+    }
+}
+
+/// The edits consolidating `selection` would make, computed in a copy of
+/// the workspace
+fn plan(opt: &Opt, selection: &Selection) -> Result<Vec<TextEdit>> {
+    let metadata = workspace::load_metadata(opt.manifest_path(), &opt.cargo)?;
+    let workspace_root = metadata.workspace_root.as_std_path();
+    let preview = Preview::new(opt, workspace_root)?;
+
+    let mut edits = Vec::new();
+    for (relative, content) in preview.run(selection)? {
+        edits.extend(text_edits(
+            &workspace_root.join(&relative),
+            &preview.originals[&relative],
+            &content,
+        ));
+    }
+    Ok(edits)
+}
+
+/// Consolidates `selection` and returns the edits that were made
+fn apply(opt: &Opt, selection: &Selection) -> Result<Vec<TextEdit>> {
+    let metadata = workspace::load_metadata(opt.manifest_path(), &opt.cargo)?;
+    let mut manifest_paths = vec![metadata
+        .workspace_root
+        .join("Cargo.toml")
+        .into_std_path_buf()];
+    for package in workspace::workspace_packages(&metadata)? {
+        manifest_paths.push(package.manifest_path.clone().into_std_path_buf());
+    }
+    manifest_paths.sort();
+    manifest_paths.dedup();
+
+    let mut originals = Vec::new();
+    for path in &manifest_paths {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        originals.push(content);
+    }
+    // stdout carries the JSON-RPC messages
+    let mut opt = opt.clone();
+    opt.embedded = true;
+    workspace::consolidate_selected(&opt, selection)?;
+
+    let mut edits = Vec::new();
+    for (path, original) in manifest_paths.iter().zip(&originals) {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        edits.extend(text_edits(path, original, &content));
+    }
+    Ok(edits)
+}
+
+/// Turns the changes from `old` to `new` into replacements of whole lines
+fn text_edits(path: &Path, old: &str, new: &str) -> Vec<TextEdit> {
+    let diff = TextDiff::from_lines(old, new);
+    let new_lines = diff.new_slices();
+    diff.grouped_ops(0)
+        .iter()
+        .filter_map(|group| {
+            let (first, last) = (group.first()?, group.last()?);
+            let old_range = first.old_range().start..last.old_range().end;
+            let new_range = first.new_range().start..last.new_range().end;
+            Some(TextEdit {
+                path: path.to_path_buf(),
+                range: Range {
+                    start: Position::line(old_range.start),
+                    end: Position::line(old_range.end),
+                },
+                new_text: new_lines[new_range].concat(),
+            })
+        })
+        .collect()
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    }
+}
+
+/// Reads the next message, or `None` at the end of input
+fn read_message(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .with_context(|| format!("Invalid header '{}'", header))?,
+                );
+            }
+        }
+    }
+
+    let content_length = content_length.context("Message without Content-Length header")?;
+    let mut content = vec![0; content_length];
+    reader
+        .read_exact(&mut content)
+        .context("Failed to read message")?;
+    Ok(Some(
+        String::from_utf8(content).context("Message isn't valid UTF-8")?,
+    ))
+}
+
+fn write_message(writer: &mut impl Write, message: &Value) -> Result<()> {
+    let content = message.to_string();
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush().context("Failed to write response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_text_edits() {
+        let path = Path::new("Cargo.toml");
+        let old = "[package]\nname = \"a\"\n\n[dependencies]\nserde = \"1\"\nlog = \"0.4\"\n";
+        let new = "[package]\nname = \"a\"\n\n[dependencies]\nserde = { workspace = true }\nlog = \"0.4\"\n";
+        assert_eq!(
+            text_edits(path, old, new),
+            vec![TextEdit {
+                path: path.to_path_buf(),
+                range: Range {
+                    start: Position::line(4),
+                    end: Position::line(5),
+                },
+                new_text: "serde = { workspace = true }\n".to_string(),
+            }]
+        );
+        assert!(text_edits(path, old, old).is_empty());
+    }
+
+    #[test]
+    fn test_serve() -> Result<()> {
+        let dir = TempDir::new()?;
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n",
+        )?;
+        for member in ["a", "b"] {
+            write_file(
+                dir.path().join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\nlog = \"0.4\"\n",
+                    member
+                ),
+            )?;
+        }
+        let manifest_path = dir.path().join("Cargo.toml");
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+        ]);
+
+        let mut input = Vec::new();
+        for request in [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "plan", "params": { "dependency": "serde" } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "nonsense" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ] {
+            write_message(&mut input, &request)?;
+        }
+        let mut output = Vec::new();
+        serve(&opt, &mut input.as_slice(), &mut output)?;
+
+        let mut output = output.as_slice();
+        let plan: Value = serde_json::from_str(&read_message(&mut output)?.unwrap())?;
+        let edits = plan["result"].as_array().unwrap();
+        // The workspace entry plus one line in each member
+        assert_eq!(edits.len(), 3);
+        assert_eq!(
+            edits[0]["newText"],
+            "\n[workspace.dependencies]\nserde = \"1\"\n"
+        );
+        assert_eq!(edits[1]["newText"], "serde = { workspace = true }\n");
+
+        let unknown: Value = serde_json::from_str(&read_message(&mut output)?.unwrap())?;
+        assert_eq!(unknown["id"], 2);
+        assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+        assert!(read_message(&mut output)?.is_none());
+
+        // Planning leaves the workspace alone
+        assert!(fs::read_to_string(&manifest_path)?.ends_with("members = [\"a\", \"b\"]\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item};
+
+use crate::manifest::Manifest;
+
+/// A source replaced through `[source]` in a cargo config, e.g. crates.io
+/// replaced by a vendored directory or a registry mirror
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceReplacement {
+    pub source: String,
+    pub replace_with: String,
+    /// `directory`, `local-registry`, `registry` or `git`
+    pub kind: String,
+    /// The directory or URL of the replacement
+    pub location: String,
+}
+
+impl SourceReplacement {
+    /// The directory holding the vendored crates, if the replacement is one
+    pub fn vendor_dir(&self) -> Option<&Path> {
+        (self.kind == "directory").then(|| Path::new(&self.location))
+    }
+}
+
+impl fmt::Display for SourceReplacement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' is replaced with '{}' ({} {})",
+            self.source, self.replace_with, self.kind, self.location
+        )
+    }
+}
+
+/// A `[source.<name>]` table, with relative paths resolved already
+#[derive(Default)]
+struct SourceEntry {
+    replace_with: Option<String>,
+    kind: Option<(String, String)>,
+}
+
+/// Returns the source replacements in effect for the workspace at
+/// `workspace_root`, read from the cargo configs in it, its ancestors and
+/// `$CARGO_HOME` the way cargo does
+pub fn detect(workspace_root: &Path) -> Result<Vec<SourceReplacement>> {
+    let mut config_paths = Vec::new();
+    for dir in workspace_root.ancestors() {
+        config_paths.push(dir.join(".cargo/config.toml"));
+        config_paths.push(dir.join(".cargo/config"));
+    }
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+    if let Some(cargo_home) = cargo_home {
+        config_paths.push(cargo_home.join("config.toml"));
+        config_paths.push(cargo_home.join("config"));
+    }
+
+    let mut configs = Vec::new();
+    for path in config_paths.iter().filter(|path| path.is_file()) {
+        configs.push((path.as_path(), Manifest::open(path)?.doc));
+    }
+    Ok(from_configs(&configs))
+}
+
+/// Resolves the replacements from the `[source]` tables of `configs`,
+/// the nearest config first. Settings of nearer configs take precedence
+fn from_configs(configs: &[(&Path, DocumentMut)]) -> Vec<SourceReplacement> {
+    let mut entries: BTreeMap<String, SourceEntry> = BTreeMap::new();
+    for (path, doc) in configs {
+        // Paths are relative to the directory containing `.cargo`
+        let base = path
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""));
+        let sources = doc.get("source").and_then(Item::as_table_like);
+        for (name, source) in sources.into_iter().flat_map(|sources| sources.iter()) {
+            let entry = entries.entry(name.to_string()).or_default();
+            if entry.replace_with.is_none() {
+                entry.replace_with = source
+                    .get("replace-with")
+                    .and_then(Item::as_str)
+                    .map(str::to_string);
+            }
+            if entry.kind.is_none() {
+                entry.kind = source_kind(source, base);
+            }
+        }
+    }
+
+    let mut replacements = Vec::new();
+    for (name, entry) in &entries {
+        let Some(replace_with) = &entry.replace_with else {
+            continue;
+        };
+        // Follow chains of replacements to the source actually used
+        let mut target = replace_with;
+        let mut hops = 0;
+        while let Some(next) = entries
+            .get(target)
+            .and_then(|entry| entry.replace_with.as_ref())
+        {
+            target = next;
+            hops += 1;
+            if hops > entries.len() {
+                break;
+            }
+        }
+        let (kind, location) = entries
+            .get(target)
+            .and_then(|entry| entry.kind.clone())
+            .unwrap_or_else(|| ("unknown".to_string(), String::new()));
+        replacements.push(SourceReplacement {
+            source: name.clone(),
+            replace_with: target.clone(),
+            kind,
+            location,
+        });
+    }
+    replacements
+}
+
+fn source_kind(source: &Item, base: &Path) -> Option<(String, String)> {
+    for kind in ["directory", "local-registry"] {
+        if let Some(path) = source.get(kind).and_then(Item::as_str) {
+            return Some((kind.to_string(), base.join(path).display().to_string()));
+        }
+    }
+    for kind in ["registry", "git"] {
+        if let Some(url) = source.get(kind).and_then(Item::as_str) {
+            return Some((kind.to_string(), url.to_string()));
+        }
+    }
+    None
+}
+
+/// Returns the version of every crate vendored into `dir`, keyed by name
+pub fn vendored_versions(dir: &Path) -> Result<Vec<(String, String)>> {
+    let mut versions = Vec::new();
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Failed to read '{}'", dir.display()))?;
+    for entry in entries {
+        let manifest_path = entry?.path().join("Cargo.toml");
+        if !manifest_path.is_file() {
+            continue;
+        }
+        let doc = Manifest::open(&manifest_path)?.doc;
+        let package = doc.get("package");
+        let (Some(name), Some(version)) = (
+            package
+                .and_then(|package| package.get("name"))
+                .and_then(Item::as_str),
+            package
+                .and_then(|package| package.get("version"))
+                .and_then(Item::as_str),
+        ) else {
+            continue;
+        };
+        versions.push((name.to_string(), version.to_string()));
+    }
+    versions.sort();
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_from_configs() -> Result<()> {
+        let workspace = r#"
+            [source.crates-io]
+            replace-with = "vendored-sources"
+
+            [source.vendored-sources]
+            directory = "vendor"
+        "#
+        .parse::<DocumentMut>()?;
+        let home = r#"
+            [source.crates-io]
+            replace-with = "mirror"
+
+            [source.mirror]
+            registry = "sparse+https://mirror.example.com/index/"
+
+            [source.upstream]
+            replace-with = "crates-io"
+        "#
+        .parse::<DocumentMut>()?;
+
+        let replacements = from_configs(&[
+            (Path::new("/ws/.cargo/config.toml"), workspace),
+            (Path::new("/home/me/.cargo/config.toml"), home),
+        ]);
+        assert_eq!(
+            replacements,
+            vec![
+                SourceReplacement {
+                    source: "crates-io".to_string(),
+                    replace_with: "vendored-sources".to_string(),
+                    kind: "directory".to_string(),
+                    location: "/ws/vendor".to_string(),
+                },
+                SourceReplacement {
+                    source: "upstream".to_string(),
+                    replace_with: "vendored-sources".to_string(),
+                    kind: "directory".to_string(),
+                    location: "/ws/vendor".to_string(),
+                },
+            ]
+        );
+        assert_eq!(replacements[0].vendor_dir(), Some(Path::new("/ws/vendor")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_vendored_versions() -> Result<()> {
+        let dir = TempDir::new()?;
+        for (crate_dir, name, version) in [
+            ("serde", "serde", "1.0.210"),
+            ("syn-1.0.109", "syn", "1.0.109"),
+        ] {
+            write_file(
+                dir.path().join(crate_dir).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"{}\"\n",
+                    name, version
+                ),
+            )?;
+        }
+
+        assert_eq!(
+            vendored_versions(dir.path())?,
+            vec![
+                ("serde".to_string(), "1.0.210".to_string()),
+                ("syn".to_string(), "1.0.109".to_string()),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+use anyhow::{Context, Result};
+use cargo_metadata::DependencyKind;
+use log::info;
+use semver::VersionReq;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item};
+
+use crate::cli::Opt;
+use crate::config::Config;
+use crate::dependency::{self, Kinds};
+use crate::diagnostic;
+use crate::git;
+use crate::metadata;
+use crate::version;
+use crate::workspace;
+
+/// A manifest staged in git, next to its version in `HEAD`
+struct Staged {
+    /// Relative to the workspace root
+    path: PathBuf,
+    doc: DocumentMut,
+    head: Option<DocumentMut>,
+}
+
+/// Fails if the manifests staged in git declare dependencies themselves
+/// which they should inherit from the workspace. Only the staged manifests
+/// and the root manifest are read, so it stays fast in a pre-commit hook.
+/// Dependencies consolidating would leave alone aren't reported
+pub fn check(opt: &Opt) -> Result<()> {
+    let manifest_path = match opt.manifest_path() {
+        Some(path) => path.to_path_buf(),
+        None => std::env::current_dir()
+            .context("Failed to get the current directory")?
+            .join("Cargo.toml"),
+    };
+    let manifest_path = fs::canonicalize(&manifest_path)
+        .with_context(|| format!("Failed to find '{}'", manifest_path.display()))?;
+    let root_manifest_path = metadata::find_workspace_root(&manifest_path)?;
+    let workspace_root = root_manifest_path
+        .parent()
+        .context("The root manifest has no parent directory")?;
+
+    // The index is what gets committed, not the working tree
+    let root_content = match git::staged_file(workspace_root, Path::new("Cargo.toml")) {
+        Some(content) => content,
+        None => fs::read_to_string(&root_manifest_path)
+            .with_context(|| format!("Failed to read '{}'", root_manifest_path.display()))?,
+    };
+    let root = root_content
+        .parse::<DocumentMut>()
+        .map_err(|err| diagnostic::locate(err.into(), &root_manifest_path, &root_content))
+        .with_context(|| format!("Failed to parse '{}'", root_manifest_path.display()))?;
+    let config = Config::load(opt.config.as_deref(), workspace_root, &root)?
+        .with_preset(opt.preset.as_deref())?;
+    let opt = config.apply_defaults(opt);
+    let mut members = metadata::find_members(&root, workspace_root)?;
+    if root.contains_key("package") {
+        members.push(root_manifest_path.clone());
+    }
+
+    let mut staged = Vec::new();
+    for path in git::staged_manifests(workspace_root)? {
+        if !members.contains(&workspace_root.join(&path)) {
+            continue;
+        }
+        let content = git::staged_file(workspace_root, &path)
+            .with_context(|| format!("Failed to read the staged '{}'", path.display()))?;
+        let doc = content
+            .parse::<DocumentMut>()
+            .map_err(|err| diagnostic::locate(err.into(), &path, &content))
+            .with_context(|| format!("Failed to parse the staged '{}'", path.display()))?;
+        let head = git::file_at(workspace_root, "HEAD", &path)
+            .and_then(|content| content.parse::<DocumentMut>().ok());
+        staged.push(Staged { path, doc, head });
+    }
+    info!("Checking {} staged member manifest(s)", staged.len());
+
+    let problems = find_problems(&root, &staged, &opt, &config);
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "{} staged dependencies should come from [workspace.dependencies]:\n{}\nRun `cargo consolidate` to fix them, then stage the changes again",
+            problems.len(),
+            problems
+                .iter()
+                .map(|problem| format!("  - {}", problem))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    println!(
+        "The {} staged member manifest(s) add no unconsolidated dependencies.",
+        staged.len()
+    );
+    Ok(())
+}
+
+/// Finds the dependencies the staged manifests start declaring themselves,
+/// or change, although the workspace has an entry for them or enough other
+/// staged manifests add them as well. Like consolidating, it goes by the
+/// configuration and leaves out members keeping their own spec on purpose
+fn find_problems(root: &DocumentMut, staged: &[Staged], opt: &Opt, config: &Config) -> Vec<String> {
+    let workspace_deps = root
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"))
+        .and_then(Item::as_table_like);
+
+    // Every dependency a staged manifest declares anew, with where it does
+    let mut added: BTreeMap<&str, Vec<(&Path, &str, &Item)>> = BTreeMap::new();
+    for manifest in staged {
+        let name = manifest
+            .doc
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(Item::as_str)
+            .unwrap_or_default();
+        let dir = manifest.path.parent().unwrap_or(Path::new(""));
+        if opt.exclude_member.iter().any(|member| member == name) || config.is_ignored(name, dir) {
+            continue;
+        }
+        for section in dependency::DEPENDENCY_SECTIONS {
+            let Some(deps) = manifest.doc.get(section).and_then(Item::as_table_like) else {
+                continue;
+            };
+            for (dep, item) in deps.iter() {
+                if dependency::is_workspace_inherited(item) {
+                    continue;
+                }
+                let before = manifest
+                    .head
+                    .as_ref()
+                    .and_then(|head| head.get(section))
+                    .and_then(|deps| deps.get(dep));
+                let unchanged = before
+                    .is_some_and(|before| before.to_string().trim() == item.to_string().trim());
+                if !unchanged {
+                    added
+                        .entry(dep)
+                        .or_default()
+                        .push((&manifest.path, section, item));
+                }
+            }
+        }
+    }
+
+    let mut problems = Vec::new();
+    for (dep, declarations) in &added {
+        if config.rule(dep).is_some_and(|rule| rule.skip) {
+            continue;
+        }
+        if let Some(entry) = workspace_deps.and_then(|deps| deps.get(dep)) {
+            for (path, section, item) in declarations {
+                if let Some(reason) = workspace::kept_apart(entry, item, opt) {
+                    info!(
+                        "'{}' in [{}] of '{}' is left alone: {}",
+                        dep,
+                        section,
+                        path.display(),
+                        reason
+                    );
+                    continue;
+                }
+                problems.push(format!(
+                    "{}: [{}] declares '{}' itself, use `{} = {{ workspace = true }}`",
+                    path.display(),
+                    section,
+                    dep,
+                    dep
+                ));
+            }
+            continue;
+        }
+
+        let mut kinds = Kinds::default();
+        for (_, section, _) in declarations {
+            kinds.insert(match *section {
+                "build-dependencies" => DependencyKind::Build,
+                "dev-dependencies" => DependencyKind::Development,
+                _ => DependencyKind::Normal,
+            });
+        }
+        let users: BTreeSet<&Path> = declarations.iter().map(|(path, _, _)| *path).collect();
+        if workspace::should_group(dep, users.len(), kinds.class(), opt.group_all, config).is_none()
+        {
+            continue;
+        }
+        // Semver-incompatible versions can't share one entry
+        let ranges: BTreeSet<String> = declarations
+            .iter()
+            .filter_map(|(_, _, item)| dependency::get_version(item))
+            .filter_map(|version| VersionReq::parse(&version).ok())
+            .map(|req| version::compatibility(&req))
+            .collect();
+        if ranges.len() > 1 && !opt.allow_major_split {
+            info!(
+                "'{}' is added with semver-incompatible versions, which are left alone",
+                dep
+            );
+            continue;
+        }
+        for (path, section, _) in declarations {
+            let others: Vec<String> = users
+                .iter()
+                .filter(|other| *other != path)
+                .map(|other| other.display().to_string())
+                .collect();
+            if others.is_empty() {
+                problems.push(format!(
+                    "{}: [{}] adds '{}', move it into [workspace.dependencies]",
+                    path.display(),
+                    section,
+                    dep
+                ));
+            } else {
+                problems.push(format!(
+                    "{}: [{}] adds '{}', which {} add(s) as well, move it into [workspace.dependencies]",
+                    path.display(),
+                    section,
+                    dep,
+                    others.join(", ")
+                ));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn opt() -> Opt {
+        Opt::parse_from(["cargo-consolidate"])
+    }
+
+    fn staged(path: &str, doc: &str, head: Option<&str>) -> Result<Staged> {
+        Ok(Staged {
+            path: PathBuf::from(path),
+            doc: doc.parse()?,
+            head: head.map(str::parse).transpose()?,
+        })
+    }
+
+    #[test]
+    fn test_find_problems() -> Result<()> {
+        let root = "[workspace]\nmembers = [\"*\"]\n\n[workspace.dependencies]\nserde = \"1\"\n"
+            .parse::<DocumentMut>()?;
+        let staged = [
+            staged(
+                "a/Cargo.toml",
+                "[dependencies]\nserde = \"1\"\ntokio = \"1\"\nlog = \"0.4\"\n",
+                Some("[dependencies]\nlog = \"0.4\"\n"),
+            )?,
+            staged(
+                "b/Cargo.toml",
+                "[dev-dependencies]\ntokio = \"1\"\nserde = { workspace = true }\n",
+                None,
+            )?,
+        ];
+
+        assert_eq!(
+            find_problems(&root, &staged, &opt(), &Config::default()),
+            vec![
+                "a/Cargo.toml: [dependencies] declares 'serde' itself, use `serde = { workspace = true }`",
+                "a/Cargo.toml: [dependencies] adds 'tokio', which b/Cargo.toml add(s) as well, move it into [workspace.dependencies]",
+                "b/Cargo.toml: [dev-dependencies] adds 'tokio', which a/Cargo.toml add(s) as well, move it into [workspace.dependencies]",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_problems_uses_config() -> Result<()> {
+        let root = "[workspace]\nmembers = [\"*\"]\n".parse::<DocumentMut>()?;
+        let staged = [
+            staged("a/Cargo.toml", "[dependencies]\ntokio = \"1\"\n", None)?,
+            staged("b/Cargo.toml", "[dependencies]\ntokio = \"1\"\n", None)?,
+        ];
+        let config: Config = toml_edit::de::from_str("min-members = 3\n")?;
+        assert!(find_problems(&root, &staged, &opt(), &config).is_empty());
+
+        let config: Config = toml_edit::de::from_str("[rules.tokio]\nskip = true\n")?;
+        assert!(find_problems(&root, &staged, &opt(), &config).is_empty());
+
+        let config = Config::from_workspace_metadata(
+            &"[workspace.metadata.consolidate]\nignore = [\"b\"]\n".parse()?,
+        )?;
+        assert!(find_problems(&root, &staged, &opt(), &config).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_problems_skips_what_is_left_alone() -> Result<()> {
+        let root = "[workspace]\nmembers = [\"*\"]\n\n[workspace.dependencies]\nserde = \"1\"\n"
+            .parse::<DocumentMut>()?;
+        let staged = [
+            // The workspace entry would loosen the pin
+            staged(
+                "a/Cargo.toml",
+                "[dependencies]\nserde = \"=1.0.100\"\n",
+                None,
+            )?,
+            // Semver-incompatible versions can't share an entry
+            staged("b/Cargo.toml", "[dependencies]\nrand = \"0.7\"\n", None)?,
+            staged("c/Cargo.toml", "[dependencies]\nrand = \"0.8\"\n", None)?,
+        ];
+        assert!(find_problems(&root, &staged, &opt(), &Config::default()).is_empty());
+
+        let mut opt = opt();
+        opt.allow_loosen = true;
+        opt.allow_major_split = true;
+        assert_eq!(
+            find_problems(&root, &staged, &opt, &Config::default()).len(),
+            3
+        );
+        Ok(())
+    }
+}
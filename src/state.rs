@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item};
+
+use crate::cache;
+use crate::cli::Opt;
+use crate::config;
+use crate::dependency;
+use crate::manifest::Manifest;
+
+/// The manifests as `--incremental` left them after the last run
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    /// Fingerprint of the options and of the files they point to
+    options: u64,
+    root: u64,
+    members: BTreeMap<PathBuf, Member>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Member {
+    hash: u64,
+    /// Dependencies inheriting the workspace entry in every section
+    inherited: BTreeSet<String>,
+}
+
+impl State {
+    /// Reads the state of the last run. A missing or unreadable state
+    /// means starting from scratch
+    pub fn load(path: &Path) -> Self {
+        let state = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok());
+        if state.is_none() {
+            debug!("No state of an earlier run in '{}'", path.display());
+        }
+        state.unwrap_or_default()
+    }
+
+    /// Records the root manifest and the member manifests as they are now
+    pub fn record(
+        options: u64,
+        root_manifest_path: &Path,
+        member_manifest_paths: &[PathBuf],
+    ) -> Result<Self> {
+        let mut members = BTreeMap::new();
+        for manifest_path in member_manifest_paths {
+            let manifest = Manifest::open(manifest_path)?;
+            members.insert(
+                manifest_path.clone(),
+                Member {
+                    hash: cache::hash_file(manifest_path),
+                    inherited: inherited_dependencies(&manifest.doc),
+                },
+            );
+        }
+        Ok(Self {
+            options,
+            root: cache::hash_file(root_manifest_path),
+            members,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create '{}'", dir.display()))?;
+        }
+        fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write '{}'", path.display()))
+    }
+
+    /// Whether the run with `options` would find the workspace exactly as
+    /// the last run left it, members included
+    pub fn is_up_to_date(
+        &self,
+        options: u64,
+        root_manifest_path: &Path,
+        member_manifest_paths: &[PathBuf],
+    ) -> bool {
+        self.options == options
+            && self.root == cache::hash_file(root_manifest_path)
+            && self.members.len() == member_manifest_paths.len()
+            && member_manifest_paths
+                .iter()
+                .all(|manifest_path| self.is_unchanged(manifest_path))
+    }
+
+    /// Whether the member still inherits `dep_name` the way the last run
+    /// left it, so that updating it again would change nothing
+    pub fn inherits(&self, manifest_path: &Path, dep_name: &str) -> bool {
+        self.is_unchanged(manifest_path) && self.members[manifest_path].inherited.contains(dep_name)
+    }
+
+    fn is_unchanged(&self, manifest_path: &Path) -> bool {
+        self.members
+            .get(manifest_path)
+            .is_some_and(|member| member.hash == cache::hash_file(manifest_path))
+    }
+}
+
+/// Where the state of the workspace is kept, next to the build output
+pub fn path(target_directory: &Path) -> PathBuf {
+    target_directory
+        .join("cargo-consolidate")
+        .join("state.json")
+}
+
+/// Fingerprints the options of a run together with the configuration,
+/// policy and catalogue files, any of which changes the outcome
+pub fn fingerprint(opt: &Opt, workspace_root: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", opt).hash(&mut hasher);
+    let files = [
+        Some(workspace_root.join(config::CONFIG_FILE_NAME)),
+        opt.config.clone(),
+        opt.policy.clone(),
+        opt.catalogue.clone(),
+    ];
+    for file in files.iter().flatten() {
+        cache::hash_file(file).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn inherited_dependencies(doc: &DocumentMut) -> BTreeSet<String> {
+    let mut inherited = BTreeSet::new();
+    let mut own = BTreeSet::new();
+    for section in dependency::DEPENDENCY_SECTIONS {
+        let Some(deps) = doc.get(section).and_then(Item::as_table_like) else {
+            continue;
+        };
+        for (dep_name, item) in deps.iter() {
+            if dependency::is_workspace_inherited(item) {
+                inherited.insert(dep_name.to_string());
+            } else {
+                own.insert(dep_name.to_string());
+            }
+        }
+    }
+    inherited.difference(&own).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_state() -> Result<()> {
+        let dir = TempDir::new()?;
+        let root = dir.path().join("Cargo.toml");
+        let member = dir.path().join("a").join("Cargo.toml");
+        fs::write(&root, "[workspace]\nmembers = [\"a\"]\n")?;
+        write_file(
+            &member,
+            "[dependencies]\nserde = { workspace = true }\ntokio = { workspace = true }\n\n[dev-dependencies]\ntokio = \"1\"\n",
+        )?;
+        let members = vec![member.clone()];
+
+        let state_path = path(&dir.path().join("target"));
+        State::record(7, &root, &members)?.save(&state_path)?;
+        let state = State::load(&state_path);
+        assert!(state.is_up_to_date(7, &root, &members));
+        assert!(!state.is_up_to_date(8, &root, &members));
+        assert!(state.inherits(&member, "serde"));
+        assert!(!state.inherits(&member, "tokio"));
+
+        fs::write(&member, "[dependencies]\nserde = \"1\"\n")?;
+        assert!(!state.is_up_to_date(7, &root, &members));
+        assert!(!state.inherits(&member, "serde"));
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item};
+
+use crate::cli::ShowFiles;
+use crate::dependency;
+
+#[derive(Debug, Default)]
+struct Moved {
+    /// The requirement of the workspace entry once the run is done
+    version: Option<String>,
+    /// Whether this run created the workspace entry
+    new: bool,
+    /// The members switched to inheritance, with the requirement they
+    /// declared before
+    members: BTreeMap<String, Option<String>>,
+}
+
+/// Where a workspace entry this run created comes from
+#[derive(Debug, Default, Clone)]
+pub struct Provenance {
+    /// The member whose spec the entry is based on, or the configuration
+    /// or catalogue providing it
+    pub basis: String,
+    /// The members whose spec differs from the entry, with how
+    pub overridden: BTreeMap<String, Vec<String>>,
+}
+
+/// Records the changes a run applied, for `--write-summary`
+#[derive(Debug, Default)]
+pub struct Summary {
+    moved: BTreeMap<String, Moved>,
+    provenance: BTreeMap<String, Provenance>,
+    edited: BTreeSet<PathBuf>,
+    /// Dependencies left alone because a single member uses them, with
+    /// that member
+    near_misses: BTreeMap<String, String>,
+    /// Which of the edited manifests to list
+    show: Option<ShowFiles>,
+}
+
+impl Summary {
+    /// Records that `member` now inherits `dep` instead of declaring
+    /// `previous`
+    pub fn moved(&mut self, dep: &str, new: bool, member: &str, previous: Option<String>) {
+        let moved = self.moved.entry(dep.to_string()).or_default();
+        moved.new |= new;
+        moved.members.insert(member.to_string(), previous);
+    }
+
+    /// Records where the new workspace entry of `dep` comes from
+    pub fn provenance(&mut self, dep: &str, provenance: Provenance) {
+        self.provenance.insert(dep.to_string(), provenance);
+    }
+
+    /// Records that `dep` wasn't consolidated because only `member` uses it
+    pub fn near_miss(&mut self, dep: &str, member: &str) {
+        self.near_misses.insert(dep.to_string(), member.to_string());
+    }
+
+    /// Lists the dependencies only one member uses, as candidates for
+    /// hoisting before a second member needs them. `embedded` runs log it
+    /// rather than taking over stdout
+    pub fn print_near_misses(&self, embedded: bool) {
+        if self.near_misses.is_empty() {
+            return;
+        }
+        let mut near_misses = "Used by a single member and left as they are:".to_string();
+        for (dep, member) in &self.near_misses {
+            near_misses.push_str(&format!("\n  {} ({})", dep, member));
+        }
+        match embedded {
+            true => info!("{}", near_misses),
+            false => println!("{}", near_misses),
+        }
+    }
+
+    /// Records that the manifest at `path`, relative to the workspace root,
+    /// was written
+    pub fn edited(&mut self, path: &Path) {
+        self.edited.insert(path.to_path_buf());
+    }
+
+    /// Takes the requirements of the workspace entries from the root
+    /// manifest as it is written. Only what changed in the manifests `show`
+    /// includes is listed: the versions and where new entries come from for
+    /// the root, the members switched to inheritance for the members
+    pub fn finish(&mut self, root: &DocumentMut, show: ShowFiles) {
+        self.show = Some(show);
+        let ws_deps = root
+            .get("workspace")
+            .and_then(|workspace| workspace.get("dependencies"))
+            .and_then(Item::as_table_like);
+        for (dep, moved) in &mut self.moved {
+            moved.version = ws_deps
+                .and_then(|deps| deps.get(dep))
+                .and_then(dependency::get_version);
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_string())
+            .with_context(|| format!("Failed to write '{}'", path.display()))
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "## Consolidated dependencies")?;
+        writeln!(f)?;
+        if self.moved.is_empty() && self.edited.is_empty() {
+            writeln!(f, "Nothing changed.")?;
+        }
+
+        let root = self.show.is_none_or(ShowFiles::root);
+        let members = self.show.is_none_or(ShowFiles::members);
+        if !self.moved.is_empty() {
+            let mut columns = vec!["Dependency"];
+            if root {
+                columns.push("Version");
+            }
+            if members {
+                columns.push("Members");
+            }
+            writeln!(f, "| {} |", columns.join(" | "))?;
+            writeln!(f, "|{}", " --- |".repeat(columns.len()))?;
+        }
+        for (dep, moved) in &self.moved {
+            let mut row = vec![format!("`{}`", dep)];
+            if root {
+                let mut version = match &moved.version {
+                    Some(version) => format!("`{}`", version),
+                    None => "-".to_string(),
+                };
+                if moved.new {
+                    version.push_str(" (new)");
+                }
+                row.push(version);
+            }
+            let moved_members: Vec<String> = moved
+                .members
+                .iter()
+                .map(|(member, previous)| match previous {
+                    // The member's own requirement gave way to the workspace's
+                    Some(previous) if moved.version.as_ref() != Some(previous) => {
+                        format!("{} (was `{}`)", member, previous)
+                    }
+                    _ => member.clone(),
+                })
+                .collect();
+            if members {
+                row.push(moved_members.join(", "));
+            }
+            writeln!(f, "| {} |", row.join(" | "))?;
+        }
+
+        let provenance = if root {
+            &self.provenance
+        } else {
+            &BTreeMap::new()
+        };
+        if !provenance.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Where the new workspace entries come from:")?;
+            writeln!(f)?;
+        }
+        for (dep, provenance) in provenance {
+            writeln!(f, "- `{}`: {}", dep, provenance)?;
+        }
+
+        let shown: Vec<&PathBuf> = self
+            .edited
+            .iter()
+            .filter(|path| self.show.is_none_or(|show| show.includes(path)))
+            .collect();
+        if !shown.is_empty() {
+            if !self.moved.is_empty() || !provenance.is_empty() {
+                writeln!(f)?;
+            }
+            writeln!(f, "Edited manifests:")?;
+            writeln!(f)?;
+            for path in shown {
+                writeln!(f, "- `{}`", path.display())?;
+            }
+        }
+
+        if !self.near_misses.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Used by a single member and left as they are:")?;
+            writeln!(f)?;
+            for (dep, member) in &self.near_misses {
+                writeln!(f, "- `{}` ({})", dep, member)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "based on {}", self.basis)?;
+        for (member, differences) in &self.overridden {
+            write!(f, "; overrides {} ({})", member, differences.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() -> Result<()> {
+        let mut summary = Summary::default();
+        assert_eq!(
+            summary.to_string(),
+            "## Consolidated dependencies\n\nNothing changed.\n"
+        );
+
+        summary.moved("serde", true, "api", Some("1.0.200".to_string()));
+        summary.moved("serde", true, "cli", Some("1.0.100".to_string()));
+        summary.moved("anyhow", false, "cli", Some("1".to_string()));
+        summary.edited(Path::new("Cargo.toml"));
+        summary.edited(Path::new("cli/Cargo.toml"));
+        summary.near_miss("rand", "api");
+        summary.provenance(
+            "serde",
+            Provenance {
+                basis: "api".to_string(),
+                overridden: BTreeMap::from([(
+                    "cli".to_string(),
+                    vec!["version `1.0.100` became `1.0.200`".to_string()],
+                )]),
+            },
+        );
+        let root = "[workspace.dependencies]\nserde = \"1.0.200\"\nanyhow = \"1\"\n".parse()?;
+        summary.finish(&root, ShowFiles::All);
+
+        assert_eq!(
+            summary.to_string(),
+            "## Consolidated dependencies\n\n\
+             | Dependency | Version | Members |\n\
+             | --- | --- | --- |\n\
+             | `anyhow` | `1` | cli |\n\
+             | `serde` | `1.0.200` (new) | api, cli (was `1.0.100`) |\n\n\
+             Where the new workspace entries come from:\n\n\
+             - `serde`: based on api; overrides cli (version `1.0.100` became `1.0.200`)\n\n\
+             Edited manifests:\n\n\
+             - `Cargo.toml`\n\
+             - `cli/Cargo.toml`\n\n\
+             Used by a single member and left as they are:\n\n\
+             - `rand` (api)\n"
+        );
+
+        summary.finish(&root, ShowFiles::Root);
+        assert_eq!(
+            summary.to_string(),
+            "## Consolidated dependencies\n\n\
+             | Dependency | Version |\n\
+             | --- | --- |\n\
+             | `anyhow` | `1` |\n\
+             | `serde` | `1.0.200` (new) |\n\n\
+             Where the new workspace entries come from:\n\n\
+             - `serde`: based on api; overrides cli (version `1.0.100` became `1.0.200`)\n\n\
+             Edited manifests:\n\n\
+             - `Cargo.toml`\n\n\
+             Used by a single member and left as they are:\n\n\
+             - `rand` (api)\n"
+        );
+
+        summary.finish(&root, ShowFiles::Members);
+        assert_eq!(
+            summary.to_string(),
+            "## Consolidated dependencies\n\n\
+             | Dependency | Members |\n\
+             | --- | --- |\n\
+             | `anyhow` | cli |\n\
+             | `serde` | api, cli (was `1.0.100`) |\n\n\
+             Edited manifests:\n\n\
+             - `cli/Cargo.toml`\n\n\
+             Used by a single member and left as they are:\n\n\
+             - `rand` (api)\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,13 @@
+//! Helpers shared by the tests
+
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+/// Writes `content` to `path`, creating the directories leading up to it
+pub fn write_file(path: impl AsRef<Path>, content: &str) -> Result<()> {
+    let path = path.as_ref();
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, content)?;
+    Ok(())
+}
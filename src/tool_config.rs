@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use cargo_metadata::Package;
+use log::info;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use toml_edit::{Item, Table};
+
+use crate::cli::{Opt, ReportFormat, ToolConfigArgs};
+use crate::lock;
+use crate::manifest::Manifest;
+use crate::workspace;
+
+/// Tools which only read `[package.metadata.<tool>]`, so hoisting their
+/// configuration would make them lose it
+const PACKAGE_ONLY_TOOLS: [(&str, &str); 1] = [("docs", "docs.rs")];
+
+/// A `[package.metadata.<tool>]` table several members declare verbatim
+#[derive(Debug, Serialize)]
+pub struct Duplicate {
+    pub tool: String,
+    /// The members sharing the table, by package name
+    pub members: Vec<String>,
+    /// The members configuring the tool differently
+    pub differing: Vec<String>,
+    /// Whether the table was moved to `[workspace.metadata.<tool>]`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub hoisted: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Duplicates {
+    pub tools: Vec<Duplicate>,
+}
+
+/// Reports the tool configuration duplicated across members, and moves the
+/// tables of the tools given with `--hoist` to the root manifest
+pub fn run(opt: &Opt, args: &ToolConfigArgs) -> Result<()> {
+    let metadata = workspace::load_metadata(opt.manifest_path(), &opt.cargo)?;
+    let packages = workspace::workspace_packages(&metadata)?;
+    let mut duplicates = find_duplicates(&packages);
+
+    if !args.hoist.is_empty() {
+        let _lock = lock::acquire(metadata.target_directory.as_std_path())?;
+        let root_manifest_path = metadata.workspace_root.join("Cargo.toml");
+        let mut root = Manifest::open(root_manifest_path.as_std_path())?;
+        let mut members = Vec::new();
+        for tool in &args.hoist {
+            let duplicate = duplicates
+                .tools
+                .iter_mut()
+                .filter(|duplicate| &duplicate.tool == tool)
+                .max_by_key(|duplicate| duplicate.members.len())
+                .with_context(|| format!("No members share [package.metadata.{}]", tool))?;
+            let paths: Vec<PathBuf> = packages
+                .iter()
+                .filter(|package| duplicate.members.contains(&package.name))
+                .map(|package| package.manifest_path.clone().into_std_path_buf())
+                .collect();
+            hoist(&mut root, tool, &paths, &mut members)?;
+            duplicate.hoisted = true;
+        }
+        for member in &members {
+            member.write(opt.symlinks)?;
+        }
+        root.write(opt.symlinks)?;
+    }
+
+    match args.format {
+        ReportFormat::Markdown => print!("{}", duplicates),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&duplicates)?),
+    }
+    Ok(())
+}
+
+/// Groups the members' `[package.metadata.<tool>]` tables by tool and
+/// content, keeping the groups shared by at least two members
+fn find_duplicates(packages: &[&Package]) -> Duplicates {
+    let mut packages = packages.to_vec();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut tools: Vec<&String> = packages
+        .iter()
+        .filter_map(|package| package.metadata.as_object())
+        .flat_map(|metadata| metadata.keys())
+        .collect();
+    tools.sort();
+    tools.dedup();
+
+    let mut duplicates = Duplicates::default();
+    for tool in tools {
+        // Each group holds one configuration of the tool and its members
+        let mut groups: Vec<(&serde_json::Value, Vec<String>)> = Vec::new();
+        for package in &packages {
+            let Some(config) = package.metadata.get(tool) else {
+                continue;
+            };
+            match groups.iter_mut().find(|(other, _)| *other == config) {
+                Some((_, members)) => members.push(package.name.to_string()),
+                None => groups.push((config, vec![package.name.to_string()])),
+            }
+        }
+        for (position, (_, members)) in groups.iter().enumerate() {
+            if members.len() < 2 {
+                continue;
+            }
+            let differing = groups
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != position)
+                .flat_map(|(_, (_, members))| members.iter().cloned())
+                .collect();
+            duplicates.tools.push(Duplicate {
+                tool: tool.clone(),
+                members: members.clone(),
+                differing,
+                hoisted: false,
+            });
+        }
+    }
+    duplicates
+}
+
+/// Moves `[package.metadata.<tool>]` of the members at `paths` to
+/// `[workspace.metadata.<tool>]` of `root`. The edited members are added to
+/// `members`
+fn hoist(
+    root: &mut Manifest,
+    tool: &str,
+    paths: &[PathBuf],
+    members: &mut Vec<Manifest>,
+) -> Result<()> {
+    if let Some((_, name)) = PACKAGE_ONLY_TOOLS.iter().find(|(key, _)| *key == tool) {
+        anyhow::bail!(
+            "{} only reads [package.metadata.{}], it can't be moved to the workspace",
+            name,
+            tool
+        );
+    }
+
+    let mut hoisted = None;
+    for path in paths {
+        let position = match members.iter().position(|member| member.path() == path) {
+            Some(position) => position,
+            None => {
+                members.push(Manifest::open(path)?);
+                members.len() - 1
+            }
+        };
+        let member = &mut members[position];
+        let metadata = member
+            .doc
+            .get_mut("package")
+            .and_then(|package| package.get_mut("metadata"))
+            .and_then(Item::as_table_like_mut)
+            .with_context(|| format!("'{}' has no [package.metadata]", path.display()))?;
+        let config = metadata
+            .remove(tool)
+            .with_context(|| format!("'{}' has no [package.metadata.{}]", path.display(), tool))?;
+        if metadata.is_empty() {
+            if let Some(package) = member
+                .doc
+                .get_mut("package")
+                .and_then(Item::as_table_like_mut)
+            {
+                package.remove("metadata");
+            }
+        }
+        hoisted.get_or_insert(config);
+    }
+    let Some(config) = hoisted else {
+        return Ok(());
+    };
+
+    let path = root.path().to_path_buf();
+    let workspace_metadata = root
+        .doc
+        .get_mut("workspace")
+        .and_then(Item::as_table_mut)
+        .context("The root manifest has no [workspace] table")?
+        .entry("metadata")
+        .or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_like_mut()
+        .context("workspace.metadata isn't a table")?;
+    if let Some(existing) = workspace_metadata.get(tool) {
+        if existing.to_string().trim() != config.to_string().trim() {
+            anyhow::bail!(
+                "'{}' already has a different [workspace.metadata.{}]",
+                path.display(),
+                tool
+            );
+        }
+        return Ok(());
+    }
+    info!(
+        "Moving [package.metadata.{}] of {} member(s) to [workspace.metadata.{}]",
+        tool,
+        paths.len(),
+        tool
+    );
+    workspace_metadata.insert(tool, config);
+    Ok(())
+}
+
+impl fmt::Display for Duplicates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Duplicated tool configuration")?;
+        writeln!(f)?;
+        if self.tools.is_empty() {
+            writeln!(f, "No members share a [package.metadata] table.")?;
+            return Ok(());
+        }
+        for duplicate in &self.tools {
+            write!(
+                f,
+                "- `[package.metadata.{}]` is identical in {}",
+                duplicate.tool,
+                duplicate.members.join(", ")
+            )?;
+            if !duplicate.differing.is_empty() {
+                write!(f, ", differs in {}", duplicate.differing.join(", "))?;
+            }
+            if duplicate.hoisted {
+                write!(f, ", moved to `[workspace.metadata.{}]`", duplicate.tool)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use clap::Parser;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_hoist() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(root.join("Cargo.toml"), "[workspace]\nmembers = [\"*\"]\n")?;
+        let release = "[package.metadata.release]\nshared-version = true\ntag = false\n";
+        let members = [
+            ("a", release),
+            (
+                "b",
+                "[package.metadata.release]\ntag = false\nshared-version = true\n",
+            ),
+            ("c", "[package.metadata.release]\ntag = true\n"),
+            ("d", "[package.metadata.docs.rs]\nall-features = true\n"),
+            ("e", "[package.metadata.docs.rs]\nall-features = true\n"),
+        ];
+        for (name, metadata) in members {
+            write_file(
+                root.join(name).join("Cargo.toml"),
+                &format!("[package]\nname = \"{}\"\n\n{}", name, metadata),
+            )?;
+        }
+
+        let metadata = crate::metadata::from_manifests(Some(root))?;
+        let packages = workspace::workspace_packages(&metadata)?;
+        let duplicates = find_duplicates(&packages);
+        assert_eq!(
+            duplicates.to_string(),
+            "# Duplicated tool configuration\n\n\
+             - `[package.metadata.docs]` is identical in d, e\n\
+             - `[package.metadata.release]` is identical in a, b, differs in c\n"
+        );
+
+        let manifest_path = root.join("Cargo.toml");
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+        ]);
+        let args = |tool: &str| ToolConfigArgs {
+            hoist: vec![tool.to_string()],
+            format: ReportFormat::Json,
+        };
+        assert!(run(&opt, &args("docs")).is_err());
+        run(&opt, &args("release"))?;
+
+        assert_eq!(
+            fs::read_to_string(&manifest_path)?,
+            "[workspace]\nmembers = [\"*\"]\n\n[workspace.metadata.release]\nshared-version = true\ntag = false\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("b/Cargo.toml"))?,
+            "[package]\nname = \"b\"\n"
+        );
+        assert!(fs::read_to_string(root.join("c/Cargo.toml"))?.contains("tag = true"));
+        Ok(())
+    }
+}
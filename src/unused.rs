@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use cargo_metadata::Package;
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::Item;
+
+use crate::dependency;
+use crate::manifest::Manifest;
+
+/// Dependencies members declare but don't use, keyed by manifest path
+#[derive(Debug, Default)]
+pub struct Unused {
+    by_manifest: HashMap<PathBuf, HashSet<String>>,
+}
+
+/// The output of `cargo udeps --output json`
+#[derive(Deserialize)]
+struct UdepsReport {
+    #[serde(default)]
+    unused_deps: HashMap<String, UdepsPackage>,
+}
+
+#[derive(Deserialize)]
+struct UdepsPackage {
+    manifest_path: PathBuf,
+    #[serde(default)]
+    normal: Vec<String>,
+    #[serde(default)]
+    development: Vec<String>,
+    #[serde(default)]
+    build: Vec<String>,
+}
+
+impl Unused {
+    /// Searches the sources of each package for the dependencies it declares,
+    /// the way cargo-machete does. Dependencies listed in
+    /// `[package.metadata.cargo-machete] ignored` always count as used
+    pub fn scan(packages: &[&Package]) -> Self {
+        let by_manifest = packages
+            .par_iter()
+            .filter_map(|package| {
+                let manifest_path = package.manifest_path.as_std_path();
+                let dir = manifest_path.parent()?;
+                let sources = read_sources(dir);
+                let ignored = ignored(manifest_path);
+                let unused: HashSet<String> = dependency::collect_dependencies(package)
+                    .into_keys()
+                    .filter(|dep| !ignored.contains(dep))
+                    .filter(|dep| !is_mentioned(&sources, &dep.replace('-', "_")))
+                    .collect();
+                (!unused.is_empty()).then(|| (manifest_path.to_path_buf(), unused))
+            })
+            .collect();
+        Self { by_manifest }
+    }
+
+    /// Reads the report of `cargo udeps --output json`
+    pub fn from_udeps(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        let report: UdepsReport = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse '{}'", path.display()))?;
+        let mut by_manifest: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+        for package in report.unused_deps.into_values() {
+            by_manifest
+                .entry(package.manifest_path)
+                .or_default()
+                .extend(
+                    package
+                        .normal
+                        .into_iter()
+                        .chain(package.development)
+                        .chain(package.build),
+                );
+        }
+        Ok(Self { by_manifest })
+    }
+
+    pub fn is_unused(&self, manifest_path: &Path, dep: &str) -> bool {
+        self.by_manifest
+            .get(manifest_path)
+            .is_some_and(|deps| deps.contains(dep))
+    }
+}
+
+/// Read from the manifest, as the metadata `--no-cargo` builds leaves
+/// `[package.metadata]` out
+fn ignored(manifest_path: &Path) -> HashSet<String> {
+    let Ok(manifest) = Manifest::open(manifest_path) else {
+        return HashSet::new();
+    };
+    manifest
+        .doc
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("cargo-machete"))
+        .and_then(|machete| machete.get("ignored"))
+        .and_then(Item::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|dep| dep.as_str().map(str::to_string))
+        .collect()
+}
+
+/// The Rust sources below `dir`, leaving out `target` and nested packages
+fn read_sources(dir: &Path) -> Vec<String> {
+    let mut sources = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                let nested_package = path.join("Cargo.toml").exists();
+                if !nested_package && entry.file_name() != "target" {
+                    dirs.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                if let Ok(content) = fs::read_to_string(&path) {
+                    sources.push(content);
+                }
+            }
+        }
+    }
+    sources
+}
+
+/// Whether `ident` appears as a whole word in any of the sources
+fn is_mentioned(sources: &[String], ident: &str) -> bool {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    sources.iter().any(|source| {
+        source.match_indices(ident).any(|(start, _)| {
+            let before = source[..start].chars().next_back();
+            let after = source[start + ident.len()..].chars().next();
+            !before.is_some_and(is_ident_char) && !after.is_some_and(is_ident_char)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata;
+    use crate::test_util::write_file;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_scan() -> Result<()> {
+        let dir = TempDir::new()?;
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\"]\n",
+        )?;
+        write_file(
+            dir.path().join("a/Cargo.toml"),
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde_json = \"1\"\nrand-core = \"0.6\"\nlog = \"0.4\"\nopenssl-sys = \"0.9\"\n\n[package.metadata.cargo-machete]\nignored = [\"openssl-sys\"]\n",
+        )?;
+        write_file(
+            dir.path().join("a/src/lib.rs"),
+            "use rand_core::RngCore;\n\npub fn f() -> serde_json::Value {\n    let logger = 1;\n    serde_json::Value::Null\n}\n",
+        )?;
+
+        let metadata = metadata::from_manifests(Some(&dir.path().join("Cargo.toml")))?;
+        let packages: Vec<&Package> = metadata.packages.iter().collect();
+        let unused = Unused::scan(&packages);
+        let manifest_path = packages[0].manifest_path.as_std_path();
+        assert!(unused.is_unused(manifest_path, "log"));
+        assert!(!unused.is_unused(manifest_path, "serde_json"));
+        assert!(!unused.is_unused(manifest_path, "rand-core"));
+        assert!(!unused.is_unused(manifest_path, "openssl-sys"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_udeps() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("udeps.json");
+        fs::write(
+            &path,
+            r#"{"success":false,"unused_deps":{"a 0.1.0 (path+file:///ws/a)":{"manifest_path":"/ws/a/Cargo.toml","normal":["log"],"development":["tempfile"],"build":[]}},"note":"Note: ..."}"#,
+        )?;
+        let unused = Unused::from_udeps(&path)?;
+        assert!(unused.is_unused(Path::new("/ws/a/Cargo.toml"), "log"));
+        assert!(unused.is_unused(Path::new("/ws/a/Cargo.toml"), "tempfile"));
+        assert!(!unused.is_unused(Path::new("/ws/a/Cargo.toml"), "serde"));
+        assert!(!unused.is_unused(Path::new("/ws/b/Cargo.toml"), "log"));
+        Ok(())
+    }
+}
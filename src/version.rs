@@ -0,0 +1,267 @@
+use semver::{Comparator, Op, Version, VersionReq};
+use std::fmt;
+
+/// Rewrites a version requirement into its canonical form: an explicit `^` is
+/// dropped and caret requirements are padded to three parts where that
+/// doesn't change their meaning, e.g. `^1.2` becomes `1.2.0`. Anything
+/// which isn't a single caret requirement is returned as is
+pub fn normalize_requirement(req: &str) -> String {
+    let trimmed = req.trim();
+    let Ok(parsed) = VersionReq::parse(trimmed) else {
+        return req.to_string();
+    };
+    let [comparator] = parsed.comparators.as_slice() else {
+        return req.to_string();
+    };
+    if comparator.op != Op::Caret {
+        return req.to_string();
+    }
+
+    let minor = comparator.minor;
+    let patch = comparator.patch;
+    // `^0` and `^0.0` allow more than `^0.0.0`, so they can't be padded
+    let (minor, patch) = match (comparator.major, minor, patch) {
+        (0, None, _) | (0, Some(0), None) => {
+            return trimmed.trim_start_matches('^').trim().to_string()
+        }
+        (_, minor, patch) => (minor.unwrap_or(0), patch.unwrap_or(0)),
+    };
+
+    let mut normalized = format!("{}.{}.{}", comparator.major, minor, patch);
+    if !comparator.pre.is_empty() {
+        normalized.push('-');
+        normalized.push_str(comparator.pre.as_str());
+    }
+    normalized
+}
+
+/// The lowest version a requirement accepts, ignoring upper bounds
+pub fn lowest_version(req: &VersionReq) -> Version {
+    req.comparators
+        .iter()
+        .filter(|cmp| !matches!(cmp.op, Op::Less | Op::LessEq))
+        .map(|cmp| match cmp.op {
+            Op::Greater => after(cmp),
+            _ => filled(cmp),
+        })
+        .max()
+        .unwrap_or(Version::new(0, 0, 0))
+}
+
+/// The range of versions cargo considers compatible with the lowest version
+/// a requirement accepts: `1` for `1.2`, `0.3` for `0.3.1` and `0.0.4` for
+/// `0.0.4`. Requirements with different ranges can't share a version
+pub fn compatibility(req: &VersionReq) -> String {
+    let lowest = lowest_version(req);
+    match (lowest.major, lowest.minor) {
+        (0, 0) => format!("0.0.{}", lowest.patch),
+        (0, minor) => format!("0.{}", minor),
+        (major, _) => major.to_string(),
+    }
+}
+
+/// Whether a requirement is bounded tighter than a plain version, like
+/// `=1.2.3`, `~1.2` or `>=1.2, <1.5`, which is usually deliberate
+pub fn is_pinned(req: &VersionReq) -> bool {
+    let range = Range::of(req);
+    let caret = VersionReq::parse(&range.lower.to_string())
+        .map(|caret| Range::of(&caret))
+        .ok();
+    match (&range.upper, caret.and_then(|caret| caret.upper)) {
+        (Some(upper), Some(caret_upper)) => *upper < caret_upper,
+        _ => false,
+    }
+}
+
+/// Whether replacing the pinned requirement `pinned` with `other` accepts
+/// versions the pin rules out
+pub fn loosens(pinned: &VersionReq, other: &VersionReq) -> bool {
+    is_pinned(pinned) && !Range::of(pinned).contains(&Range::of(other))
+}
+
+/// The version a comparator names, with the missing parts filled with zeros
+fn filled(cmp: &Comparator) -> Version {
+    let mut version = Version::new(cmp.major, cmp.minor.unwrap_or(0), cmp.patch.unwrap_or(0));
+    version.pre = cmp.pre.clone();
+    version
+}
+
+/// The version after the last one matching the parts a comparator gives,
+/// e.g. `1.3.0` for `1.2`
+fn after(cmp: &Comparator) -> Version {
+    match (cmp.minor, cmp.patch) {
+        (None, _) => Version::new(cmp.major + 1, 0, 0),
+        (Some(minor), None) => Version::new(cmp.major, minor + 1, 0),
+        (Some(minor), Some(patch)) => Version::new(cmp.major, minor, patch + 1),
+    }
+}
+
+/// The versions a requirement accepts, from `lower` up to but excluding
+/// `upper`. Pre-release subtleties are ignored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    pub lower: Version,
+    /// `None` if there is no upper bound
+    pub upper: Option<Version>,
+}
+
+impl Range {
+    pub fn of(req: &VersionReq) -> Self {
+        let mut range = Range {
+            lower: Version::new(0, 0, 0),
+            upper: None,
+        };
+        for comparator in &req.comparators {
+            range = range.intersect(&Self::of_comparator(comparator));
+        }
+        range
+    }
+
+    fn of_comparator(cmp: &Comparator) -> Self {
+        let unbounded = Version::new(0, 0, 0);
+        let (lower, upper) = match cmp.op {
+            Op::Exact | Op::Wildcard => (filled(cmp), Some(after(cmp))),
+            Op::Greater => (after(cmp), None),
+            Op::GreaterEq => (filled(cmp), None),
+            Op::Less => (unbounded, Some(filled(cmp))),
+            Op::LessEq => (unbounded, Some(after(cmp))),
+            Op::Tilde => {
+                let upper = match cmp.minor {
+                    None => Version::new(cmp.major + 1, 0, 0),
+                    Some(minor) => Version::new(cmp.major, minor + 1, 0),
+                };
+                (filled(cmp), Some(upper))
+            }
+            Op::Caret => {
+                let upper = match (cmp.major, cmp.minor, cmp.patch) {
+                    (0, None, _) => Version::new(1, 0, 0),
+                    (0, Some(0), None) => Version::new(0, 1, 0),
+                    (0, Some(0), Some(patch)) => Version::new(0, 0, patch + 1),
+                    (0, Some(minor), _) => Version::new(0, minor + 1, 0),
+                    (major, _, _) => Version::new(major + 1, 0, 0),
+                };
+                (filled(cmp), Some(upper))
+            }
+            _ => (unbounded, None),
+        };
+        Range { lower, upper }
+    }
+
+    /// The versions both ranges accept, which may be none
+    pub fn intersect(&self, other: &Range) -> Range {
+        let upper = match (&self.upper, &other.upper) {
+            (Some(a), Some(b)) => Some(a.min(b).clone()),
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        Range {
+            lower: self.lower.clone().max(other.lower.clone()),
+            upper,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.upper
+            .as_ref()
+            .is_some_and(|upper| *upper <= self.lower)
+    }
+
+    /// Whether every version of `other` is in this range
+    pub fn contains(&self, other: &Range) -> bool {
+        other.lower >= self.lower
+            && match (&self.upper, &other.upper) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(upper), Some(other)) => other <= upper,
+            }
+    }
+}
+
+/// The shortest requirement for the range, a plain version where the range
+/// is what a caret requirement accepts
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let caret = VersionReq::parse(&self.lower.to_string())
+            .map(|req| Range::of(&req))
+            .ok();
+        match &self.upper {
+            _ if caret.as_ref() == Some(self) => write!(f, "{}", self.lower),
+            None => write!(f, ">={}", self.lower),
+            Some(upper) => write!(f, ">={}, <{}", self.lower, upper),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_requirement() {
+        assert_eq!(normalize_requirement("^1.2"), "1.2.0");
+        assert_eq!(normalize_requirement("1"), "1.0.0");
+        assert_eq!(normalize_requirement("1.2.3"), "1.2.3");
+        assert_eq!(normalize_requirement("0.3"), "0.3.0");
+        assert_eq!(normalize_requirement("^1.0.0-rc.2"), "1.0.0-rc.2");
+        assert_eq!(normalize_requirement("^0"), "0");
+        assert_eq!(normalize_requirement("0.0"), "0.0");
+        assert_eq!(normalize_requirement("~1.2"), "~1.2");
+        assert_eq!(normalize_requirement(">=1.2, <1.5"), ">=1.2, <1.5");
+        assert_eq!(normalize_requirement("*"), "*");
+    }
+
+    #[test]
+    fn test_lowest_version() {
+        let lowest = |req: &str| lowest_version(&VersionReq::parse(req).unwrap()).to_string();
+        assert_eq!(lowest("1.2"), "1.2.0");
+        assert_eq!(lowest(">=1.0.150, <2"), "1.0.150");
+        assert_eq!(lowest("*"), "0.0.0");
+        assert_eq!(lowest("<2"), "0.0.0");
+        assert_eq!(lowest(">1.2.3"), "1.2.4");
+        assert_eq!(lowest(">1.2"), "1.3.0");
+        assert_eq!(lowest(">1"), "2.0.0");
+        assert_eq!(lowest(">0.3, <0.9"), "0.4.0");
+    }
+
+    #[test]
+    fn test_range() {
+        let range = |req: &str| Range::of(&VersionReq::parse(req).unwrap());
+        assert_eq!(range("1.2").to_string(), "1.2.0");
+        assert_eq!(range("~1.2").to_string(), ">=1.2.0, <1.3.0");
+        assert_eq!(range("0.3").to_string(), "0.3.0");
+        assert_eq!(range("=1.2.3").to_string(), ">=1.2.3, <1.2.4");
+        assert_eq!(range(">=1.0.150").to_string(), ">=1.0.150");
+
+        let shared = range(">=1.2, <1.5").intersect(&range("1.3"));
+        assert_eq!(shared.to_string(), ">=1.3.0, <1.5.0");
+        assert!(!shared.is_empty());
+        assert!(shared.contains(&range("~1.4")));
+        assert!(!shared.contains(&range("1.3")));
+        assert!(range("1.3").intersect(&range("<1.2")).is_empty());
+    }
+
+    #[test]
+    fn test_loosens() {
+        let req = |req: &str| VersionReq::parse(req).unwrap();
+        assert!(is_pinned(&req("=1.2.3")));
+        assert!(is_pinned(&req("~1.2")));
+        assert!(is_pinned(&req(">=1.2, <1.5")));
+        assert!(!is_pinned(&req("1.2")));
+        assert!(!is_pinned(&req(">=1.2, <2")));
+        assert!(!is_pinned(&req("0.3.1")));
+
+        assert!(loosens(&req("=1.2.3"), &req("1")));
+        assert!(loosens(&req("~1.2"), &req("1.2")));
+        assert!(!loosens(&req("~1.2"), &req("=1.2.5")));
+        assert!(!loosens(&req("1.2"), &req("1")));
+    }
+
+    #[test]
+    fn test_compatibility() {
+        let compatibility = |req: &str| compatibility(&VersionReq::parse(req).unwrap());
+        assert_eq!(compatibility("1.2"), "1");
+        assert_eq!(compatibility("2.0.50"), "2");
+        assert_eq!(compatibility("0.3.1"), "0.3");
+        assert_eq!(compatibility("0.0.4"), "0.0.4");
+        assert_eq!(compatibility(">=1.0.150, <3"), "1");
+    }
+}
@@ -1,60 +1,548 @@
 use anyhow::{Context, Result};
-use camino::Utf8PathBuf;
-use cargo_metadata::MetadataCommand;
-use log::info;
-use std::collections::{HashMap, HashSet};
-use std::fs;
-use std::path::PathBuf;
-use toml_edit::{DocumentMut, InlineTable, Item, Table, Value};
+use cargo_metadata::{Metadata, MetadataCommand, Package, PackageId};
+use log::{info, warn};
+use rayon::prelude::*;
+use semver::VersionReq;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Table};
 
-use crate::dependency;
+use crate::cache;
+use crate::catalogue::Catalogue;
+use crate::cli::{
+    CargoOptions, MismatchPolicy, Opt, PrereleasePolicy, VersionStrategy, ViolationPolicy,
+    WildcardPolicy,
+};
+use crate::config::Config;
+use crate::dependency::{self, Class, Kinds};
+use crate::diagnostic;
+use crate::discover;
+use crate::events::{Event, Events};
+use crate::explain::Explain;
+use crate::git;
+use crate::groups;
+use crate::lock::{self, Lock};
+use crate::lockfile::Lockfile;
+use crate::manifest::{resolve_write_path, Manifest, Manifests};
+use crate::metadata;
+use crate::policy::{self, Policy};
+use crate::progress::{self, Progress};
+use crate::sources;
+use crate::state::{self, State};
+use crate::summary::{Provenance, Summary};
+use crate::unused::Unused;
+use crate::version::{self, Range};
 
-pub fn consolidate_dependencies(manifest_path: Option<PathBuf>, group_all: bool) -> Result<()> {
+/// Marks the comments maintained by `--used-by-comments`
+const USED_BY_MARKER: &str = "# used by:";
+
+/// Failures collected while running with `--keep-going`
+#[derive(Default)]
+struct Failures {
+    entries: Vec<(String, anyhow::Error)>,
+}
+
+impl Failures {
+    /// Records a failed step if `keep_going` is set, otherwise hands the error back
+    fn record(&mut self, keep_going: bool, what: String, result: Result<()>) -> Result<bool> {
+        match result {
+            Ok(()) => Ok(true),
+            Err(err) if keep_going => {
+                warn!("{}: {:#}", what, err);
+                self.entries.push((what, err));
+                Ok(false)
+            }
+            Err(err) => Err(err.context(what)),
+        }
+    }
+
+    fn into_result(self) -> Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut summary = format!("{} item(s) could not be processed:", self.entries.len());
+        for (what, err) in &self.entries {
+            summary.push_str(&format!("\n  - {}: {:#}", what, err));
+        }
+        Err(anyhow::anyhow!(summary))
+    }
+}
+
+/// The dependencies and members a run consolidates, all of them unless
+/// deselected in `--review` or narrowed down to one by `--serve`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// The only dependency to consolidate, if not all of them
+    only: Option<String>,
+    dependencies: HashSet<String>,
+    members: HashSet<(String, String)>,
+}
+
+impl Selection {
+    /// Selects just `dep`, leaving every other dependency alone
+    pub fn only(dep: &str) -> Self {
+        Self {
+            only: Some(dep.to_string()),
+            ..Self::default()
+        }
+    }
+
+    pub fn deselect(&mut self, dep: &str) {
+        self.dependencies.insert(dep.to_string());
+    }
+
+    /// Leaves `member`'s spec of `dep` alone while the others inherit it
+    pub fn deselect_member(&mut self, dep: &str, member: &str) {
+        self.members.insert((dep.to_string(), member.to_string()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.only.is_none() && self.dependencies.is_empty() && self.members.is_empty()
+    }
+
+    fn includes(&self, dep: &str) -> bool {
+        self.only.as_ref().is_none_or(|only| only == dep) && !self.dependencies.contains(dep)
+    }
+
+    fn includes_member(&self, dep: &str, member: &str) -> bool {
+        !self
+            .members
+            .contains(&(dep.to_string(), member.to_string()))
+    }
+}
+
+/// Runs `cargo metadata` for the project containing `manifest_path`, or the
+/// current directory. With `--no-cargo` the manifests are read instead, with
+/// `--cache` the result of an earlier run is reused while it is up to date
+pub fn load_metadata(manifest_path: Option<&Path>, cargo: &CargoOptions) -> Result<Metadata> {
+    if cargo.no_cargo {
+        return metadata::from_manifests(manifest_path);
+    }
+    if cargo.cache {
+        return cache::metadata(manifest_path, cargo, || {
+            run_cargo_metadata(manifest_path, cargo)
+        });
+    }
+    run_cargo_metadata(manifest_path, cargo)
+}
+
+fn run_cargo_metadata(manifest_path: Option<&Path>, cargo: &CargoOptions) -> Result<Metadata> {
+    metadata_command(manifest_path, cargo).exec().map_err(|err| {
+        // cargo metadata reports paths as UTF-8 only, so point at the culprit
+        let project_path = match manifest_path {
+            Some(path) => path.to_path_buf(),
+            None => std::env::current_dir().unwrap_or_default(),
+        };
+        match non_utf8_component(&project_path) {
+            Some(component) => anyhow::Error::new(err).context(format!(
+                "Failed to execute `cargo metadata` command: path component '{}' of '{}' is not valid UTF-8",
+                component,
+                project_path.display()
+            )),
+            None => anyhow::Error::new(err).context("Failed to execute `cargo metadata` command"),
+        }
+    })
+}
+
+/// The `cargo metadata` command to run. `--cargo` takes precedence over
+/// `$CARGO`, which is used by cargo_metadata otherwise
+fn metadata_command(manifest_path: Option<&Path>, cargo: &CargoOptions) -> MetadataCommand {
     let mut cmd = MetadataCommand::new();
-    if let Some(path) = &manifest_path {
+    if let Some(path) = &cargo.cargo {
+        cmd.cargo_path(path);
+    }
+    if let Some(path) = manifest_path {
         cmd.manifest_path(path);
     }
+    // Only the members and their declared dependencies are needed, resolving
+    // the whole graph is slow and may hit the registry
+    if !cargo.full_metadata {
+        cmd.no_deps();
+    }
+    cmd.other_options(cargo.args());
+    cmd
+}
+
+/// Returns the workspace members in the order of `workspace_members`,
+/// looking each of them up by id
+pub fn workspace_packages(metadata: &Metadata) -> Result<Vec<&Package>> {
+    let packages: HashMap<&PackageId, &Package> = metadata
+        .packages
+        .iter()
+        .map(|package| (&package.id, package))
+        .collect();
+    metadata
+        .workspace_members
+        .iter()
+        .map(|package_id| {
+            packages
+                .get(package_id)
+                .copied()
+                .with_context(|| format!("Failed to find package '{}' in metadata", package_id))
+        })
+        .collect()
+}
+
+/// A workspace with fewer than two members, which have nothing to share
+#[derive(Debug)]
+pub struct TooFewMembers {
+    root: PathBuf,
+    member: Option<String>,
+}
+
+impl std::error::Error for TooFewMembers {}
+
+impl std::fmt::Display for TooFewMembers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.member {
+            Some(member) => write!(
+                f,
+                "'{}' is the only member of the workspace at '{}', there's nothing to share between members. Pass --group-all to move its dependencies into [workspace.dependencies] anyway, e.g. before splitting it up",
+                member,
+                self.root.display()
+            ),
+            None => write!(
+                f,
+                "The workspace at '{}' has no members, there's nothing to consolidate",
+                self.root.display()
+            ),
+        }
+    }
+}
 
-    let metadata = cmd
-        .exec()
-        .context("Failed to execute `cargo metadata` command")?;
+pub fn consolidate_dependencies(opt: &Opt) -> Result<()> {
+    consolidate_selected(opt, &Selection::default())
+}
 
-    // Convert PathBuf to Utf8PathBuf safely
-    let workspace_manifest_path = match manifest_path {
-        Some(path) => {
-            Utf8PathBuf::try_from(path).context("Failed to convert manifest path to UTF-8 path")?
+/// Like [`consolidate_dependencies`], for a workspace this run just set up,
+/// where a single member is no mistake. `lock` was taken before setting it
+/// up, so no other run gets in between
+pub fn consolidate_new_workspace(opt: &Opt, lock: Lock) -> Result<()> {
+    match with_phase(|| consolidate(opt, &Selection::default(), Some(lock))) {
+        Err(err) if err.is::<TooFewMembers>() => {
+            info!("{}", err.root_cause());
+            Ok(())
         }
-        None => metadata.workspace_root.join("Cargo.toml"),
+        result => result,
+    }
+}
+
+/// Consolidates everything but what `selection` leaves out
+pub fn consolidate_selected(opt: &Opt, selection: &Selection) -> Result<()> {
+    with_phase(|| consolidate(opt, selection, None))
+}
+
+/// Runs `consolidate`, naming the phase it failed in
+fn with_phase(consolidate: impl FnOnce() -> Result<()>) -> Result<()> {
+    progress::take_phase();
+    consolidate().map_err(|err| match progress::take_phase() {
+        Some(phase) => err.context(format!("Failed in the {} phase", phase.to_lowercase())),
+        None => err,
+    })
+}
+
+fn consolidate(opt: &Opt, selection: &Selection, lock: Option<Lock>) -> Result<()> {
+    // --manifest-path only locates the project, it can point at any member
+    let mut metadata = {
+        let _progress = Progress::start(opt, "Metadata", None);
+        let mut cargo = opt.cargo.clone();
+        // Only resolved packages come with their crates.io categories
+        cargo.full_metadata |= opt.group_by_category;
+        load_metadata(opt.manifest_path(), &cargo)?
+    };
+    let workspace_manifest_path = metadata
+        .workspace_root
+        .join("Cargo.toml")
+        .into_std_path_buf();
+    // A hook and an editor running at the same time would interleave their
+    // writes, the other one gives up instead
+    let _lock = match lock {
+        Some(lock) => lock,
+        None => lock::acquire(metadata.target_directory.as_std_path())?,
     };
 
     // Read and parse root Cargo.toml
-    let root_cargo_toml_content = fs::read_to_string(&workspace_manifest_path)
-        .with_context(|| format!("Failed to read '{}'", workspace_manifest_path))?;
-    let mut root_doc = root_cargo_toml_content
-        .parse::<DocumentMut>()
-        .context("Failed to parse root Cargo.toml")?;
+    let mut root_manifest =
+        Manifest::open(&workspace_manifest_path).context("Failed to parse root Cargo.toml")?;
+
+    let workspace_deps = get_workspace_dependencies(&root_manifest.doc);
+    let excludes =
+        get_workspace_excludes(&root_manifest.doc, metadata.workspace_root.as_std_path());
+
+    // Crates outside of `members` are invisible to cargo metadata
+    let member_manifests: HashSet<&Path> = workspace_packages(&metadata)?
+        .iter()
+        .map(|package| package.manifest_path.as_std_path())
+        .collect();
+    let orphans = find_orphans(
+        metadata.workspace_root.as_std_path(),
+        &member_manifests,
+        &excludes,
+    )?;
+    if !orphans.is_empty() {
+        let list = orphans.join(", ");
+        if opt.add_orphans {
+            info!("Adding {} to workspace.members", list);
+            add_workspace_members(&mut root_manifest.doc, &orphans)
+                .map_err(|err| root_manifest.locate(err))?;
+            // cargo only sees the new members on disk. The manifest is put
+            // back right away, the write phase writes it with everything else
+            root_manifest.write(opt.symlinks)?;
+            let reloaded = load_metadata(Some(&workspace_manifest_path), &opt.cargo);
+            root_manifest.write_rendered(root_manifest.source(), opt.symlinks)?;
+            metadata = reloaded?;
+        } else {
+            warn!(
+                "Crates below the workspace root aren't members and are left out: {}. Pass --add-orphans to add them to workspace.members",
+                list
+            );
+        }
+    }
+
+    // --incremental skips workspaces the last run left as they are
+    let state_path = state::path(metadata.target_directory.as_std_path());
+    let fingerprint = state::fingerprint(opt, metadata.workspace_root.as_std_path());
+    let state = if opt.incremental {
+        State::load(&state_path)
+    } else {
+        State::default()
+    };
+    let packages = workspace_packages(&metadata)?;
+    // A lone member can still inherit existing entries, and the options for
+    // the root manifest apply either way
+    let root_options = opt.set_resolver.is_some()
+        || opt.normalize_existing
+        || opt.clean_inherited
+        || opt.group_by_category;
+    if packages.len() < 2 && workspace_deps.is_empty() && !root_options {
+        let member = packages.first().map(|package| package.name.clone());
+        match (&member, opt.group_all) {
+            (Some(member), true) => info!(
+                "'{}' is the only member, setting up [workspace.dependencies] for its dependencies",
+                member
+            ),
+            _ => {
+                // Not a failure of the metadata phase, the workspace is fine
+                progress::take_phase();
+                return Err(TooFewMembers {
+                    root: metadata.workspace_root.clone().into_std_path_buf(),
+                    member,
+                }
+                .into());
+            }
+        }
+    }
+    let member_manifest_paths: Vec<PathBuf> = packages
+        .iter()
+        .map(|package| package.manifest_path.clone().into_std_path_buf())
+        .collect();
+    if opt.incremental
+        && state.is_up_to_date(
+            fingerprint,
+            &workspace_manifest_path,
+            &member_manifest_paths,
+        )
+    {
+        info!("Nothing changed since the last run");
+        return Ok(());
+    }
+
+    progress::enter("Configuration");
+    let config = Config::load(
+        opt.config.as_deref(),
+        metadata.workspace_root.as_std_path(),
+        &root_manifest.doc,
+    )?
+    .with_preset(opt.preset.as_deref())?;
+    consolidate_workspace(
+        opt,
+        selection,
+        &metadata,
+        config,
+        &state,
+        root_manifest,
+        &mut Manifests::default(),
+    )?;
+
+    // Deselected dependencies would be consolidated by the next run, so it
+    // can't be skipped
+    if opt.incremental && selection.is_empty() {
+        // A state which can't be written only costs time
+        let saved = State::record(
+            fingerprint,
+            &workspace_manifest_path,
+            &member_manifest_paths,
+        )
+        .and_then(|state| state.save(&state_path));
+        if let Err(err) = saved {
+            warn!("Failed to save the state for --incremental: {:#}", err);
+        }
+    }
+
+    info!("Successfully updated workspace dependencies.");
+    Ok(())
+}
 
-    // Collect existing workspace dependencies
-    let mut workspace_deps = get_workspace_dependencies(&root_doc);
+/// Consolidates the workspace `metadata` describes, with the settings of
+/// `config`. The members are read through `manifests`, which is where the
+/// edited manifests are written to as well, `root_manifest` included
+#[allow(clippy::too_many_arguments)]
+pub fn consolidate_workspace(
+    opt: &Opt,
+    selection: &Selection,
+    metadata: &Metadata,
+    config: Config,
+    state: &State,
+    mut root_manifest: Manifest,
+    manifests: &mut Manifests,
+) -> Result<()> {
+    let opt = &config.apply_defaults(opt);
+    let workspace_manifest_path = root_manifest.path().to_path_buf();
+    let mut workspace_deps = get_workspace_dependencies(&root_manifest.doc);
+    let excludes =
+        get_workspace_excludes(&root_manifest.doc, metadata.workspace_root.as_std_path());
+    let packages = workspace_packages(metadata)?;
+    let policy = match &opt.policy {
+        Some(path) => Policy::load(path)?,
+        None => Policy::default(),
+    };
     let mut dep_usage: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut dep_aliases: HashMap<String, BTreeSet<String>> = HashMap::new();
+    let mut dep_kinds: HashMap<String, Kinds> = HashMap::new();
+    // Features of their dependencies members enable in their own [features]
+    let mut feature_references: HashMap<PathBuf, HashMap<String, BTreeSet<String>>> =
+        HashMap::new();
     let mut package_manifest_paths = HashMap::new();
+    let mut failures = Failures::default();
+    let mut explain = Explain::default();
+    let mut summary = Summary::default();
+    let events = Events::new(opt);
+    let catalogue = match &opt.catalogue {
+        Some(path) => Catalogue::load(path)?,
+        None => Catalogue::default(),
+    };
+    // Manifests held in memory have no cargo configuration next to them
+    let replacements = match manifests.is_in_memory() {
+        true => Vec::new(),
+        false => sources::detect(metadata.workspace_root.as_std_path())?,
+    };
+    for replacement in &replacements {
+        info!("Source replacement: {}", replacement);
+    }
+    let lockfile = match opt.wildcard {
+        WildcardPolicy::Lockfile => {
+            // Vendored crates stand in for a missing Cargo.lock
+            let lockfile_path = metadata.workspace_root.join("Cargo.lock");
+            let vendor_dirs: Vec<&Path> = replacements
+                .iter()
+                .filter_map(|replacement| replacement.vendor_dir())
+                .filter(|dir| dir.is_dir())
+                .collect();
+            let mut lockfile = if lockfile_path.exists() || vendor_dirs.is_empty() {
+                Lockfile::load(lockfile_path.as_std_path())?
+            } else {
+                Lockfile::default()
+            };
+            for dir in vendor_dirs {
+                lockfile.add_vendored(dir)?;
+            }
+            Some(lockfile)
+        }
+        _ => None,
+    };
 
-    // Analyze dependencies across workspace members
-    for package_id in &metadata.workspace_members {
-        let package = metadata
-            .packages
-            .iter()
-            .find(|p| &p.id == package_id)
-            .context("Failed to find package in metadata")?;
+    let unused = match (&opt.unused_from, opt.skip_unused) {
+        (Some(path), _) => Some(Unused::from_udeps(path)?),
+        (None, true) => Some(Unused::scan(&packages)),
+        (None, false) => None,
+    };
 
+    // Resolved, so symlinked members are compared by where they really are
+    let workspace_root = fs::canonicalize(&metadata.workspace_root)
+        .unwrap_or_else(|_| metadata.workspace_root.clone().into_std_path_buf());
+
+    // Analyze dependencies across workspace members
+    let mut progress = Progress::start(opt, "Analyzing", Some(packages.len()));
+    for package in &packages {
+        progress.inc(&package.name);
         let package_name = &package.name;
-        let manifest_path = &package.manifest_path;
-        package_manifest_paths.insert(package_name.clone(), manifest_path.clone());
+        let manifest_path = package.manifest_path.as_std_path();
+
+        // cargo already leaves excluded packages out, but never touch them
+        // should one slip through (e.g. as a path dependency of a member)
+        if is_excluded(manifest_path, &excludes) {
+            info!(
+                "Skipping '{}', it is listed in workspace.exclude",
+                package_name
+            );
+            explain.skip_member(package_name, "listed in workspace.exclude");
+            continue;
+        }
+        if !opt.allow_external && is_external(manifest_path, &workspace_root) {
+            warn!(
+                "Skipping '{}', its manifest '{}' is outside the workspace root '{}'. Pass --allow-external to edit it anyway",
+                package_name,
+                manifest_path.display(),
+                workspace_root.display()
+            );
+            explain.skip_member(package_name, "outside the workspace root");
+            continue;
+        }
+
+        let relative_dir = manifest_path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(&metadata.workspace_root).ok())
+            .unwrap_or(Path::new(""));
+        if opt.exclude_member.contains(package_name) {
+            info!("Ignoring member '{}'", package_name);
+            explain.skip_member(package_name, "excluded with --exclude-member");
+            continue;
+        }
+        if config.is_ignored(package_name, relative_dir) {
+            info!("Ignoring member '{}'", package_name);
+            explain.skip_member(
+                package_name,
+                "matches an ignore pattern of the configuration",
+            );
+            continue;
+        }
+
+        package_manifest_paths.insert(package_name.clone(), manifest_path.to_path_buf());
+        feature_references.insert(
+            manifest_path.to_path_buf(),
+            dependency::collect_feature_references(&package.features),
+        );
 
         // Collect dependencies from the package
         let deps = dependency::collect_dependencies(package);
+        let kinds = dependency::collect_kinds(package);
 
-        for dep in deps {
+        for (dep, dep_package) in deps {
+            if unused
+                .as_ref()
+                .is_some_and(|unused| unused.is_unused(manifest_path, &dep))
+            {
+                warn!(
+                    "'{}' doesn't use '{}', remove it instead of consolidating it",
+                    package_name, dep
+                );
+                explain.note(&dep, format!("'{}' doesn't use it", package_name));
+                events.emit(Event::Unused {
+                    dependency: &dep,
+                    manifest: manifest_path,
+                });
+                continue;
+            }
+            dep_aliases
+                .entry(dep_package)
+                .or_default()
+                .insert(dep.clone());
+            if let Some(kinds) = kinds.get(&dep) {
+                dep_kinds.entry(dep.clone()).or_default().extend(*kinds);
+            }
             dep_usage
                 .entry(dep)
                 .or_default()
@@ -62,188 +550,3855 @@ pub fn consolidate_dependencies(manifest_path: Option<PathBuf>, group_all: bool)
         }
     }
 
-    // Process and consolidate dependencies
-    for (dep, users) in dep_usage.iter() {
-        let should_group = if group_all { true } else { users.len() >= 2 };
+    drop(progress);
+    let dep_classes: HashMap<String, Class> = dep_kinds
+        .into_iter()
+        .map(|(dep, kinds)| (dep, kinds.class()))
+        .collect();
 
-        if should_group {
-            // Add to workspace dependencies if not already present
-            if !workspace_deps.contains_key(dep) {
-                info!(
-                    "Adding dependency '{}' to workspace.dependencies (used in {:?})",
-                    dep, users
-                );
-                add_dependency_to_workspace(&mut root_doc, dep, users, &package_manifest_paths)
-                    .with_context(|| {
-                        format!("Failed to add '{}' to workspace dependencies", dep)
-                    })?;
-                workspace_deps.insert(dep.clone(), Item::None);
+    // With --since, members which didn't change still count as users, but
+    // are neither analyzed nor rewritten
+    let changed_manifests = match &opt.since {
+        Some(rev) => Some(git::changed_manifests(
+            metadata.workspace_root.as_std_path(),
+            rev,
+        )?),
+        None => None,
+    };
+    let is_changed = |manifest_path: &Path| {
+        changed_manifests
+            .as_ref()
+            .is_none_or(|changed| changed.contains(manifest_path))
+    };
+
+    let progress = Progress::start(opt, "Preflight", None);
+    let problems = preflight(
+        &root_manifest,
+        &package_manifest_paths,
+        manifests,
+        &dep_usage,
+        &dep_classes,
+        &workspace_deps,
+        &config,
+        opt,
+        lockfile.as_ref(),
+    );
+    drop(progress);
+    if !problems.is_empty() {
+        // With --keep-going the run goes on with the full plan. The steps
+        // the problems affect fail again and are recorded then
+        let outcome = if opt.keep_going {
+            "continuing anyway, the steps they affect will fail and be reported again"
+        } else {
+            "nothing was changed"
+        };
+        let mut summary = format!(
+            "Preflight found {} problem(s), {}:",
+            problems.len(),
+            outcome
+        );
+        for problem in &problems {
+            // Located errors span several lines
+            summary.push_str(&format!("\n  - {}", problem.replace('\n', "\n    ")));
+        }
+        if !opt.keep_going {
+            return Err(anyhow::anyhow!(summary));
+        }
+        warn!("{}", summary);
+    }
+
+    let mut violations = policy.check_workspace(&root_manifest.doc);
+    let mut members: Vec<_> = package_manifest_paths.iter().collect();
+    members.sort();
+    // Unreadable manifests were already reported by the preflight
+    let member_violations: Vec<_> = members
+        .par_iter()
+        .filter_map(|(member, manifest_path)| {
+            let manifest = manifests.loaded(manifest_path)?;
+            Some(policy.check_member(member, &manifest.doc))
+        })
+        .collect();
+    violations.extend(member_violations.into_iter().flatten());
+    if !violations.is_empty() {
+        match opt.on_policy_violation {
+            ViolationPolicy::Error if !opt.keep_going => {
+                return Err(anyhow::anyhow!(policy::summarize(
+                    "Version policy violated, nothing was changed",
+                    &violations
+                )));
             }
+            _ => warn!(
+                "{}",
+                policy::summarize("Version policy violated", &violations)
+            ),
+        }
+    }
+
+    if opt.clean_inherited {
+        let mut manifest_paths: Vec<_> = package_manifest_paths
+            .values()
+            .filter(|manifest_path| is_changed(manifest_path))
+            .collect();
+        manifest_paths.sort();
+        for manifest_path in manifest_paths {
+            failures.record(
+                opt.keep_going,
+                format!("Failed to clean up '{}'", manifest_path.display()),
+                clean_inherited_dependencies(manifests, manifest_path),
+            )?;
+        }
+    }
+
+    // Members can import the same package under different names
+    for (dep_package, aliases) in dep_aliases.iter().filter(|(_, aliases)| aliases.len() > 1) {
+        if !opt.unify_alias {
+            warn!(
+                "'{}' is used under different names ({}), each of them gets its own workspace entry. Pass --unify-alias to rename them to '{}'",
+                dep_package,
+                aliases.iter().cloned().collect::<Vec<_>>().join(", "),
+                dep_package
+            );
+            continue;
+        }
+        // Renaming only pays off if the package ends up in one workspace entry
+        let users: HashSet<&String> = aliases
+            .iter()
+            .filter_map(|alias| dep_usage.get(alias))
+            .flatten()
+            .collect();
+        let class = class_of(&dep_classes, dep_package);
+        let grouped = workspace_deps.contains_key(dep_package)
+            || should_group(dep_package, users.len(), class, opt.group_all, &config).is_some();
+        if !grouped || !selection.includes(dep_package) {
+            explain.note(
+                dep_package,
+                format!(
+                    "not unified, as it isn't consolidated ({})",
+                    aliases.iter().cloned().collect::<Vec<_>>().join(", ")
+                ),
+            );
+            continue;
+        }
 
-            // Update member Cargo.toml files to use workspace = true
+        for alias in aliases.iter().filter(|alias| *alias != dep_package) {
+            let users = dep_usage.remove(alias).unwrap_or_default();
             for user in users {
-                let manifest_path = package_manifest_paths.get(user).unwrap();
-                update_member_to_use_workspace(manifest_path, dep).with_context(|| {
-                    format!("Failed to update '{}' in '{}'", dep, manifest_path)
-                })?;
+                let manifest_path = package_manifest_paths.get(&user).unwrap();
+                let renamed = failures.record(
+                    opt.keep_going,
+                    format!(
+                        "Failed to rename '{}' to '{}' in '{}'",
+                        alias,
+                        dep_package,
+                        manifest_path.display()
+                    ),
+                    manifests.get_mut(manifest_path).map(|manifest| {
+                        dependency::rename_dependency(&mut manifest.doc, alias, dep_package);
+                    }),
+                )?;
+                if renamed {
+                    warn!(
+                        "Renamed '{}' to '{}' in '{}', code referring to '{}' needs to be updated",
+                        alias,
+                        dep_package,
+                        manifest_path.display(),
+                        alias
+                    );
+                    rename_feature_references(
+                        &mut feature_references,
+                        manifest_path,
+                        alias,
+                        dep_package,
+                    );
+                    dep_usage
+                        .entry(dep_package.clone())
+                        .or_default()
+                        .insert(user);
+                }
             }
         }
     }
 
-    // Write back the modified root Cargo.toml
-    fs::write(&workspace_manifest_path, root_doc.to_string())
-        .with_context(|| format!("Failed to write '{}'", workspace_manifest_path))?;
+    // Members can need semver-incompatible versions of the same dependency,
+    // which a single workspace entry can't serve
+    let mut incompatible: HashMap<String, String> = HashMap::new();
+    let mut deps: Vec<String> = dep_usage.keys().cloned().collect();
+    deps.sort();
+    for dep in deps {
+        // Left as the members declare it, whatever versions they need
+        if config.rule(&dep).is_some_and(|rule| rule.skip) {
+            continue;
+        }
+        let ranges =
+            compatibility_ranges(&dep, &dep_usage[&dep], &package_manifest_paths, manifests)?;
+        if ranges.len() < 2 {
+            continue;
+        }
+        let summary = ranges
+            .iter()
+            .map(|(range, users)| format!("{} ({})", range, users.join(", ")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if !opt.allow_major_split {
+            warn!(
+                "Members need semver-incompatible versions of '{}': {}. It is left alone, pass --allow-major-split to give each version its own workspace entry",
+                dep, summary
+            );
+            incompatible.insert(
+                dep,
+                format!("members need semver-incompatible versions: {}", summary),
+            );
+            continue;
+        }
+
+        // An alias already naming another dependency would merge the two
+        let aliases: Vec<String> = ranges
+            .keys()
+            .map(|range| format!("{}_{}", dep, range.replace('.', "_")))
+            .collect();
+        let taken: Vec<&str> = aliases
+            .iter()
+            .filter(|alias| {
+                let aliased = dep_aliases
+                    .get(&dep)
+                    .is_some_and(|names| names.contains(*alias));
+                (dep_usage.contains_key(*alias) && !aliased)
+                    || workspace_deps.get(*alias).is_some_and(|entry| {
+                        entry.get("package").and_then(Item::as_str) != Some(dep.as_str())
+                    })
+            })
+            .map(String::as_str)
+            .collect();
+        if !taken.is_empty() {
+            warn!(
+                "Members need semver-incompatible versions of '{}': {}. It is left alone, as '{}' already names another dependency",
+                dep,
+                summary,
+                taken.join("', '")
+            );
+            incompatible.insert(
+                dep,
+                format!(
+                    "the aliases for its versions are taken: {}",
+                    taken.join(", ")
+                ),
+            );
+            continue;
+        }
 
-    info!("Successfully updated workspace dependencies.");
-    Ok(())
-}
+        for ((range, users), alias) in ranges.into_iter().zip(aliases) {
+            let class = class_of(&dep_classes, &dep);
+            if should_group(&alias, users.len(), class, opt.group_all, &config).is_none() {
+                explain.note(
+                    &dep,
+                    format!("version {} is only used by {}", range, users.join(", ")),
+                );
+                continue;
+            }
+            for user in users {
+                let manifest_path = &package_manifest_paths[&user];
+                let renamed = failures.record(
+                    opt.keep_going,
+                    format!(
+                        "Failed to rename '{}' to '{}' in '{}'",
+                        dep,
+                        alias,
+                        manifest_path.display()
+                    ),
+                    manifests.get_mut(manifest_path).map(|manifest| {
+                        dependency::alias_dependency(&mut manifest.doc, &dep, &alias);
+                    }),
+                )?;
+                if renamed {
+                    warn!(
+                        "Renamed '{}' to '{}' in '{}', code referring to '{}' needs to be updated",
+                        dep,
+                        alias,
+                        manifest_path.display(),
+                        dep
+                    );
+                    rename_feature_references(&mut feature_references, manifest_path, &dep, &alias);
+                    if let Some(users) = dep_usage.get_mut(&dep) {
+                        users.remove(&user);
+                    }
+                    dep_usage.entry(alias.clone()).or_default().insert(user);
+                }
+            }
+        }
+        if dep_usage.get(&dep).is_some_and(HashSet::is_empty) {
+            dep_usage.remove(&dep);
+        }
+    }
 
-fn get_workspace_dependencies(doc: &DocumentMut) -> HashMap<String, Item> {
-    doc.get("workspace")
-        .and_then(|ws| ws.as_table())
-        .and_then(|ws_table| ws_table.get("dependencies"))
-        .and_then(|deps| deps.as_table())
-        .map(|ws_deps| {
-            ws_deps
+    // Process and consolidate dependencies
+    let mut loosened = Vec::new();
+    let wrap_width = opt.wrap_width.unwrap_or(dependency::DEFAULT_WRAP_WIDTH);
+    let mut progress = Progress::start(opt, "Consolidating", Some(dep_usage.len()));
+    for (dep, users) in dep_usage.iter() {
+        progress.inc(dep);
+        if !selection.includes(dep) {
+            explain.skip(dep, "deselected in the review");
+            continue;
+        }
+        if let Some(reason) = incompatible.get(dep) {
+            explain.skip(dep, reason);
+            continue;
+        }
+        if let Some(rev) = &opt.since {
+            if !users
                 .iter()
-                .map(|(dep_name, item)| (dep_name.to_string(), item.clone()))
-                .collect()
-        })
-        .unwrap_or_default()
-}
-
-fn add_dependency_to_workspace(
-    doc: &mut DocumentMut,
-    dep_name: &str,
-    users: &HashSet<String>,
-    package_manifest_paths: &HashMap<String, Utf8PathBuf>,
-) -> Result<()> {
-    // Take the first user's dependency specification
-    let first_user = users.iter().next().unwrap();
-    let manifest_path = package_manifest_paths.get(first_user).unwrap();
-    let dep_item = dependency::get_dependency_from_member(manifest_path, dep_name)?;
+                .any(|user| is_changed(&package_manifest_paths[user]))
+            {
+                explain.skip(dep, format!("no member using it changed since '{}'", rev));
+                continue;
+            }
+        }
+        let mut user_names: Vec<_> = users.iter().map(String::as_str).collect();
+        user_names.sort_unstable();
+        let usage = format!(
+            "used by {} member(s) ({})",
+            users.len(),
+            user_names.join(", ")
+        );
 
-    // Ensure workspace table exists
-    let ws_deps = doc
-        .entry("workspace")
-        .or_insert_with(|| Item::Table(Table::new()))
-        .as_table_mut()
-        .unwrap()
-        .entry("dependencies")
-        .or_insert_with(|| Item::Table(Table::new()))
-        .as_table_mut()
-        .unwrap();
+        let class = class_of(&dep_classes, dep);
+        // Members inherit existing entries no matter how many use them
+        let existing =
+            workspace_deps.contains_key(dep) && !config.rule(dep).is_some_and(|rule| rule.skip);
+        let grouped = should_group(dep, users.len(), class, opt.group_all, &config)
+            .or(existing.then_some("already listed in [workspace.dependencies]"));
+        events.emit(Event::DependencyAnalyzed {
+            dependency: dep,
+            members: user_names.clone(),
+            consolidated: grouped.is_some(),
+        });
+        let Some(reason) = grouped else {
+            let skipped = config
+                .class_settings(class)
+                .is_some_and(|settings| settings.skip);
+            let reason = if config.rule(dep).is_some_and(|rule| rule.skip) {
+                format!("{}, skip is set in its rule", usage)
+            } else if let Some(table) = class.table().filter(|_| skipped) {
+                format!("{}, skip is set in [{}]", usage, table)
+            } else {
+                if let [member] = user_names.as_slice() {
+                    summary.near_miss(dep, member);
+                }
+                format!(
+                    "{}, below the threshold of {}",
+                    usage,
+                    config.min_members_of(class)
+                )
+            };
+            explain.skip(dep, reason);
+            continue;
+        };
+        let opt = &*dep_opt(opt, &config, dep, class);
+        let hoist = config.rule(dep).is_some_and(|rule| rule.hoist_features);
+        explain.group(dep, format!("{}, {}", usage, reason));
 
-    ws_deps.insert(dep_name, dep_item);
+        // The members this run may edit
+        let mut editable = Vec::new();
+        for user in &user_names {
+            if !selection.includes_member(dep, user) {
+                explain.note(dep, format!("'{}' was deselected in the review", user));
+            } else if !is_changed(&package_manifest_paths[*user]) {
+                let rev = opt.since.as_deref().unwrap_or_default();
+                explain.note(dep, format!("'{}' didn't change since '{}'", user, rev));
+            } else {
+                editable.push(*user);
+            }
+        }
 
-    Ok(())
-}
+        if opt.align_only {
+            let target = match (config.pins.get(dep), workspace_deps.get(dep)) {
+                (Some(pin), _) => Ok(pin.to_item()),
+                (None, Some(entry)) if !entry.is_none() => Ok(entry.clone()),
+                _ => pick_spec(
+                    dep,
+                    metadata.workspace_root.as_std_path(),
+                    users,
+                    &package_manifest_paths,
+                    manifests,
+                    opt,
+                    lockfile.as_ref(),
+                )
+                .map(|(spec, _)| spec),
+            };
+            let aligned = target.and_then(|target| {
+                let version = dependency::get_version(&target)
+                    .with_context(|| format!("The spec picked for '{}' has no version", dep))?;
+                align_members(
+                    manifests,
+                    &editable,
+                    &package_manifest_paths,
+                    dep,
+                    &version,
+                    &events,
+                    opt.embedded,
+                )
+            });
+            failures.record(
+                opt.keep_going,
+                format!("Failed to align the requirements of '{}'", dep),
+                aligned,
+            )?;
+            continue;
+        }
 
-fn update_member_to_use_workspace(manifest_path: &Utf8PathBuf, dep_name: &str) -> Result<()> {
-    let cargo_toml_content = fs::read_to_string(manifest_path)
-        .with_context(|| format!("Failed to read '{}'", manifest_path))?;
-    let mut doc = cargo_toml_content
-        .parse::<DocumentMut>()
-        .with_context(|| format!("Failed to parse '{}'", manifest_path))?;
+        let mut from_catalogue = false;
+        if let Some(pin) = config.pins.get(dep) {
+            // Pinned specs always win over existing entries and members
+            info!("Using pinned spec for '{}' from the configuration", dep);
+            explain.note(dep, "workspace entry taken from pins in the configuration");
+            let mut dep_item = pin.to_item();
+            if opt.normalize {
+                dependency::normalize_spec(&mut dep_item);
+            }
+            let declared = readable_specs(
+                dep,
+                metadata.workspace_root.as_std_path(),
+                users,
+                &package_manifest_paths,
+                manifests,
+            );
+            summary.provenance(
+                dep,
+                provenance(
+                    "the pins in the configuration".to_string(),
+                    &declared,
+                    &dep_item,
+                ),
+            );
+            insert_workspace_dependency(&mut root_manifest.doc, dep, dep_item, wrap_width);
+            workspace_deps.insert(dep.clone(), Item::None);
+        } else if let Some(spec) = catalogue
+            .get(dep)
+            .filter(|_| !workspace_deps.contains_key(dep))
+        {
+            info!("Using the catalogue's spec for '{}'", dep);
+            explain.note(dep, "workspace entry taken from the catalogue");
+            let mut dep_item = spec.clone();
+            if opt.normalize {
+                dependency::normalize_spec(&mut dep_item);
+            }
+            let declared = readable_specs(
+                dep,
+                metadata.workspace_root.as_std_path(),
+                users,
+                &package_manifest_paths,
+                manifests,
+            );
+            summary.provenance(
+                dep,
+                provenance("the catalogue".to_string(), &declared, &dep_item),
+            );
+            insert_workspace_dependency(&mut root_manifest.doc, dep, dep_item, wrap_width);
+            workspace_deps.insert(dep.clone(), Item::None);
+            from_catalogue = true;
+        } else if !workspace_deps.contains_key(dep) {
+            // Add to workspace dependencies if not already present
+            info!(
+                "Adding dependency '{}' to workspace.dependencies (used in {:?})",
+                dep, users
+            );
+            let result = add_dependency_to_workspace(
+                &mut root_manifest.doc,
+                dep,
+                metadata.workspace_root.as_std_path(),
+                users,
+                &package_manifest_paths,
+                manifests,
+                opt,
+                lockfile.as_ref(),
+            )
+            .and_then(|provenance| match hoist {
+                true => hoist_features(
+                    &mut root_manifest.doc,
+                    dep,
+                    users,
+                    &package_manifest_paths,
+                    manifests,
+                )
+                .map(|()| provenance),
+                false => Ok(provenance),
+            })
+            .map(|provenance| {
+                explain.note(dep, format!("workspace entry {}", provenance));
+                summary.provenance(dep, provenance);
+            });
+            if let Err(err) = &result {
+                explain.skip(dep, format!("{:#}", err));
+            }
+            let added = failures.record(
+                opt.keep_going,
+                format!("Failed to add '{}' to workspace dependencies", dep),
+                result,
+            )?;
+            if !added {
+                // Members can't inherit an entry that doesn't exist
+                continue;
+            }
+            workspace_deps.insert(dep.clone(), Item::None);
+        } else {
+            explain.note(dep, "already in [workspace.dependencies]");
+        }
 
-    let dep_tables = ["dependencies", "build-dependencies", "dev-dependencies"];
+        // Members must not be switched to a new entry outside of the policy
+        let is_new = workspace_deps.get(dep).is_some_and(Item::is_none);
+        let violation = root_manifest
+            .doc
+            .get("workspace")
+            .and_then(|workspace| workspace.get("dependencies"))
+            .and_then(|deps| deps.get(dep))
+            .and_then(|item| policy.check(policy::WORKSPACE, dep, item))
+            .filter(|_| is_new);
+        if let Some(violation) = violation {
+            if opt.on_policy_violation == ViolationPolicy::Error {
+                remove_workspace_dependency(&mut root_manifest.doc, dep);
+                workspace_deps.remove(dep);
+                explain.skip(dep, violation.to_string());
+                failures.record(
+                    opt.keep_going,
+                    format!("Failed to add '{}' to workspace dependencies", dep),
+                    Err(anyhow::anyhow!(violation)),
+                )?;
+                continue;
+            }
+            warn!("{}", violation);
+        }
 
-    for table_name in &dep_tables {
-        if let Some(dep_table) = doc.get_mut(table_name).and_then(Item::as_table_like_mut) {
-            if dep_table.contains_key(dep_name) {
-                let mut inline_table = InlineTable::default();
-                inline_table.insert("workspace", Value::from(true));
-
-                // Preserve existing features
-                if let Some(features) = dependency::merge_features(
-                    dep_table.get(dep_name),
-                    &Item::Value(inline_table.clone().into()),
-                ) {
-                    inline_table.insert("features", features);
-                }
+        // Entries we just created match their members, existing ones and
+        // those from the catalogue might not
+        let existed = workspace_deps.get(dep).is_some_and(|item| !item.is_none());
+        let reconcile = existed || from_catalogue;
+        let on_mismatch = opt.on_mismatch.unwrap_or_default();
+        if reconcile && on_mismatch == MismatchPolicy::Update {
+            // Decided once for all members, before the first one switches.
+            // Unreadable manifests are reported when switching them below
+            let mut specs = Vec::new();
+            for user in &editable {
+                let manifest_path = &package_manifest_paths[*user];
+                let Ok(member) = manifests.get(manifest_path) else {
+                    continue;
+                };
+                let sections = dependency::get_dependency_sections(&member.doc, manifest_path, dep);
+                specs.extend(
+                    sections
+                        .into_iter()
+                        .flatten()
+                        .map(|(_, item)| item)
+                        .filter(|item| !dependency::is_workspace_inherited(item)),
+                );
+            }
+            update_workspace_entry(&mut root_manifest.doc, dep, &specs);
+        }
 
-                dep_table.insert(dep_name, Item::Value(inline_table.into()));
+        // Update member Cargo.toml files to use workspace = true
+        for user in &editable {
+            let manifest_path = &package_manifest_paths[*user];
+            if opt.incremental && state.inherits(manifest_path, dep) {
+                explain.note(
+                    dep,
+                    format!(
+                        "'{}' inherits it unchanged since the last run",
+                        manifest_path.display()
+                    ),
+                );
+                continue;
             }
+            let result = manifests.get_mut(manifest_path).and_then(|member| {
+                let sections = if reconcile {
+                    reconcile_member(
+                        &mut root_manifest.doc,
+                        &member.doc,
+                        manifest_path,
+                        dep,
+                        feature_references
+                            .get(manifest_path)
+                            .and_then(|references| references.get(dep)),
+                        on_mismatch,
+                        &mut explain,
+                        &events,
+                    )?
+                } else {
+                    dependency::DEPENDENCY_SECTIONS.to_vec()
+                };
+                let sections = filter_prerelease_sections(
+                    &root_manifest.doc,
+                    &member.doc,
+                    manifest_path,
+                    dep,
+                    sections,
+                    opt.prerelease,
+                    &mut explain,
+                )?;
+                let sections = filter_loosened_sections(
+                    &root_manifest.doc,
+                    &member.doc,
+                    manifest_path,
+                    dep,
+                    sections,
+                    opt.allow_loosen,
+                    &mut loosened,
+                )?;
+                // What the member declared before, unless it inherited already
+                let previous = sections
+                    .iter()
+                    .filter_map(|section| member.doc.get(section).and_then(|deps| deps.get(dep)))
+                    .find(|item| !dependency::is_workspace_inherited(item))
+                    .map(dependency::get_version);
+                update_member_to_use_workspace(&mut member.doc, dep, &sections);
+                if hoist {
+                    drop_hoisted_features(&root_manifest.doc, &mut member.doc, dep, &sections);
+                }
+                Ok(previous)
+            });
+            let result = result.map(|previous| {
+                if let Some(previous) = previous {
+                    summary.moved(dep, is_new, user, previous);
+                }
+            });
+            failures.record(
+                opt.keep_going,
+                format!(
+                    "Failed to update '{}' in '{}'",
+                    dep,
+                    manifest_path.display()
+                ),
+                result,
+            )?;
         }
     }
 
-    // Write back the modified Cargo.toml
-    fs::write(manifest_path, doc.to_string())
-        .with_context(|| format!("Failed to write '{}'", manifest_path))?;
-
-    Ok(())
-}
+    drop(progress);
+    if !loosened.is_empty() {
+        warn!(
+            "Left {} pinned requirement(s) as they are, the workspace entry would loosen them. Pass --allow-loosen to switch them anyway:\n  - {}",
+            loosened.len(),
+            loosened.join("\n  - ")
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::Result;
-    use camino::Utf8PathBuf;
-    use std::collections::{HashMap, HashSet};
-    use tempfile::TempDir;
-    use toml_edit::{Item, Table, Value};
+    if opt.normalize_existing {
+        normalize_workspace_dependencies(&mut root_manifest.doc);
+    }
 
-    #[test]
-    fn test_get_workspace_dependencies() {
-        let mut doc = DocumentMut::default();
-        let mut workspace_table = Table::new();
-        let mut deps_table = Table::new();
-        deps_table.insert("dep1", Item::Value(Value::from("1.0.0")));
-        workspace_table.insert("dependencies", Item::Table(deps_table));
-        doc.insert("workspace", Item::Table(workspace_table));
+    if let Some(resolver) = &opt.set_resolver {
+        set_resolver(&mut root_manifest.doc, resolver);
+    }
 
-        let workspace_deps = get_workspace_dependencies(&doc);
-        assert_eq!(workspace_deps.len(), 1);
-        assert!(workspace_deps.contains_key("dep1"));
+    // Entries written in this run are marked with `Item::None`
+    let annotate: HashSet<String> = if opt.used_by_comments {
+        workspace_deps
+            .iter()
+            .filter(|(_, item)| item.is_none())
+            .map(|(dep, _)| dep.clone())
+            .collect()
+    } else {
+        HashSet::new()
+    };
+    update_used_by_comments(&mut root_manifest.doc, &dep_usage, &annotate);
+    if opt.group_by_category {
+        let groups = groups::assign(&root_manifest.doc, metadata, &config);
+        groups::apply(&mut root_manifest.doc, &groups);
     }
 
-    #[test]
-    fn test_add_dependency_to_workspace() -> Result<()> {
-        let mut doc = DocumentMut::default();
-        let temp_dir = TempDir::new()?;
-        let manifest_path =
-            Utf8PathBuf::from_path_buf(temp_dir.path().join("test_package/Cargo.toml")).unwrap();
+    // A root package was edited like any member, but its manifest is
+    // written as the root. Everything but [workspace] comes from the
+    // member's copy, e.g. [features] following a renamed dependency
+    if let Some(root_package) = manifests.take_changed(&workspace_manifest_path) {
+        let removed: Vec<String> = root_manifest
+            .doc
+            .iter()
+            .map(|(key, _)| key.to_string())
+            .filter(|key| key != "workspace" && !root_package.doc.contains_key(key))
+            .collect();
+        for key in removed {
+            root_manifest.doc.remove(&key);
+        }
+        for (key, item) in root_package.doc.iter() {
+            if key != "workspace" {
+                root_manifest.doc.insert(key, item.clone());
+            }
+        }
+    }
+    let changed: Vec<(PathBuf, String)> = manifests
+        .render_changed()
+        .into_iter()
+        .map(|(manifest, content)| (manifest.path().to_path_buf(), content))
+        .collect();
+    let mut progress = Progress::start(opt, "Writing", Some(changed.len()));
+    for (manifest_path, content) in changed {
+        progress.inc(&manifest_path.display().to_string());
+        let written = failures.record(
+            opt.keep_going,
+            format!("Failed to write '{}'", manifest_path.display()),
+            manifests.write(&manifest_path, &content, opt.symlinks),
+        )?;
+        if written {
+            events.emit(Event::FileWritten {
+                manifest: &manifest_path,
+            });
+            summary.edited(
+                manifest_path
+                    .strip_prefix(&metadata.workspace_root)
+                    .unwrap_or(&manifest_path),
+            );
+        }
+    }
 
-        // Create the directory structure and a dummy Cargo.toml file with dep1
-        fs::create_dir_all(manifest_path.parent().unwrap())?;
-        let cargo_toml_content = r#"
-            [dependencies]
-            dep1 = "1.0.0"
-        "#;
-        fs::write(&manifest_path, cargo_toml_content)?;
+    drop(progress);
 
-        let mut package_manifest_paths = HashMap::new();
-        package_manifest_paths.insert("test_package".to_string(), manifest_path.clone());
+    // Write back the root Cargo.toml, unless the run left it as it was
+    let content = root_manifest.render();
+    let original = Manifest::parse(root_manifest.path(), root_manifest.source())?.render();
+    if content != original {
+        manifests.write(root_manifest.path(), &content, opt.symlinks)?;
+        events.emit(Event::FileWritten {
+            manifest: root_manifest.path(),
+        });
+        summary.edited(
+            root_manifest
+                .path()
+                .strip_prefix(&metadata.workspace_root)
+                .unwrap_or(root_manifest.path()),
+        );
+    }
 
-        let mut users = HashSet::new();
-        users.insert("test_package".to_string());
+    // The events already tell which members use each dependency
+    if !events.enabled() {
+        summary.print_near_misses(opt.embedded);
+    }
 
-        add_dependency_to_workspace(&mut doc, "dep1", &users, &package_manifest_paths)?;
+    if let Some(path) = &opt.write_summary {
+        summary.finish(&root_manifest.doc, opt.show);
+        summary.write(path)?;
+        info!("Wrote the summary to '{}'", path.display());
+    }
 
-        let workspace_deps = get_workspace_dependencies(&doc);
-        assert!(workspace_deps.contains_key("dep1"));
-        Ok(())
+    if opt.explain {
+        // stdout belongs to the events with --format json-lines
+        if events.enabled() || opt.embedded {
+            eprint!("{}", explain);
+        } else {
+            print!("{}", explain);
+        }
     }
 
-    #[test]
+    failures.into_result()
+}
+
+/// Checks everything that can be checked before the first edit, so a broken
+/// member doesn't leave the workspace half consolidated. Returns all problems
+#[allow(clippy::too_many_arguments)]
+fn preflight(
+    root_manifest: &Manifest,
+    package_manifest_paths: &HashMap<String, PathBuf>,
+    manifests: &mut Manifests,
+    dep_usage: &HashMap<String, HashSet<String>>,
+    dep_classes: &HashMap<String, Class>,
+    workspace_deps: &HashMap<String, Item>,
+    config: &Config,
+    opt: &Opt,
+    lockfile: Option<&Lockfile>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut manifest_paths: Vec<_> = package_manifest_paths
+        .values()
+        .map(PathBuf::as_path)
+        .collect();
+    manifest_paths.sort();
+    // Parsing all members up front lets it happen in parallel. A root
+    // package is read as a member as well
+    for err in manifests.preload(&manifest_paths) {
+        problems.push(format!("{:#}", err));
+    }
+
+    // Only the manifests this run writes have to be writable, e.g. a symlinked
+    // member without anything to consolidate is fine with --symlinks refuse.
+    // Cleaning up and renaming aliases can touch any member
+    let mut written: HashSet<&str> = HashSet::new();
+    for (dep, users) in dep_usage {
+        let class = class_of(dep_classes, dep);
+        let existing =
+            workspace_deps.contains_key(dep) && !config.rule(dep).is_some_and(|rule| rule.skip);
+        if existing || should_group(dep, users.len(), class, opt.group_all, config).is_some() {
+            written.extend(users.iter().map(String::as_str));
+        }
+    }
+    // Manifests held in memory can always be written
+    let mut manifest_paths: Vec<_> = package_manifest_paths
+        .iter()
+        .filter(|_| !manifests.is_in_memory())
+        .filter(|(member, _)| {
+            opt.clean_inherited || opt.unify_alias || written.contains(member.as_str())
+        })
+        .map(|(_, manifest_path)| manifest_path.as_path())
+        .collect();
+    manifest_paths.sort();
+    if !manifests.is_in_memory() {
+        manifest_paths.insert(0, root_manifest.path());
+    }
+    for manifest_path in manifest_paths {
+        if let Err(err) = resolve_write_path(manifest_path, opt.symlinks) {
+            problems.push(format!("{:#}", err));
+            continue;
+        }
+        if let Err(err) = OpenOptions::new().write(true).open(manifest_path) {
+            problems.push(format!(
+                "'{}' is not writable: {}",
+                manifest_path.display(),
+                err
+            ));
+        }
+    }
+
+    // Build every planned workspace entry on a scratch copy of the root manifest
+    let workspace_root = root_manifest.path().parent().unwrap_or(Path::new(""));
+    let mut scratch = root_manifest.doc.clone();
+    let mut deps: Vec<_> = dep_usage.iter().collect();
+    deps.sort_by_key(|(dep, _)| *dep);
+    for (dep, users) in deps {
+        let class = class_of(dep_classes, dep);
+        if should_group(dep, users.len(), class, opt.group_all, config).is_none()
+            || config.pins.contains_key(dep)
+            || workspace_deps.contains_key(dep)
+        {
+            continue;
+        }
+        // Left alone or split into one entry per version later on
+        let ranges = compatibility_ranges(dep, users, package_manifest_paths, manifests);
+        if ranges.is_ok_and(|ranges| ranges.len() > 1) {
+            continue;
+        }
+        if let Err(err) = add_dependency_to_workspace(
+            &mut scratch,
+            dep,
+            workspace_root,
+            users,
+            package_manifest_paths,
+            manifests,
+            &dep_opt(opt, config, dep, class),
+            lockfile,
+        ) {
+            problems.push(format!(
+                "Can't create workspace entry for '{}': {:#}",
+                dep, err
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Returns how the members use `dep`, normal unless it's only a dev- or only
+/// a build-dependency
+fn class_of(dep_classes: &HashMap<String, Class>, dep: &str) -> Class {
+    dep_classes.get(dep).copied().unwrap_or(Class::Normal)
+}
+
+/// `opt` with the version strategy configured for `dep` or its class of
+/// dependencies, if there is one. The rule of `dep` goes first
+fn dep_opt<'a>(opt: &'a Opt, config: &Config, dep: &str, class: Class) -> Cow<'a, Opt> {
+    let strategy = config.rule(dep).and_then(|rule| rule.strategy).or_else(|| {
+        config
+            .class_settings(class)
+            .and_then(|settings| settings.version_strategy)
+    });
+    match strategy {
+        Some(strategy) => Cow::Owned(Opt {
+            version_strategy: Some(strategy),
+            ..opt.clone()
+        }),
+        None => Cow::Borrowed(opt),
+    }
+}
+
+/// Returns why `dep` should be consolidated, or `None` if it shouldn't.
+/// Dependencies of a class with `skip` set are left alone unless listed in
+/// force-include, those whose rule has `skip` set always are
+pub fn should_group(
+    dep: &str,
+    user_count: usize,
+    class: Class,
+    group_all: bool,
+    config: &Config,
+) -> Option<&'static str> {
+    if config.rule(dep).is_some_and(|rule| rule.skip) {
+        return None;
+    }
+    let forced = config.force_include.iter().any(|name| name == dep);
+    let skipped = config
+        .class_settings(class)
+        .is_some_and(|settings| settings.skip);
+    if skipped && !forced {
+        None
+    } else if user_count >= config.min_members_of(class) {
+        Some("shared by multiple members")
+    } else if forced {
+        Some("listed in force-include")
+    } else if group_all {
+        Some("--group-all is set")
+    } else {
+        None
+    }
+}
+
+/// Returns the first component of `path` that can't be represented as UTF-8
+fn non_utf8_component(path: &Path) -> Option<String> {
+    path.components()
+        .map(|component| component.as_os_str())
+        .find(|component| component.to_str().is_none())
+        .map(|component| component.to_string_lossy().into_owned())
+}
+
+/// Returns the entries of `workspace.exclude`, resolved against the workspace root
+fn get_workspace_excludes(doc: &DocumentMut, workspace_root: &Path) -> Vec<PathBuf> {
+    doc.get("workspace")
+        .and_then(|ws| ws.get("exclude"))
+        .and_then(|exclude| exclude.as_array())
+        .map(|exclude| {
+            exclude
+                .iter()
+                .filter_map(|path| path.as_str())
+                .map(|path| workspace_root.join(path))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the directories, relative to the workspace root, of crates below
+/// it which are neither members nor excluded. Crates of nested workspaces
+/// and vendored crates don't count
+fn find_orphans(
+    workspace_root: &Path,
+    member_manifests: &HashSet<&Path>,
+    excludes: &[PathBuf],
+) -> Result<Vec<String>> {
+    let root_manifest_path = workspace_root.join("Cargo.toml");
+    let nested_roots: Vec<PathBuf> = discover::find_workspace_roots(workspace_root)?
+        .into_iter()
+        .filter(|path| *path != root_manifest_path)
+        .filter_map(|path| path.parent().map(Path::to_path_buf))
+        .collect();
+
+    let packages = discover::find_manifests(workspace_root, |doc| doc.contains_key("package"))?;
+    let mut orphans = Vec::new();
+    for manifest_path in packages {
+        let Some(dir) = manifest_path.parent() else {
+            continue;
+        };
+        if manifest_path == root_manifest_path
+            || member_manifests.contains(manifest_path.as_path())
+            || is_excluded(&manifest_path, excludes)
+            || nested_roots.iter().any(|root| dir.starts_with(root))
+            // `cargo vendor` leaves a checksum file in every crate
+            || dir.join(".cargo-checksum.json").exists()
+        {
+            continue;
+        }
+        if let Ok(relative) = dir.strip_prefix(workspace_root) {
+            orphans.push(relative.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(orphans)
+}
+
+fn add_workspace_members(doc: &mut DocumentMut, members: &[String]) -> Result<()> {
+    let workspace_members = doc
+        .entry("workspace")
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_mut()
+        .ok_or_else(|| diagnostic::unexpected(&["workspace"], "workspace is not a table"))?
+        .entry("members")
+        .or_insert_with(|| toml_edit::value(toml_edit::Array::new()))
+        .as_array_mut()
+        .ok_or_else(|| {
+            diagnostic::unexpected(
+                &["workspace", "members"],
+                "workspace.members is not an array",
+            )
+        })?;
+    for member in members {
+        workspace_members.push(member.as_str());
+    }
+    Ok(())
+}
+
+fn is_excluded(manifest_path: &Path, excludes: &[PathBuf]) -> bool {
+    manifest_path
+        .parent()
+        .map(|dir| excludes.iter().any(|exclude| dir.starts_with(exclude)))
+        .unwrap_or(false)
+}
+
+/// Whether a manifest resolves to a path outside the (resolved) workspace
+/// root, like a sibling checkout pulled in through a path dependency
+fn is_external(manifest_path: &Path, workspace_root: &Path) -> bool {
+    let resolved = fs::canonicalize(manifest_path).unwrap_or_else(|_| manifest_path.into());
+    !resolved.starts_with(workspace_root)
+}
+
+fn get_workspace_dependencies(doc: &DocumentMut) -> HashMap<String, Item> {
+    doc.get("workspace")
+        .and_then(|ws| ws.as_table())
+        .and_then(|ws_table| ws_table.get("dependencies"))
+        .and_then(|deps| deps.as_table())
+        .map(|ws_deps| {
+            ws_deps
+                .iter()
+                .map(|(dep_name, item)| (dep_name.to_string(), item.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn add_dependency_to_workspace(
+    doc: &mut DocumentMut,
+    dep_name: &str,
+    workspace_root: &Path,
+    users: &HashSet<String>,
+    package_manifest_paths: &HashMap<String, PathBuf>,
+    manifests: &mut Manifests,
+    opt: &Opt,
+    lockfile: Option<&Lockfile>,
+) -> Result<Provenance> {
+    let (dep_item, provenance) = pick_spec(
+        dep_name,
+        workspace_root,
+        users,
+        package_manifest_paths,
+        manifests,
+        opt,
+        lockfile,
+    )?;
+    let wrap_width = opt.wrap_width.unwrap_or(dependency::DEFAULT_WRAP_WIDTH);
+    insert_workspace_dependency(doc, dep_name, dep_item, wrap_width);
+    Ok(provenance)
+}
+
+/// Replaces the features of the workspace entry of `dep_name` with those
+/// all members in `users` enable, for rules with `hoist-features`
+fn hoist_features(
+    doc: &mut DocumentMut,
+    dep_name: &str,
+    users: &HashSet<String>,
+    package_manifest_paths: &HashMap<String, PathBuf>,
+    manifests: &mut Manifests,
+) -> Result<()> {
+    let mut common: Option<BTreeSet<String>> = None;
+    for user in users {
+        let manifest_path = &package_manifest_paths[user];
+        let member = manifests.get(manifest_path)?;
+        let dep_item =
+            dependency::get_dependency_from_member(&member.doc, manifest_path, dep_name)?;
+        let features: BTreeSet<String> = dependency::get_features(&dep_item)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        common = Some(match common {
+            Some(common) => common.intersection(&features).cloned().collect(),
+            None => features,
+        });
+    }
+
+    let entry = doc
+        .get_mut("workspace")
+        .and_then(|ws| ws.get_mut("dependencies"))
+        .and_then(|deps| deps.get_mut(dep_name));
+    if let Some(entry) = entry {
+        dependency::set_features(entry, &common.unwrap_or_default());
+    }
+    Ok(())
+}
+
+/// Drops the features from the given sections of a member which the
+/// workspace entry of `dep_name` enables already
+fn drop_hoisted_features(
+    root: &DocumentMut,
+    member: &mut DocumentMut,
+    dep_name: &str,
+    sections: &[&str],
+) {
+    let hoisted: BTreeSet<String> = root
+        .get("workspace")
+        .and_then(|ws| ws.get("dependencies"))
+        .and_then(|deps| deps.get(dep_name))
+        .and_then(dependency::get_features)
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    for section in sections {
+        let Some(tbl) = member
+            .get_mut(section)
+            .and_then(|deps| deps.get_mut(dep_name))
+            .and_then(Item::as_table_like_mut)
+        else {
+            continue;
+        };
+        let Some(features) = tbl.get_mut("features").and_then(Item::as_array_mut) else {
+            continue;
+        };
+        features.retain(|feature| feature.as_str().is_none_or(|name| !hoisted.contains(name)));
+        if features.is_empty() {
+            tbl.remove("features");
+        }
+    }
+}
+
+/// Moves the features a member enables of `from` through its own feature
+/// definitions over to `to`, once the dependency was renamed
+fn rename_feature_references(
+    feature_references: &mut HashMap<PathBuf, HashMap<String, BTreeSet<String>>>,
+    manifest_path: &Path,
+    from: &str,
+    to: &str,
+) {
+    let Some(references) = feature_references.get_mut(manifest_path) else {
+        return;
+    };
+    if let Some(features) = references.remove(from) {
+        references
+            .entry(to.to_string())
+            .or_default()
+            .extend(features);
+    }
+}
+
+/// Groups the members using `dep_name` by the range of compatible versions
+/// their requirement falls into, see [`version::compatibility`]. Members
+/// without a version requirement, e.g. wildcards or git dependencies, fit
+/// any range and are left out
+fn compatibility_ranges(
+    dep_name: &str,
+    users: &HashSet<String>,
+    package_manifest_paths: &HashMap<String, PathBuf>,
+    manifests: &mut Manifests,
+) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut users: Vec<_> = users.iter().collect();
+    users.sort();
+
+    let mut ranges: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for user in users {
+        let manifest_path = &package_manifest_paths[user];
+        let member = manifests.get(manifest_path)?;
+        let dep_item =
+            dependency::get_dependency_from_member(&member.doc, manifest_path, dep_name)?;
+        if dependency::is_wildcard(&dep_item) || dependency::is_workspace_inherited(&dep_item) {
+            continue;
+        }
+        let Some(req) =
+            dependency::get_version(&dep_item).and_then(|version| VersionReq::parse(&version).ok())
+        else {
+            continue;
+        };
+        ranges
+            .entry(version::compatibility(&req))
+            .or_default()
+            .push(user.clone());
+    }
+    Ok(ranges)
+}
+
+/// Picks the spec of `dep_name` the members should share from theirs,
+/// according to `--version-strategy` and the wildcard and pre-release
+/// policies. Paths are rebased onto `workspace_root`, and a path dependency
+/// gets both its path and its version even if members only declare either.
+/// Also returns which member's spec was picked and how the others differ
+fn pick_spec(
+    dep_name: &str,
+    workspace_root: &Path,
+    users: &HashSet<String>,
+    package_manifest_paths: &HashMap<String, PathBuf>,
+    manifests: &mut Manifests,
+    opt: &Opt,
+    lockfile: Option<&Lockfile>,
+) -> Result<(Item, Provenance)> {
+    let mut specs = declared_specs(
+        dep_name,
+        workspace_root,
+        users,
+        package_manifest_paths,
+        manifests,
+    )?;
+
+    // The spec and features each section of the members asks for
+    let mut declared = Vec::new();
+    let mut section_features: Vec<(&str, BTreeSet<String>)> = Vec::new();
+    for (user, _) in &specs {
+        let manifest_path = &package_manifest_paths[user];
+        let sections = declared_sections(dep_name, workspace_root, manifest_path, manifests)?;
+        let several = sections.len() > 1;
+        for (section, item) in sections {
+            let features = dependency::get_features(&item).unwrap_or_default();
+            section_features.push((section, features.into_iter().collect()));
+            declared.push((section_label(user, section, several), item));
+        }
+    }
+
+    let mut paths: Vec<(&str, &str)> = specs
+        .iter()
+        .filter_map(|(user, item)| Some((user.as_str(), item.get("path")?.as_str()?)))
+        .collect();
+    paths.sort_by_key(|(_, path)| *path);
+    if paths.first().map(|(_, path)| path) != paths.last().map(|(_, path)| path) {
+        return Err(anyhow::anyhow!(
+            "'{}' points to different paths: {}",
+            dep_name,
+            paths
+                .iter()
+                .map(|(user, path)| format!("{} ({})", user, path))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    let path = paths.first().map(|(_, path)| path.to_string());
+
+    let wildcard_users: Vec<_> = specs
+        .iter()
+        .filter(|(_, item)| dependency::is_wildcard(item))
+        .map(|(user, _)| user.as_str())
+        .collect();
+    if opt.wildcard == WildcardPolicy::Reject && !wildcard_users.is_empty() {
+        return Err(anyhow::anyhow!(
+            "'{}' is declared with a wildcard version by {}",
+            dep_name,
+            wildcard_users.join(", ")
+        ));
+    }
+
+    let prerelease_users: Vec<_> = specs
+        .iter()
+        .filter(|(_, item)| dependency::is_prerelease(item))
+        .map(|(user, _)| user.as_str())
+        .collect();
+    if opt.prerelease == PrereleasePolicy::Error
+        && !prerelease_users.is_empty()
+        && prerelease_users.len() < specs.len()
+    {
+        return Err(anyhow::anyhow!(
+            "'{}' is declared with a pre-release version by {} and a stable version by the other members",
+            dep_name,
+            prerelease_users.join(", ")
+        ));
+    }
+
+    // Once they inherit it, every section of the members has to accept the
+    // shared version
+    let requirements: Vec<(&str, String, Range)> = declared
+        .iter()
+        .filter(|(_, item)| !dependency::is_wildcard(item))
+        .filter_map(|(user, item)| {
+            let version = dependency::get_version(item)?;
+            let range = Range::of(&VersionReq::parse(&version).ok()?);
+            Some((user.as_str(), version, range))
+        })
+        .collect();
+    let shared = requirements
+        .iter()
+        .fold(Range::of(&VersionReq::STAR), |shared, (_, _, range)| {
+            shared.intersect(range)
+        });
+    if shared.is_empty() {
+        return Err(no_shared_version(dep_name, &requirements, &shared));
+    }
+
+    if opt.wildcard == WildcardPolicy::Ignore {
+        specs.retain(|(_, item)| !dependency::is_wildcard(item));
+        if specs.is_empty() {
+            return Err(anyhow::anyhow!(
+                "All members declare '{}' with a wildcard version, there's no version to pick",
+                dep_name
+            ));
+        }
+    }
+    let (candidate_users, mut candidates): (Vec<String>, Vec<Item>) = specs.into_iter().unzip();
+
+    // The strategy picks from the specs accepting nothing but shared versions
+    let fitting: Vec<usize> = (0..candidates.len())
+        .filter(|&position| {
+            // A wildcard is replaced by the locked version later on
+            dependency::is_wildcard(&candidates[position])
+                || dependency::get_version(&candidates[position])
+                    .and_then(|version| VersionReq::parse(&version).ok())
+                    .is_none_or(|req| shared.contains(&Range::of(&req)))
+        })
+        .collect();
+    let position = if fitting.is_empty() {
+        dependency::pick(&candidates, opt.version_strategy.unwrap_or_default())
+    } else {
+        let fitting_items: Vec<Item> = fitting
+            .iter()
+            .map(|&position| candidates[position].clone())
+            .collect();
+        fitting[dependency::pick(&fitting_items, opt.version_strategy.unwrap_or_default())]
+    };
+    // The version a path dependency takes when the picked spec only has a path
+    let versioned: Vec<Item> = candidates
+        .iter()
+        .enumerate()
+        .filter(|(position, _)| fitting.contains(position))
+        .map(|(_, item)| item.clone())
+        .filter(|item| dependency::get_version(item).is_some())
+        .collect();
+    let fallback_version = (!versioned.is_empty())
+        .then(|| {
+            let position = dependency::pick(&versioned, opt.version_strategy.unwrap_or_default());
+            dependency::get_version(&versioned[position])
+        })
+        .flatten();
+    let mut dep_item = candidates.swap_remove(position);
+    if fitting.is_empty() {
+        info!(
+            "No member's requirement of '{}' fits all of them, using '{}'",
+            dep_name, shared
+        );
+        dependency::set_version(&mut dep_item, &shared.to_string());
+    }
+    if let Some(path) = &path {
+        if dep_item.get("path").is_none() {
+            dependency::set_path(&mut dep_item, path);
+        }
+        if let (None, Some(version)) = (dependency::get_version(&dep_item), &fallback_version) {
+            dependency::set_version(&mut dep_item, version);
+        }
+    }
+
+    if opt.wildcard == WildcardPolicy::Lockfile && dependency::is_wildcard(&dep_item) {
+        let package = dep_item
+            .get("package")
+            .and_then(|package| package.as_str())
+            .unwrap_or(dep_name);
+        let version = lockfile
+            .and_then(|lockfile| lockfile.version_of(package))
+            .with_context(|| format!("'{}' is not in Cargo.lock", package))?
+            .to_string();
+        info!(
+            "Replacing wildcard version of '{}' with locked version '{}'",
+            dep_name, version
+        );
+        dependency::set_version(&mut dep_item, &version);
+    }
+
+    // Build scripts are compiled for the host, so the features they need
+    // must not leak into the other sections, nor the other way round. The
+    // entry only keeps those every section asks for, the sections keep the
+    // rest for themselves
+    if section_features
+        .iter()
+        .any(|(section, _)| *section == "build-dependencies")
+    {
+        let common = section_features
+            .into_iter()
+            .map(|(_, features)| features)
+            .reduce(|common, features| common.intersection(&features).cloned().collect())
+            .unwrap_or_default();
+        dependency::set_features(&mut dep_item, &common);
+    }
+
+    if opt.normalize {
+        dependency::normalize_spec(&mut dep_item);
+    }
+    let provenance = provenance(
+        format!("the spec of {}", candidate_users[position]),
+        &declared,
+        &dep_item,
+    );
+    Ok((dep_item, provenance))
+}
+
+/// The specs the members in `users` declare for `dep_name` themselves,
+/// sorted by member, with their paths rebased onto `workspace_root`
+fn declared_specs(
+    dep_name: &str,
+    workspace_root: &Path,
+    users: &HashSet<String>,
+    package_manifest_paths: &HashMap<String, PathBuf>,
+    manifests: &mut Manifests,
+) -> Result<Vec<(String, Item)>> {
+    let mut users: Vec<_> = users.iter().collect();
+    users.sort();
+
+    let mut specs = Vec::new();
+    for user in users {
+        let manifest_path = &package_manifest_paths[user];
+        let dep_item = declared_spec(dep_name, workspace_root, manifest_path, manifests)?;
+        specs.push((user.clone(), dep_item));
+    }
+    Ok(specs)
+}
+
+/// The spec of every section the members in `users` declare `dep_name` in,
+/// labelled by [`section_label`], leaving out the members whose specs can't
+/// be read. Only for describing an entry, switching them reports why
+fn readable_specs(
+    dep_name: &str,
+    workspace_root: &Path,
+    users: &HashSet<String>,
+    package_manifest_paths: &HashMap<String, PathBuf>,
+    manifests: &mut Manifests,
+) -> Vec<(String, Item)> {
+    let mut users: Vec<_> = users.iter().collect();
+    users.sort();
+
+    users
+        .into_iter()
+        .filter_map(|user| {
+            let manifest_path = &package_manifest_paths[user];
+            declared_sections(dep_name, workspace_root, manifest_path, manifests)
+                .ok()
+                .map(|sections| (user, sections))
+        })
+        .flat_map(|(user, sections)| {
+            let several = sections.len() > 1;
+            sections
+                .into_iter()
+                .map(move |(section, item)| (section_label(user, section, several), item))
+        })
+        .collect()
+}
+
+/// Names a section of `user` declaring a dependency, by the member alone
+/// unless it declares the dependency in `several` sections
+fn section_label(user: &str, section: &str, several: bool) -> String {
+    if several {
+        format!("{} [{}]", user, section)
+    } else {
+        user.to_string()
+    }
+}
+
+/// The spec of every section the member at `manifest_path` declares
+/// `dep_name` in, with their paths rebased onto `workspace_root`
+fn declared_sections(
+    dep_name: &str,
+    workspace_root: &Path,
+    manifest_path: &Path,
+    manifests: &mut Manifests,
+) -> Result<Vec<(&'static str, Item)>> {
+    let member = manifests.get(manifest_path)?;
+    let mut sections = dependency::get_dependency_sections(&member.doc, manifest_path, dep_name)?;
+    if let Some(prefix) = manifest_path
+        .parent()
+        .and_then(|dir| dir.strip_prefix(workspace_root).ok())
+    {
+        for (_, item) in &mut sections {
+            dependency::rebase_path(item, prefix);
+        }
+    }
+    Ok(sections)
+}
+
+/// The spec the member at `manifest_path` declares for `dep_name`, with its
+/// path rebased onto `workspace_root`
+fn declared_spec(
+    dep_name: &str,
+    workspace_root: &Path,
+    manifest_path: &Path,
+    manifests: &mut Manifests,
+) -> Result<Item> {
+    let member = manifests.get(manifest_path)?;
+    let mut dep_item =
+        dependency::get_dependency_from_member(&member.doc, manifest_path, dep_name)?;
+    // Member paths are relative to the member, the workspace entry's to the root
+    if let Some(prefix) = manifest_path
+        .parent()
+        .and_then(|dir| dir.strip_prefix(workspace_root).ok())
+    {
+        dependency::rebase_path(&mut dep_item, prefix);
+    }
+    Ok(dep_item)
+}
+
+fn provenance(basis: String, declared: &[(String, Item)], entry: &Item) -> Provenance {
+    let overridden = declared
+        .iter()
+        .filter(|(_, item)| !dependency::is_workspace_inherited(item))
+        .map(|(user, item)| (user.clone(), dependency::spec_differences(item, entry)))
+        .filter(|(_, differences)| !differences.is_empty())
+        .collect();
+    Provenance { basis, overridden }
+}
+
+/// Names the members requiring more than `shared.lower` allows and those
+/// ruling out every version from there on
+fn no_shared_version(
+    dep_name: &str,
+    requirements: &[(&str, String, Range)],
+    shared: &Range,
+) -> anyhow::Error {
+    let describe = |users: Vec<&(&str, String, Range)>| {
+        users
+            .iter()
+            .map(|(user, version, _)| format!("{} ({})", user, version))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let needing = requirements
+        .iter()
+        .filter(|(_, _, range)| range.lower == shared.lower)
+        .collect();
+    let ruling_out = requirements
+        .iter()
+        .filter(|(_, _, range)| {
+            range
+                .upper
+                .as_ref()
+                .is_some_and(|upper| *upper <= shared.lower)
+        })
+        .collect();
+    anyhow::anyhow!(
+        "No version of '{}' satisfies all members: {} need(s) at least {}, which {} rule(s) out",
+        dep_name,
+        describe(needing),
+        shared.lower,
+        describe(ruling_out)
+    )
+}
+
+/// Drops the sections of a member which declare a pre-release requirement of
+/// `dep_name` that differs from the workspace entry, unless `policy` says to
+/// switch them over anyway
+fn filter_prerelease_sections(
+    doc: &DocumentMut,
+    member: &DocumentMut,
+    manifest_path: &Path,
+    dep_name: &str,
+    sections: Vec<&'static str>,
+    policy: PrereleasePolicy,
+    explain: &mut Explain,
+) -> Result<Vec<&'static str>> {
+    let workspace_version = doc
+        .get("workspace")
+        .and_then(|ws| ws.get("dependencies"))
+        .and_then(|deps| deps.get(dep_name))
+        .and_then(dependency::get_version);
+    let member_sections = dependency::get_dependency_sections(member, manifest_path, dep_name)?;
+
+    let mut kept = Vec::new();
+    for section in sections {
+        let Some((_, member_item)) = member_sections
+            .iter()
+            .find(|(member_section, _)| *member_section == section)
+        else {
+            continue;
+        };
+        let member_version = Some(member_item)
+            .filter(|item| dependency::is_prerelease(item))
+            .and_then(dependency::get_version);
+        let Some(member_version) =
+            member_version.filter(|version| Some(version) != workspace_version.as_ref())
+        else {
+            kept.push(section);
+            continue;
+        };
+
+        explain.note(
+            dep_name,
+            format!(
+                "[{}] of '{}' requires pre-release '{}' ({:?} policy)",
+                section,
+                manifest_path.display(),
+                member_version,
+                policy
+            ),
+        );
+        match policy {
+            PrereleasePolicy::Keep => warn!(
+                "Keeping pre-release requirement '{}' of '{}' in [{}] of '{}'",
+                member_version,
+                dep_name,
+                section,
+                manifest_path.display()
+            ),
+            PrereleasePolicy::PreferStable => {
+                warn!(
+                    "Replacing pre-release requirement '{}' of '{}' in [{}] of '{}' with the workspace entry",
+                    member_version,
+                    dep_name,
+                    section,
+                    manifest_path.display()
+                );
+                kept.push(section);
+            }
+            PrereleasePolicy::Error => {
+                return Err(anyhow::anyhow!(
+                    "pre-release requirement '{}' of '{}' in [{}] differs from the workspace entry",
+                    member_version,
+                    dep_name,
+                    section
+                ))
+            }
+        }
+    }
+
+    Ok(kept)
+}
+
+/// Adds a `# used by: ...` comment to the workspace entries in `annotate` and
+/// refreshes the comment of every entry which already has one
+fn update_used_by_comments(
+    doc: &mut DocumentMut,
+    dep_usage: &HashMap<String, HashSet<String>>,
+    annotate: &HashSet<String>,
+) {
+    let Some(ws_deps) = doc
+        .get_mut("workspace")
+        .and_then(|ws| ws.get_mut("dependencies"))
+        .and_then(Item::as_table_like_mut)
+    else {
+        return;
+    };
+
+    for (dep_name, dep_item) in ws_deps.iter_mut() {
+        let Some(value) = dep_item.as_value_mut() else {
+            continue;
+        };
+        let annotated = value
+            .decor()
+            .suffix()
+            .and_then(|suffix| suffix.as_str())
+            .is_some_and(|suffix| suffix.contains(USED_BY_MARKER));
+        if !annotated && !annotate.contains(dep_name.get()) {
+            continue;
+        }
+
+        let mut users: Vec<_> = dep_usage
+            .get(dep_name.get())
+            .map(|users| users.iter().map(String::as_str).collect())
+            .unwrap_or_default();
+        users.sort_unstable();
+        let users = if users.is_empty() {
+            "-".to_string()
+        } else {
+            users.join(", ")
+        };
+        value
+            .decor_mut()
+            .set_suffix(format!(" {} {}", USED_BY_MARKER, users));
+    }
+}
+
+fn normalize_workspace_dependencies(doc: &mut DocumentMut) {
+    let Some(ws_deps) = doc
+        .get_mut("workspace")
+        .and_then(|ws| ws.get_mut("dependencies"))
+        .and_then(Item::as_table_like_mut)
+    else {
+        return;
+    };
+
+    for (_, dep_item) in ws_deps.iter_mut() {
+        dependency::normalize_spec(dep_item);
+    }
+}
+
+/// Writes `resolver` into `[workspace]` unless the workspace already uses the
+/// same or a newer one. Returns whether it was changed
+fn set_resolver(doc: &mut DocumentMut, resolver: &str) -> bool {
+    let Some(workspace) = doc.get_mut("workspace").and_then(Item::as_table_mut) else {
+        return false;
+    };
+    let current = workspace.get("resolver").and_then(Item::as_str);
+    if current.is_some_and(|current| current >= resolver) {
+        info!("Keeping resolver = \"{}\"", current.unwrap_or_default());
+        return false;
+    }
+
+    warn!(
+        "Setting resolver = \"{}\" (was {}). {}",
+        resolver,
+        current.map_or("unset".to_string(), |current| format!("\"{}\"", current)),
+        match resolver {
+            "2" => "Features are no longer unified between normal, build and dev dependencies or across targets, which can change what gets compiled",
+            _ => "Besides the changes of resolver 2, dependency versions compatible with each package's rust-version are preferred",
+        }
+    );
+    workspace.insert("resolver", toml_edit::value(resolver));
+    true
+}
+
+/// Inserts the entry of `dep_name`, expanded if it's wider than
+/// `wrap_width`. An entry replacing an expanded one, or one with a
+/// multi-line features array, keeps that layout
+pub fn insert_workspace_dependency(
+    doc: &mut DocumentMut,
+    dep_name: &str,
+    mut dep_item: Item,
+    wrap_width: usize,
+) {
+    // Ensure workspace table exists
+    let ws_deps = doc
+        .entry("workspace")
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_mut()
+        .unwrap()
+        .entry("dependencies")
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_mut()
+        .unwrap();
+
+    let existing = ws_deps.get(dep_name);
+    let multiline_features = existing
+        .and_then(|item| item.get("features"))
+        .and_then(Item::as_array)
+        .is_some_and(dependency::is_multiline);
+    match existing {
+        Some(Item::Table(table)) if dep_item.is_inline_table() => {
+            let decor = table.decor().clone();
+            let position = table.position();
+            dependency::expand_spec(&mut dep_item, multiline_features);
+            if let Item::Table(table) = &mut dep_item {
+                *table.decor_mut() = decor;
+                if let Some(position) = position {
+                    table.set_position(position);
+                }
+            }
+        }
+        _ if multiline_features => {
+            let features = dep_item.get_mut("features").and_then(Item::as_array_mut);
+            if let Some(features) = features {
+                dependency::set_multiline(features);
+            }
+        }
+        _ => dependency::wrap_spec(dep_name, &mut dep_item, wrap_width),
+    }
+    // Comments above an inline entry go above the header of the table
+    let comments = ws_deps
+        .key(dep_name)
+        .filter(|_| existing.is_some_and(Item::is_value))
+        .and_then(|key| key.leaf_decor().prefix())
+        .and_then(|prefix| prefix.as_str())
+        .map(str::to_string);
+    if let (Some(comments), Item::Table(table)) = (comments, &mut dep_item) {
+        table.decor_mut().set_prefix(format!("\n{}", comments));
+    }
+    ws_deps.insert(dep_name, dep_item);
+}
+
+fn remove_workspace_dependency(doc: &mut DocumentMut, dep_name: &str) {
+    if let Some(ws_deps) = doc
+        .get_mut("workspace")
+        .and_then(|ws| ws.get_mut("dependencies"))
+        .and_then(Item::as_table_like_mut)
+    {
+        ws_deps.remove(dep_name);
+    }
+}
+
+/// Why consolidating keeps a member's own `member_item` instead of switching
+/// it to the existing `workspace_item`, if it does: the entry would loosen
+/// its pinned requirement, or `--on-mismatch` leaves the difference alone
+pub fn kept_apart(workspace_item: &Item, member_item: &Item, opt: &Opt) -> Option<String> {
+    if dependency::is_workspace_inherited(member_item) {
+        return None;
+    }
+    let requirement = |item: &Item| {
+        dependency::get_version(item).and_then(|version| VersionReq::parse(&version).ok())
+    };
+    if let (Some(member_req), Some(workspace_req)) =
+        (requirement(member_item), requirement(workspace_item))
+    {
+        if !opt.allow_loosen && version::loosens(&member_req, &workspace_req) {
+            return Some(format!(
+                "the workspace entry would loosen its pinned requirement '{}'",
+                member_req
+            ));
+        }
+    }
+    match opt.on_mismatch.unwrap_or_default() {
+        MismatchPolicy::Skip => dependency::find_mismatch(workspace_item, member_item),
+        MismatchPolicy::Update => {
+            // The entry is updated to the version first
+            let mut updated = member_item.clone();
+            if let Some(version) = dependency::get_version(workspace_item) {
+                dependency::set_version(&mut updated, &version);
+            }
+            dependency::find_mismatch(workspace_item, &updated)
+        }
+        MismatchPolicy::Warn | MismatchPolicy::Error => None,
+    }
+}
+
+/// Leaves out the sections whose pinned requirement of `dep_name`, like
+/// `=1.2.3`, the workspace entry would loosen, unless `allow_loosen`. They are
+/// described in `loosened`
+fn filter_loosened_sections(
+    doc: &DocumentMut,
+    member: &DocumentMut,
+    manifest_path: &Path,
+    dep_name: &str,
+    sections: Vec<&'static str>,
+    allow_loosen: bool,
+    loosened: &mut Vec<String>,
+) -> Result<Vec<&'static str>> {
+    let Some(workspace_version) = doc
+        .get("workspace")
+        .and_then(|ws| ws.get("dependencies"))
+        .and_then(|deps| deps.get(dep_name))
+        .and_then(dependency::get_version)
+    else {
+        return Ok(sections);
+    };
+    let Ok(workspace_req) = VersionReq::parse(&workspace_version) else {
+        return Ok(sections);
+    };
+    let member_sections = dependency::get_dependency_sections(member, manifest_path, dep_name)?;
+
+    let mut kept = Vec::new();
+    for section in sections {
+        let member_version = member_sections
+            .iter()
+            .find(|(member_section, _)| *member_section == section)
+            .filter(|(_, item)| !dependency::is_workspace_inherited(item))
+            .and_then(|(_, item)| dependency::get_version(item));
+        let is_loosened = member_version.as_deref().is_some_and(|version| {
+            VersionReq::parse(version).is_ok_and(|req| version::loosens(&req, &workspace_req))
+        });
+        let Some(member_version) = member_version.filter(|_| is_loosened) else {
+            kept.push(section);
+            continue;
+        };
+
+        let description = format!(
+            "'{}' in [{}] of '{}': '{}' would become '{}'",
+            dep_name,
+            section,
+            manifest_path.display(),
+            member_version,
+            workspace_version
+        );
+        if allow_loosen {
+            warn!("Loosening {}", description);
+            kept.push(section);
+        } else {
+            loosened.push(description);
+        }
+    }
+
+    Ok(kept)
+}
+
+/// Compares each section's spec of `dep_name` in a member with the existing
+/// workspace entry and applies `policy` on a mismatch. Features the member
+/// enables through its own feature definitions, `enabled`, don't count as
+/// added by the workspace entry. Returns the sections which should switch to
+/// the workspace entry
+#[allow(clippy::too_many_arguments)]
+fn reconcile_member(
+    doc: &mut DocumentMut,
+    member: &DocumentMut,
+    manifest_path: &Path,
+    dep_name: &str,
+    enabled: Option<&BTreeSet<String>>,
+    policy: MismatchPolicy,
+    explain: &mut Explain,
+    events: &Events,
+) -> Result<Vec<&'static str>> {
+    let mut sections = Vec::new();
+
+    for (section, member_item) in
+        dependency::get_dependency_sections(member, manifest_path, dep_name)?
+    {
+        let Some(workspace_item) = doc
+            .get_mut("workspace")
+            .and_then(|ws| ws.get_mut("dependencies"))
+            .and_then(|deps| deps.get_mut(dep_name))
+        else {
+            sections.push(section);
+            continue;
+        };
+        if dependency::is_workspace_inherited(&member_item) {
+            explain.note(
+                dep_name,
+                format!(
+                    "[{}] of '{}' already inherits the workspace entry",
+                    section,
+                    manifest_path.display()
+                ),
+            );
+        }
+        let mismatch = dependency::find_mismatch_enabling(
+            workspace_item,
+            &member_item,
+            enabled.unwrap_or(&BTreeSet::new()),
+        );
+        let Some(mismatch) = mismatch else {
+            sections.push(section);
+            continue;
+        };
+        explain.note(
+            dep_name,
+            format!(
+                "[{}] of '{}' conflicts with the workspace entry: {} ({:?} policy)",
+                section,
+                manifest_path.display(),
+                mismatch,
+                policy
+            ),
+        );
+        events.emit(Event::Conflict {
+            dependency: dep_name,
+            manifest: manifest_path,
+            section,
+            message: mismatch.to_string(),
+        });
+
+        match policy {
+            MismatchPolicy::Warn => {
+                warn!(
+                    "'{}' in [{}] of '{}': {}, switching to the workspace entry anyway",
+                    dep_name,
+                    section,
+                    manifest_path.display(),
+                    mismatch
+                );
+                sections.push(section);
+            }
+            MismatchPolicy::Error => {
+                return Err(anyhow::anyhow!(
+                    "'{}' in [{}] doesn't match the workspace entry: {}",
+                    dep_name,
+                    section,
+                    mismatch
+                ))
+            }
+            MismatchPolicy::Update => {
+                // The version of the entry was settled by
+                // `update_workspace_entry`, a spec which still differs in
+                // anything but its version stays as it is
+                let mut updated = member_item.clone();
+                if let Some(version) = dependency::get_version(workspace_item) {
+                    dependency::set_version(&mut updated, &version);
+                }
+                let none = BTreeSet::new();
+                let enabled = enabled.unwrap_or(&none);
+                match dependency::find_mismatch_enabling(workspace_item, &updated, enabled) {
+                    Some(mismatch) => info!(
+                        "Leaving '{}' in [{}] of '{}' alone, updating the version isn't enough: {}",
+                        dep_name,
+                        section,
+                        manifest_path.display(),
+                        mismatch
+                    ),
+                    None => sections.push(section),
+                }
+            }
+            MismatchPolicy::Skip => {
+                info!(
+                    "Leaving '{}' in [{}] of '{}' alone: {}",
+                    dep_name,
+                    section,
+                    manifest_path.display(),
+                    mismatch
+                );
+            }
+        }
+    }
+
+    Ok(sections)
+}
+
+/// With `--on-mismatch update`, raises the existing workspace entry of
+/// `dep_name` to the highest version of the member `specs` which differ from
+/// it. It is never lowered, members inheriting it would go down as well.
+/// Happens once before any member switches, so the outcome doesn't depend
+/// on the order of the members
+fn update_workspace_entry(doc: &mut DocumentMut, dep_name: &str, specs: &[Item]) {
+    let Some(workspace_item) = doc
+        .get_mut("workspace")
+        .and_then(|ws| ws.get_mut("dependencies"))
+        .and_then(|deps| deps.get_mut(dep_name))
+    else {
+        return;
+    };
+    let workspace_version = dependency::get_version(workspace_item);
+    let differing: Vec<Item> = specs
+        .iter()
+        .filter(|spec| {
+            let version = dependency::get_version(spec);
+            version.is_some() && version != workspace_version
+        })
+        .cloned()
+        .collect();
+    if differing.is_empty() {
+        return;
+    }
+    let picked = dependency::pick(&differing, VersionStrategy::Highest);
+    let lowest = |version: &str| {
+        VersionReq::parse(version)
+            .ok()
+            .map(|req| version::lowest_version(&req))
+    };
+    if let Some(version) = dependency::get_version(&differing[picked]) {
+        // Members inheriting the entry already must not go down with it
+        if let (Some(picked), Some(current)) = (
+            lowest(&version),
+            workspace_version.as_deref().and_then(lowest),
+        ) {
+            if picked <= current {
+                return;
+            }
+        }
+        info!(
+            "Updating workspace entry of '{}' to version '{}'",
+            dep_name, version
+        );
+        dependency::set_version(workspace_item, &version);
+    }
+}
+
+/// Rewrites the requirement of `dep_name` which `users` declare themselves
+/// to `version`, reporting every change
+fn align_members(
+    manifests: &mut Manifests,
+    users: &[&str],
+    package_manifest_paths: &HashMap<String, PathBuf>,
+    dep_name: &str,
+    version: &str,
+    events: &Events,
+    embedded: bool,
+) -> Result<()> {
+    for user in users {
+        let manifest_path = &package_manifest_paths[*user];
+        let member = manifests.get_mut(manifest_path)?;
+        for (section, old) in align_member(&mut member.doc, dep_name, version) {
+            if events.enabled() {
+                events.emit(Event::Aligned {
+                    dependency: dep_name,
+                    manifest: manifest_path,
+                    section,
+                    from: &old,
+                    to: version,
+                });
+            } else {
+                let line = format!(
+                    "{}: [{}] {} '{}' -> '{}'",
+                    manifest_path.display(),
+                    section,
+                    dep_name,
+                    old,
+                    version
+                );
+                match embedded {
+                    true => info!("{}", line),
+                    false => println!("{}", line),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sets the requirement of `dep_name` to `version` in every section which
+/// declares it with another one. Returns the sections with their old
+/// requirement
+fn align_member(
+    doc: &mut DocumentMut,
+    dep_name: &str,
+    version: &str,
+) -> Vec<(&'static str, String)> {
+    let mut changes = Vec::new();
+    for section in dependency::DEPENDENCY_SECTIONS {
+        let Some(item) = doc.get_mut(section).and_then(|deps| deps.get_mut(dep_name)) else {
+            continue;
+        };
+        // Inherited specs and path or git dependencies have nothing to align
+        if dependency::is_workspace_inherited(item) {
+            continue;
+        }
+        let Some(old) = dependency::get_version(item) else {
+            continue;
+        };
+        if old != version {
+            dependency::set_version(item, version);
+            changes.push((section, old));
+        }
+    }
+    changes
+}
+
+fn clean_inherited_dependencies(manifests: &mut Manifests, manifest_path: &Path) -> Result<()> {
+    let manifest = manifests.get_mut(manifest_path)?;
+    let removed = dependency::strip_inherited_conflicts(&mut manifest.doc);
+    for (section, dep_name, key) in removed {
+        warn!(
+            "Removed '{}' from '{}' in [{}] of '{}', it is inherited from the workspace",
+            key,
+            dep_name,
+            section,
+            manifest_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Switches `dep_name` to `workspace = true` in the given sections of a
+/// member. Each section keeps its own features
+pub fn update_member_to_use_workspace(doc: &mut DocumentMut, dep_name: &str, sections: &[&str]) {
+    for table_name in sections {
+        if let Some(dep_table) = doc.get_mut(table_name).and_then(Item::as_table_like_mut) {
+            if let Some(dep_item) = dep_table.get(dep_name) {
+                let inherited = dependency::inherited_spec(dep_item);
+                dep_table.insert(dep_name, Item::Value(inherited.into()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_file;
+    use anyhow::Result;
+    use clap::Parser;
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use tempfile::TempDir;
+    use toml_edit::{Item, Table, Value};
+
+    #[test]
+    fn test_get_workspace_dependencies() {
+        let mut doc = DocumentMut::default();
+        let mut workspace_table = Table::new();
+        let mut deps_table = Table::new();
+        deps_table.insert("dep1", Item::Value(Value::from("1.0.0")));
+        workspace_table.insert("dependencies", Item::Table(deps_table));
+        doc.insert("workspace", Item::Table(workspace_table));
+
+        let workspace_deps = get_workspace_dependencies(&doc);
+        assert_eq!(workspace_deps.len(), 1);
+        assert!(workspace_deps.contains_key("dep1"));
+    }
+
+    #[test]
+    fn test_add_dependency_to_workspace() -> Result<()> {
+        let mut doc = DocumentMut::default();
+        let temp_dir = TempDir::new()?;
+        let manifest_path = temp_dir.path().join("test_package/Cargo.toml");
+
+        // Create the directory structure and a dummy Cargo.toml file with dep1
+        let cargo_toml_content = r#"
+            [dependencies]
+            dep1 = "1.0.0"
+        "#;
+        write_file(&manifest_path, cargo_toml_content)?;
+
+        let mut package_manifest_paths = HashMap::new();
+        package_manifest_paths.insert("test_package".to_string(), manifest_path.clone());
+
+        let mut users = HashSet::new();
+        users.insert("test_package".to_string());
+
+        add_dependency_to_workspace(
+            &mut doc,
+            "dep1",
+            temp_dir.path(),
+            &users,
+            &package_manifest_paths,
+            &mut Manifests::default(),
+            &Opt::parse_from(["cargo-consolidate"]),
+            None,
+        )?;
+
+        let workspace_deps = get_workspace_dependencies(&doc);
+        assert!(workspace_deps.contains_key("dep1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_workspace_dependency_layout() -> Result<()> {
+        let mut doc = r#"[workspace.dependencies]
+# async runtime
+tokio = "1"
+serde = { version = "1", features = [
+    "derive",
+] }
+
+[workspace.dependencies.anyhow]
+version = "1"
+"#
+        .parse::<DocumentMut>()?;
+        let spec = |spec: &str| -> Result<Item> {
+            Ok(format!("dep = {}", spec).parse::<DocumentMut>()?["dep"].clone())
+        };
+
+        let tokio = r#"{ version = "1.40", features = ["macros", "rt-multi-thread", "sync"] }"#;
+        insert_workspace_dependency(&mut doc, "tokio", spec(tokio)?, 60);
+        insert_workspace_dependency(
+            &mut doc,
+            "serde",
+            spec(r#"{ version = "1.0.200", features = ["derive", "rc"] }"#)?,
+            100,
+        );
+        insert_workspace_dependency(&mut doc, "anyhow", spec(r#"{ version = "1.0.80" }"#)?, 100);
+        assert_eq!(
+            doc.to_string(),
+            r#"[workspace.dependencies]
+serde = { version = "1.0.200", features = [
+    "derive",
+    "rc",
+] }
+
+# async runtime
+[workspace.dependencies.tokio]
+version = "1.40"
+features = [
+    "macros",
+    "rt-multi-thread",
+    "sync",
+]
+
+[workspace.dependencies.anyhow]
+version = "1.0.80"
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
     fn test_update_member_to_use_workspace() -> Result<()> {
+        let mut doc = r#"
+            [dependencies]
+            dep1 = "1.0.0"
+        "#
+        .parse::<DocumentMut>()?;
+
+        update_member_to_use_workspace(&mut doc, "dep1", &dependency::DEPENDENCY_SECTIONS);
+
+        assert!(doc.to_string().contains("workspace = true"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failures_keep_going() -> Result<()> {
+        let mut failures = Failures::default();
+
+        assert!(failures.record(true, "ok".to_string(), Ok(()))?);
+        assert!(!failures.record(
+            true,
+            "Failed to update 'dep1'".to_string(),
+            Err(anyhow::anyhow!("broken manifest"))
+        )?);
+
+        let err = failures.into_result().unwrap_err().to_string();
+        assert!(err.contains("1 item(s) could not be processed"));
+        assert!(err.contains("Failed to update 'dep1': broken manifest"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failures_abort_without_keep_going() {
+        let mut failures = Failures::default();
+        let result = failures.record(
+            false,
+            "Failed to update 'dep1'".to_string(),
+            Err(anyhow::anyhow!("broken manifest")),
+        );
+
+        assert!(result.is_err());
+        assert!(failures.into_result().is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_component() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut path = PathBuf::from("/home/user");
+        path.push(OsStr::from_bytes(b"caf\xe9"));
+        path.push("Cargo.toml");
+
+        assert_eq!(non_utf8_component(&path), Some("caf\u{FFFD}".to_string()));
+        assert_eq!(non_utf8_component(Path::new("/home/user/Cargo.toml")), None);
+    }
+
+    #[test]
+    fn test_find_orphans() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        for (path, content) in [
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"crates/a\"]\nexclude = [\"examples\"]\n",
+            ),
+            ("crates/a/Cargo.toml", "[package]\nname = \"a\"\n"),
+            ("crates/b/Cargo.toml", "[package]\nname = \"b\"\n"),
+            ("examples/demo/Cargo.toml", "[package]\nname = \"demo\"\n"),
+            ("nested/Cargo.toml", "[workspace]\nmembers = [\"c\"]\n"),
+            ("nested/c/Cargo.toml", "[package]\nname = \"c\"\n"),
+            ("vendor/serde/Cargo.toml", "[package]\nname = \"serde\"\n"),
+            ("vendor/serde/.cargo-checksum.json", "{}"),
+            // Skipped with a warning instead of failing the run
+            ("broken/Cargo.toml", "[package\nname = \"broken\"\n"),
+        ] {
+            write_file(root.join(path), content)?;
+        }
+
+        let member = root.join("crates/a/Cargo.toml");
+        let members = HashSet::from([member.as_path()]);
+        let excludes = get_workspace_excludes(&root_doc(root)?, root);
+        assert_eq!(find_orphans(root, &members, &excludes)?, vec!["crates/b"]);
+
+        let mut doc = root_doc(root)?;
+        add_workspace_members(&mut doc, &["crates/b".to_string()])?;
+        assert!(doc
+            .to_string()
+            .contains(r#"members = ["crates/a", "crates/b"]"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_add_orphans() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let root_manifest = "[workspace]\nmembers = [\"a\"]\n";
+        for (path, content) in [
+            ("Cargo.toml", root_manifest),
+            ("a/Cargo.toml", "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1.0.100\"\n"),
+            ("a/src/lib.rs", ""),
+            ("b/Cargo.toml", "[package]\nname = \"b\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1.0.100\"\n"),
+            ("b/src/lib.rs", ""),
+            ("policy.toml", "[allowed]\nserde = \">=1.0.150\"\n"),
+        ] {
+            write_file(root.join(path), content)?;
+        }
+        let manifest_path = root.join("Cargo.toml");
+        let policy_path = root.join("policy.toml");
+        let opt = |policy: bool| {
+            let mut args = vec![
+                "cargo-consolidate",
+                "--no-cargo",
+                "--add-orphans",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+            ];
+            if policy {
+                args.extend([
+                    "--policy",
+                    policy_path.to_str().unwrap(),
+                    "--on-policy-violation",
+                    "error",
+                ]);
+            }
+            Opt::parse_from(args)
+        };
+
+        // A failing run leaves the root manifest as it was
+        assert!(consolidate_dependencies(&opt(true)).is_err());
+        assert_eq!(fs::read_to_string(&manifest_path)?, root_manifest);
+
+        consolidate_dependencies(&opt(false))?;
+        let manifest = fs::read_to_string(&manifest_path)?;
+        assert!(manifest.contains(r#"members = ["a", "b"]"#));
+        assert!(manifest.contains("[workspace.dependencies]\nserde = \"1.0.100\"\n"));
+        Ok(())
+    }
+
+    fn root_doc(root: &Path) -> Result<DocumentMut> {
+        Ok(fs::read_to_string(root.join("Cargo.toml"))?.parse()?)
+    }
+
+    #[test]
+    fn test_set_resolver() -> Result<()> {
+        let mut doc = "[workspace]\nmembers = []\n".parse::<DocumentMut>()?;
+        assert!(set_resolver(&mut doc, "2"));
+        assert_eq!(
+            doc.to_string(),
+            "[workspace]\nmembers = []\nresolver = \"2\"\n"
+        );
+        assert!(!set_resolver(&mut doc, "2"));
+        assert!(set_resolver(&mut doc, "3"));
+        assert!(doc.to_string().contains("resolver = \"3\""));
+        assert!(!set_resolver(&mut doc, "2"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let doc = r#"
+            [workspace]
+            members = ["crates/*"]
+            exclude = ["crates/skip"]
+        "#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let excludes = get_workspace_excludes(&doc, Path::new("/ws"));
+
+        assert!(is_excluded(
+            Path::new("/ws/crates/skip/Cargo.toml"),
+            &excludes
+        ));
+        assert!(!is_excluded(
+            Path::new("/ws/crates/skipper/Cargo.toml"),
+            &excludes
+        ));
+        assert!(!is_excluded(
+            Path::new("/ws/crates/a/Cargo.toml"),
+            &excludes
+        ));
+    }
+
+    #[test]
+    fn test_consolidate_skips_excluded_members() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\", \"shared\"]\nexclude = [\"crates/skip\"]\n",
+        )?;
+        write_file(
+            root.join("shared/Cargo.toml"),
+            "[package]\nname = \"shared\"\nversion = \"0.1.0\"\n",
+        )?;
+        write_file(root.join("shared/src/lib.rs"), "")?;
+        for name in ["a", "b", "skip"] {
+            write_file(
+                root.join(format!("crates/{}/Cargo.toml", name)),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\nshared = {{ path = \"../../shared\" }}\n",
+                    name
+                ),
+            )?;
+            write_file(root.join(format!("crates/{}/src/lib.rs", name)), "")?;
+        }
+        let skipped = fs::read_to_string(root.join("crates/skip/Cargo.toml"))?;
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        consolidate_dependencies(&opt)?;
+
+        let member = fs::read_to_string(root.join("crates/a/Cargo.toml"))?;
+        assert!(member.contains("shared = { workspace = true }"));
+        assert_eq!(
+            fs::read_to_string(root.join("crates/skip/Cargo.toml"))?,
+            skipped
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_consolidate_skips_external_members() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("ws");
+
+        let shared = root.join("shared");
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\", \"shared\"]\n",
+        )?;
+        write_file(
+            shared.join("Cargo.toml"),
+            "[package]\nname = \"shared\"\nversion = \"0.1.0\"\n",
+        )?;
+        write_file(shared.join("src/lib.rs"), "")?;
+        // A sibling checkout, linked in as a member
+        let sibling = temp_dir.path().join("sibling");
+        for (name, dir) in [("a", root.join("crates/a")), ("ext", sibling.clone())] {
+            write_file(
+                dir.join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\nshared = {{ path = {:?} }}\n",
+                    name,
+                    shared.to_str().unwrap()
+                ),
+            )?;
+            write_file(dir.join("src/lib.rs"), "")?;
+        }
+        std::os::unix::fs::symlink(&sibling, root.join("crates/ext"))?;
+        let external = fs::read_to_string(sibling.join("Cargo.toml"))?;
+
+        let manifest_path = root.join("Cargo.toml");
+        let consolidate = |extra: &[&str]| {
+            let mut args = vec![
+                "cargo-consolidate",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+                "--group-all",
+            ];
+            args.extend(extra);
+            consolidate_dependencies(&Opt::parse_from(args))
+        };
+        consolidate(&[])?;
+        let member = fs::read_to_string(root.join("crates/a/Cargo.toml"))?;
+        assert!(member.contains("shared = { workspace = true }"));
+        assert_eq!(fs::read_to_string(sibling.join("Cargo.toml"))?, external);
+
+        consolidate(&["--allow-external"])?;
+        let member = fs::read_to_string(sibling.join("Cargo.toml"))?;
+        assert!(member.contains("shared = { workspace = true }"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_preflight_ignores_symlinks_left_alone() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        for (path, content) in [
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n",
+            ),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\n",
+            ),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\n",
+            ),
+            (
+                "shared/c.toml",
+                "[package]\nname = \"c\"\nversion = \"0.1.0\"\n\n[dependencies]\nrand = \"0.8\"\n",
+            ),
+        ] {
+            write_file(root.join(path), content)?;
+        }
+        for member in ["a", "b", "c"] {
+            write_file(root.join(member).join("src/lib.rs"), "")?;
+        }
+        // Nothing of c is consolidated, so its symlink is never written
+        std::os::unix::fs::symlink(root.join("shared/c.toml"), root.join("c/Cargo.toml"))?;
+
+        consolidate_dependencies(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--symlinks",
+            "refuse",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]))?;
+        assert!(
+            fs::read_to_string(root.join("a/Cargo.toml"))?.contains("serde = { workspace = true }")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_near_misses() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n",
+        )?;
+        for (member, deps) in [
+            ("a", "serde = \"1\"\nrand = \"0.8\"\n"),
+            ("b", "serde = \"1\"\n"),
+        ] {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\n{}",
+                    member, deps
+                ),
+            )?;
+        }
+
+        let summary_path = root.join("summary.md");
+        consolidate_dependencies(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--write-summary",
+            summary_path.to_str().unwrap(),
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]))?;
+        let summary = fs::read_to_string(&summary_path)?;
+        assert!(
+            summary.ends_with("Used by a single member and left as they are:\n\n- `rand` (a)\n"),
+            "{}",
+            summary
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_nothing_to_consolidate() -> Result<()> {
         let temp_dir = TempDir::new()?;
-        let manifest_path =
-            Utf8PathBuf::from_path_buf(temp_dir.path().join("test_package/Cargo.toml")).unwrap();
-        let dep_name = "dep1";
+        let root = temp_dir.path();
 
-        // Mock the Cargo.toml content and fs operations for testing
-        let cargo_toml_content = r#"
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n",
+        )?;
+        for (member, dep) in [("a", "serde"), ("b", "log")] {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\n{} = \"1\"\n",
+                    member, dep
+                ),
+            )?;
+        }
+
+        let summary_path = root.join("summary.md");
+        consolidate_dependencies(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--write-summary",
+            summary_path.to_str().unwrap(),
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]))?;
+        let summary = fs::read_to_string(&summary_path)?;
+        assert!(summary.contains("Nothing changed."), "{}", summary);
+        assert!(!summary.contains("Cargo.toml"), "{}", summary);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_command() {
+        let cargo = CargoOptions {
+            cargo: Some(PathBuf::from("/opt/toolchain/bin/cargo")),
+            offline: true,
+            ..Default::default()
+        };
+        let cmd = metadata_command(None, &cargo).cargo_command();
+        assert_eq!(cmd.get_program(), "/opt/toolchain/bin/cargo");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert!(args.contains(&"--no-deps".as_ref()));
+        assert!(args.contains(&"--offline".as_ref()));
+
+        let cargo = CargoOptions {
+            full_metadata: true,
+            ..Default::default()
+        };
+        let cmd = metadata_command(None, &cargo).cargo_command();
+        assert!(!cmd.get_args().any(|arg| arg == "--no-deps"));
+    }
+
+    #[test]
+    fn test_consolidate_from_member_manifest() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\", \"shared\"]\n",
+        )?;
+        write_file(
+            root.join("shared/Cargo.toml"),
+            "[package]\nname = \"shared\"\nversion = \"0.1.0\"\n",
+        )?;
+        for name in ["a", "b"] {
+            write_file(
+                root.join(format!("{}/Cargo.toml", name)),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\nshared = {{ path = \"../shared\" }}\n",
+                    name
+                ),
+            )?;
+        }
+        for dir in ["a", "b", "shared"] {
+            write_file(root.join(format!("{}/src/lib.rs", dir)), "")?;
+        }
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--manifest-path",
+            root.join("a/Cargo.toml").to_str().unwrap(),
+        ]);
+        consolidate_dependencies(&opt)?;
+
+        let root_manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(root_manifest.contains("[workspace.dependencies]"));
+        let member = fs::read_to_string(root.join("a/Cargo.toml"))?;
+        assert!(!member.contains("[workspace"));
+        assert!(member.contains("shared = { workspace = true }"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_workspace_packages() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(root.join("Cargo.toml"), "[workspace]\nmembers = [\"*\"]\n")?;
+        for name in ["a", "b"] {
+            write_file(
+                root.join(name).join("Cargo.toml"),
+                &format!("[package]\nname = \"{}\"\n", name),
+            )?;
+        }
+
+        let mut metadata = crate::metadata::from_manifests(Some(root))?;
+        metadata.packages.reverse();
+        let names: Vec<_> = workspace_packages(&metadata)?
+            .iter()
+            .map(|package| package.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        metadata.packages.pop();
+        assert!(workspace_packages(&metadata).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_root_package() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\nanyhow = \"1\"\n\n[workspace]\nmembers = [\"a\"]\n",
+        )?;
+        write_file(
+            root.join("a/Cargo.toml"),
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nanyhow = \"1\"\n",
+        )?;
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        consolidate_dependencies(&opt)?;
+
+        // The root package's own edits survive writing the root manifest
+        let root_manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(root_manifest.contains("[dependencies]\nanyhow = { workspace = true }\n"));
+        assert!(root_manifest.contains("[workspace.dependencies]\nanyhow = \"1\"\n"));
+        let member = fs::read_to_string(root.join("a/Cargo.toml"))?;
+        assert!(member.contains("anyhow = { workspace = true }"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_root_package_rename() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\njwt = { package = \"jsonwebtoken\", version = \"9\", optional = true }\n\n\
+             [features]\nauth = [\"dep:jwt\"]\n\n\
+             [workspace]\nmembers = [\"a\"]\n",
+        )?;
+        write_file(
+            root.join("a/Cargo.toml"),
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\njsonwebtoken = \"9\"\n",
+        )?;
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--unify-alias",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        consolidate_dependencies(&opt)?;
+
+        // [features] of the root package follow the rename
+        let root_manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(root_manifest
+            .contains("[dependencies]\njsonwebtoken = { workspace = true, optional = true }\n"));
+        assert!(root_manifest.contains("[features]\nauth = [\"dep:jsonwebtoken\"]\n"));
+        assert!(root_manifest.contains("[workspace.dependencies]\njsonwebtoken = \"9\"\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_unify_alias_only_consolidated() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.metadata.consolidate]\nmin-members = 3\n",
+        )?;
+        let a = "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\njwt = { package = \"jsonwebtoken\", version = \"9\" }\n";
+        write_file(root.join("a/Cargo.toml"), a)?;
+        write_file(
+            root.join("b/Cargo.toml"),
+            "[package]\nname = \"b\"\nversion = \"0.1.0\"\n\n[dependencies]\njsonwebtoken = \"9\"\n",
+        )?;
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--unify-alias",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        consolidate_dependencies(&opt)?;
+
+        // Two users stay below min-members, so nothing is renamed
+        assert_eq!(fs::read_to_string(root.join("a/Cargo.toml"))?, a);
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_path_and_version() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )?;
+        let members = [
+            ("a", "foo = { path = \"../foo\" }"),
+            ("b", "foo = { version = \"0.4\", path = \"../foo\" }"),
+            ("c", "foo = \"0.4\""),
+            ("foo", ""),
+        ];
+        for (name, deps) in members {
+            write_file(
+                root.join("crates").join(name).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.4.1\"\n\n[dependencies]\n{}\n",
+                    name, deps
+                ),
+            )?;
+        }
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        consolidate_dependencies(&opt)?;
+
+        // Whichever member comes first, the entry has both keys
+        let root_manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(root_manifest.contains("foo = { path = \"crates/foo\", version = \"0.4\" }"));
+        for name in ["a", "b", "c"] {
+            let member = fs::read_to_string(root.join("crates").join(name).join("Cargo.toml"))?;
+            assert!(member.contains("foo = { workspace = true }"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_since() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n",
+        )?;
+        for (member, deps) in [
+            ("a", "serde = \"1\"\nlog = \"0.4\"\n"),
+            ("b", "serde = \"1\"\n"),
+            ("c", "log = \"0.4\"\n"),
+        ] {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\n\n[dependencies]\n{}",
+                    member, deps
+                ),
+            )?;
+        }
+        git::git(root, &["init", "--quiet"])?;
+        git::git(root, &["add", "."])?;
+        git::git(
+            root,
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                "init",
+            ],
+        )?;
+        fs::write(
+            root.join("b/Cargo.toml"),
+            "[package]\nname = \"b\"\nversion = \"0.2.0\"\n\n[dependencies]\nserde = \"1\"\n",
+        )?;
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--since",
+            "HEAD",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        consolidate_dependencies(&opt)?;
+
+        // `a` still counts as a user of serde, but only `b` changed
+        let root_manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(root_manifest.contains("[workspace.dependencies]\nserde = \"1\"\n"));
+        assert!(!root_manifest.contains("log"));
+        assert!(
+            fs::read_to_string(root.join("b/Cargo.toml"))?.contains("serde = { workspace = true }")
+        );
+        assert!(fs::read_to_string(root.join("a/Cargo.toml"))?.contains("serde = \"1\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_keeps_pins() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.dependencies]\nserde = \"1\"\n",
+        )?;
+        for (member, serde) in [("a", "=1.0.100"), ("b", "1")] {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\n\n[dependencies]\nserde = \"{}\"\n",
+                    member, serde
+                ),
+            )?;
+        }
+
+        let opt = |allow_loosen: bool| {
+            let manifest_path = root.join("Cargo.toml");
+            let mut args = vec![
+                "cargo-consolidate",
+                "--no-cargo",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+            ];
+            if allow_loosen {
+                args.push("--allow-loosen");
+            }
+            Opt::parse_from(args)
+        };
+        consolidate_dependencies(&opt(false))?;
+        assert!(fs::read_to_string(root.join("a/Cargo.toml"))?.contains("serde = \"=1.0.100\""));
+        assert!(
+            fs::read_to_string(root.join("b/Cargo.toml"))?.contains("serde = { workspace = true }")
+        );
+
+        consolidate_dependencies(&opt(true))?;
+        assert!(
+            fs::read_to_string(root.join("a/Cargo.toml"))?.contains("serde = { workspace = true }")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_rules() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n",
+        )?;
+        fs::write(
+            root.join("consolidate.toml"),
+            "[rules]\ntokio = { strategy = \"highest\", hoist-features = true }\nopenssl = { skip = true }\n",
+        )?;
+        let members = [
+            (
+                "a",
+                "{ version = \"1.38\", features = [\"rt\", \"macros\"] }",
+            ),
+            ("b", "{ version = \"1.40\", features = [\"rt\", \"net\"] }"),
+        ];
+        for (member, tokio) in members {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\n\n[dependencies]\ntokio = {}\nopenssl = \"0.10\"\n",
+                    member, tokio
+                ),
+            )?;
+        }
+
+        let manifest_path = root.join("Cargo.toml");
+        consolidate_dependencies(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+        ]))?;
+
+        let root_manifest = fs::read_to_string(&manifest_path)?;
+        assert!(root_manifest.contains("tokio = { version = \"1.40\", features = [\"rt\"] }"));
+        assert!(!root_manifest.contains("openssl"));
+        let a = fs::read_to_string(root.join("a/Cargo.toml"))?;
+        assert!(a.contains("tokio = { workspace = true, features = [\"macros\"] }"));
+        assert!(a.contains("openssl = \"0.10\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_build_dependencies() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"api\", \"proto\", \"web\"]\n",
+        )?;
+        let members = [
+            (
+                "api",
+                "[build-dependencies]\nprost = { version = \"0.12\", features = [\"prost-derive\", \"std\"] }\n",
+            ),
+            (
+                "proto",
+                "[dependencies]\nprost = { version = \"0.12\", features = [\"std\"] }\n\n\
+                 [build-dependencies]\nprost = { version = \"0.12\", features = [\"prost-derive\", \"std\"] }\n",
+            ),
+            ("web", "[dependencies]\nprost = { version = \"0.12\", features = [\"std\"] }\n"),
+        ];
+        for (member, deps) in members {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!("[package]\nname = \"{}\"\n\n{}", member, deps),
+            )?;
+        }
+
+        let manifest_path = root.join("Cargo.toml");
+        consolidate_dependencies(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+        ]))?;
+
+        // prost-derive is only for the build scripts
+        assert!(fs::read_to_string(&manifest_path)?
+            .contains("prost = { version = \"0.12\", features = [\"std\"] }"));
+        let proto = fs::read_to_string(root.join("proto/Cargo.toml"))?;
+        assert!(proto.contains(
+            "[dependencies]\nprost = { workspace = true, features = [\"std\"] }\n\n\
+             [build-dependencies]\nprost = { workspace = true, features = [\"prost-derive\", \"std\"] }\n"
+        ));
+        let web = fs::read_to_string(root.join("web/Cargo.toml"))?;
+        assert!(web.contains("prost = { workspace = true, features = [\"std\"] }"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_optional_dependencies() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\", \"c\", \"d\"]\n",
+        )?;
+        let members = [
+            (
+                "a",
+                "[dependencies]\nrand = { version = \"0.7\", optional = true }\n\n\
+                 [features]\nsmall = [\"dep:rand\", \"rand?/small_rng\"]\n",
+            ),
+            // Without `dep:`, the dependency comes with a feature `rand`
+            (
+                "b",
+                "[dependencies]\nrand = { version = \"0.7\", optional = true }\n\n\
+                 [features]\nfull = [\"rand\"]\n",
+            ),
+            ("c", "[dependencies]\nrand = \"0.8\"\n"),
+            ("d", "[dependencies]\nrand = \"0.8\"\n"),
+        ];
+        for (member, deps) in members {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!("[package]\nname = \"{}\"\n\n{}", member, deps),
+            )?;
+        }
+
+        let manifest_path = root.join("Cargo.toml");
+        consolidate_dependencies(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--allow-major-split",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+        ]))?;
+
+        // Optional is up to each member, never the workspace entry
+        let root_manifest = fs::read_to_string(&manifest_path)?;
+        assert!(root_manifest.contains("rand_0_7 = { version = \"0.7\", package = \"rand\" }\n"));
+        assert_eq!(
+            fs::read_to_string(root.join("a/Cargo.toml"))?,
+            "[package]\nname = \"a\"\n\n\
+             [dependencies]\nrand_0_7 = { workspace = true, optional = true }\n\n\
+             [features]\nsmall = [\"dep:rand_0_7\", \"rand_0_7?/small_rng\"]\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("b/Cargo.toml"))?,
+            "[package]\nname = \"b\"\n\n\
+             [dependencies]\nrand_0_7 = { workspace = true, optional = true }\n\n\
+             [features]\nfull = [\"rand\"]\nrand = [\"dep:rand_0_7\"]\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_align_only() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let root_content = "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n";
+        fs::write(root.join("Cargo.toml"), root_content)?;
+        for (member, deps) in [
+            ("a", "serde = \"1.0.100\"\n"),
+            (
+                "b",
+                "serde = { version = \"1.0.200\", features = [\"derive\"] }\n",
+            ),
+            (
+                "c",
+                "rand = \"0.8\"\n\n[dev-dependencies]\nserde = \"1.0.150\"\n",
+            ),
+        ] {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\n\n[dependencies]\n{}",
+                    member, deps
+                ),
+            )?;
+        }
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--align-only",
+            "--version-strategy",
+            "highest",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        consolidate_dependencies(&opt)?;
+
+        assert_eq!(fs::read_to_string(root.join("Cargo.toml"))?, root_content);
+        assert!(fs::read_to_string(root.join("a/Cargo.toml"))?.contains("serde = \"1.0.200\""));
+        assert!(fs::read_to_string(root.join("b/Cargo.toml"))?
+            .contains("serde = { version = \"1.0.200\", features = [\"derive\"] }"));
+        let c = fs::read_to_string(root.join("c/Cargo.toml"))?;
+        assert!(c.contains("[dev-dependencies]\nserde = \"1.0.200\""));
+        assert!(c.contains("rand = \"0.8\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_skip_unused() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n",
+        )?;
+        for (member, source) in [
+            ("a", "use serde::Serialize;\n"),
+            ("b", "use serde::Serialize;\n"),
+            ("c", "fn main() {}\n"),
+        ] {
+            write_file(
+                root.join(member).join("Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{}\"\n\n[dependencies]\nserde = \"1\"\n",
+                    member
+                ),
+            )?;
+            write_file(root.join(member).join("src/lib.rs"), source)?;
+        }
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--skip-unused",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        consolidate_dependencies(&opt)?;
+
+        assert!(fs::read_to_string(root.join("Cargo.toml"))?.contains("serde = \"1\""));
+        assert!(
+            fs::read_to_string(root.join("a/Cargo.toml"))?.contains("serde = { workspace = true }")
+        );
+        // Flagged for removal instead of being rewritten
+        assert!(fs::read_to_string(root.join("c/Cargo.toml"))?.contains("serde = \"1\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_single_member() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let manifest =
+            "[package]\nname = \"single\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\n";
+        fs::write(root.join("Cargo.toml"), manifest)?;
+        write_file(root.join("src/lib.rs"), "")?;
+
+        let manifest_path = root.join("Cargo.toml");
+        let opt = |group_all: bool| {
+            let mut args = vec![
+                "cargo-consolidate",
+                "--no-cargo",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+            ];
+            if group_all {
+                args.push("--group-all");
+            }
+            Opt::parse_from(args)
+        };
+        let err = consolidate_dependencies(&opt(false)).unwrap_err();
+        assert!(err.is::<TooFewMembers>());
+        assert_eq!(crate::exit_code(&err), 3);
+        assert!(!format!("{:#}", err).contains("phase"));
+        assert_eq!(fs::read_to_string(root.join("Cargo.toml"))?, manifest);
+
+        consolidate_dependencies(&opt(true))?;
+        let manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        assert!(manifest.contains("serde = { workspace = true }\n"));
+        assert!(manifest.contains("[workspace.dependencies]\nserde = \"1\"\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_single_member_with_work_left() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\"]\n\n[workspace.dependencies]\nserde = \"1\"\n",
+        )?;
+        write_file(root.join("a/src/lib.rs"), "")?;
+        fs::write(
+            root.join("a/Cargo.toml"),
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\n",
+        )?;
+
+        // The member inherits the existing entry
+        let manifest_path = root.join("Cargo.toml");
+        let manifest_path = manifest_path.to_str().unwrap();
+        consolidate_dependencies(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            manifest_path,
+        ]))?;
+        assert!(fs::read_to_string(root.join("a/Cargo.toml"))?
+            .contains("serde = { workspace = true }\n"));
+
+        // Options for the root manifest apply without any entries
+        fs::write(root.join("Cargo.toml"), "[workspace]\nmembers = [\"a\"]\n")?;
+        consolidate_dependencies(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--set-resolver",
+            "2",
+            "--manifest-path",
+            manifest_path,
+        ]))?;
+        assert!(fs::read_to_string(root.join("Cargo.toml"))?.contains("resolver = \"2\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_reports_location_and_phase() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n",
+        )?;
+        for name in ["a", "b"] {
+            write_file(
+                root.join(name).join("Cargo.toml"),
+                &format!("[package]\nname = \"{}\"\n", name),
+            )?;
+        }
+        fs::write(
+            root.join("consolidate.toml"),
+            "ignore = [\"fuzz/*\"]\nmin-members = \"two\"\n",
+        )?;
+
+        let opt = Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]);
+        let err = consolidate_dependencies(&opt).unwrap_err();
+        let chain: Vec<String> = err.chain().map(ToString::to_string).collect();
+        assert_eq!(chain[0], "Failed in the configuration phase");
+        assert!(chain[2].contains("consolidate.toml:2:15\n"));
+        assert!(chain[2].ends_with("2 | min-members = \"two\"\n  |               ^^^^^"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consolidate_major_split() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let root_content = "[workspace]\nmembers = [\"a\", \"b\", \"c\", \"d\"]\n";
+        let write = || -> Result<()> {
+            fs::write(root.join("Cargo.toml"), root_content)?;
+            for (member, syn) in [("a", "1"), ("b", "1.0.100"), ("c", "2"), ("d", "2.0.50")] {
+                write_file(
+                    root.join(member).join("Cargo.toml"),
+                    &format!(
+                        "[package]\nname = \"{}\"\n\n[dependencies]\nsyn = \"{}\"\n",
+                        member, syn
+                    ),
+                )?;
+            }
+            Ok(())
+        };
+        let manifest_path = root.join("Cargo.toml");
+        let args = ["cargo-consolidate", "--no-cargo", "--manifest-path"];
+
+        // Left alone by default
+        write()?;
+        let opt = Opt::parse_from(
+            args.iter()
+                .copied()
+                .chain([manifest_path.to_str().unwrap()]),
+        );
+        consolidate_dependencies(&opt)?;
+        assert_eq!(fs::read_to_string(&manifest_path)?, root_content);
+        assert!(fs::read_to_string(root.join("a/Cargo.toml"))?.contains("syn = \"1\""));
+
+        write()?;
+        let opt = Opt::parse_from(
+            args.iter()
+                .copied()
+                .chain([manifest_path.to_str().unwrap(), "--allow-major-split"]),
+        );
+        consolidate_dependencies(&opt)?;
+        let root_manifest = fs::read_to_string(&manifest_path)?;
+        assert!(root_manifest.contains("syn_1 = { package = \"syn\", version = \"1.0.100\" }"));
+        assert!(root_manifest.contains("syn_2 = { package = \"syn\", version = \"2.0.50\" }"));
+        assert!(
+            fs::read_to_string(root.join("b/Cargo.toml"))?.contains("syn_1 = { workspace = true }")
+        );
+        assert!(
+            fs::read_to_string(root.join("d/Cargo.toml"))?.contains("syn_2 = { workspace = true }")
+        );
+
+        // The alias of version 2 names another crate already
+        write()?;
+        let taken = format!(
+            "{}\n[workspace.dependencies]\nsyn_2 = \"0.3\"\n",
+            root_content
+        );
+        fs::write(&manifest_path, &taken)?;
+        consolidate_dependencies(&opt)?;
+        assert_eq!(fs::read_to_string(&manifest_path)?, taken);
+        assert!(fs::read_to_string(root.join("d/Cargo.toml"))?.contains("syn = \"2.0.50\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_group_force_include() {
+        let config: Config = toml_edit::de::from_str("force-include = [\"serde\"]").unwrap();
+
+        assert!(should_group("serde", 1, Class::Normal, false, &config).is_some());
+        assert!(should_group("rand", 1, Class::Normal, false, &config).is_none());
+        assert!(should_group("rand", 2, Class::Normal, false, &config).is_some());
+        assert!(should_group("rand", 1, Class::Normal, true, &config).is_some());
+    }
+
+    #[test]
+    fn test_should_group_classes() {
+        let config: Config = toml_edit::de::from_str(
+            "force-include = [\"insta\"]\n\n[dev-only]\nmin-members = 3\n\n[build-only]\nskip = true\n",
+        )
+        .unwrap();
+
+        assert!(should_group("rstest", 2, Class::Normal, false, &config).is_some());
+        assert!(should_group("rstest", 2, Class::DevOnly, false, &config).is_none());
+        assert!(should_group("rstest", 3, Class::DevOnly, false, &config).is_some());
+        assert!(should_group("cc", 5, Class::BuildOnly, false, &config).is_none());
+        assert!(should_group("insta", 1, Class::BuildOnly, false, &config).is_some());
+    }
+
+    #[test]
+    fn test_reconcile_member() -> Result<()> {
+        let manifest_path = Path::new("Cargo.toml");
+        let member = "[dependencies]\ndep1 = \"1.4\"\n".parse::<DocumentMut>()?;
+        let root = "[workspace.dependencies]\ndep1 = \"1.2\"\n";
+
+        let mut doc = root.parse::<DocumentMut>()?;
+        let reconcile = |doc: &mut DocumentMut, policy| {
+            reconcile_member(
+                doc,
+                &member,
+                manifest_path,
+                "dep1",
+                None,
+                policy,
+                &mut Explain::default(),
+                &Events::default(),
+            )
+        };
+        assert_eq!(
+            reconcile(&mut doc, MismatchPolicy::Warn)?,
+            vec!["dependencies"]
+        );
+        assert!(reconcile(&mut doc, MismatchPolicy::Error).is_err());
+        assert!(reconcile(&mut doc, MismatchPolicy::Skip)?.is_empty());
+        assert_eq!(doc.to_string(), root);
+
+        let specs = ["1.4", "1.3"].map(toml_edit::value);
+        update_workspace_entry(&mut doc, "dep1", &specs);
+        assert_eq!(
+            get_workspace_dependencies(&doc)["dep1"].as_str(),
+            Some("1.4")
+        );
+        assert_eq!(
+            reconcile(&mut doc, MismatchPolicy::Update)?,
+            vec!["dependencies"]
+        );
+
+        // A version doesn't make up for features the member lacks
+        let mut doc =
+            "[workspace.dependencies]\ndep1 = { version = \"1.2\", features = [\"std\"] }\n"
+                .parse::<DocumentMut>()?;
+        update_workspace_entry(&mut doc, "dep1", &[toml_edit::value("1.4")]);
+        assert!(reconcile(&mut doc, MismatchPolicy::Update)?.is_empty());
+        assert!(doc.to_string().contains("version = \"1.4\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_never_lowers_the_workspace_entry() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        write_file(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.dependencies]\nserde = \"1.5\"\n",
+        )?;
+        write_file(
+            root.join("a/Cargo.toml"),
+            "[package]\nname = \"a\"\n\n[dependencies]\nserde = \"1.2\"\n",
+        )?;
+        write_file(
+            root.join("b/Cargo.toml"),
+            "[package]\nname = \"b\"\n\n[dependencies]\nserde = { workspace = true }\n",
+        )?;
+
+        consolidate_dependencies(&Opt::parse_from([
+            "cargo-consolidate",
+            "--no-cargo",
+            "--on-mismatch",
+            "update",
+            "--manifest-path",
+            root.join("Cargo.toml").to_str().unwrap(),
+        ]))?;
+        // b inherits the entry, so it stays at 1.5 and a moves up to it
+        assert!(fs::read_to_string(root.join("Cargo.toml"))?.contains("serde = \"1.5\""));
+        assert!(
+            fs::read_to_string(root.join("a/Cargo.toml"))?.contains("serde = { workspace = true }")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconcile_member_sections_independently() -> Result<()> {
+        let mut member = r#"
             [dependencies]
-            dep1 = "1.0.0"
-        "#;
-        fs::create_dir_all(manifest_path.parent().unwrap())?;
-        fs::write(&manifest_path, cargo_toml_content)?;
+            foo = "1"
+
+            [dev-dependencies]
+            foo = { version = "1", features = ["test-util"] }
+
+            [build-dependencies]
+            foo = "0.9"
+        "#
+        .parse::<DocumentMut>()?;
+        let mut doc = "[workspace.dependencies]\nfoo = \"1\"\n".parse::<DocumentMut>()?;
+
+        let sections = reconcile_member(
+            &mut doc,
+            &member,
+            Path::new("Cargo.toml"),
+            "foo",
+            None,
+            MismatchPolicy::Skip,
+            &mut Explain::default(),
+            &Events::default(),
+        )?;
+        assert_eq!(sections, vec!["dependencies", "dev-dependencies"]);
+
+        update_member_to_use_workspace(&mut member, "foo", &sections);
+        let updated = member.to_string();
+        assert!(updated.contains("foo = { workspace = true }\n"));
+        assert!(updated.contains("foo = { workspace = true, features = [\"test-util\"] }"));
+        assert!(updated.contains("foo = \"0.9\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconcile_member_feature_references() -> Result<()> {
+        let mut member = r#"
+            [dependencies]
+            foo = { version = "1", optional = true }
+
+            [features]
+            extra = ["foo?/extra"]
+        "#
+        .parse::<DocumentMut>()?;
+        let root = "[workspace.dependencies]\nfoo = { version = \"1\", features = [\"extra\"] }\n";
+        let mut doc = root.parse::<DocumentMut>()?;
+
+        let mut reconcile = |enabled| {
+            reconcile_member(
+                &mut doc,
+                &member,
+                Path::new("Cargo.toml"),
+                "foo",
+                enabled,
+                MismatchPolicy::Skip,
+                &mut Explain::default(),
+                &Events::default(),
+            )
+        };
+        assert!(reconcile(None)?.is_empty());
+        let enabled = BTreeSet::from(["extra".to_string()]);
+        let sections = reconcile(Some(&enabled))?;
+        assert_eq!(sections, vec!["dependencies"]);
+
+        update_member_to_use_workspace(&mut member, "foo", &sections);
+        assert!(member
+            .to_string()
+            .contains("foo = { workspace = true, optional = true }"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_dependency_to_workspace_wildcard() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut package_manifest_paths = HashMap::new();
+        for (name, version) in [("a", "*"), ("b", "1.2")] {
+            let manifest_path = temp_dir.path().join(name).join("Cargo.toml");
+            write_file(
+                &manifest_path,
+                &format!("[dependencies]\ndep1 = \"{}\"\n", version),
+            )?;
+            package_manifest_paths.insert(name.to_string(), manifest_path);
+        }
+        let users: HashSet<_> = ["a".to_string(), "b".to_string()].into();
+        let lockfile = Lockfile::parse("[[package]]\nname = \"dep1\"\nversion = \"1.4.0\"\n")?;
+
+        let add = |wildcard: &str| -> Result<Option<String>> {
+            let mut doc = DocumentMut::default();
+            add_dependency_to_workspace(
+                &mut doc,
+                "dep1",
+                temp_dir.path(),
+                &users,
+                &package_manifest_paths,
+                &mut Manifests::default(),
+                &Opt::parse_from(["cargo-consolidate", "--wildcard", wildcard]),
+                Some(&lockfile),
+            )?;
+            Ok(get_workspace_dependencies(&doc)["dep1"]
+                .as_str()
+                .map(String::from))
+        };
+
+        assert_eq!(add("ignore")?.as_deref(), Some("1.2"));
+        assert_eq!(add("lockfile")?.as_deref(), Some("1.4.0"));
+        assert!(add("reject").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_dependency_to_workspace_intersection() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let add = |a: &str, b: &str| -> Result<String> {
+            let mut package_manifest_paths = HashMap::new();
+            for (name, version) in [("a", a), ("b", b)] {
+                let manifest_path = temp_dir.path().join(name).join("Cargo.toml");
+                write_file(
+                    &manifest_path,
+                    &format!("[dependencies]\ndep1 = \"{}\"\n", version),
+                )?;
+                package_manifest_paths.insert(name.to_string(), manifest_path);
+            }
+            let users: HashSet<_> = ["a".to_string(), "b".to_string()].into();
+            let mut doc = DocumentMut::default();
+            add_dependency_to_workspace(
+                &mut doc,
+                "dep1",
+                temp_dir.path(),
+                &users,
+                &package_manifest_paths,
+                &mut Manifests::default(),
+                &Opt::parse_from(["cargo-consolidate"]),
+                None,
+            )?;
+            Ok(get_workspace_dependencies(&doc)["dep1"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string())
+        };
+
+        assert_eq!(add("1.2", "1.3")?, "1.3");
+        assert_eq!(add("1.3", "~1.4")?, "~1.4");
+        assert_eq!(add(">=1.2, <1.5", "1.3")?, ">=1.3.0, <1.5.0");
+        let err = add("1.6", "<1.5").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No version of 'dep1' satisfies all members: a (1.6) need(s) at least 1.6.0, which b (<1.5) rule(s) out"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pick_spec_provenance() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut package_manifest_paths = HashMap::new();
+        for (name, spec) in [
+            ("a", r#"{ version = "1.2", default-features = false }"#),
+            ("b", r#""1.3""#),
+            ("c", r#""1.3""#),
+        ] {
+            let manifest_path = temp_dir.path().join(name).join("Cargo.toml");
+            write_file(
+                &manifest_path,
+                &format!("[dependencies]\ndep1 = {}\n", spec),
+            )?;
+            package_manifest_paths.insert(name.to_string(), manifest_path);
+        }
+        let users: HashSet<_> = ["a", "b", "c"].map(String::from).into();
+
+        let mut doc = DocumentMut::default();
+        let provenance = add_dependency_to_workspace(
+            &mut doc,
+            "dep1",
+            temp_dir.path(),
+            &users,
+            &package_manifest_paths,
+            &mut Manifests::default(),
+            &Opt::parse_from(["cargo-consolidate", "--version-strategy", "highest"]),
+            None,
+        )?;
+        assert_eq!(
+            provenance.to_string(),
+            "based on the spec of b; overrides a (version `1.2` became `1.3`, default-features `false` became `true`)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pick_spec_every_section() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut package_manifest_paths = HashMap::new();
+        for (name, content) in [
+            (
+                "a",
+                "[dependencies]\ncc = \"1.0\"\n\n[build-dependencies]\ncc = \"1.0.80\"\n",
+            ),
+            ("b", "[dependencies]\ncc = \"1.0\"\n"),
+        ] {
+            let manifest_path = temp_dir.path().join(name).join("Cargo.toml");
+            write_file(&manifest_path, content)?;
+            package_manifest_paths.insert(name.to_string(), manifest_path);
+        }
+        let users: HashSet<_> = ["a", "b"].map(String::from).into();
+
+        let mut doc = DocumentMut::default();
+        let provenance = add_dependency_to_workspace(
+            &mut doc,
+            "cc",
+            temp_dir.path(),
+            &users,
+            &package_manifest_paths,
+            &mut Manifests::default(),
+            &Opt::parse_from(["cargo-consolidate"]),
+            None,
+        )?;
+        // The build script's minimum isn't lowered to the other section's
+        assert_eq!(
+            dependency::get_version(&doc["workspace"]["dependencies"]["cc"]).as_deref(),
+            Some("1.0.80")
+        );
+        assert_eq!(
+            provenance.to_string(),
+            "based on the spec of a; overrides a [dependencies] (version `1.0` became `1.0.80`); overrides b (version `1.0` became `1.0.80`)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prerelease_policy() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut package_manifest_paths = HashMap::new();
+        for (name, version) in [("a", "1.0.0-rc.2"), ("b", "1")] {
+            let manifest_path = temp_dir.path().join(name).join("Cargo.toml");
+            write_file(
+                &manifest_path,
+                &format!("[dependencies]\ndep1 = \"{}\"\n", version),
+            )?;
+            package_manifest_paths.insert(name.to_string(), manifest_path);
+        }
+        let users: HashSet<_> = ["a".to_string(), "b".to_string()].into();
+
+        let mut doc = DocumentMut::default();
+        let opt = Opt::parse_from(["cargo-consolidate", "--prerelease", "error"]);
+        assert!(add_dependency_to_workspace(
+            &mut doc,
+            "dep1",
+            temp_dir.path(),
+            &users,
+            &package_manifest_paths,
+            &mut Manifests::default(),
+            &opt,
+            None
+        )
+        .is_err());
+
+        let opt = Opt::parse_from(["cargo-consolidate"]);
+        add_dependency_to_workspace(
+            &mut doc,
+            "dep1",
+            temp_dir.path(),
+            &users,
+            &package_manifest_paths,
+            &mut Manifests::default(),
+            &opt,
+            None,
+        )?;
+        assert_eq!(get_workspace_dependencies(&doc)["dep1"].as_str(), Some("1"));
+
+        let prerelease_member = &package_manifest_paths["a"];
+        let prerelease_doc = Manifest::open(prerelease_member)?.doc;
+        let sections = dependency::DEPENDENCY_SECTIONS.to_vec();
+        assert!(filter_prerelease_sections(
+            &doc,
+            &prerelease_doc,
+            prerelease_member,
+            "dep1",
+            sections.clone(),
+            PrereleasePolicy::Keep,
+            &mut Explain::default()
+        )?
+        .is_empty());
+        assert_eq!(
+            filter_prerelease_sections(
+                &doc,
+                &prerelease_doc,
+                prerelease_member,
+                "dep1",
+                sections.clone(),
+                PrereleasePolicy::PreferStable,
+                &mut Explain::default()
+            )?,
+            vec!["dependencies"]
+        );
+        assert!(filter_prerelease_sections(
+            &doc,
+            &prerelease_doc,
+            prerelease_member,
+            "dep1",
+            sections,
+            PrereleasePolicy::Error,
+            &mut Explain::default()
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_workspace_dependencies() -> Result<()> {
+        let mut doc = r#"
+[workspace.dependencies]
+serde = { version = "^1.0" }
+tokio = { version = "^1.40", features = ["rt"] }
+syn = "~2.0"
+"#
+        .parse::<DocumentMut>()?;
 
-        update_member_to_use_workspace(&manifest_path, dep_name)?;
+        normalize_workspace_dependencies(&mut doc);
+        assert_eq!(
+            doc.to_string(),
+            r#"
+[workspace.dependencies]
+serde = "1.0.0"
+tokio = { version = "1.40.0", features = ["rt"] }
+syn = "~2.0"
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_used_by_comments() -> Result<()> {
+        let mut doc = r#"
+[workspace.dependencies]
+serde = "1" # used by: api
+tokio = "1"
+anyhow = "1" # error handling
+"#
+        .parse::<DocumentMut>()?;
+        let mut dep_usage: HashMap<String, HashSet<String>> = HashMap::new();
+        for (dep, user) in [
+            ("serde", "worker"),
+            ("serde", "api"),
+            ("tokio", "cli"),
+            ("anyhow", "cli"),
+        ] {
+            dep_usage
+                .entry(dep.to_string())
+                .or_default()
+                .insert(user.to_string());
+        }
+
+        update_used_by_comments(&mut doc, &dep_usage, &HashSet::from(["tokio".to_string()]));
+        assert_eq!(
+            doc.to_string(),
+            r#"
+[workspace.dependencies]
+serde = "1" # used by: api, worker
+tokio = "1" # used by: cli
+anyhow = "1" # error handling
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_preflight_reports_all_problems() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root_path = temp_dir.path().join("Cargo.toml");
+        fs::write(&root_path, "[workspace]\nmembers = [\"a\", \"b\"]\n")?;
+        let root_manifest = Manifest::open(&root_path)?;
+
+        let mut package_manifest_paths = HashMap::new();
+        for (name, content) in [
+            ("a", "[dependencies]\ndep1 = \"*\"\n"),
+            ("b", "[dependencies\ndep1 = \"*\"\n"),
+        ] {
+            let manifest_path = temp_dir.path().join(name).join("Cargo.toml");
+            write_file(&manifest_path, content)?;
+            package_manifest_paths.insert(name.to_string(), manifest_path);
+        }
+        let dep_usage = HashMap::from([(
+            "dep1".to_string(),
+            HashSet::from(["a".to_string(), "b".to_string()]),
+        )]);
 
-        let updated_content = fs::read_to_string(&manifest_path)?;
-        assert!(updated_content.contains("workspace = true"));
+        let problems = preflight(
+            &root_manifest,
+            &package_manifest_paths,
+            &mut Manifests::default(),
+            &dep_usage,
+            &HashMap::new(),
+            &HashMap::new(),
+            &Config::default(),
+            &Opt::parse_from(["cargo-consolidate"]),
+            None,
+        );
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("Failed to parse"));
+        assert!(problems[1].contains("Can't create workspace entry for 'dep1'"));
         Ok(())
     }
 }